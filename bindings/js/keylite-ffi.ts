@@ -28,7 +28,13 @@ const lib = ffi.Library(
     keylite_scan: ["int", [voidPtr, ucharPtr, sizeT, ucharPtr, sizeT, voidPtrPtr]],
     keylite_scan_str: ["int", [voidPtr, "string", "string", voidPtrPtr]],
     keylite_iter_next: ["int", [voidPtr, ucharPtrPtr, sizeTPtr, ucharPtrPtr, sizeTPtr]],
-    keylite_iter_free: ["void", [voidPtr]]
+    keylite_iter_free: ["void", [voidPtr]],
+    keylite_txn_begin: ["int", [voidPtr, voidPtrPtr]],
+    keylite_txn_put: ["int", [voidPtr, ucharPtr, sizeT, ucharPtr, sizeT]],
+    keylite_txn_get: ["int", [voidPtr, ucharPtr, sizeT, ucharPtrPtr, sizeTPtr]],
+    keylite_txn_del: ["int", [voidPtr, ucharPtr, sizeT]],
+    keylite_txn_commit: ["int", [voidPtr]],
+    keylite_txn_abort: ["void", [voidPtr]]
   })
 
 export default lib;
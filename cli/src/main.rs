@@ -188,13 +188,13 @@ fn execute_command(db: &mut Option<(String, Db)>, line: &str) -> Result<CommandR
                 }
             };
             let start_bound: Option<&[u8]> = if parts.len() > 1 {
-                Some(&parts[1].as_bytes())
+                Some(parts[1].as_bytes())
             } else {
                 None
             };
 
             let end_bound: Option<&[u8]> = if parts.len() > 2 {
-                Some(&parts[2].as_bytes())
+                Some(parts[2].as_bytes())
             } else {
                 None
             };
@@ -230,7 +230,7 @@ fn main() -> Result<()> {
     println!();
 
     let mut rl = DefaultEditor::new()?;
-    let history_path = format!("/var/lib/keylite/.keylite_history");
+    let history_path = "/var/lib/keylite/.keylite_history".to_string();
     let _ = rl.load_history(&history_path);
 
     loop {
@@ -81,7 +81,7 @@ fn bench_delete_by_id(db: &KeyLite, collection: &str, ids: &[String]) -> (u128,
 
     for id in ids.iter() {
         let t = Instant::now();
-        let _ = db.delete_doc_by_id(collection, id).unwrap();
+        db.delete_doc_by_id(collection, id).unwrap();
         lat.push(t.elapsed().as_micros());
     }
 
@@ -1,6 +1,8 @@
 use std::path::Path;
+use std::sync::Arc;
 
-use keylite_kv::core::Db;
+use keylite_kv::core::{Db, DbOptions, WriteBatch};
+use keylite_kv::prefix::SeparatorPrefix;
 use serde_json::Value;
 
 use crate::{
@@ -23,7 +25,10 @@ pub struct KeyLite {
 
 impl KeyLite {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let kv = Db::open(path).map_err(DocError::from)?;
+        // documents are scanned by `col:<collection>:doc:`, index entries by
+        // `idx:n:<collection>:...`, both start with a prefix of three separators
+        let opts = DbOptions::default().prefix_extractor(Arc::new(SeparatorPrefix::new(b':', 3)));
+        let kv = Db::open_with(path, opts).map_err(DocError::from)?;
         Ok(Self { kv })
     }
 
@@ -39,9 +44,8 @@ impl KeyLite {
 
     pub fn create_collection(&self, name: &str, indexes: Option<Vec<Index>>) -> Result<()> {
         let key = collection_meta_key(name);
-        match self.kv.get(&key).map_err(DocError::from)? {
-            Some(_) => return Ok(()),
-            None => {}
+        if self.kv.get(&key).map_err(DocError::from)?.is_some() {
+            return Ok(());
         }
 
         let meta = CollectionMeta {
@@ -97,13 +101,14 @@ impl KeyLite {
             )));
         }
 
-        self.del_by_prefix(&format!("idx:u:{collection}:{index_field}:"))?;
-        self.del_by_prefix(&format!("idx:n:{collection}:{index_field}:"))?;
         let updated_bytes = rmp_serde::to_vec(&meta)?;
 
-        self.kv.put(&key, &updated_bytes).map_err(DocError::from)?;
+        let mut batch = WriteBatch::new();
+        del_by_prefix(&mut batch, &format!("idx:u:{collection}:{index_field}:"));
+        del_by_prefix(&mut batch, &format!("idx:n:{collection}:{index_field}:"));
+        batch.put(&key, &updated_bytes);
 
-        Ok(())
+        self.kv.write(batch).map_err(DocError::from)
     }
 
     pub fn list_index(&self, collection: &str) -> Result<Vec<Index>> {
@@ -118,20 +123,12 @@ impl KeyLite {
         Ok(meta.indexes.unwrap_or_default())
     }
 
-    fn del_by_prefix(&self, prefix: &str) -> Result<()> {
-        let (start, end) = prefix_range(prefix);
-        let iter = self.kv.scan(Some(&start), Some(&end));
-        for (k, _) in iter {
-            self.kv.del(&k).map_err(DocError::from)?;
-        }
-        Ok(())
-    }
-
     pub fn drop_collection(&self, name: &str) -> Result<()> {
-        let meta = collection_meta_key(&name);
-        self.kv.del(&meta).map_err(DocError::from)?;
-        self.del_by_prefix(&format!("col:{name}:doc:"))?;
-        Ok(())
+        let meta = collection_meta_key(name);
+        let mut batch = WriteBatch::new();
+        batch.delete(&meta);
+        del_by_prefix(&mut batch, &format!("col:{name}:doc:"));
+        self.kv.write(batch).map_err(DocError::from)
     }
 
     pub fn insert(&self, collection: &str, mut doc: Value) -> Result<String> {
@@ -149,7 +146,10 @@ impl KeyLite {
         let doc_bytes = rmp_serde::to_vec(&doc)?;
         let dkey = doc_key(collection, &id);
 
-        self.kv.put(&dkey, &doc_bytes).map_err(DocError::from)?;
+        // the document and its index entries are written together, a unique constraint violation
+        // leaves nothing behind
+        let mut batch = WriteBatch::new();
+        batch.put(&dkey, &doc_bytes);
 
         let indexes = self.list_index(collection)?;
 
@@ -165,18 +165,19 @@ impl KeyLite {
                         value: value_to_string(&field_value),
                     });
                 }
-                self.kv.put(&ukey, id.as_bytes()).map_err(DocError::from)?;
+                batch.put(&ukey, id.as_bytes());
             } else {
                 let ikey = non_unique_index(collection, field, &field_value, &id);
-                self.kv.put(&ikey, &[1]).map_err(DocError::from)?;
+                batch.put(&ikey, &[1]);
             }
         }
 
+        self.kv.write(batch).map_err(DocError::from)?;
         Ok(id)
     }
 
     pub fn get_doc_by_id(&self, collection: &str, id: &str) -> Result<Option<Value>> {
-        let key = doc_key(&collection, &id);
+        let key = doc_key(collection, id);
 
         Ok(match self.kv.get(&key).map_err(DocError::from)? {
             Some(val) => {
@@ -194,8 +195,7 @@ impl KeyLite {
 
     pub fn scan_collection(&self, collection: &str) -> Result<Vec<Value>> {
         let prefix = format!("col:{collection}:doc:");
-        let (start, end) = prefix_range(&prefix);
-        let iter = self.kv.scan(Some(&start), Some(&end));
+        let iter = self.kv.scan_prefix(prefix.as_bytes());
         let mut out = Vec::new();
         for (_k, v) in iter {
             let doc: Value = rmp_serde::from_slice(&v)?;
@@ -205,7 +205,7 @@ impl KeyLite {
     }
 
     pub fn get_by_index(&self, collection: &str, field: &str, value: &Value) -> Result<Vec<Value>> {
-        let meta_key = collection_meta_key(&collection);
+        let meta_key = collection_meta_key(collection);
         let meta_bytes = self
             .kv
             .get(&meta_key)
@@ -229,15 +229,14 @@ impl KeyLite {
             }
         } else {
             let prefix = format!("idx:n:{collection}:{field}:{}", value_to_string(value));
-            let (start, end) = prefix_range(&prefix);
-            let iter = self.kv.scan(Some(&start), Some(&end));
+            let iter = self.kv.scan_prefix(prefix.as_bytes());
 
             for (k, _) in iter {
                 let key_str = String::from_utf8(k)?;
-                if let Some(id) = key_str.split(':').last() {
-                    if let Some(doc) = self.get_doc_by_id(collection, id)? {
-                        results.push(doc);
-                    }
+                if let Some(id) = key_str.split(':').next_back()
+                    && let Some(doc) = self.get_doc_by_id(collection, id)?
+                {
+                    results.push(doc);
                 }
             }
         }
@@ -251,8 +250,7 @@ impl KeyLite {
         value: &Value,
     ) -> Result<Vec<Value>> {
         let prefix = format!("col:{collection}:doc:");
-        let (start, end) = prefix_range(&prefix);
-        let iter = self.kv.scan(Some(&start), Some(&end));
+        let iter = self.kv.scan_prefix(prefix.as_bytes());
         let mut result = Vec::new();
         for (_, v) in iter {
             let doc: Value = match rmp_serde::from_slice(&v) {
@@ -260,10 +258,10 @@ impl KeyLite {
                 Err(_) => continue,
             };
 
-            if let Some(field_value) = doc.get(field) {
-                if field_value.to_string().to_lowercase() == value.to_string().to_lowercase() {
-                    result.push(doc);
-                }
+            if let Some(field_value) = doc.get(field)
+                && field_value.to_string().to_lowercase() == value.to_string().to_lowercase()
+            {
+                result.push(doc);
             }
         }
         Ok(result)
@@ -271,10 +269,15 @@ impl KeyLite {
 
     pub fn begin(&self) -> Txn<'_> {
         let transaction = self.kv.begin();
-        Txn::new(&self, transaction)
+        Txn::new(self, transaction)
     }
 
     pub fn query(&self, collection: &str) -> Query<'_> {
-        Query::new(&self, collection)
+        Query::new(self, collection)
     }
 }
+
+fn del_by_prefix(batch: &mut WriteBatch, prefix: &str) {
+    let (start, end) = prefix_range(prefix);
+    batch.delete_range(&start, &end);
+}
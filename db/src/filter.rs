@@ -14,10 +14,10 @@ impl Filter {
     pub fn matches(&self, doc: &Value) -> bool {
         match self {
             Filter::Eq { field, value } => {
-                get_field(&doc, field).map(|v| v == value).unwrap_or(false)
+                get_field(doc, field).map(|v| v == value).unwrap_or(false)
             }
 
-            Filter::Gt { field, value } => match (get_field(&doc, field), value) {
+            Filter::Gt { field, value } => match (get_field(doc, field), value) {
                 (Some(Value::Number(a)), Value::Number(b)) => match (a.as_f64(), b.as_f64()) {
                     (Some(a_f), Some(b_f)) => a_f > b_f,
                     _ => false,
@@ -25,7 +25,7 @@ impl Filter {
                 (Some(Value::String(a)), Value::String(b)) => a > b,
                 _ => false,
             },
-            Filter::Lt { field, value } => match (get_field(&doc, field), value) {
+            Filter::Lt { field, value } => match (get_field(doc, field), value) {
                 (Some(Value::Number(a)), Value::Number(b)) => match (a.as_f64(), b.as_f64()) {
                     (Some(a_f), Some(b_f)) => a_f < b_f,
                     _ => false,
@@ -33,10 +33,10 @@ impl Filter {
                 (Some(Value::String(a)), Value::String(b)) => a < b,
                 _ => false,
             },
-            Filter::In { field, values } => get_field(&doc, field)
+            Filter::In { field, values } => get_field(doc, field)
                 .map(|v| values.contains(v))
                 .unwrap_or(false),
-            Filter::Exists { field } => get_field(&doc, field).is_some(),
+            Filter::Exists { field } => get_field(doc, field).is_some(),
         }
     }
 }
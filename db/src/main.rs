@@ -31,7 +31,7 @@ fn main() {
         })
         .collect();
     for user in users {
-        db.insert("users", user.into()).unwrap();
+        db.insert("users", user).unwrap();
     }
 
     let user = db.get_by_index("users", "email", &"rachel@example.com".into());
@@ -141,5 +141,5 @@ fn main() {
     for user in in_filter_result {
         println!("  - {}: age {}", user["name"], user["age"]);
     }
-    fs::remove_dir("testdb");
+    let _ = fs::remove_dir("testdb");
 }
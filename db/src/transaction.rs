@@ -60,7 +60,7 @@ impl<'a> Txn<'a> {
     }
 
     pub fn get_doc_by_id(&mut self, collection: &str, id: &str) -> Result<Option<Value>> {
-        let key = doc_key(&collection, &id);
+        let key = doc_key(collection, id);
 
         Ok(match self.txn.get(&key).map_err(DocError::from)? {
             Some(val) => {
@@ -77,7 +77,7 @@ impl<'a> Txn<'a> {
         field: &str,
         value: &Value,
     ) -> Result<Vec<Value>> {
-        let meta_key = collection_meta_key(&collection);
+        let meta_key = collection_meta_key(collection);
         let meta_bytes = self
             .txn
             .get(&meta_key)
@@ -102,14 +102,20 @@ impl<'a> Txn<'a> {
         } else {
             let prefix = format!("idx:n:{collection}:{field}:{}", value_to_string(value));
             let (start, end) = prefix_range(&prefix);
-            let iter = self.txn.scan(Some(&start), Some(&end));
-
-            for (k, _) in iter {
+            // the scan borrows the transaction, so the index keys are collected before the
+            // documents are looked up
+            let keys: Vec<_> = self
+                .txn
+                .scan(Some(&start), Some(&end))
+                .map(|(k, _)| k)
+                .collect();
+
+            for k in keys {
                 let key_str = String::from_utf8(k)?;
-                if let Some(id) = key_str.split(':').last() {
-                    if let Some(doc) = self.get_doc_by_id(collection, id)? {
-                        results.push(doc);
-                    }
+                if let Some(id) = key_str.split(':').next_back()
+                    && let Some(doc) = self.get_doc_by_id(collection, id)?
+                {
+                    results.push(doc);
                 }
             }
         }
@@ -133,10 +139,10 @@ impl<'a> Txn<'a> {
                 Err(_) => continue,
             };
 
-            if let Some(field_value) = doc.get(field) {
-                if field_value.to_string().to_lowercase() == value.to_string().to_lowercase() {
-                    result.push(doc);
-                }
+            if let Some(field_value) = doc.get(field)
+                && field_value.to_string().to_lowercase() == value.to_string().to_lowercase()
+            {
+                result.push(doc);
             }
         }
         Ok(result)
@@ -0,0 +1,41 @@
+// generates keylite-kv.h from the extern "C" functions in src/lib.rs
+//
+// the header checked in next to Cargo.toml is the one the bindings use, a build only writes it
+// into OUT_DIR so it never touches the source tree. with KEYLITE_REGENERATE_HEADER set the
+// checked in header is replaced as well, after a change to the C API
+//
+// a failure here is only a warning, the library itself doesn't need the header to build
+
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("set by cargo"));
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=KEYLITE_REGENERATE_HEADER");
+
+    let config = match cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("cargo:warning=invalid cbindgen.toml: {}", e);
+            return;
+        }
+    };
+
+    match cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+    {
+        Ok(bindings) => {
+            bindings.write_to_file(out_dir.join("keylite-kv.h"));
+            if env::var_os("KEYLITE_REGENERATE_HEADER").is_some() {
+                bindings.write_to_file(crate_dir.join("keylite-kv.h"));
+            }
+        }
+        Err(e) => println!("cargo:warning=failed to generate keylite-kv.h: {}", e),
+    }
+}
@@ -1,66 +1,142 @@
 #pragma once
 
-/* Generated with cbindgen:0.29.2 */
+/* Generated with cbindgen:0.29.4 */
 
 #include <stdarg.h>
 #include <stdbool.h>
 #include <stddef.h>
 #include <stdint.h>
-#include <stdio.h>
 #include <stdlib.h>
-#include <string>
 #define VERSION 1
 
 typedef enum KeyliteResult {
-  Ok = 0,
-  ErrNull = 1,
-  ErrIo = 2,
-  ErrUtf8 = 3,
-  ErrOther = 4,
+   Ok = 0,
+   ErrNull = 1,
+   ErrIo = 2,
+   ErrUtf8 = 3,
+   ErrOther = 4,
+   ErrConflict = 5,
 } KeyliteResult;
 
 typedef struct KeyliteDb KeyliteDb;
 
 typedef struct KeyliteIterator KeyliteIterator;
 
+typedef struct KeyliteTxn KeyliteTxn;
+
+/**
+ * Closes a database opened with keylite_open, NULL is ignored.
+ */
 void keylite_close(struct KeyliteDb *Db);
 
-enum KeyliteResult keylite_del(struct KeyliteDb *Db, const uint8_t *Key,
+enum KeyliteResult keylite_del(struct KeyliteDb *Db,
+                               const uint8_t *Key,
                                size_t KeyLen);
 
 enum KeyliteResult keylite_del_str(struct KeyliteDb *Db, const char *Key);
 
+/**
+ * Releases a string returned by keylite_get_str.
+ */
 void keylite_free_str(char *Val);
 
-void keylite_free_value(uint8_t *Val, size_t Len);
-
-enum KeyliteResult keylite_get(struct KeyliteDb *Db, const uint8_t *Key,
-                               size_t KeyLen, uint8_t **ValOut,
+/**
+ * Releases a value returned by keylite_get, keylite_txn_get or keylite_iter_next.
+ */
+void keylite_free_value(uint8_t *Val,
+                        size_t Len);
+
+/**
+ * Looks up a key, the value is set to NULL if the key doesn't exist.
+ */
+enum KeyliteResult keylite_get(struct KeyliteDb *Db,
+                               const uint8_t *Key,
+                               size_t KeyLen,
+                               uint8_t **ValOut,
                                size_t *ValLenOut);
 
-enum KeyliteResult keylite_get_str(struct KeyliteDb *Db, const char *Key,
+/**
+ * Looks up a key, the value is set to NULL if the key doesn't exist.
+ */
+enum KeyliteResult keylite_get_str(struct KeyliteDb *Db,
+                                   const char *Key,
                                    char **ValOut);
 
 void keylite_iter_free(struct KeyliteIterator *Iter);
 
+/**
+ * Advances the iterator, key and value are set to NULL once it's exhausted.
+ */
 enum KeyliteResult keylite_iter_next(struct KeyliteIterator *Iter,
-                                     uint8_t **KeyOut, size_t *KeyLenOut,
-                                     uint8_t **ValOut, size_t *ValLenOut);
-
+                                     uint8_t **KeyOut,
+                                     size_t *KeyLenOut,
+                                     uint8_t **ValOut,
+                                     size_t *ValLenOut);
+
+/**
+ * Opens (or creates) the database in the given directory.
+ */
 enum KeyliteResult keylite_open(const char *Path, struct KeyliteDb **DbOut);
 
-enum KeyliteResult keylite_put(struct KeyliteDb *Db, const uint8_t *Key,
-                               size_t KeyLen, const uint8_t *Val,
+enum KeyliteResult keylite_put(struct KeyliteDb *Db,
+                               const uint8_t *Key,
+                               size_t KeyLen,
+                               const uint8_t *Val,
                                size_t ValLen);
 
-enum KeyliteResult keylite_put_str(struct KeyliteDb *Db, const char *Key,
+enum KeyliteResult keylite_put_str(struct KeyliteDb *Db,
+                                   const char *Key,
                                    const char *Val);
 
-enum KeyliteResult keylite_scan(struct KeyliteDb *Db, const uint8_t *Start,
-                                size_t StartLen, const uint8_t *End,
+/**
+ * Iterates over the keys in [start, end), a NULL bound is unbounded.
+ */
+enum KeyliteResult keylite_scan(struct KeyliteDb *Db,
+                                const uint8_t *Start,
+                                size_t StartLen,
+                                const uint8_t *End,
                                 size_t EndLen,
                                 struct KeyliteIterator **IterOut);
 
-enum KeyliteResult keylite_scan_str(struct KeyliteDb *Db, const char *Start,
+/**
+ * Iterates over the keys in [start, end), a NULL bound is unbounded.
+ */
+enum KeyliteResult keylite_scan_str(struct KeyliteDb *Db,
+                                    const char *Start,
                                     const char *End,
                                     struct KeyliteIterator **IterOut);
+
+/**
+ * Discards and releases the transaction, NULL is ignored.
+ */
+void keylite_txn_abort(struct KeyliteTxn *Txn);
+
+/**
+ * Starts a transaction, it sees the database as of this call plus its own writes.
+ */
+enum KeyliteResult keylite_txn_begin(struct KeyliteDb *Db,
+                                     struct KeyliteTxn **TxnOut);
+
+/**
+ * Commits and releases the transaction, the handle is invalid afterwards even on error.
+ */
+enum KeyliteResult keylite_txn_commit(struct KeyliteTxn *Txn);
+
+enum KeyliteResult keylite_txn_del(struct KeyliteTxn *Txn,
+                                   const uint8_t *Key,
+                                   size_t KeyLen);
+
+/**
+ * Looks up a key in the transaction, the value is set to NULL if the key doesn't exist.
+ */
+enum KeyliteResult keylite_txn_get(struct KeyliteTxn *Txn,
+                                   const uint8_t *Key,
+                                   size_t KeyLen,
+                                   uint8_t **ValOut,
+                                   size_t *ValLenOut);
+
+enum KeyliteResult keylite_txn_put(struct KeyliteTxn *Txn,
+                                   const uint8_t *Key,
+                                   size_t KeyLen,
+                                   const uint8_t *Val,
+                                   size_t ValLen);
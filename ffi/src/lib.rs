@@ -0,0 +1,500 @@
+// C API of keylite-kv, built as libkeylite_kv.so, the matching keylite-kv.h header is generated by
+// build.rs
+//
+// ownership rules:
+// - handles handed out through an out pointer (database, iterator, transaction) belong to the
+//   caller and must be released with keylite_close, keylite_iter_free or
+//   keylite_txn_commit / keylite_txn_abort
+// - values handed out through an out pointer belong to the caller, binary values are released
+//   with keylite_free_value, strings with keylite_free_str
+// - a missing key and an exhausted iterator are reported as Ok with the out pointer set to NULL
+// - iterators and transactions keep the database alive, the database is only shut down once it
+//   was closed and the last of them is released
+//
+// no panic ever unwinds into the caller, a panic inside the engine is reported as ErrOther
+
+// the safety contract of every function is the same and described above
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+use kv::core::{Db, DbIterator};
+use kv::error::DbError;
+use kv::transaction::Transaction;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyliteResult {
+    Ok = 0,
+    ErrNull = 1,
+    ErrIo = 2,
+    ErrUtf8 = 3,
+    ErrOther = 4,
+    ErrConflict = 5,
+}
+
+impl From<DbError> for KeyliteResult {
+    fn from(err: DbError) -> Self {
+        match err {
+            DbError::Io(_) => KeyliteResult::ErrIo,
+            DbError::Conflict(_) => KeyliteResult::ErrConflict,
+            _ => KeyliteResult::ErrOther,
+        }
+    }
+}
+
+pub struct KeyliteDb {
+    db: Arc<Db>,
+}
+
+pub struct KeyliteIterator {
+    iter: DbIterator,
+}
+
+pub struct KeyliteTxn {
+    // borrows from the database owned by `db`, fields are dropped in declaration order so the
+    // transaction always goes away before the reference that keeps the database alive
+    txn: Transaction<'static>,
+    db: Arc<Db>,
+}
+
+// runs the body of an exported function, turning a panic into ErrOther
+fn guard(f: impl FnOnce() -> KeyliteResult) -> KeyliteResult {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(KeyliteResult::ErrOther)
+}
+
+unsafe fn bytes_arg<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(ptr, len))
+    }
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char) -> Result<&'a str, KeyliteResult> {
+    if ptr.is_null() {
+        return Err(KeyliteResult::ErrNull);
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| KeyliteResult::ErrUtf8)
+}
+
+// hands a value over to the caller, released again with keylite_free_value
+unsafe fn write_value(val: Option<Vec<u8>>, out: *mut *mut u8, len_out: *mut usize) {
+    match val {
+        Some(val) => {
+            let boxed = val.into_boxed_slice();
+            *len_out = boxed.len();
+            *out = Box::into_raw(boxed) as *mut u8;
+        }
+        None => {
+            *len_out = 0;
+            *out = ptr::null_mut();
+        }
+    }
+}
+
+// hands a string over to the caller, released again with keylite_free_str
+unsafe fn write_str(val: Option<Vec<u8>>, out: *mut *mut c_char) -> KeyliteResult {
+    *out = ptr::null_mut();
+    let Some(val) = val else {
+        return KeyliteResult::Ok;
+    };
+    if std::str::from_utf8(&val).is_err() {
+        return KeyliteResult::ErrUtf8;
+    }
+    match CString::new(val) {
+        Ok(s) => {
+            *out = s.into_raw();
+            KeyliteResult::Ok
+        }
+        // an interior NUL can't be represented as a C string
+        Err(_) => KeyliteResult::ErrOther,
+    }
+}
+
+/// Opens (or creates) the database in the given directory.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_open(
+    path: *const c_char,
+    db_out: *mut *mut KeyliteDb,
+) -> KeyliteResult {
+    guard(|| {
+        if db_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        *db_out = ptr::null_mut();
+        let path = match str_arg(path) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+        match Db::open(path) {
+            Ok(db) => {
+                *db_out = Box::into_raw(Box::new(KeyliteDb { db: Arc::new(db) }));
+                KeyliteResult::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Closes a database opened with keylite_open, NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_close(db: *mut KeyliteDb) {
+    if db.is_null() {
+        return;
+    }
+    // dropping the last reference flushes the memtables and joins the background threads
+    let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(db))));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_put(
+    db: *mut KeyliteDb,
+    key: *const u8,
+    key_len: usize,
+    val: *const u8,
+    val_len: usize,
+) -> KeyliteResult {
+    guard(|| {
+        let (Some(db), Some(key), Some(val)) = (
+            db.as_ref(),
+            bytes_arg(key, key_len),
+            bytes_arg(val, val_len),
+        ) else {
+            return KeyliteResult::ErrNull;
+        };
+        match db.db.put(key, val) {
+            Ok(()) => KeyliteResult::Ok,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Looks up a key, the value is set to NULL if the key doesn't exist.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_get(
+    db: *mut KeyliteDb,
+    key: *const u8,
+    key_len: usize,
+    val_out: *mut *mut u8,
+    val_len_out: *mut usize,
+) -> KeyliteResult {
+    guard(|| {
+        let (Some(db), Some(key)) = (db.as_ref(), bytes_arg(key, key_len)) else {
+            return KeyliteResult::ErrNull;
+        };
+        if val_out.is_null() || val_len_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        match db.db.get(key) {
+            Ok(val) => {
+                write_value(val, val_out, val_len_out);
+                KeyliteResult::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_del(
+    db: *mut KeyliteDb,
+    key: *const u8,
+    key_len: usize,
+) -> KeyliteResult {
+    guard(|| {
+        let (Some(db), Some(key)) = (db.as_ref(), bytes_arg(key, key_len)) else {
+            return KeyliteResult::ErrNull;
+        };
+        match db.db.del(key) {
+            Ok(()) => KeyliteResult::Ok,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Releases a value returned by keylite_get, keylite_txn_get or keylite_iter_next.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_free_value(val: *mut u8, len: usize) {
+    if val.is_null() {
+        return;
+    }
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(val, len)));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_put_str(
+    db: *mut KeyliteDb,
+    key: *const c_char,
+    val: *const c_char,
+) -> KeyliteResult {
+    guard(|| {
+        let Some(db) = db.as_ref() else {
+            return KeyliteResult::ErrNull;
+        };
+        let (key, val) = match (str_arg(key), str_arg(val)) {
+            (Ok(key), Ok(val)) => (key, val),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+        match db.db.put(key.as_bytes(), val.as_bytes()) {
+            Ok(()) => KeyliteResult::Ok,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Looks up a key, the value is set to NULL if the key doesn't exist.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_get_str(
+    db: *mut KeyliteDb,
+    key: *const c_char,
+    val_out: *mut *mut c_char,
+) -> KeyliteResult {
+    guard(|| {
+        let Some(db) = db.as_ref() else {
+            return KeyliteResult::ErrNull;
+        };
+        if val_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        let key = match str_arg(key) {
+            Ok(key) => key,
+            Err(e) => return e,
+        };
+        match db.db.get(key.as_bytes()) {
+            Ok(val) => write_str(val, val_out),
+            Err(e) => e.into(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_del_str(db: *mut KeyliteDb, key: *const c_char) -> KeyliteResult {
+    guard(|| {
+        let Some(db) = db.as_ref() else {
+            return KeyliteResult::ErrNull;
+        };
+        let key = match str_arg(key) {
+            Ok(key) => key,
+            Err(e) => return e,
+        };
+        match db.db.del(key.as_bytes()) {
+            Ok(()) => KeyliteResult::Ok,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Releases a string returned by keylite_get_str.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_free_str(val: *mut c_char) {
+    if val.is_null() {
+        return;
+    }
+    drop(CString::from_raw(val));
+}
+
+/// Iterates over the keys in [start, end), a NULL bound is unbounded.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_scan(
+    db: *mut KeyliteDb,
+    start: *const u8,
+    start_len: usize,
+    end: *const u8,
+    end_len: usize,
+    iter_out: *mut *mut KeyliteIterator,
+) -> KeyliteResult {
+    guard(|| {
+        let Some(db) = db.as_ref() else {
+            return KeyliteResult::ErrNull;
+        };
+        if iter_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        let iter = db
+            .db
+            .scan(bytes_arg(start, start_len), bytes_arg(end, end_len));
+        *iter_out = Box::into_raw(Box::new(KeyliteIterator { iter }));
+        KeyliteResult::Ok
+    })
+}
+
+/// Iterates over the keys in [start, end), a NULL bound is unbounded.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_scan_str(
+    db: *mut KeyliteDb,
+    start: *const c_char,
+    end: *const c_char,
+    iter_out: *mut *mut KeyliteIterator,
+) -> KeyliteResult {
+    guard(|| {
+        let Some(db) = db.as_ref() else {
+            return KeyliteResult::ErrNull;
+        };
+        if iter_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        let bound = |ptr: *const c_char| match ptr.is_null() {
+            true => Ok(None),
+            false => str_arg(ptr).map(Some),
+        };
+        let (start, end) = match (bound(start), bound(end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => return e,
+        };
+        let iter = db.db.scan(start.map(str::as_bytes), end.map(str::as_bytes));
+        *iter_out = Box::into_raw(Box::new(KeyliteIterator { iter }));
+        KeyliteResult::Ok
+    })
+}
+
+/// Advances the iterator, key and value are set to NULL once it's exhausted.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_iter_next(
+    iter: *mut KeyliteIterator,
+    key_out: *mut *mut u8,
+    key_len_out: *mut usize,
+    val_out: *mut *mut u8,
+    val_len_out: *mut usize,
+) -> KeyliteResult {
+    guard(|| {
+        let Some(iter) = iter.as_mut() else {
+            return KeyliteResult::ErrNull;
+        };
+        if key_out.is_null() || key_len_out.is_null() || val_out.is_null() || val_len_out.is_null()
+        {
+            return KeyliteResult::ErrNull;
+        }
+        let (key, val) = iter.iter.next().unzip();
+        write_value(key, key_out, key_len_out);
+        write_value(val, val_out, val_len_out);
+        KeyliteResult::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_iter_free(iter: *mut KeyliteIterator) {
+    if iter.is_null() {
+        return;
+    }
+    let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(iter))));
+}
+
+/// Starts a transaction, it sees the database as of this call plus its own writes.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_txn_begin(
+    db: *mut KeyliteDb,
+    txn_out: *mut *mut KeyliteTxn,
+) -> KeyliteResult {
+    guard(|| {
+        let Some(db) = db.as_ref() else {
+            return KeyliteResult::ErrNull;
+        };
+        if txn_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        let db = Arc::clone(&db.db);
+        // the Db lives in the Arc allocation which is kept alive by the KeyliteTxn itself
+        let db_ref: &'static Db = &*Arc::as_ptr(&db);
+        let txn = db_ref.begin();
+        *txn_out = Box::into_raw(Box::new(KeyliteTxn { txn, db }));
+        KeyliteResult::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_txn_put(
+    txn: *mut KeyliteTxn,
+    key: *const u8,
+    key_len: usize,
+    val: *const u8,
+    val_len: usize,
+) -> KeyliteResult {
+    guard(|| {
+        let (Some(txn), Some(key), Some(val)) = (
+            txn.as_mut(),
+            bytes_arg(key, key_len),
+            bytes_arg(val, val_len),
+        ) else {
+            return KeyliteResult::ErrNull;
+        };
+        txn.txn.put(key, val);
+        KeyliteResult::Ok
+    })
+}
+
+/// Looks up a key in the transaction, the value is set to NULL if the key doesn't exist.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_txn_get(
+    txn: *mut KeyliteTxn,
+    key: *const u8,
+    key_len: usize,
+    val_out: *mut *mut u8,
+    val_len_out: *mut usize,
+) -> KeyliteResult {
+    guard(|| {
+        let (Some(txn), Some(key)) = (txn.as_ref(), bytes_arg(key, key_len)) else {
+            return KeyliteResult::ErrNull;
+        };
+        if val_out.is_null() || val_len_out.is_null() {
+            return KeyliteResult::ErrNull;
+        }
+        match txn.txn.get(key) {
+            Ok(val) => {
+                write_value(val, val_out, val_len_out);
+                KeyliteResult::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn keylite_txn_del(
+    txn: *mut KeyliteTxn,
+    key: *const u8,
+    key_len: usize,
+) -> KeyliteResult {
+    guard(|| {
+        let (Some(txn), Some(key)) = (txn.as_mut(), bytes_arg(key, key_len)) else {
+            return KeyliteResult::ErrNull;
+        };
+        txn.txn.del(key);
+        KeyliteResult::Ok
+    })
+}
+
+/// Commits and releases the transaction, the handle is invalid afterwards even on error.
+///
+/// ErrConflict means data the transaction read was changed since it began, nothing was written
+/// and the work can be retried in a new transaction.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_txn_commit(txn: *mut KeyliteTxn) -> KeyliteResult {
+    if txn.is_null() {
+        return KeyliteResult::ErrNull;
+    }
+    guard(|| {
+        let KeyliteTxn { txn, db } = *Box::from_raw(txn);
+        let result = match txn.commit() {
+            Ok(()) => KeyliteResult::Ok,
+            Err(e) => e.into(),
+        };
+        drop(db);
+        result
+    })
+}
+
+/// Discards and releases the transaction, NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn keylite_txn_abort(txn: *mut KeyliteTxn) {
+    if txn.is_null() {
+        return;
+    }
+    let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(txn))));
+}
@@ -0,0 +1,177 @@
+// exercises the C API the way a C program would, built and run by tests/c_api_test.rs
+//
+// usage: keylite_test <database directory>
+
+#include <stdio.h>
+#include <string.h>
+
+#include "keylite-kv.h"
+
+#define CHECK(cond)                                                          \
+   do {                                                                      \
+      if (!(cond)) {                                                         \
+         fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,    \
+                 #cond);                                                     \
+         return 1;                                                           \
+      }                                                                      \
+   } while (0)
+
+static int test_binary(KeyliteDb *db) {
+   const uint8_t key[] = {'k', 0, 1};
+   const uint8_t val[] = {0xde, 0xad, 0x00, 0xbe, 0xef};
+   uint8_t *out = NULL;
+   size_t out_len = 0;
+
+   CHECK(keylite_put(db, key, sizeof(key), val, sizeof(val)) == Ok);
+   CHECK(keylite_get(db, key, sizeof(key), &out, &out_len) == Ok);
+   CHECK(out != NULL);
+   CHECK(out_len == sizeof(val));
+   CHECK(memcmp(out, val, sizeof(val)) == 0);
+   keylite_free_value(out, out_len);
+
+   CHECK(keylite_del(db, key, sizeof(key)) == Ok);
+   CHECK(keylite_get(db, key, sizeof(key), &out, &out_len) == Ok);
+   CHECK(out == NULL);
+
+   CHECK(keylite_put(NULL, key, sizeof(key), val, sizeof(val)) == ErrNull);
+   CHECK(keylite_get(db, NULL, 0, &out, &out_len) == ErrNull);
+   return 0;
+}
+
+static int test_strings(KeyliteDb *db) {
+   char *out = NULL;
+
+   CHECK(keylite_put_str(db, "name", "keylite") == Ok);
+   CHECK(keylite_get_str(db, "name", &out) == Ok);
+   CHECK(out != NULL && strcmp(out, "keylite") == 0);
+   keylite_free_str(out);
+
+   CHECK(keylite_get_str(db, "missing", &out) == Ok);
+   CHECK(out == NULL);
+
+   CHECK(keylite_put_str(db, "bad", "\xff\xfe") == ErrUtf8);
+
+   CHECK(keylite_del_str(db, "name") == Ok);
+   CHECK(keylite_get_str(db, "name", &out) == Ok);
+   CHECK(out == NULL);
+   return 0;
+}
+
+static int test_scan(KeyliteDb *db) {
+   char key[16];
+   KeyliteIterator *iter = NULL;
+   uint8_t *k = NULL, *v = NULL;
+   size_t k_len = 0, v_len = 0;
+   int count = 0;
+
+   for (int i = 0; i < 10; i++) {
+      snprintf(key, sizeof(key), "scan_%02d", i);
+      CHECK(keylite_put_str(db, key, "v") == Ok);
+   }
+
+   CHECK(keylite_scan_str(db, "scan_03", "scan_07", &iter) == Ok);
+   for (;;) {
+      CHECK(keylite_iter_next(iter, &k, &k_len, &v, &v_len) == Ok);
+      if (k == NULL) {
+         break;
+      }
+      snprintf(key, sizeof(key), "scan_%02d", count + 3);
+      CHECK(k_len == strlen(key) && memcmp(k, key, k_len) == 0);
+      keylite_free_value(k, k_len);
+      keylite_free_value(v, v_len);
+      count++;
+   }
+   CHECK(count == 4);
+   keylite_iter_free(iter);
+
+   // unbounded on both ends
+   CHECK(keylite_scan(db, NULL, 0, NULL, 0, &iter) == Ok);
+   count = 0;
+   for (;;) {
+      CHECK(keylite_iter_next(iter, &k, &k_len, &v, &v_len) == Ok);
+      if (k == NULL) {
+         break;
+      }
+      keylite_free_value(k, k_len);
+      keylite_free_value(v, v_len);
+      count++;
+   }
+   CHECK(count == 10);
+   keylite_iter_free(iter);
+   return 0;
+}
+
+static int test_txn(KeyliteDb *db) {
+   KeyliteTxn *txn = NULL;
+   uint8_t *out = NULL;
+   size_t out_len = 0;
+   const uint8_t a[] = "a", b[] = "b", one[] = "1", two[] = "2";
+
+   CHECK(keylite_put(db, a, 1, one, 1) == Ok);
+
+   CHECK(keylite_txn_begin(db, &txn) == Ok);
+   CHECK(keylite_txn_put(txn, b, 1, two, 1) == Ok);
+   CHECK(keylite_txn_del(txn, a, 1) == Ok);
+
+   // own writes are visible inside the transaction only
+   CHECK(keylite_txn_get(txn, b, 1, &out, &out_len) == Ok);
+   CHECK(out != NULL && out_len == 1 && out[0] == '2');
+   keylite_free_value(out, out_len);
+   CHECK(keylite_txn_get(txn, a, 1, &out, &out_len) == Ok);
+   CHECK(out == NULL);
+   CHECK(keylite_get(db, b, 1, &out, &out_len) == Ok);
+   CHECK(out == NULL);
+
+   CHECK(keylite_txn_commit(txn) == Ok);
+   CHECK(keylite_get(db, b, 1, &out, &out_len) == Ok);
+   CHECK(out != NULL && out[0] == '2');
+   keylite_free_value(out, out_len);
+   CHECK(keylite_get(db, a, 1, &out, &out_len) == Ok);
+   CHECK(out == NULL);
+
+   // an aborted transaction leaves no trace
+   CHECK(keylite_txn_begin(db, &txn) == Ok);
+   CHECK(keylite_txn_put(txn, a, 1, two, 1) == Ok);
+   keylite_txn_abort(txn);
+   CHECK(keylite_get(db, a, 1, &out, &out_len) == Ok);
+   CHECK(out == NULL);
+
+   // a key read by the transaction was overwritten before it committed
+   CHECK(keylite_txn_begin(db, &txn) == Ok);
+   CHECK(keylite_txn_get(txn, b, 1, &out, &out_len) == Ok);
+   keylite_free_value(out, out_len);
+   CHECK(keylite_put(db, b, 1, one, 1) == Ok);
+   CHECK(keylite_txn_put(txn, a, 1, two, 1) == Ok);
+   CHECK(keylite_txn_commit(txn) == ErrConflict);
+   CHECK(keylite_get(db, a, 1, &out, &out_len) == Ok);
+   CHECK(out == NULL);
+   return 0;
+}
+
+int main(int argc, char **argv) {
+   KeyliteDb *db = NULL;
+   char *out = NULL;
+
+   if (argc != 2) {
+      fprintf(stderr, "usage: %s <dir>\n", argv[0]);
+      return 2;
+   }
+
+   CHECK(keylite_open(argv[1], &db) == Ok);
+   CHECK(db != NULL);
+
+   if (test_binary(db) || test_strings(db) || test_scan(db) || test_txn(db)) {
+      return 1;
+   }
+   keylite_close(db);
+
+   // everything written above survives a reopen
+   CHECK(keylite_open(argv[1], &db) == Ok);
+   CHECK(keylite_get_str(db, "scan_09", &out) == Ok);
+   CHECK(out != NULL && strcmp(out, "v") == 0);
+   keylite_free_str(out);
+   keylite_close(db);
+
+   printf("ok\n");
+   return 0;
+}
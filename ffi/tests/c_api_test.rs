@@ -0,0 +1,59 @@
+// builds tests/c/keylite_test.c against keylite-kv.h and the freshly built libkeylite_kv and runs
+// it, this is the only test that proves the header and the library agree
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// target/<profile>, where cargo puts the cdylib
+fn target_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    exe.parent().unwrap().parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn test_c_program_against_library() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = target_dir();
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let exe = out_dir.join("keylite_test");
+    let db_dir = out_dir.join("c_api_db");
+    let _ = fs::remove_dir_all(&db_dir);
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = match Command::new(&cc)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I")
+        .arg(&crate_dir)
+        .arg(crate_dir.join("tests/c/keylite_test.c"))
+        .arg("-o")
+        .arg(&exe)
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg("-lkeylite_kv")
+        .status()
+    {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("skipping, no C compiler available ({}): {}", cc, e);
+            return;
+        }
+    };
+    assert!(status.success(), "failed to compile the C test program");
+
+    let output = Command::new(&exe)
+        .arg(&db_dir)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "C test program failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "ok");
+
+    let _ = fs::remove_dir_all(&db_dir);
+}
@@ -48,7 +48,7 @@ fn bench_del(db: &Db, keys: &[Vec<u8>]) -> (u128, Vec<u128>) {
 
     for key in keys.iter() {
         let t = Instant::now();
-        let _ = db.del(key).unwrap();
+        db.del(key).unwrap();
         lat.push(t.elapsed().as_micros());
     }
 
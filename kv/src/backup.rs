@@ -0,0 +1,357 @@
+// backups of a live database, built on top of `Db::checkpoint`
+//
+// a backup directory holds any number of backups, the tables and blob files they have in common
+// are stored only once:
+//
+// shared/             tables and blob files, named <file>_<crc32>_<size>.<ext>
+// private/<id>/       the MANIFEST and WAL segments of a backup
+// meta/<id>           what a backup consists of, see BackupMeta
+//
+// a table id doesn't identify its content on its own, a database restored from an older backup
+// hands out the ids the newer backups already used, hence the checksum and size in the names
+//
+// a backup only exists once its meta file is renamed into place, a crash while it's created
+// leaves files in shared/ and private/ that no meta file references, the next backup or delete
+// removes them
+
+use crc32fast::Hasher;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blob::parse_blob_id;
+use crate::core::Db;
+use crate::error::{DbError, Result};
+use crate::manifest::sync_dir;
+use crate::sst::reader::parse_sst_id;
+
+const SHARED_DIR: &str = "shared";
+const PRIVATE_DIR: &str = "private";
+const META_DIR: &str = "meta";
+const META_HEADER: &str = "keylite-backup 1";
+
+/// a backup as listed by `BackupEngine::list_backups`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u64,
+    /// when the backup was taken, in seconds since the unix epoch
+    pub timestamp: u64,
+    /// total size in bytes of the files of the backup, shared ones included
+    pub size: u64,
+    pub num_files: usize,
+}
+
+// a file of a backup: where it's stored, relative to the backup directory, and the name it's
+// restored under
+struct BackupFile {
+    stored: String,
+    name: String,
+    size: u64,
+    crc: u32,
+}
+
+// the contents of a meta file, one line each:
+//
+// keylite-backup 1
+// timestamp <secs>
+// file <stored> <name> <size> <crc32 in hex>
+struct BackupMeta {
+    timestamp: u64,
+    files: Vec<BackupFile>,
+}
+
+impl BackupMeta {
+    fn encode(&self) -> String {
+        let mut out = format!("{}\ntimestamp {}\n", META_HEADER, self.timestamp);
+        for file in &self.files {
+            out.push_str(&format!(
+                "file {} {} {} {:08x}\n",
+                file.stored, file.name, file.size, file.crc
+            ));
+        }
+        out
+    }
+
+    fn decode(data: &str) -> Option<Self> {
+        let mut lines = data.lines();
+        if lines.next()? != META_HEADER {
+            return None;
+        }
+        let timestamp = lines.next()?.strip_prefix("timestamp ")?.parse().ok()?;
+        let mut files = Vec::new();
+        for line in lines {
+            let mut parts = line.strip_prefix("file ")?.split(' ');
+            files.push(BackupFile {
+                stored: parts.next()?.to_string(),
+                name: parts.next()?.to_string(),
+                size: parts.next()?.parse().ok()?,
+                crc: u32::from_str_radix(parts.next()?, 16).ok()?,
+            });
+        }
+        Some(Self { timestamp, files })
+    }
+}
+
+/// incremental backups of databases in a directory of their own, see `create_backup`
+///
+/// ```no_run
+/// use keylite_kv::backup::BackupEngine;
+/// use keylite_kv::core::Db;
+///
+/// let db = Db::open("my_db")?;
+/// let backups = BackupEngine::open("my_backups")?;
+/// let id = backups.create_backup(&db)?;
+/// backups.verify_backup(id)?;
+/// backups.restore_backup(id, "restored_db")?;
+/// # Ok::<(), keylite_kv::error::DbError>(())
+/// ```
+pub struct BackupEngine {
+    dir: PathBuf,
+}
+
+impl BackupEngine {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        for sub in [SHARED_DIR, PRIVATE_DIR, META_DIR] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        Ok(Self { dir })
+    }
+
+    /// backs up the database while writers keep going and returns the id of the backup. only
+    /// the tables and blob files no earlier backup has are copied
+    pub fn create_backup(&self, db: &Db) -> Result<u64> {
+        let id = self.backup_ids()?.last().map_or(1, |id| id + 1);
+        self.collect_garbage()?;
+
+        let checkpoint = self.dir.join(format!("checkpoint-{}", id));
+        let _ = fs::remove_dir_all(&checkpoint);
+        db.checkpoint(&checkpoint)?;
+
+        let private = self.dir.join(PRIVATE_DIR).join(id.to_string());
+        fs::create_dir_all(&private)?;
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&checkpoint)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+
+        // the checkpoint lives in the backup directory, so its files are moved rather than
+        // copied. the tables are usually hard links of the database's files anyway
+        let mut files = Vec::new();
+        for name in names {
+            let path = checkpoint.join(&name);
+            let (size, crc) = checksum(&path)?;
+            let stored = if is_shared(&name) {
+                let stored = format!("{}/{}", SHARED_DIR, shared_name(&name, size, crc));
+                if !self.dir.join(&stored).exists() {
+                    fs::rename(&path, self.dir.join(&stored))?;
+                }
+                stored
+            } else {
+                let stored = format!("{}/{}/{}", PRIVATE_DIR, id, name);
+                fs::rename(&path, self.dir.join(&stored))?;
+                stored
+            };
+            files.push(BackupFile {
+                stored,
+                name,
+                size,
+                crc,
+            });
+        }
+        sync_dir(&self.dir.join(SHARED_DIR))?;
+        sync_dir(&private)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let meta = BackupMeta { timestamp, files };
+        let meta_path = self.meta_path(id);
+        let tmp_path = meta_path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(meta.encode().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &meta_path)?;
+        sync_dir(&self.dir.join(META_DIR))?;
+
+        fs::remove_dir_all(&checkpoint)?;
+        Ok(id)
+    }
+
+    /// every backup in the directory, oldest first
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        let mut backups = Vec::new();
+        for id in self.backup_ids()? {
+            let meta = self.read_meta(id)?;
+            backups.push(BackupInfo {
+                id,
+                timestamp: meta.timestamp,
+                size: meta.files.iter().map(|f| f.size).sum(),
+                num_files: meta.files.len(),
+            });
+        }
+        Ok(backups)
+    }
+
+    /// checks that every file of the backup is there with the size and checksum it was backed
+    /// up with
+    pub fn verify_backup(&self, id: u64) -> Result<()> {
+        for file in self.read_meta(id)?.files {
+            let path = self.dir.join(&file.stored);
+            let (size, crc) = checksum(&path).map_err(|e| {
+                DbError::DataCorruption(format!("backup file {:?} can't be read: {}", path, e))
+            })?;
+            if size != file.size || crc != file.crc {
+                return Err(DbError::DataCorruption(format!(
+                    "backup file {:?} doesn't match its checksum",
+                    path
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// restores the backup into `dest`, which must not exist or be empty. the files are
+    /// verified on the way, a damaged backup fails the restore
+    pub fn restore_backup(&self, id: u64, dest: impl AsRef<Path>) -> Result<()> {
+        let dest = dest.as_ref();
+        let meta = self.read_meta(id)?;
+        if dest.exists() && fs::read_dir(dest)?.next().is_some() {
+            return Err(DbError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("restore destination {:?} isn't empty", dest),
+            )));
+        }
+        fs::create_dir_all(dest)?;
+
+        for file in &meta.files {
+            let src = self.dir.join(&file.stored);
+            let dst = dest.join(&file.name);
+            let (size, crc) = copy_with_checksum(&src, &dst)?;
+            if size != file.size || crc != file.crc {
+                let _ = fs::remove_dir_all(dest);
+                return Err(DbError::DataCorruption(format!(
+                    "backup file {:?} doesn't match its checksum",
+                    src
+                )));
+            }
+        }
+        sync_dir(dest)
+    }
+
+    /// deletes the backup, the shared files only it referenced go along with it
+    pub fn delete_backup(&self, id: u64) -> Result<()> {
+        let meta_path = self.meta_path(id);
+        if !meta_path.exists() {
+            return Err(DbError::Backup(format!("backup {} doesn't exist", id)));
+        }
+        fs::remove_file(&meta_path)?;
+        sync_dir(&self.dir.join(META_DIR))?;
+        self.collect_garbage()
+    }
+
+    // removes the files no backup references, left over by deleted backups or by a backup that
+    // didn't finish
+    fn collect_garbage(&self) -> Result<()> {
+        let ids = self.backup_ids()?;
+        let mut referenced = HashSet::new();
+        for id in &ids {
+            referenced.extend(self.read_meta(*id)?.files.into_iter().map(|f| f.stored));
+        }
+
+        for entry in fs::read_dir(self.dir.join(SHARED_DIR))? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !referenced.contains(&format!("{}/{}", SHARED_DIR, name)) {
+                fs::remove_file(self.dir.join(SHARED_DIR).join(name))?;
+            }
+        }
+        for entry in fs::read_dir(self.dir.join(PRIVATE_DIR))? {
+            let entry = entry?;
+            let live = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+                .is_some_and(|id| ids.contains(&id));
+            if !live {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn backup_ids(&self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(self.dir.join(META_DIR))? {
+            if let Some(id) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn meta_path(&self, id: u64) -> PathBuf {
+        self.dir.join(META_DIR).join(id.to_string())
+    }
+
+    fn read_meta(&self, id: u64) -> Result<BackupMeta> {
+        let path = self.meta_path(id);
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(DbError::Backup(format!("backup {} doesn't exist", id)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        BackupMeta::decode(&data).ok_or_else(|| {
+            DbError::DataCorruption(format!("backup meta file {:?} is damaged", path))
+        })
+    }
+}
+
+// tables and blob files are immutable, they can be shared between backups
+fn is_shared(name: &str) -> bool {
+    let path = Path::new(name);
+    parse_sst_id(path).is_some() || parse_blob_id(path).is_some()
+}
+
+// sst-12.db -> sst-12_<crc>_<size>.db
+fn shared_name(name: &str, size: u64, crc: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}_{:08x}_{}.{}", stem, crc, size, ext),
+        None => format!("{}_{:08x}_{}", name, crc, size),
+    }
+}
+
+// size and crc32 of the file
+fn checksum(path: &Path) -> io::Result<(u64, u32)> {
+    copy_into(&mut File::open(path)?, &mut io::sink())
+}
+
+// copies src to dst and returns the size and crc32 of what was copied
+fn copy_with_checksum(src: &Path, dst: &Path) -> Result<(u64, u32)> {
+    let mut out = File::create(dst)?;
+    let result = copy_into(&mut File::open(src)?, &mut out)?;
+    out.sync_all()?;
+    Ok(result)
+}
+
+fn copy_into(src: &mut impl Read, dst: &mut impl Write) -> io::Result<(u64, u32)> {
+    let mut hasher = Hasher::new();
+    let mut size = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        dst.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    Ok((size, hasher.finalize()))
+}
@@ -0,0 +1,117 @@
+// a group of writes that is applied atomically through `Db::write`
+//
+// the whole batch is logged as a single checksummed WAL record and shares one sequence number,
+// so a crash either replays all of it or none of it. operations are applied in the order they
+// were added, a later write to a key wins over an earlier one of the same batch. the operations
+// may go to different column families, the plain ones go to the default family
+
+use crate::core::ColumnFamily;
+
+// `cf` is the id of the column family the operation applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    Put {
+        cf: u32,
+        key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    Delete {
+        cf: u32,
+        key: Vec<u8>,
+    },
+    // removes every key in [start, end)
+    DeleteRange {
+        cf: u32,
+        start: Vec<u8>,
+        end: Vec<u8>,
+    },
+}
+
+/// writes that become durable and visible together
+///
+/// ```
+/// use keylite_kv::core::WriteBatch;
+///
+/// let mut batch = WriteBatch::new();
+/// batch.put(b"from", b"90");
+/// batch.put(b"to", b"110");
+/// batch.delete(b"pending");
+/// assert_eq!(batch.len(), 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) {
+        self.put_in(0, key, val);
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.delete_in(0, key);
+    }
+
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.delete_range_in(0, start, end);
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: &[u8], val: &[u8]) {
+        self.put_in(cf.id(), key, val);
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: &[u8]) {
+        self.delete_in(cf.id(), key);
+    }
+
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamily, start: &[u8], end: &[u8]) {
+        self.delete_range_in(cf.id(), start, end);
+    }
+
+    fn put_in(&mut self, cf: u32, key: &[u8], val: &[u8]) {
+        self.ops.push(BatchOp::Put {
+            cf,
+            key: key.to_vec(),
+            val: val.to_vec(),
+        });
+    }
+
+    fn delete_in(&mut self, cf: u32, key: &[u8]) {
+        self.ops.push(BatchOp::Delete {
+            cf,
+            key: key.to_vec(),
+        });
+    }
+
+    fn delete_range_in(&mut self, cf: u32, start: &[u8], end: &[u8]) {
+        self.ops.push(BatchOp::DeleteRange {
+            cf,
+            start: start.to_vec(),
+            end: end.to_vec(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+}
@@ -0,0 +1,268 @@
+// blob files - key-value separation for large values
+//
+// a value of at least `DbOptions::min_blob_size` bytes doesn't stay inline in the sstables, a
+// flush or compaction appends it to a blob file and the table stores a `ValueType::BlobIndex`
+// version whose value is a pointer to it. compaction then only moves the small pointers around
+// instead of rewriting every large value on each merge
+//
+// a blob file is written once and never modified:
+//
+// | magic (8 bytes) | record | record | ... |
+//
+// every record is stored as:
+//
+// | key len (u32) | value len (u64) | key | value | crc32 of everything before it (u32) |
+//
+// the key isn't needed to read the value back, it's kept so a blob file can be inspected on its
+// own
+//
+// the meta block of every table lists the blob files it points into (see sst/meta.rs), a blob
+// file is live as long as a live table points into it, so it goes away once compaction drops the
+// last table referencing it. values of overwritten or deleted keys stay in their blob file until
+// then, to get rid of them compaction moves the live values of the oldest blob files into new
+// ones, see `DbOptions::blob_gc_age_cutoff`
+
+mod output;
+
+pub(crate) use output::BlobOutput;
+
+use crc32fast::Hasher;
+use memmap2::Mmap;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+
+pub const BLOB_MAGIC: &[u8; 8] = b"KLBLOB\0\x01";
+pub const POINTER_SIZE: usize = 24;
+
+// key len (u32) + value len (u64) in front of a record and the crc32 behind it
+const RECORD_HEADER_SIZE: usize = 12;
+const RECORD_TRAILER_SIZE: usize = 4;
+
+/// where a value kept in a blob file lives, the value of a `ValueType::BlobIndex` version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobPointer {
+    pub file_id: u64,
+    // offset of the record holding the value
+    pub offset: u64,
+    pub value_len: u64,
+}
+
+impl BlobPointer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(POINTER_SIZE);
+        buf.extend_from_slice(&self.file_id.to_le_bytes());
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+        buf.extend_from_slice(&self.value_len.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        if data.len() != POINTER_SIZE {
+            return Err(invalid("blob pointer has the wrong size"));
+        }
+        let read = |pos: usize| u64::from_le_bytes(data[pos..pos + 8].try_into().expect("8 bytes"));
+        Ok(Self {
+            file_id: read(0),
+            offset: read(8),
+            value_len: read(16),
+        })
+    }
+}
+
+// the mapped file of a blob file, shared by every table pointing into it
+//
+// like the files of the tables (see sst/reader.rs) a blob file that is no longer live is only
+// marked obsolete, the last handle to go away deletes it
+pub struct BlobFile {
+    id: u64,
+    path: PathBuf,
+    mmap: Mmap,
+    obsolete: AtomicBool,
+}
+
+impl BlobFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let id = parse_blob_id(&path).ok_or_else(|| invalid("not a blob file"))?;
+        let file = File::open(&path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < BLOB_MAGIC.len() || &mmap[..BLOB_MAGIC.len()] != BLOB_MAGIC {
+            return Err(invalid("blob file has a bad magic"));
+        }
+        Ok(Self {
+            id,
+            path,
+            mmap,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.mmap.len() as u64
+    }
+
+    // reads and verifies the value the pointer refers to
+    pub fn read(&self, pointer: &BlobPointer) -> io::Result<Vec<u8>> {
+        let start = pointer.offset as usize;
+        let header = self
+            .mmap
+            .get(start..start + RECORD_HEADER_SIZE)
+            .ok_or_else(|| invalid("blob record out of bounds"))?;
+        let key_len = u32::from_le_bytes(header[0..4].try_into().expect("4 bytes")) as usize;
+        let value_len = u64::from_le_bytes(header[4..12].try_into().expect("8 bytes"));
+        if value_len != pointer.value_len {
+            return Err(invalid("blob record doesn't match its pointer"));
+        }
+
+        let value_start = start + RECORD_HEADER_SIZE + key_len;
+        let value_end = value_start + value_len as usize;
+        let crc = self
+            .mmap
+            .get(value_end..value_end + RECORD_TRAILER_SIZE)
+            .ok_or_else(|| invalid("blob record out of bounds"))?;
+        let mut hasher = Hasher::new();
+        hasher.update(&self.mmap[start..value_end]);
+        if hasher.finalize() != u32::from_le_bytes(crc.try_into().expect("4 bytes")) {
+            return Err(invalid("blob record checksum mismatch"));
+        }
+
+        Ok(self.mmap[value_start..value_end].to_vec())
+    }
+
+    // the blob file is no longer live, it's deleted as soon as the last handle to it is dropped
+    pub fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for BlobFile {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::Acquire) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// appends values to a new blob file, the file must be finished before a table pointing into it
+// is written
+pub struct BlobWriter {
+    id: u64,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl BlobWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let id = parse_blob_id(path).ok_or_else(|| invalid("not a blob file"))?;
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(BLOB_MAGIC)?;
+        Ok(Self {
+            id,
+            file,
+            size: BLOB_MAGIC.len() as u64,
+        })
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> io::Result<BlobPointer> {
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        header[0..4].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        header[4..12].copy_from_slice(&(value.len() as u64).to_le_bytes());
+
+        let mut hasher = Hasher::new();
+        hasher.update(&header);
+        hasher.update(key);
+        hasher.update(value);
+
+        self.file.write_all(&header)?;
+        self.file.write_all(key)?;
+        self.file.write_all(value)?;
+        self.file.write_all(&hasher.finalize().to_le_bytes())?;
+
+        let pointer = BlobPointer {
+            file_id: self.id,
+            offset: self.size,
+            value_len: value.len() as u64,
+        };
+        self.size += (RECORD_HEADER_SIZE + key.len() + value.len() + RECORD_TRAILER_SIZE) as u64;
+        Ok(pointer)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        // the values must be durable before the manifest references a table pointing at them
+        self.file.get_ref().sync_all()
+    }
+}
+
+// the open blob files of a database directory, every table pointing into a blob file shares a
+// single mapping of it
+pub struct BlobFiles {
+    dir: PathBuf,
+    open: Mutex<HashMap<u64, Weak<BlobFile>>>,
+}
+
+impl BlobFiles {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get_or_open(&self, id: u64) -> io::Result<Arc<BlobFile>> {
+        let mut open = self.open.lock();
+        if let Some(file) = open.get(&id).and_then(Weak::upgrade) {
+            return Ok(file);
+        }
+        let file = Arc::new(BlobFile::open(blob_path(&self.dir, id))?);
+        open.insert(id, Arc::downgrade(&file));
+        Ok(file)
+    }
+
+    // the blob files are no longer live, a file still read by some table handle is deleted along
+    // with the last one, any other right away
+    pub(crate) fn retire(&self, ids: impl IntoIterator<Item = u64>) {
+        let mut open = self.open.lock();
+        for id in ids {
+            match open.remove(&id).and_then(|file| file.upgrade()) {
+                Some(file) => file.mark_obsolete(),
+                None => {
+                    let _ = std::fs::remove_file(blob_path(&self.dir, id));
+                }
+            }
+        }
+    }
+}
+
+// path of the blob file with the given id inside the database directory
+pub fn blob_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("blob-{}.blob", id))
+}
+
+// extracts N from a path of the form .../blob-N.blob
+pub fn parse_blob_id(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("blob-")?
+        .strip_suffix(".blob")?
+        .parse()
+        .ok()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
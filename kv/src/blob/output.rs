@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+
+use crate::core::DbOptions;
+use crate::error::Result;
+use crate::manifest::Manifest;
+use crate::types::ValueType;
+
+use super::{BlobPointer, BlobWriter};
+
+// the blob files written by a flush or compaction, a new one is started once the current one
+// crosses blob_file_size. every file is finished by `finish`, which has to happen before the
+// tables pointing into them are handed to the manifest
+pub(crate) struct BlobOutput<'a> {
+    manifest: &'a Manifest,
+    opts: &'a DbOptions,
+    // blob files whose values are moved into the output, see DbOptions::blob_gc_age_cutoff
+    relocate: HashSet<u64>,
+    current: Option<BlobWriter>,
+}
+
+impl<'a> BlobOutput<'a> {
+    pub(crate) fn new(manifest: &'a Manifest, opts: &'a DbOptions, relocate: HashSet<u64>) -> Self {
+        Self {
+            manifest,
+            opts,
+            relocate,
+            current: None,
+        }
+    }
+
+    // the type and value a version goes into a table with: a large value is moved into a blob
+    // file, and so is a value pointed to in a blob file that's being garbage collected
+    pub(crate) fn separate(
+        &mut self,
+        key: &[u8],
+        value_type: ValueType,
+        value: Vec<u8>,
+    ) -> Result<(ValueType, Vec<u8>)> {
+        match value_type {
+            ValueType::Put => self.put(key, value),
+            ValueType::BlobIndex => {
+                let pointer = BlobPointer::decode(&value)?;
+                if !self.relocate.contains(&pointer.file_id) {
+                    return Ok((value_type, value));
+                }
+                let value = self.manifest.read_blob(&pointer)?;
+                self.put(key, value)
+            }
+            // a value with a TTL stays inline, the expiry is checked without reading a blob. so
+            // does a merge operand, they're small and read along with the older versions
+            ValueType::Delete | ValueType::ExpiringPut | ValueType::Merge => {
+                Ok((value_type, value))
+            }
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<(ValueType, Vec<u8>)> {
+        if self.opts.min_blob_size.is_none_or(|min| value.len() < min) {
+            return Ok((ValueType::Put, value));
+        }
+
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|w| w.size() >= self.opts.blob_file_size)
+        {
+            self.current.take().expect("writer is set").finish()?;
+        }
+        let writer = match &mut self.current {
+            Some(writer) => writer,
+            None => self
+                .current
+                .insert(BlobWriter::create(self.manifest.new_blob_path())?),
+        };
+
+        let pointer = writer.add(key, &value)?;
+        Ok((ValueType::BlobIndex, pointer.encode()))
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        if let Some(writer) = self.current {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+// change data capture: a subscription receives the writes to a range of keys as they're
+// committed, instead of the subscriber polling with scans. see `Db::subscribe`
+//
+// the changes are published by the WAL thread right after it logged them, so they arrive in the
+// order recovery would replay them, and the operations of a WriteBatch or a transaction arrive
+// together as one ChangeBatch under the batch's sequence number. writes made with
+// `WalMode::Disabled` are handed to the WAL thread just to be published, while anybody listens
+//
+// a subscriber that went away can resume from the sequence number it got to: the WAL thread opens
+// the segments that are still on disk when the subscription starts, the subscription reads the
+// batches it missed from them before it switches over to the live ones. segments are deleted as
+// soon as their memtables are flushed unless `DbOptions::wal_retained_segments` keeps some of
+// them around in an archive. resuming from further back than that fails with
+// `DbError::SequenceGap` rather than skipping the batches that are gone
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::types::{inline_value, ValueType};
+use crate::wal::reader::{WalReader, WalRecord};
+
+/// options of a subscription, see `Db::subscribe_with`
+///
+/// ```
+/// use keylite_kv::cdc::SubscribeOptions;
+///
+/// let opts = SubscribeOptions::default().from_sequence(42);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubscribeOptions {
+    /// also deliver the batches with this sequence number or a newer one that were committed
+    /// before the subscription started. the WAL has to still have all of them, otherwise the
+    /// subscription fails with `DbError::SequenceGap`. None only delivers what's committed from
+    /// now on
+    pub from_sequence: Option<u64>,
+}
+
+impl SubscribeOptions {
+    pub fn from_sequence(mut self, seq: u64) -> Self {
+        self.from_sequence = Some(seq);
+        self
+    }
+}
+
+/// what a write did to a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeOp {
+    /// the key was set to the value, `expires_at` is the expiry time in milliseconds since the
+    /// unix epoch of a value written with a TTL
+    Put {
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    },
+    Delete,
+    /// an operand for the merge operator of the column family, see `Db::merge`
+    Merge(Vec<u8>),
+    /// every key in [key, end) was deleted
+    DeleteRange {
+        end: Vec<u8>,
+    },
+}
+
+/// a single operation of a committed write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// id of the column family of the key
+    pub cf: u32,
+    pub key: Vec<u8>,
+    pub op: ChangeOp,
+}
+
+/// the operations of one committed write that fall into the range of the subscription, a batch
+/// or a transaction commits as a single write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeBatch {
+    pub seq: u64,
+    pub changes: Vec<Change>,
+}
+
+// the keys a subscription is interested in: [start, end) of one column family, None is unbounded
+#[derive(Debug, Clone)]
+pub(crate) struct ChangeFilter {
+    cf: u32,
+    start: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+}
+
+impl ChangeFilter {
+    pub(crate) fn new(cf: u32, start: Option<&[u8]>, end: Option<&[u8]>) -> Self {
+        Self {
+            cf,
+            start: start.map(|s| s.to_vec()),
+            end: end.map(|e| e.to_vec()),
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.start.as_deref().is_none_or(|start| key >= start)
+            && self.end.as_deref().is_none_or(|end| key < end)
+    }
+
+    fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        self.start.as_deref().is_none_or(|s| end > s)
+            && self.end.as_deref().is_none_or(|e| start < e)
+    }
+
+    // the part of the record the subscriber gets to see, None if that's nothing
+    fn select(&self, record: &WalRecord) -> Option<ChangeBatch> {
+        let mut changes = Vec::new();
+        for entry in &record.entries {
+            if entry.cf != self.cf || !self.contains(&entry.key) {
+                continue;
+            }
+            let op = match entry.value_type {
+                ValueType::Delete => ChangeOp::Delete,
+                ValueType::Merge => ChangeOp::Merge(entry.val.clone()),
+                ValueType::ExpiringPut => ChangeOp::Put {
+                    expires_at: entry
+                        .val
+                        .get(..8)
+                        .map(|b| u64::from_le_bytes(b.try_into().expect("8 bytes"))),
+                    value: inline_value(ValueType::ExpiringPut, entry.val.clone()),
+                },
+                // blob pointers only ever live in tables
+                ValueType::Put | ValueType::BlobIndex => ChangeOp::Put {
+                    value: entry.val.clone(),
+                    expires_at: None,
+                },
+            };
+            changes.push(Change {
+                cf: entry.cf,
+                key: entry.key.clone(),
+                op,
+            });
+        }
+        for (cf, start, end) in &record.range_deletes {
+            if *cf == self.cf && self.overlaps(start, end) {
+                changes.push(Change {
+                    cf: *cf,
+                    key: start.clone(),
+                    op: ChangeOp::DeleteRange { end: end.clone() },
+                });
+            }
+        }
+
+        (!changes.is_empty()).then_some(ChangeBatch {
+            seq: record.seq,
+            changes,
+        })
+    }
+}
+
+/// the sending half of a subscription, kept by the WAL thread
+//
+// a send only fails once the subscription is gone, `alive` tells without anything to send
+pub struct Subscriber {
+    filter: ChangeFilter,
+    tx: Sender<ChangeBatch>,
+    alive: Weak<()>,
+}
+
+impl Subscriber {
+    // false once the subscription has been dropped
+    fn publish(&self, record: &WalRecord) -> bool {
+        match self.filter.select(record) {
+            Some(batch) => self.tx.send(batch).is_ok(),
+            None => self.alive.strong_count() > 0,
+        }
+    }
+}
+
+// the subscribers of a database, owned by the WAL thread. their number is shared with the
+// database, which only hands unlogged writes over while it's not 0. the database counts a
+// subscriber before it asks the WAL thread to add it, so no write slips through in between
+pub(crate) struct ChangeFeed {
+    subscribers: Vec<Subscriber>,
+    count: Arc<AtomicUsize>,
+}
+
+impl ChangeFeed {
+    pub(crate) fn new(count: Arc<AtomicUsize>) -> Self {
+        Self {
+            subscribers: Vec::new(),
+            count,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    // adds a subscriber, handing it the records it would have missed otherwise first
+    pub(crate) fn add(&mut self, subscriber: Subscriber, missed: &[WalRecord]) {
+        if missed.iter().all(|record| subscriber.publish(record)) {
+            self.subscribers.push(subscriber);
+        } else {
+            self.count.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    // a subscriber the database counted couldn't be added
+    pub(crate) fn reject(&self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    // hands the records to every subscriber, the ones whose subscription is gone are dropped
+    pub(crate) fn publish(&mut self, records: &[WalRecord]) {
+        if records.is_empty() {
+            return;
+        }
+        let before = self.subscribers.len();
+        self.subscribers
+            .retain(|subscriber| records.iter().all(|record| subscriber.publish(record)));
+        self.count
+            .fetch_sub(before - self.subscribers.len(), Ordering::AcqRel);
+    }
+}
+
+/// the committed writes to a range of keys, see `Db::subscribe`
+///
+/// iterating blocks until the next batch is committed and ends once the database is closed
+pub struct Subscription {
+    filter: ChangeFilter,
+    from_sequence: u64,
+    // the segments the batches committed before the subscription started are read from, oldest
+    // first
+    replay: VecDeque<WalReader>,
+    // a record read ahead from `replay` that isn't delivered yet
+    pending: Option<WalRecord>,
+    live: Receiver<ChangeBatch>,
+    _alive: Arc<()>,
+}
+
+impl Subscription {
+    // the subscription and the subscriber the WAL thread publishes to
+    pub(crate) fn new(filter: ChangeFilter, from_sequence: u64) -> (Self, Subscriber) {
+        let (tx, live) = crossbeam_channel::unbounded();
+        let alive = Arc::new(());
+        let subscriber = Subscriber {
+            filter: filter.clone(),
+            tx,
+            alive: Arc::downgrade(&alive),
+        };
+        let subscription = Self {
+            filter,
+            from_sequence,
+            replay: VecDeque::new(),
+            pending: None,
+            live,
+            _alive: alive,
+        };
+        (subscription, subscriber)
+    }
+
+    // the segments to read the batches committed before the subscription started from
+    pub(crate) fn replay_from(&mut self, segments: Vec<WalReader>) {
+        self.replay = segments.into();
+    }
+
+    /// the next batch without waiting for one, None if there is none right now
+    pub fn try_next(&mut self) -> Option<Result<ChangeBatch>> {
+        if let Some(replayed) = self.next_replayed().transpose() {
+            return Some(replayed);
+        }
+        match self.live.try_recv() {
+            Ok(batch) => Some(Ok(batch)),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// the next batch, waiting at most `timeout` for one to be committed. None if none was or
+    /// the database is closed
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<ChangeBatch>> {
+        if let Some(replayed) = self.next_replayed().transpose() {
+            return Some(replayed);
+        }
+        match self.live.recv_timeout(timeout) {
+            Ok(batch) => Some(Ok(batch)),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    // sequence number of the oldest record in the segments to replay, None if they are empty
+    pub(crate) fn first_replayed(&mut self) -> Result<Option<u64>> {
+        if self.pending.is_none() {
+            self.pending = self.next_record()?;
+        }
+        Ok(self.pending.as_ref().map(|record| record.seq))
+    }
+
+    // the next record from the retained segments, None once they are all read
+    fn next_record(&mut self) -> Result<Option<WalRecord>> {
+        if let Some(record) = self.pending.take() {
+            return Ok(Some(record));
+        }
+        while let Some(reader) = self.replay.front_mut() {
+            match reader.next_record()? {
+                Some(record) => return Ok(Some(record)),
+                None => {
+                    self.replay.pop_front();
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // the next batch from the retained segments, None once they are all read
+    fn next_replayed(&mut self) -> Result<Option<ChangeBatch>> {
+        while let Some(record) = self.next_record()? {
+            if record.seq >= self.from_sequence {
+                if let Some(batch) = self.filter.select(&record) {
+                    return Ok(Some(batch));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Result<ChangeBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(replayed) = self.next_replayed().transpose() {
+            return Some(replayed);
+        }
+        self.live.recv().ok().map(Ok)
+    }
+}
@@ -1,4 +1,6 @@
+pub mod picker;
 pub mod worker;
 
+pub use picker::{needs_compaction, sort_tables};
 pub use worker::compaction_worker;
 pub use worker::CompactionMessage;
@@ -0,0 +1,148 @@
+// leveled compaction picker
+//
+// sstables are organised in levels L0..Ln:
+// - L0 holds the freshly flushed memtables, tables in L0 may overlap each other
+// - every level below L0 holds tables with non overlapping key ranges, and every level is
+//   `level_size_multiplier` times bigger than the level above it
+//
+// the global sstable list is always kept in lookup order: L0 newest first, then L1 sorted by key
+// range, then L2 sorted by key range and so on, so a point lookup can walk the list front to back
+// and stop at the first hit
+//
+// a compaction is picked as follows:
+// - L0 reached `max_sstables` files: all L0 tables plus every overlapping L1 table are merged into
+//   L1
+// - some level Li (i >= 1) is bigger than its target size: ONE table of Li (round robin over the
+//   key space) plus the overlapping tables of Li+1 are merged into Li+1
+//
+// the level with the highest score (size / target) wins
+
+use crate::core::DbOptions;
+use crate::sst::SSTReader;
+
+pub struct CompactionTask {
+    pub output_level: usize,
+    // inputs in lookup order, newest data first
+    pub inputs: Vec<SSTReader>,
+    // key ranges of the tables below the output level, a tombstone can only be dropped if none
+    // of them can still hold an older version of the key
+    pub deeper_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CompactionTask {
+    pub fn is_bottommost(&self, key: &[u8]) -> bool {
+        !self
+            .deeper_ranges
+            .iter()
+            .any(|(smallest, largest)| key >= smallest.as_slice() && key <= largest.as_slice())
+    }
+
+    // same as is_bottommost for a range tombstone covering [start, end)
+    pub fn range_is_bottommost(&self, start: &[u8], end: &[u8]) -> bool {
+        !self
+            .deeper_ranges
+            .iter()
+            .any(|(smallest, largest)| smallest.as_slice() < end && largest.as_slice() >= start)
+    }
+}
+
+// sorts the tables into lookup order, see the comment at the top of this file
+pub fn sort_tables(tables: &mut [SSTReader]) {
+    tables.sort_by(|a, b| {
+        a.level().cmp(&b.level()).then_with(|| {
+            if a.level() == 0 {
+                b.id().cmp(&a.id())
+            } else {
+                a.smallest_key().cmp(b.smallest_key())
+            }
+        })
+    });
+}
+
+pub fn level_tables(tables: &[SSTReader], level: usize) -> impl Iterator<Item = &SSTReader> {
+    tables.iter().filter(move |t| t.level() as usize == level)
+}
+
+pub fn level_bytes(tables: &[SSTReader], level: usize) -> u64 {
+    level_tables(tables, level).map(|t| t.file_size()).sum()
+}
+
+// score of every level, a score >= 1.0 means the level needs compaction
+fn level_scores(tables: &[SSTReader], opts: &DbOptions) -> Vec<f64> {
+    let mut scores = vec![0.0; opts.max_levels];
+
+    scores[0] = level_tables(tables, 0).count() as f64 / opts.max_sstables as f64;
+
+    // the last level can't be pushed down any further so it's never scored
+    for (level, score) in scores
+        .iter_mut()
+        .enumerate()
+        .take(opts.max_levels - 1)
+        .skip(1)
+    {
+        *score = level_bytes(tables, level) as f64 / opts.level_target_bytes(level) as f64;
+    }
+
+    scores
+}
+
+pub fn needs_compaction(tables: &[SSTReader], opts: &DbOptions) -> bool {
+    level_scores(tables, opts).iter().any(|&s| s >= 1.0)
+}
+
+// picks the next compaction, `pointers` remembers per level the largest key of the last table that
+// was compacted out of it so that consecutive compactions rotate over the key space
+pub fn pick_compaction(
+    tables: &[SSTReader],
+    opts: &DbOptions,
+    pointers: &mut [Option<Vec<u8>>],
+) -> Option<CompactionTask> {
+    let scores = level_scores(tables, opts);
+    let (level, score) = scores
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(l, s)| (l, *s))?;
+
+    if score < 1.0 {
+        return None;
+    }
+
+    let output_level = level + 1;
+
+    let mut inputs: Vec<SSTReader> = if level == 0 {
+        level_tables(tables, 0).cloned().collect()
+    } else {
+        let candidates: Vec<&SSTReader> = level_tables(tables, level).collect();
+        let picked = match &pointers[level] {
+            Some(last) => candidates
+                .iter()
+                .find(|t| t.smallest_key() > last.as_slice())
+                .or(candidates.first()),
+            None => candidates.first(),
+        }?;
+        pointers[level] = Some(picked.largest_key().to_vec());
+        vec![(*picked).clone()]
+    };
+
+    let smallest = inputs.iter().map(|t| t.smallest_key()).min()?.to_vec();
+    let largest = inputs.iter().map(|t| t.largest_key()).max()?.to_vec();
+
+    inputs.extend(
+        level_tables(tables, output_level)
+            .filter(|t| t.overlaps(&smallest, &largest))
+            .cloned(),
+    );
+
+    let deeper_ranges = tables
+        .iter()
+        .filter(|t| t.level() as usize > output_level)
+        .map(|t| (t.smallest_key().to_vec(), t.largest_key().to_vec()))
+        .collect();
+
+    Some(CompactionTask {
+        output_level,
+        inputs,
+        deeper_ranges,
+    })
+}
@@ -1,16 +1,27 @@
 use arc_swap::ArcSwap;
 use crossbeam_channel::Receiver;
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::collections::{BinaryHeap, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::blob::{BlobOutput, BlobPointer};
+use crate::core::{ColumnFamily, DbOptions, FamilyMap, SnapshotList};
 use crate::error::DbError;
-use crate::sst::{SSTIterator, SSTReader, SSTWriter};
+use crate::manifest::Manifest;
+use crate::merge::MergeOperator;
+use crate::sst::{SSTIterator, SSTWriter};
+use crate::stats::{Histogram, Statistics, Ticker};
+use crate::types::{covering_seq, is_expired, RangeTombstone, ValueType};
+
+use super::picker::{pick_compaction, CompactionTask};
 
 type Result<T> = std::result::Result<T, DbError>;
 
+// seq, type and value of a version of a key
+type Version = (u64, ValueType, Vec<u8>);
+
 pub enum CompactionMessage {
     Compact,
     Shutdown,
@@ -20,6 +31,7 @@ struct MergeEntry {
     key: Vec<u8>,
     value: Vec<u8>,
     seq: u64,
+    value_type: ValueType,
     sst_idx: usize,
 }
 
@@ -47,186 +59,422 @@ impl Ord for MergeEntry {
             .cmp(&self.key)
             // for same key, we want the newest version first → higher seq first
             .then(self.seq.cmp(&other.seq))
-            // tie-breaker on sst index (newer/older sst)
+            // tie-breaker on sst index, a lower index is a newer table and wins
             .then(other.sst_idx.cmp(&self.sst_idx))
     }
 }
 
+// every column family is compacted on its own, with its own options
 pub fn compaction_worker(
     receiver: Receiver<CompactionMessage>,
-    dir: std::path::PathBuf,
-    sstables: Arc<ArcSwap<Vec<SSTReader>>>,
-    next_sst_id: Arc<AtomicU64>,
+    manifest: Arc<Manifest>,
+    families: Arc<ArcSwap<FamilyMap>>,
+    snapshots: Arc<SnapshotList>,
+    stats: Arc<Statistics>,
+) {
+    // per family and level round robin pointer, see compaction/picker.rs
+    let mut pointers: HashMap<u32, Vec<Option<Vec<u8>>>> = HashMap::new();
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            CompactionMessage::Compact => {
+                for family in families.load().values() {
+                    let pointers = pointers
+                        .entry(family.id())
+                        .or_insert_with(|| vec![None; family.opts.max_levels]);
+                    compact_family(&manifest, family, pointers, &snapshots, &stats);
+                }
+            }
+            CompactionMessage::Shutdown => break,
+        }
+    }
+}
+
+// keep going until every level is within its target, compacting into level n can push level n
+// over its own target
+fn compact_family(
+    manifest: &Manifest,
+    family: &ColumnFamily,
+    pointers: &mut [Option<Vec<u8>>],
+    snapshots: &SnapshotList,
+    stats: &Statistics,
 ) {
     loop {
-        match receiver.recv() {
-            Ok(CompactionMessage::Compact) => {
-                if let Err(e) = compact_sstables(&dir, &sstables, &next_sst_id) {
-                    eprintln!("Error during compaction: {}", e);
+        let current = family.sstables.load_full();
+        let Some(task) = pick_compaction(&current, &family.opts, pointers) else {
+            break;
+        };
+        // snapshots taken after this point are newer than every input entry and only ever see
+        // the newest versions, which are always kept
+        let live = snapshots.sequences();
+        let start = Instant::now();
+        let read: u64 = task.inputs.iter().map(|sst| sst.file_size()).sum();
+        match compact_sstables(manifest, family, task, &live) {
+            Ok(written) => {
+                stats.add(Ticker::Compactions, 1);
+                stats.add(Ticker::BytesCompactedRead, read);
+                stats.add(Ticker::BytesCompactedWritten, written);
+                stats.record_since(Histogram::Compaction, start);
+            }
+            Err(e) => {
+                stats.add(Ticker::CompactionErrors, 1);
+                eprintln!("Error during compaction: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+// output tables of a compaction, a new table is started once the current one crosses
+// target_file_size
+//
+// the range tombstones that survive the compaction are split along the same cuts: every table
+// gets the part of them that falls in between its first key and the first key of the next table,
+// so the tables of the output level keep disjoint key ranges
+struct CompactionOutput<'a> {
+    manifest: &'a Manifest,
+    opts: &'a DbOptions,
+    level: u32,
+    current: Option<(SSTWriter, PathBuf)>,
+    finished: Vec<PathBuf>,
+    range_tombstones: Vec<RangeTombstone>,
+    // first key of the current table, None for the first one which also takes whatever the
+    // tombstones cover below its first key
+    window_start: Option<Vec<u8>>,
+}
+
+impl CompactionOutput<'_> {
+    // returns the writer the key should go to, must only be called when a new user key starts
+    // so that all the versions of a key end up in the same table
+    fn writer_for_new_key(&mut self, key: &[u8]) -> Result<&mut SSTWriter> {
+        let full = matches!(&self.current, Some((w, _)) if w.estimated_size() >= self.opts.target_file_size);
+        if full {
+            self.finish_current(Some(key))?;
+            self.window_start = Some(key.to_vec());
+        }
+
+        if self.current.is_none() {
+            self.start_table()?;
+        }
+
+        Ok(&mut self.current.as_mut().expect("writer was just created").0)
+    }
+
+    fn start_table(&mut self) -> Result<()> {
+        let sst_path = self.manifest.new_table_path();
+        let mut writer = SSTWriter::with_options(&sst_path, self.opts.writer_options())?;
+        writer.set_level(self.level);
+        self.current = Some((writer, sst_path));
+        Ok(())
+    }
+
+    // hands the current table the range tombstones clipped to [window_start, window_end)
+    fn add_range_tombstones(&mut self, window_end: Option<&[u8]>) {
+        let Some((writer, _)) = self.current.as_mut() else {
+            return;
+        };
+        for t in &self.range_tombstones {
+            let start = match &self.window_start {
+                Some(lo) if lo > &t.start => lo.clone(),
+                _ => t.start.clone(),
+            };
+            let end = match window_end {
+                Some(hi) if hi < t.end.as_slice() => hi.to_vec(),
+                _ => t.end.clone(),
+            };
+            if start < end {
+                writer.add_range_tombstone(RangeTombstone {
+                    start,
+                    end,
+                    seq: t.seq,
+                });
+            }
+        }
+    }
+
+    fn finish_current(&mut self, window_end: Option<&[u8]>) -> Result<()> {
+        self.add_range_tombstones(window_end);
+        if let Some((writer, path)) = self.current.take() {
+            writer.finish()?;
+            self.finished.push(path);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<PathBuf>> {
+        // every data entry was dropped but some range tombstones have to live on
+        if self.current.is_none() && self.finished.is_empty() && !self.range_tombstones.is_empty() {
+            self.start_table()?;
+        }
+        self.finish_current(None)?;
+        Ok(self.finished)
+    }
+}
+
+// the versions of a key, newest first, that some reader can still see
+//
+// a reader at seq s sees the newest version older than s, so a version with seq v whose next
+// newer version has seq n is only seen by the snapshots in (v, n], the newest version is also seen
+// by every plain read. a reader that sees a merge operand reads on into the next older version
+fn retained_versions(
+    key: &[u8],
+    versions: Vec<Version>,
+    snapshots: &[u64],
+    tombstones: &[RangeTombstone],
+    task: &CompactionTask,
+) -> Vec<Version> {
+    let mut kept = Vec::new();
+    let mut newer: Option<u64> = None;
+    // readers that saw a merge operand in the newer version
+    let mut merging: Vec<u64> = Vec::new();
+    for (seq, value_type, value) in versions {
+        let mut readers: Vec<u64> = snapshots
+            .iter()
+            .copied()
+            .filter(|s| seq < *s && newer.is_none_or(|n| *s <= n))
+            .collect();
+        if newer.is_none() {
+            readers.push(u64::MAX);
+        }
+        readers.append(&mut merging);
+        // a reader that sees a range tombstone covering the version doesn't see the version
+        readers.retain(|s| covering_seq(tombstones, key, *s) <= seq);
+        if !readers.is_empty() {
+            kept.push((seq, value_type, value));
+        }
+        if value_type == ValueType::Merge {
+            merging = readers;
+        }
+        newer = Some(seq);
+    }
+
+    // a deletion is stored as a Delete version of the key, the oldest
+    // tombstones we keep can only be dropped if no deeper level may still hold an older version of
+    // the key, otherwise that older version would come back to life
+    if task.is_bottommost(key) {
+        while kept
+            .last()
+            .is_some_and(|(_, value_type, _)| *value_type == ValueType::Delete)
+        {
+            kept.pop();
+        }
+    }
+    kept
+}
+
+// folds the merge operands of a key, oldest first, into the values they produce as long as the
+// version below them is known: a value, a tombstone, or nothing at all at the bottommost level.
+// every operand turns into a Put of the value a reader at its seq would fold, so snapshots keep
+// reading what they did. operands on top of a value with a TTL are left alone since that value
+// reads as deleted once it expires, and so are the operands of a key a range tombstone among the
+// inputs covers, which may hide part of the versions from some of the readers only
+fn collapse_merges(
+    manifest: &Manifest,
+    operator: &dyn MergeOperator,
+    key: &[u8],
+    mut versions: Vec<Version>,
+    tombstones: &[RangeTombstone],
+    bottommost: bool,
+) -> Result<Vec<Version>> {
+    if covering_seq(tombstones, key, u64::MAX) > 0 {
+        return Ok(versions);
+    }
+
+    // the version right below the one at hand, None if it may be in a table below the inputs
+    let mut below: Option<(ValueType, Vec<u8>)> =
+        bottommost.then(|| (ValueType::Delete, Vec::new()));
+    for (_, value_type, value) in versions.iter_mut().rev() {
+        if *value_type == ValueType::Merge {
+            let existing = match below.take() {
+                Some((ValueType::Delete, _)) => Some(None),
+                Some((ValueType::Put, value)) => Some(Some(value)),
+                Some((ValueType::BlobIndex, pointer)) => {
+                    Some(Some(manifest.read_blob(&BlobPointer::decode(&pointer)?)?))
                 }
+                _ => None,
+            };
+            let merged = existing.and_then(|existing| {
+                operator.full_merge(key, existing.as_deref(), std::slice::from_ref(value))
+            });
+            if let Some(merged) = merged {
+                *value_type = ValueType::Put;
+                *value = merged;
             }
-            Ok(CompactionMessage::Shutdown) | Err(_) => break,
         }
+        below = Some((*value_type, value.clone()));
     }
+    Ok(versions)
 }
 
+// merges the input tables into the next level, returns the size of the tables written
 fn compact_sstables(
-    dir: &Path,
-    sstables: &Arc<ArcSwap<Vec<SSTReader>>>,
-    next_sst_id: &Arc<AtomicU64>,
-) -> Result<()> {
-    // load the old sstables but DON'T clear them yet
-    // we need to keep them available for reads during compaction
-    let old_sstables = (**sstables.load()).clone();
-
-    // no sst to flush to disk
-    if old_sstables.is_empty() {
-        return Ok(());
-    }
-
-    // take all the iterators for sstables
-    // implemented in /sst/iterator.rs
-    // rev() because new sstables will override the older ones, newest data to be considered as
-    // truth
-    let mut iterators: Vec<_> = old_sstables
+    manifest: &Manifest,
+    family: &ColumnFamily,
+    task: CompactionTask,
+    snapshots: &[u64],
+) -> Result<u64> {
+    let opts = &*family.opts;
+    // the input tables stay in the global list during compaction so reads can still be served
+    // from them, they're only swapped out once the output tables are written
+    if task.inputs.is_empty() {
+        return Ok(0);
+    }
+
+    // one iterator per input table, implemented in /sst/iterator.rs
+    // inputs are in lookup order so a lower index means newer data
+    let mut iterators: Vec<SSTIterator> = task
+        .inputs
         .iter()
-        .rev()
-        .enumerate()
-        .filter_map(|(idx, sst)| {
-            match SSTReader::open(sst.path()) {
-                Ok(reader) => Some(SSTIterator::new(reader)),
-                Err(e) => {
-                    eprintln!("Failed to open SST file {} for compaction: {}. Skipping.", idx, e);
-                    None
-                }
-            }
-        })
+        .map(|sst| SSTIterator::new(sst.clone()))
         .collect();
 
     // binaryheap works as max heap
-    // but since the cmp method is over written (see line 39 of this file) to give the reverse
+    // but since the cmp method is over written (see the Ord impl of MergeEntry) to give the reverse
     // ordering it works as min heap (smallest key on top)
     let mut heap = BinaryHeap::new();
 
     // put the first Entry from each sst to the binary heap to proceed with k-way merge
     for (idx, iter) in iterators.iter_mut().enumerate() {
-        if let Some(Ok((key, value, seq))) = iter.next() {
+        if let Some(next) = iter.next() {
+            let (key, value, seq, value_type) = next?;
             heap.push(MergeEntry {
                 key,
                 value,
                 seq,
+                value_type,
                 sst_idx: idx,
             });
         }
     }
 
-    // get the new sst_id and add one to the db struct
-    let sst_id = next_sst_id.fetch_add(1, AtomicOrdering::Relaxed);
-    let sst_path = dir.join(format!("sst-{}.db", sst_id));
-    let mut writer = SSTWriter::new(&sst_path)?;
+    // a range tombstone is carried over unless nothing below the output level can hold a version
+    // it deletes, the data it deletes among the inputs is dropped right here. a snapshot older
+    // than the tombstone may keep covered versions alive, then the tombstone has to stay to hide
+    // them from everyone else
+    let input_tombstones: Vec<RangeTombstone> = task
+        .inputs
+        .iter()
+        .flat_map(|sst| sst.range_tombstones().iter().cloned())
+        .collect();
+    let range_tombstones = input_tombstones
+        .iter()
+        .filter(|t| {
+            !task.range_is_bottommost(&t.start, &t.end) || snapshots.iter().any(|s| *s <= t.seq)
+        })
+        .cloned()
+        .collect();
 
-    // store last key to dodge duplication
-    let mut last_key: Option<Vec<u8>> = None;
-    let mut entry_count = 0;
+    // blob garbage collection: the values the inputs point to in the oldest blob files are
+    // moved into new ones, once no table points into an old file anymore it's deleted. see
+    // DbOptions::blob_gc_age_cutoff
+    let live_blobs = manifest.live_blob_ids();
+    let relocate_count = (live_blobs.len() as f64 * opts.blob_gc_age_cutoff) as usize;
+    let relocate = live_blobs.into_iter().take(relocate_count).collect();
+    let mut blobs = BlobOutput::new(manifest, opts, relocate);
 
-    // heap.pop() will give the smallest key entry
-    while let Some(entry) = heap.pop() {
-        // the popped key is equal to the last key, means we need not to use it,
-        // i.e. we will keep the Entry from the latest sstable, and discard all the entry with the
-        // same key from old sstabels
-        if let Some(ref lk) = last_key {
-            if lk == &entry.key {
-                // get a new entry from the same sst to componsate the duplicate entry
-                if let Some(Ok((key, value, seq))) = iterators[entry.sst_idx].next() {
-                    heap.push(MergeEntry {
-                        key,
-                        value,
-                        seq,
-                        sst_idx: entry.sst_idx,
-                    });
-                }
-                // don't put the duplicate key in the sst writer, hence continue
-                continue;
+    let write_versions = |output: &mut CompactionOutput,
+                          blobs: &mut BlobOutput,
+                          key: &[u8],
+                          mut versions: Vec<Version>|
+     -> Result<()> {
+        // a version left behind by a repeated flush shows up once per table, its copies must not
+        // be folded twice
+        versions.dedup_by_key(|(seq, _, _)| *seq);
+        if let Some(operator) = &opts.merge_operator {
+            if versions.iter().any(|(_, t, _)| *t == ValueType::Merge) {
+                versions = collapse_merges(
+                    manifest,
+                    operator.as_ref(),
+                    key,
+                    versions,
+                    &input_tombstones,
+                    task.is_bottommost(key),
+                )?;
             }
         }
-
-        // if value is not empty (i.e. it's not tombstoned, deletion is equivalent of putting an
-        // emtpy value for that particular key) only then add it to sstwriter
-        if !entry.value.is_empty() {
+        let kept = retained_versions(key, versions, snapshots, &input_tombstones, &task);
+        if kept.is_empty() {
+            return Ok(());
+        }
+        let writer = output.writer_for_new_key(key)?;
+        for (seq, value_type, value) in kept {
+            let (value_type, value) = blobs.separate(key, value_type, value)?;
             // pass seq into the new SST, preserving version ordering
-            writer.add(&entry.key, &entry.value, entry.seq)?;
-            entry_count += 1;
+            writer.add_entry(key, value_type, &value, seq)?;
         }
+        Ok(())
+    };
+
+    let mut output = CompactionOutput {
+        manifest,
+        opts,
+        level: task.output_level as u32,
+        current: None,
+        finished: Vec::new(),
+        range_tombstones,
+        window_start: None,
+    };
 
-        // if the entry is valid to be added to writer store it in last_key so that any other entry
-        // with the same key can be discarded in the next loop
-        last_key = Some(entry.key);
+    // a value whose TTL ran out reads as deleted for everyone, so it's turned into a tombstone
+    // that keeps hiding the older versions of the key until it can be dropped as well
+    let now = opts.clock.now_millis();
 
+    // every version of the current key, newest first
+    let mut current_key: Option<Vec<u8>> = None;
+    let mut versions: Vec<Version> = Vec::new();
+
+    // heap.pop() will give the smallest key entry
+    while let Some(entry) = heap.pop() {
         // push a new entry from the same sst to the heap
-        if let Some(Ok((key, value, seq))) = iterators[entry.sst_idx].next() {
+        if let Some(next) = iterators[entry.sst_idx].next() {
+            let (key, value, seq, value_type) = next?;
             heap.push(MergeEntry {
                 key,
                 value,
                 seq,
+                value_type,
                 sst_idx: entry.sst_idx,
             });
         }
-    }
 
-    // if entry_count is 0 hence the compaction doesn't produce any entry, hence every entry was
-    // tombstoned, so all the sst are waste of storage containing only the tombstones, hence they
-    // all should be deleted
-    if entry_count == 0 {
-        // clear the SST list since all entries were tombstoned
-        loop {
-            let current = sstables.load();
-            let prev = sstables.compare_and_swap(&current, Arc::new(Vec::new()));
-            if Arc::ptr_eq(&*prev, &*current) {
-                break;
+        if current_key.as_deref() != Some(entry.key.as_slice()) {
+            if let Some(key) = current_key.take() {
+                write_versions(&mut output, &mut blobs, &key, std::mem::take(&mut versions))?;
             }
+            current_key = Some(entry.key);
         }
-        // remove old SST files
-        for sst in old_sstables {
-            let _ = std::fs::remove_file(sst.path());
+        if is_expired(entry.value_type, &entry.value, now) {
+            versions.push((entry.seq, ValueType::Delete, Vec::new()));
+        } else {
+            versions.push((entry.seq, entry.value_type, entry.value));
         }
-        // return on the spot, we do not need to do the writing to file stuff, because technically
-        // no new sst is created
-        return Ok(());
+    }
+    if let Some(key) = current_key {
+        write_versions(&mut output, &mut blobs, &key, versions)?;
     }
 
-    // writed.finish() method flushes the newly created sst to the disk
-    // check /sst/writer.rs
-    writer.finish()?;
-
-    // create a new sst reader to put in the db struct
-    let reader = SSTReader::open(&sst_path)?;
+    // the blob files must be durable before the tables pointing into them are opened
+    blobs.finish()?;
+    let output_paths = output.finish()?;
 
-    // replace the OLD sst list with the newly created compacted sst
-    // but preserve any NEW ssts that were added by flushes during compaction
-    loop {
-        let current = sstables.load();
-        let current_ssts = (**current).clone();
-        
-        // find all SSTs that were added during compaction (not in old_sstables)
-        let new_ssts: Vec<SSTReader> = current_ssts
-            .into_iter()
-            .filter(|sst| !old_sstables.iter().any(|old| old.path() == sst.path()))
-            .collect();
-        
-        // combine: new flushes + compacted SST
-        let mut updated_ssts = vec![reader.clone()];
-        updated_ssts.extend(new_ssts);
-        
-        let prev = sstables.compare_and_swap(&current, Arc::new(updated_ssts));
-        if Arc::ptr_eq(&*prev, &*current) {
-            break;
-        }
+    let mut outputs = Vec::with_capacity(output_paths.len());
+    for path in &output_paths {
+        outputs.push(manifest.open_table(path)?);
     }
 
-    // remove the old ssts from the file syst
-    for sst in old_sstables {
-        let _ = std::fs::remove_file(sst.path());
+    // replace the input tables with the compacted ones in a single manifest edit, tables added by
+    // flushes during compaction are preserved. once the edit is durable the inputs are dead, if we
+    // crash before deleting them they're garbage collected at the next open
+    let written = outputs.iter().map(|t| t.file_size()).sum();
+    let outputs = outputs.into_iter().map(|t| (family.id(), t)).collect();
+    manifest.log_and_apply(outputs, &task.inputs)?;
+
+    // remove the input tables from the file system once nobody reads from them anymore, their
+    // cached blocks can never be hit by a new read again
+    for sst in &task.inputs {
+        sst.evict_blocks();
+        sst.mark_obsolete();
     }
 
-    Ok(())
+    Ok(written)
 }
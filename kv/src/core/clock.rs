@@ -0,0 +1,25 @@
+// the time source values written with a TTL expire by, see `Db::put_with_ttl`
+//
+// the database only ever asks the clock of its `DbOptions` for the time, so a test can swap in a
+// clock of its own and move time forward by hand
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// source of the current time, in milliseconds since the unix epoch
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now_millis(&self) -> u64;
+}
+
+/// the wall clock of the system, the default clock of a database
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
@@ -1,5 +1,325 @@
-//TODO: make these sizes to be configurable in the DB::open() method
+// default sizes used when the database is opened with `Db::open`, use `DbOptions` together with
+// `Db::open_with` to override them per database
+
+use std::sync::Arc;
+
+use super::clock::{Clock, SystemClock};
+use crate::error::{DbError, Result};
+use crate::merge::MergeOperator;
+use crate::prefix::PrefixExtractor;
+use crate::sst::writer::WriterOptions;
+use crate::sst::{CompressionType, BLOCK_SIZE, BLOOM_BITS_PER_KEY, BLOOM_SIZE};
+use crate::wal::WalMode;
 
 pub const MEMTABLE_SIZE_THRESHOLD: usize = 1024 * 1024;
 pub const MAX_SSTABLES: usize = 3;
 pub const BLOCK_CACHE_CAPACITY: usize = 256;
+pub const WAL_SYNC_INTERVAL_MS: u64 = 20;
+pub const MAX_LEVELS: usize = 7;
+pub const LEVEL_BASE_BYTES: u64 = 4 * 1024 * 1024;
+pub const LEVEL_SIZE_MULTIPLIER: u64 = 10;
+pub const TARGET_FILE_SIZE: u64 = 2 * 1024 * 1024;
+pub const BLOB_FILE_SIZE: u64 = 64 * 1024 * 1024;
+pub const BLOB_GC_AGE_CUTOFF: f64 = 0.25;
+
+/// runtime configuration of a database
+///
+/// every setter consumes and returns the options so they can be chained:
+///
+/// ```
+/// use keylite_kv::core::DbOptions;
+///
+/// let opts = DbOptions::default()
+///     .memtable_size_threshold(256 * 1024)
+///     .max_sstables(2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// size in bytes after which the mutable memtable is frozen and queued for flush
+    pub memtable_size_threshold: usize,
+    /// number of level 0 sstables that triggers a compaction into level 1
+    pub max_sstables: usize,
+    /// number of decoded data blocks the block cache may hold, 0 disables the cache
+    pub block_cache_capacity: usize,
+    /// target size in bytes of a single sstable data block
+    pub block_size: usize,
+    /// upper bound in bytes of the bloom filter written into every sstable
+    pub bloom_size: usize,
+    /// filter bits spent on every key of an sstable, 10 bits give about 1% false positives.
+    /// tables written with a different value stay readable
+    pub bloom_bits_per_key: usize,
+    /// how often the WAL thread fsyncs the log, in milliseconds
+    pub wal_sync_interval_ms: u64,
+    /// durability of writes that don't ask for a mode of their own through `WriteOptions`
+    pub wal_mode: WalMode,
+    /// number of WAL segments kept in an archive once their memtables are flushed, so
+    /// `Db::subscribe_with` can resume from an older sequence number. 0 deletes them right away
+    pub wal_retained_segments: usize,
+    /// number of levels in the LSM tree, level 0 included
+    pub max_levels: usize,
+    /// target size in bytes of level 1, every deeper level is `level_size_multiplier` times
+    /// bigger than the one above it
+    pub level_base_bytes: u64,
+    pub level_size_multiplier: u64,
+    /// size in bytes after which compaction starts a new output sstable
+    pub target_file_size: u64,
+    /// codec used for the data blocks of newly written sstables, existing tables keep the codec
+    /// they were written with
+    pub compression: CompressionType,
+    /// values of at least this many bytes are moved out of the sstables into blob files when
+    /// they're flushed or compacted, None keeps every value inline. see blob/mod.rs
+    pub min_blob_size: Option<usize>,
+    /// size in bytes after which a flush or compaction starts a new blob file
+    pub blob_file_size: u64,
+    /// fraction of the live blob files, oldest first, whose values compaction moves into new
+    /// blob files, so the space of overwritten and deleted values is eventually given back. 0
+    /// never moves a value
+    pub blob_gc_age_cutoff: f64,
+    /// time source the values written with a TTL expire by, see `Db::put_with_ttl`
+    pub clock: Arc<dyn Clock>,
+    /// folds the operands written by `Db::merge`, None rejects merges. see merge.rs
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// groups keys by prefix, new sstables get a filter over the prefixes that
+    /// `Db::scan_prefix` skips tables by. see prefix.rs
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            memtable_size_threshold: MEMTABLE_SIZE_THRESHOLD,
+            max_sstables: MAX_SSTABLES,
+            block_cache_capacity: BLOCK_CACHE_CAPACITY,
+            block_size: BLOCK_SIZE,
+            bloom_size: BLOOM_SIZE,
+            bloom_bits_per_key: BLOOM_BITS_PER_KEY,
+            wal_sync_interval_ms: WAL_SYNC_INTERVAL_MS,
+            wal_mode: WalMode::default(),
+            wal_retained_segments: 0,
+            max_levels: MAX_LEVELS,
+            level_base_bytes: LEVEL_BASE_BYTES,
+            level_size_multiplier: LEVEL_SIZE_MULTIPLIER,
+            target_file_size: TARGET_FILE_SIZE,
+            compression: CompressionType::default(),
+            min_blob_size: None,
+            blob_file_size: BLOB_FILE_SIZE,
+            blob_gc_age_cutoff: BLOB_GC_AGE_CUTOFF,
+            clock: Arc::new(SystemClock),
+            merge_operator: None,
+            prefix_extractor: None,
+        }
+    }
+}
+
+impl DbOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn memtable_size_threshold(mut self, bytes: usize) -> Self {
+        self.memtable_size_threshold = bytes;
+        self
+    }
+
+    pub fn max_sstables(mut self, count: usize) -> Self {
+        self.max_sstables = count;
+        self
+    }
+
+    pub fn block_cache_capacity(mut self, blocks: usize) -> Self {
+        self.block_cache_capacity = blocks;
+        self
+    }
+
+    pub fn block_size(mut self, bytes: usize) -> Self {
+        self.block_size = bytes;
+        self
+    }
+
+    pub fn bloom_size(mut self, bytes: usize) -> Self {
+        self.bloom_size = bytes;
+        self
+    }
+
+    pub fn bloom_bits_per_key(mut self, bits: usize) -> Self {
+        self.bloom_bits_per_key = bits;
+        self
+    }
+
+    pub fn wal_sync_interval_ms(mut self, ms: u64) -> Self {
+        self.wal_sync_interval_ms = ms;
+        self
+    }
+
+    pub fn wal_mode(mut self, mode: WalMode) -> Self {
+        self.wal_mode = mode;
+        self
+    }
+
+    pub fn wal_retained_segments(mut self, count: usize) -> Self {
+        self.wal_retained_segments = count;
+        self
+    }
+
+    pub fn max_levels(mut self, levels: usize) -> Self {
+        self.max_levels = levels;
+        self
+    }
+
+    pub fn level_base_bytes(mut self, bytes: u64) -> Self {
+        self.level_base_bytes = bytes;
+        self
+    }
+
+    pub fn level_size_multiplier(mut self, multiplier: u64) -> Self {
+        self.level_size_multiplier = multiplier;
+        self
+    }
+
+    pub fn target_file_size(mut self, bytes: u64) -> Self {
+        self.target_file_size = bytes;
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn min_blob_size(mut self, bytes: usize) -> Self {
+        self.min_blob_size = Some(bytes);
+        self
+    }
+
+    pub fn blob_file_size(mut self, bytes: u64) -> Self {
+        self.blob_file_size = bytes;
+        self
+    }
+
+    pub fn blob_gc_age_cutoff(mut self, cutoff: f64) -> Self {
+        self.blob_gc_age_cutoff = cutoff;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    pub fn prefix_extractor(mut self, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+
+    // target size in bytes of the given level, level 0 is sized by file count instead
+    pub(crate) fn level_target_bytes(&self, level: usize) -> u64 {
+        let mut target = self.level_base_bytes;
+        for _ in 1..level {
+            target = target.saturating_mul(self.level_size_multiplier);
+        }
+        target
+    }
+
+    // the options of a column family: the settings of its own come from `family`, the ones the
+    // whole database shares (the WAL, the block cache and the clock) from these options
+    pub(crate) fn for_column_family(&self, family: DbOptions) -> DbOptions {
+        DbOptions {
+            block_cache_capacity: self.block_cache_capacity,
+            wal_sync_interval_ms: self.wal_sync_interval_ms,
+            wal_mode: self.wal_mode,
+            wal_retained_segments: self.wal_retained_segments,
+            clock: Arc::clone(&self.clock),
+            ..family
+        }
+    }
+
+    // reject values that would make the engine misbehave, e.g. a bloom filter without any bits
+    // per key would answer "maybe" to every lookup
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.memtable_size_threshold == 0 {
+            return Err(DbError::InvalidOptions(
+                "memtable_size_threshold must be greater than 0".to_string(),
+            ));
+        }
+        if self.max_sstables == 0 {
+            return Err(DbError::InvalidOptions(
+                "max_sstables must be greater than 0".to_string(),
+            ));
+        }
+        if self.block_size == 0 {
+            return Err(DbError::InvalidOptions(
+                "block_size must be greater than 0".to_string(),
+            ));
+        }
+        if self.bloom_size == 0 || self.bloom_bits_per_key == 0 {
+            return Err(DbError::InvalidOptions(
+                "bloom_size and bloom_bits_per_key must be greater than 0".to_string(),
+            ));
+        }
+        if self.max_levels < 2 {
+            return Err(DbError::InvalidOptions(
+                "max_levels must be at least 2".to_string(),
+            ));
+        }
+        if self.level_base_bytes == 0 || self.target_file_size == 0 {
+            return Err(DbError::InvalidOptions(
+                "level_base_bytes and target_file_size must be greater than 0".to_string(),
+            ));
+        }
+        if self.level_size_multiplier < 2 {
+            return Err(DbError::InvalidOptions(
+                "level_size_multiplier must be at least 2".to_string(),
+            ));
+        }
+        if self.blob_file_size == 0 {
+            return Err(DbError::InvalidOptions(
+                "blob_file_size must be greater than 0".to_string(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.blob_gc_age_cutoff) {
+            return Err(DbError::InvalidOptions(
+                "blob_gc_age_cutoff must be between 0 and 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn writer_options(&self) -> WriterOptions {
+        WriterOptions {
+            block_size: self.block_size,
+            bloom_size: self.bloom_size,
+            bloom_bits_per_key: self.bloom_bits_per_key,
+            compression: self.compression,
+            prefix_extractor: self.prefix_extractor.clone(),
+        }
+    }
+}
+
+/// options of a single write, see `Db::put_with`
+///
+/// ```
+/// use keylite_kv::core::{WalMode, WriteOptions};
+///
+/// let opts = WriteOptions::default().wal_mode(WalMode::Sync);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// overrides `DbOptions::wal_mode` for this write, None uses the database default
+    pub wal_mode: Option<WalMode>,
+}
+
+impl WriteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn wal_mode(mut self, mode: WalMode) -> Self {
+        self.wal_mode = Some(mode);
+        self
+    }
+}
@@ -1,167 +1,714 @@
 use arc_swap::ArcSwap;
-use std::fs::read_dir;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::compaction::{compaction_worker, CompactionMessage};
-use crate::core::iterator::DbIterator;
+use crate::batch::{BatchOp, WriteBatch};
+use crate::cdc::{ChangeFilter, SubscribeOptions, Subscription};
+use crate::compaction::{compaction_worker, needs_compaction, CompactionMessage};
+use crate::core::iterator::{DbIterator, DbRevIterator};
 use crate::error::{DbError, Result};
-use crate::flush::{flush_memtable_to_disk, flush_worker, FlushMessage, FlushQueue};
+use crate::flush::{flush_memtables, flush_worker, FlushMessage, FlushQueue};
+use crate::manifest::{sync_dir, Manifest};
 use crate::memtable::Memtable;
-use crate::sst::SSTReader;
-use crate::transaction::Transaction;
-use crate::wal::reader::{WalEntry, WalReader};
-use crate::wal::thread::{wal_thread, WalMessage};
+use crate::prefix::prefix_end;
+use crate::sst::{BlockCache, BlockCacheStats, SSTIterator, MAX_KEY_SIZE, MAX_VALUE_SIZE};
+use crate::stats::{DbStats, Histogram, Statistics, Ticker};
+use crate::transaction::{Transaction, MAX_TRANSACT_ATTEMPTS};
+use crate::types::{expiring_value, inline_value, is_expired, ValueType};
+use crate::wal::reader::{encoded_len, WalEntry, WalReader, WalRecord};
+use crate::wal::thread::{wal_thread, BackgroundError, WalMessage};
+use crate::wal::{
+    list_archived_segments, list_segments, retire_segment, segment_path, WalMode, MAX_RECORD_SIZE,
+};
 use crossbeam_channel::Sender;
+use parking_lot::{Mutex, MutexGuard, RwLock};
 
-use super::config::{MAX_SSTABLES, MEMTABLE_SIZE_THRESHOLD};
+use super::config::{DbOptions, WriteOptions};
+use super::family::{ColumnFamily, FamilyMap, MemtableSet, DEFAULT_COLUMN_FAMILY};
+use super::{Snapshot, SnapshotList};
 
 pub struct Db {
-    dir: PathBuf,
-    memtable: Arc<ArcSwap<Memtable>>,
-    immutable_memtables: Arc<ArcSwap<Vec<Arc<Memtable>>>>,
-    sstables: Arc<ArcSwap<Vec<SSTReader>>>,
-    next_sst_id: Arc<AtomicU64>,
+    // the memtables of every column family, see core/family.rs
+    memtables: Arc<ArcSwap<MemtableSet>>,
+    immutable_memtables: Arc<ArcSwap<Vec<Arc<MemtableSet>>>>,
+    families: Arc<ArcSwap<FamilyMap>>,
+    default_family: Arc<ColumnFamily>,
+    // held while the memtables are frozen and while a column family is created or dropped, so
+    // each of them works on the latest set of memtables
+    switch_lock: Mutex<()>,
+    // writers hold it shared from picking their sequence number until their record is in the
+    // memtables, the memtables are only frozen under it exclusively. so a frozen memtable never
+    // holds a version newer than one in the memtables that replaced it, which compaction relies on
+    // when it folds merge operands
+    rotation_lock: RwLock<()>,
+    manifest: Arc<Manifest>,
+    block_cache: Option<Arc<BlockCache>>,
     flush_sender: Sender<FlushMessage>,
     compaction_sender: Sender<CompactionMessage>,
     wal_sender: Sender<WalMessage>,
+    // a WAL failure no writer waited for, reported to the next asynchronous writer
+    wal_error: BackgroundError,
     flush_thread: Option<JoinHandle<()>>,
     compaction_thread: Option<JoinHandle<()>>,
     wal_thread: Option<JoinHandle<()>>,
     global_sequence: Arc<AtomicU64>,
+    // sequence numbers picked by writers whose record isn't in the memtables yet, see
+    // visible_sequence
+    in_flight: Mutex<BTreeSet<u64>>,
+    snapshots: Arc<SnapshotList>,
+    // id of the WAL segment the next memtable logs into
+    next_wal_segment: AtomicU64,
+    commit_lock: Mutex<()>,
+    // number of change feed subscribers, see cdc.rs
+    subscribers: Arc<AtomicUsize>,
+    // counters shared with the workers and the WAL thread, see stats.rs
+    stats: Arc<Statistics>,
+    opts: Arc<DbOptions>,
 }
 
 impl Db {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let dir = path.as_ref().to_path_buf();
-        std::fs::create_dir_all(&dir)?;
+        Self::open_with(path, DbOptions::default())
+    }
 
-        let mut sst_ids = Vec::new();
-        let mut has_wal = false;
-        for entry in read_dir(&dir)? {
-            let e = entry?;
-            let name = e.file_name().into_string().unwrap_or_default();
-            if let Some(s) = name
-                .strip_prefix("sst-")
-                .and_then(|s| s.strip_suffix(".db"))
-            {
-                if let Ok(id) = s.parse::<u64>() {
-                    sst_ids.push(id);
-                }
-            }
-            if name.starts_with("wal") {
-                has_wal = true;
+    // same as open but with caller provided options instead of the defaults in core/config.rs
+    pub fn open_with(path: impl AsRef<Path>, opts: DbOptions) -> Result<Self> {
+        Self::open_with_column_families(path, opts, Vec::new())
+    }
+
+    // open with the options of the column families besides the default one, which takes `opts`.
+    // a listed family that doesn't exist yet is created, a family of the database that isn't
+    // listed is opened with `opts`. the settings the families share (the WAL, the block cache
+    // and the clock) always come from `opts`
+    pub fn open_with_column_families(
+        path: impl AsRef<Path>,
+        opts: DbOptions,
+        column_families: Vec<(String, DbOptions)>,
+    ) -> Result<Self> {
+        opts.validate()?;
+        let mut family_opts = BTreeMap::new();
+        for (name, cf_opts) in column_families {
+            if name == DEFAULT_COLUMN_FAMILY {
+                return Err(DbError::InvalidOptions(
+                    "the default column family takes the options of the database".to_string(),
+                ));
             }
+            let cf_opts = opts.for_column_family(cf_opts);
+            cf_opts.validate()?;
+            family_opts.insert(name, cf_opts);
         }
+        let opts = Arc::new(opts);
 
-        sst_ids.sort_unstable();
-        let next_id = sst_ids.last().map(|&id| id + 1).unwrap_or(1);
+        let dir = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
 
-        // open SSTables in reverse order -> newest first for faster lookups
-        let mut sstables = Vec::new();
-        for id in sst_ids.iter().rev() {
-            let path = dir.join(format!("sst-{}.db", id));
-            if let Ok(reader) = SSTReader::open(&path) {
-                sstables.push(reader);
+        // the manifest knows exactly which tables are live, it hands them back in lookup order ->
+        // level 0 newest first, then the deeper levels sorted by key range, see
+        // compaction/picker.rs
+        let block_cache = (opts.block_cache_capacity > 0)
+            .then(|| Arc::new(BlockCache::new(opts.block_cache_capacity)));
+        let manifest = Arc::new(Manifest::open(&dir, block_cache.clone())?);
+
+        let existing = manifest.families();
+        for name in family_opts.keys() {
+            if !existing.iter().any(|(_, existing)| existing == name) {
+                manifest.create_family(name)?;
             }
         }
+        let mut families = FamilyMap::new();
+        for (id, name) in manifest.families() {
+            let cf_opts = match family_opts.remove(&name) {
+                Some(cf_opts) => Arc::new(cf_opts),
+                None => Arc::clone(&opts),
+            };
+            let sstables = manifest.sstables(id).expect("family is live");
+            families.insert(
+                id,
+                Arc::new(ColumnFamily::new(id, &name, cf_opts, sstables)),
+            );
+        }
+        let default_family = Arc::clone(&families[&0]);
+        let families = Arc::new(ArcSwap::from_pointee(families));
 
-        let sstables = Arc::new(ArcSwap::from_pointee(sstables));
-        let next_sst_id = Arc::new(AtomicU64::new(next_id));
         let immutable_memtables = Arc::new(ArcSwap::from_pointee(Vec::new()));
+        let stats = Arc::new(Statistics::new());
 
         let flush_queue = FlushQueue::new();
         let flush_sender = flush_queue.sender();
         let flush_receiver = flush_queue.receiver();
 
-        let flush_dir = dir.clone();
-        let flush_sstables = Arc::clone(&sstables);
+        let flush_manifest = Arc::clone(&manifest);
         let flush_immutables = Arc::clone(&immutable_memtables);
-        let flush_next_id = Arc::clone(&next_sst_id);
+        let flush_families = Arc::clone(&families);
 
         let (wal_tx, wal_rx) = crossbeam_channel::unbounded();
         let wal_tx_for_flush = wal_tx.clone();
+        let flush_stats = Arc::clone(&stats);
         let flush_thread = thread::spawn(move || {
             flush_worker(
                 flush_receiver,
-                flush_dir,
-                flush_sstables,
+                flush_manifest,
                 flush_immutables,
-                flush_next_id,
+                flush_families,
                 wal_tx_for_flush,
+                flush_stats,
             )
         });
 
         let (compaction_sender, compaction_receiver) = crossbeam_channel::unbounded();
 
-        let compaction_dir = dir.clone();
-        let compaction_sstables = Arc::clone(&sstables);
-        let compaction_next_id = Arc::clone(&next_sst_id);
+        let compaction_manifest = Arc::clone(&manifest);
+        let compaction_families = Arc::clone(&families);
+        let snapshots = Arc::new(SnapshotList::default());
+        let compaction_snapshots = Arc::clone(&snapshots);
+        let compaction_stats = Arc::clone(&stats);
 
         let compaction_thread = thread::spawn(move || {
             compaction_worker(
                 compaction_receiver,
-                compaction_dir,
-                compaction_sstables,
-                compaction_next_id,
+                compaction_manifest,
+                compaction_families,
+                compaction_snapshots,
+                compaction_stats,
             )
         });
-        let mut max_seq = 0;
-        let memtable = Memtable::new();
-
-        for sst in sstables.load().iter() {
-            max_seq = max_seq.max(sst.max_sequence());
-        }
-
-        if has_wal {
-            let wal_path = dir.join("wal.log");
-            if wal_path.exists() {
-                let mut wal_reader = WalReader::new(&wal_path)?;
-                while let Some(record) = wal_reader.next_entry()? {
-                    max_seq = max_seq.max(record.seq);
-                    memtable.put(record.key, record.val, record.seq);
-                    if memtable.size_bytes() >= MEMTABLE_SIZE_THRESHOLD {
-                        flush_memtable_to_disk(
-                            &memtable,
-                            &dir,
-                            &sstables,
-                            &next_sst_id,
-                            wal_tx.clone(),
-                        )?;
-                        memtable.clear();
+        let mut max_seq = manifest.last_sequence();
+
+        // the single log of older versions comes first, then the segments oldest first
+        let segments = list_segments(&dir)?;
+        let mut logs = Vec::new();
+        let legacy_wal = dir.join("wal.log");
+        if legacy_wal.exists() {
+            logs.push(legacy_wal.clone());
+        }
+        logs.extend(segments.iter().map(|(_, path)| path.clone()));
+
+        // the writes of a column family that was dropped since are skipped
+        let live_families = families.load_full();
+        let mut replayed = MemtableSet::new(0, live_families.keys().copied());
+        for path in &logs {
+            let mut wal_reader = WalReader::new(path)?;
+            while let Some(record) = wal_reader.next_record()? {
+                max_seq = max_seq.max(record.seq);
+                for entry in record.entries {
+                    if let Some(memtable) = replayed.get(entry.cf) {
+                        memtable.insert(entry.key, entry.value_type, entry.val, record.seq);
                     }
                 }
+                for (cf, start, end) in record.range_deletes {
+                    if let Some(memtable) = replayed.get(cf) {
+                        memtable.delete_range(start, end, record.seq);
+                    }
+                }
+                if memtables_full(&replayed, &live_families) {
+                    flush_memtables(&replayed, &live_families, &manifest)?;
+                    replayed = replayed.fresh(0);
+                }
             }
         }
 
-        let global_sequence = Arc::new(AtomicU64::new(max_seq.saturating_add(1)));
+        // whatever the logs hold is moved into tables before they are retired, so the database
+        // starts over with empty memtables logging into a fresh segment
+        flush_memtables(&replayed, &live_families, &manifest)?;
+        if legacy_wal.exists() {
+            std::fs::remove_file(&legacy_wal)?;
+        }
+        for (id, _) in &segments {
+            retire_segment(&dir, *id, opts.wal_retained_segments)?;
+        }
 
-        let wal_path = dir.join("wal.log");
+        // a new segment must not take the id of an archived one
+        let archived = list_archived_segments(&dir)?;
+        let first_segment = segments
+            .iter()
+            .chain(&archived)
+            .map(|(id, _)| id + 1)
+            .max()
+            .unwrap_or(1);
+        manifest.segments_flushed_below(first_segment);
+        let memtables = MemtableSet::new(first_segment, live_families.keys().copied());
+
+        let global_sequence = Arc::new(AtomicU64::new(max_seq.saturating_add(1)));
 
+        let wal_dir = dir.clone();
+        let wal_sync_interval_ms = opts.wal_sync_interval_ms;
+        let wal_retained_segments = opts.wal_retained_segments;
+        let subscribers = Arc::new(AtomicUsize::new(0));
+        let wal_subscribers = Arc::clone(&subscribers);
+        let wal_stats = Arc::clone(&stats);
+        let wal_error = BackgroundError::default();
+        let wal_thread_error = Arc::clone(&wal_error);
         let wal_thread = thread::spawn(move || {
-            wal_thread(wal_path, wal_rx, 20);
+            wal_thread(
+                wal_dir,
+                wal_rx,
+                wal_sync_interval_ms,
+                wal_retained_segments,
+                wal_subscribers,
+                wal_thread_error,
+                wal_stats,
+            );
         });
 
         Ok(Self {
-            dir,
-            memtable: Arc::new(ArcSwap::from_pointee(memtable)),
+            memtables: Arc::new(ArcSwap::from_pointee(memtables)),
             immutable_memtables,
-            sstables,
-            next_sst_id,
+            families,
+            default_family,
+            switch_lock: Mutex::new(()),
+            rotation_lock: RwLock::new(()),
+            manifest,
+            block_cache,
             flush_sender,
             compaction_sender,
             wal_sender: wal_tx,
+            wal_error,
             flush_thread: Some(flush_thread),
             compaction_thread: Some(compaction_thread),
             global_sequence,
+            in_flight: Mutex::new(BTreeSet::new()),
+            snapshots,
+            next_wal_segment: AtomicU64::new(first_segment + 1),
+            commit_lock: Mutex::new(()),
             wal_thread: Some(wal_thread),
+            subscribers,
+            stats,
+            opts,
         })
     }
 
+    pub fn options(&self) -> &DbOptions {
+        &self.opts
+    }
+
+    // number of sstables currently living in the given level of the LSM tree
+    pub fn num_files_at_level(&self, level: usize) -> usize {
+        self.num_files_at_level_cf(&self.default_family, level)
+    }
+
+    // the same for the tables of a column family
+    pub fn num_files_at_level_cf(&self, cf: &ColumnFamily, level: usize) -> usize {
+        cf.sstables
+            .load()
+            .iter()
+            .filter(|sst| sst.level() as usize == level)
+            .count()
+    }
+
+    // creates a column family with options of its own, see core/family.rs. the settings every
+    // family shares (the WAL, the block cache and the clock) are taken from the database options
+    // instead
+    pub fn create_column_family(&self, name: &str, opts: DbOptions) -> Result<Arc<ColumnFamily>> {
+        let opts = self.opts.for_column_family(opts);
+        opts.validate()?;
+
+        let _switch = self.switch_lock.lock();
+        let id = self.manifest.create_family(name)?;
+        let sstables = self.manifest.sstables(id).expect("family was just created");
+        let family = Arc::new(ColumnFamily::new(id, name, Arc::new(opts), sstables));
+
+        self.families.rcu(|families| {
+            let mut families = (**families).clone();
+            families.insert(id, Arc::clone(&family));
+            families
+        });
+        let memtables = self.memtables.load_full();
+        self.memtables.store(Arc::new(memtables.with_family(id)));
+        Ok(family)
+    }
+
+    // the column family with the given name, None if there's no such family
+    pub fn column_family(&self, name: &str) -> Option<Arc<ColumnFamily>> {
+        self.families
+            .load()
+            .values()
+            .find(|family| family.name() == name)
+            .cloned()
+    }
+
+    pub fn default_column_family(&self) -> Arc<ColumnFamily> {
+        Arc::clone(&self.default_family)
+    }
+
+    // names of all the column families, the default one first
+    pub fn column_family_names(&self) -> Vec<String> {
+        self.families
+            .load()
+            .values()
+            .map(|family| family.name().to_string())
+            .collect()
+    }
+
+    // drops the column family and all of its data with a single manifest edit, its tables are
+    // deleted once the reads still using them are done. the default family can't be dropped
+    pub fn drop_column_family(&self, name: &str) -> Result<()> {
+        let family = self.column_family(name).ok_or_else(|| {
+            DbError::ColumnFamily(format!("column family {:?} doesn't exist", name))
+        })?;
+
+        let _switch = self.switch_lock.lock();
+        self.manifest.drop_family(family.id())?;
+        family.mark_dropped();
+
+        self.families.rcu(|families| {
+            let mut families = (**families).clone();
+            families.remove(&family.id());
+            families
+        });
+        // the frozen memtables of the family are left to the flush worker, which skips them
+        let memtables = self.memtables.load_full();
+        self.memtables
+            .store(Arc::new(memtables.without_family(family.id())));
+        Ok(())
+    }
+
+    fn check_live(&self, cf: &ColumnFamily) -> Result<()> {
+        if cf.is_dropped() {
+            return Err(DbError::ColumnFamily(format!(
+                "column family {:?} was dropped",
+                cf.name()
+            )));
+        }
+        Ok(())
+    }
+
+    // the memtables holding writes of the column family, newest first
+    fn family_memtables(&self, cf: u32) -> Vec<Arc<Memtable>> {
+        let memtables = self.memtables.load_full();
+        let immutables = self.immutable_memtables.load();
+        std::iter::once(&memtables)
+            .chain(immutables.iter().rev())
+            .filter_map(|set| set.get(cf).cloned())
+            .collect()
+    }
+
+    // hit/miss counters of the block cache, all zero if the cache is disabled
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.block_cache
+            .as_ref()
+            .map(|cache| cache.stats())
+            .unwrap_or_default()
+    }
+
+    // the counters and latency histograms of the database along with the size of its
+    // memtables and tables, see stats.rs
+    pub fn stats(&self) -> DbStats {
+        let mut stats = self.stats.snapshot();
+        for family in self.families.load().values() {
+            let sstables = family.sstables.load();
+            stats.num_sstables += sstables.len();
+            stats.total_sst_bytes += sstables.iter().map(|sst| sst.file_size()).sum::<u64>();
+            stats.memtable_bytes += self.memtable_bytes(family.id());
+        }
+        stats.num_immutable_memtables = self.immutable_memtables.load().len();
+        stats.block_cache = self.block_cache_stats();
+        stats
+    }
+
+    // the value of a property of the default column family as text, None if there's no
+    // property of that name. see `property_cf` for the names
+    pub fn property(&self, name: &str) -> Option<String> {
+        self.property_cf(&self.default_family, name)
+    }
+
+    // the value of a property as text, None if there's no property of that name:
+    //
+    // - `keylite.num-sstables`: live tables of the column family
+    // - `keylite.num-files-at-level<N>`: its tables in level N
+    // - `keylite.total-sst-bytes`: size of its tables
+    // - `keylite.memtable-bytes`: size of its memtables, the frozen ones included
+    // - `keylite.num-entries-memtables`: entries in its memtables
+    // - `keylite.num-immutable-memtables`: frozen memtable sets of the database waiting to be
+    //   flushed
+    // - `keylite.stats`: every statistic of the database, see `Db::stats`
+    pub fn property_cf(&self, cf: &ColumnFamily, name: &str) -> Option<String> {
+        let name = name.strip_prefix("keylite.")?;
+        if let Some(level) = name.strip_prefix("num-files-at-level") {
+            let level = level.parse().ok()?;
+            return Some(self.num_files_at_level_cf(cf, level).to_string());
+        }
+        let value = match name {
+            "num-sstables" => cf.sstables.load().len().to_string(),
+            "total-sst-bytes" => cf
+                .sstables
+                .load()
+                .iter()
+                .map(|sst| sst.file_size())
+                .sum::<u64>()
+                .to_string(),
+            "memtable-bytes" => self.memtable_bytes(cf.id()).to_string(),
+            "num-entries-memtables" => self
+                .family_memtables(cf.id())
+                .iter()
+                .map(|mt| mt.len())
+                .sum::<usize>()
+                .to_string(),
+            "num-immutable-memtables" => self.immutable_memtables.load().len().to_string(),
+            "stats" => self.stats().to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // size of the memtables of the column family, the frozen ones included
+    fn memtable_bytes(&self, cf: u32) -> usize {
+        self.family_memtables(cf)
+            .iter()
+            .map(|mt| mt.size_bytes())
+            .sum()
+    }
+
+    // writes a consistent copy of the database into `dest`, which must not exist yet, while
+    // writers keep going. the copy can be opened like any other database
+    //
+    // the tables are hard linked into it wherever possible, the writes that are still only in
+    // the memtables come along as the tail of the WAL. writes made with `WalMode::Disabled`
+    // that haven't been flushed yet aren't part of it
+    pub fn checkpoint(&self, dest: impl AsRef<Path>) -> Result<()> {
+        let dest = dest.as_ref();
+        if dest.exists() {
+            return Err(DbError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("checkpoint destination {:?} already exists", dest),
+            )));
+        }
+
+        // the checkpoint is assembled next to its destination and renamed into place once it's
+        // complete, a crash never leaves a half written checkpoint behind under that name
+        let mut staging = dest.as_os_str().to_owned();
+        staging.push(".tmp");
+        let staging = PathBuf::from(staging);
+        let _ = fs::remove_dir_all(&staging);
+        fs::create_dir_all(&staging)?;
+
+        // the WAL thread syncs the segments and reports how long they are at that point, the
+        // copy of the WAL ends there however much gets appended while it's being made
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        self.wal_sender
+            .send(WalMessage::Checkpoint(ack_tx))
+            .map_err(|_| DbError::Other("wal thread is not running".to_string()))?;
+        let lengths = ack_rx
+            .recv()
+            .map_err(|_| DbError::Other("wal thread exited before syncing".to_string()))??;
+
+        // the segments that aren't flushed are opened while the manifest is locked, an open
+        // file survives its segment being retired right after
+        let segments = self.manifest.checkpoint(&staging, |flushed| {
+            let mut open = Vec::new();
+            for (id, len) in lengths {
+                if !flushed.contains(id) {
+                    open.push((id, File::open(segment_path(self.manifest.dir(), id))?, len));
+                }
+            }
+            Ok(open)
+        })?;
+        for (id, file, len) in segments {
+            let mut copy = File::create(segment_path(&staging, id))?;
+            io::copy(&mut file.take(len), &mut copy)?;
+            copy.sync_all()?;
+        }
+        sync_dir(&staging)?;
+
+        fs::rename(&staging, dest)?;
+        if let Some(parent) = dest.parent().filter(|p| !p.as_os_str().is_empty()) {
+            sync_dir(parent)?;
+        }
+        Ok(())
+    }
+
+    // the writes committed from now on to the keys in [start, end) of the default column
+    // family, None is unbounded. a batch or a transaction arrives as one `ChangeBatch`
+    //
+    // the batches come in the order they were logged, concurrent writers may log theirs
+    // slightly out of sequence order. a batch is published as soon as it's logged, a reader
+    // that reacts to it may get there before the write is visible to `get`
+    pub fn subscribe(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<Subscription> {
+        self.subscribe_with(start, end, SubscribeOptions::default())
+    }
+
+    // same as subscribe but with `SubscribeOptions`, e.g. to resume from the sequence number
+    // the subscriber got to before. writes made with `WalMode::Disabled` can't be resumed from,
+    // resuming from writes whose WAL segment is gone fails with `DbError::SequenceGap`, see
+    // `DbOptions::wal_retained_segments`
+    pub fn subscribe_with(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        opts: SubscribeOptions,
+    ) -> Result<Subscription> {
+        self.subscribe_cf(&self.default_family, start, end, opts)
+    }
+
+    // subscription to a column family
+    pub fn subscribe_cf(
+        &self,
+        cf: &ColumnFamily,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        opts: SubscribeOptions,
+    ) -> Result<Subscription> {
+        self.check_live(cf)?;
+        let filter = ChangeFilter::new(cf.id(), start, end);
+        let (mut subscription, subscriber) =
+            Subscription::new(filter, opts.from_sequence.unwrap_or(0));
+
+        // counted before the WAL thread adds it, see ChangeFeed
+        self.subscribers.fetch_add(1, Ordering::AcqRel);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let subscribe = WalMessage::Subscribe {
+            subscriber,
+            replay: opts.from_sequence.is_some(),
+            ack: ack_tx,
+        };
+        if self.wal_sender.send(subscribe).is_err() {
+            self.subscribers.fetch_sub(1, Ordering::AcqRel);
+            return Err(DbError::Other("wal thread is not running".to_string()));
+        }
+        let segments = ack_rx
+            .recv()
+            .map_err(|_| DbError::Other("wal thread exited before subscribing".to_string()))??;
+        subscription.replay_from(segments);
+
+        // the batches older than the retained segments were flushed, the family's tables tell
+        // whether any of them is one the subscriber asked for
+        if let Some(from) = opts.from_sequence {
+            let oldest = subscription.first_replayed()?;
+            let retained_from = oldest.unwrap_or(u64::MAX);
+            let lost = cf
+                .sstables
+                .load()
+                .iter()
+                .any(|t| t.max_sequence() >= from && t.min_sequence() < retained_from);
+            if from < retained_from && lost {
+                let oldest = oldest.unwrap_or_else(|| self.global_sequence.load(Ordering::Acquire));
+                return Err(DbError::SequenceGap(oldest));
+            }
+        }
+        Ok(subscription)
+    }
+
+    // the snapshot never includes the sequence number of a write that isn't in the memtables yet,
+    // its reads would change once the write lands and transaction validation, which only looks at
+    // newer sequence numbers, would never notice it
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshots.acquire(self.visible_sequence())
+    }
+
+    // the sequence number every write below has made it into the memtables. writers pick theirs
+    // before they log and insert their record, so the ones in flight hold it back
+    fn visible_sequence(&self) -> u64 {
+        let in_flight = self.in_flight.lock();
+        in_flight
+            .first()
+            .copied()
+            .unwrap_or_else(|| self.global_sequence.load(Ordering::Acquire))
+    }
+
     pub fn begin(&self) -> Transaction<'_> {
-        Transaction::new(self.global_sequence.load(Ordering::Acquire), self)
+        Transaction::new(self.snapshot(), self)
+    }
+
+    // runs `f` in a transaction and commits it, starting over with a fresh transaction whenever
+    // the commit hits a conflict. an error returned by `f` aborts the transaction and is passed
+    // through as is, after `MAX_TRANSACT_ATTEMPTS` conflicts in a row the last one is returned
+    pub fn transact<T>(&self, mut f: impl FnMut(&mut Transaction<'_>) -> Result<T>) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            let mut txn = self.begin();
+            let out = f(&mut txn)?;
+            match txn.commit() {
+                Ok(()) => return Ok(out),
+                Err(DbError::Conflict(_)) if attempt < MAX_TRANSACT_ATTEMPTS => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // held by a transaction from validation until its writes are in the memtable, so commits
+    // never interleave
+    pub(crate) fn lock_commits(&self) -> MutexGuard<'_, ()> {
+        self.commit_lock.lock()
+    }
+
+    // whether a version of the key (a value or a tombstone) with seq >= since_seq exists in the
+    // column family
+    pub(crate) fn key_written_since(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        since_seq: u64,
+    ) -> Result<bool> {
+        let written = |seq: Option<u64>| seq.is_some_and(|seq| seq >= since_seq);
+
+        // a range tombstone covering the key counts as a write of the key
+        let deleted = |seq: u64| seq > 0 && seq >= since_seq;
+
+        for mt in self.family_memtables(cf.id()) {
+            if written(mt.newest_sequence(key)) || deleted(mt.covering_seq(key, u64::MAX)) {
+                return Ok(true);
+            }
+        }
+        // tables holding only older data can be skipped without touching them
+        for sst in cf.sstables.load().iter() {
+            if sst.max_sequence() < since_seq || !sst.may_contain_key(key) {
+                continue;
+            }
+            if written(sst.newest_sequence(key)?) || deleted(sst.covering_seq(key, u64::MAX)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // whether any key in [start, end) of the column family got a version with seq >= since_seq,
+    // None bounds are unbounded
+    pub(crate) fn range_written_since(
+        &self,
+        cf: &ColumnFamily,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        since_seq: u64,
+    ) -> Result<bool> {
+        let start = start.unwrap_or(&[]);
+
+        for mt in self.family_memtables(cf.id()) {
+            if mt.written_since(start, end, since_seq) {
+                return Ok(true);
+            }
+        }
+        for sst in cf.sstables.load().iter() {
+            let overlaps =
+                sst.largest_key() >= start && end.is_none_or(|end| sst.smallest_key() < end);
+            if sst.max_sequence() < since_seq || !overlaps {
+                continue;
+            }
+            let deleted = sst
+                .range_tombstones()
+                .iter()
+                .any(|t| t.seq >= since_seq && t.overlaps(start, end));
+            if deleted {
+                return Ok(true);
+            }
+            // only the part of the table inside the range is read
+            let mut iter = SSTIterator::new(sst.clone());
+            iter.seek(start);
+            for entry in iter {
+                let (key, _, seq, _) = entry?;
+                if end.is_some_and(|end| key.as_slice() >= end) {
+                    break;
+                }
+                if seq >= since_seq {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
     }
 
     // sllocate a new sequence number for transaction commits or other operations
@@ -175,36 +722,264 @@ impl Db {
     // at any time 1 mutable memtable and 2 immutable memtables are allowed, if immutable memtables
     // crosses 2 then the oldest one gets flushed in the SST file
     pub fn put(&self, key: &[u8], val: &[u8]) -> Result<()> {
-        let seq = self.global_sequence.fetch_add(1, Ordering::SeqCst);
+        self.put_with(key, val, &WriteOptions::default())
+    }
 
-        let _ = self.wal_sender.send(WalMessage::Append(WalEntry {
-            seq,
-            key: key.to_vec(),
-            val: val.to_vec(),
-        }));
+    // put with per write options, e.g. to wait for the WAL fsync of this one write
+    pub fn put_with(&self, key: &[u8], val: &[u8], opts: &WriteOptions) -> Result<()> {
+        let mode = opts.wal_mode.unwrap_or(self.opts.wal_mode);
+        self.apply(single_entry(0, key, ValueType::Put, val), None, mode)
+    }
 
-        let memtable = self.memtable.load();
-        memtable.put(key.to_vec(), val.to_vec(), seq);
+    // put into a column family, see create_column_family
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], val: &[u8]) -> Result<()> {
+        self.check_live(cf)?;
+        self.apply(
+            single_entry(cf.id(), key, ValueType::Put, val),
+            None,
+            self.opts.wal_mode,
+        )
+    }
 
-        // flush if needed
-        self.flush_if_needed();
+    // put of a value that reads as absent once the ttl has passed, from then on it hides the
+    // older versions of the key just like a delete and compaction drops it. the expiry is taken
+    // from `DbOptions::clock`, a ttl that reaches past the end of its millisecond count never
+    // expires
+    pub fn put_with_ttl(&self, key: &[u8], val: &[u8], ttl: Duration) -> Result<()> {
+        self.put_expiring(0, key, val, ttl, self.opts.wal_mode)
+    }
 
-        Ok(())
+    // put_with_ttl with per write options
+    pub fn put_with_ttl_opts(
+        &self,
+        key: &[u8],
+        val: &[u8],
+        ttl: Duration,
+        opts: &WriteOptions,
+    ) -> Result<()> {
+        let mode = opts.wal_mode.unwrap_or(self.opts.wal_mode);
+        self.put_expiring(0, key, val, ttl, mode)
+    }
+
+    // put_with_ttl into a column family
+    pub fn put_with_ttl_cf(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        val: &[u8],
+        ttl: Duration,
+    ) -> Result<()> {
+        self.check_live(cf)?;
+        self.put_expiring(cf.id(), key, val, ttl, self.opts.wal_mode)
+    }
+
+    fn put_expiring(
+        &self,
+        cf: u32,
+        key: &[u8],
+        val: &[u8],
+        ttl: Duration,
+        mode: WalMode,
+    ) -> Result<()> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let expires_at = self.opts.clock.now_millis().saturating_add(ttl);
+        let val = expiring_value(expires_at, val);
+        self.apply(
+            single_entry(cf, key, ValueType::ExpiringPut, &val),
+            None,
+            mode,
+        )
+    }
+
+    // stores an operand of the merge operator for the key without reading its value, reads fold
+    // the operands on top of the value the key had before them, see merge.rs. fails if the
+    // options have no merge operator
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_cf(&self.default_family, key, operand)
+    }
+
+    // merge into a column family, with the merge operator of the family
+    pub fn merge_cf(&self, cf: &ColumnFamily, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.check_live(cf)?;
+        if cf.opts.merge_operator.is_none() {
+            return Err(DbError::InvalidOptions(
+                "merge needs a merge operator, see DbOptions::merge_operator".to_string(),
+            ));
+        }
+        self.apply(
+            single_entry(cf.id(), key, ValueType::Merge, operand),
+            None,
+            self.opts.wal_mode,
+        )
     }
 
     // put but with of a particular seq
     // used in transactions
     pub fn put_seq(&self, key: &[u8], val: &[u8], seq: u64) -> Result<()> {
-        let _ = self.wal_sender.send(WalMessage::Append(WalEntry {
-            seq,
-            key: key.to_vec(),
-            val: val.to_vec(),
-        }));
-        let memtable = self.memtable.load();
-        memtable.put(key.to_vec(), val.to_vec(), seq);
+        self.apply(
+            single_entry(0, key, ValueType::Put, val),
+            Some(seq),
+            self.opts.wal_mode,
+        )
+    }
+
+    // applies every operation of the batch atomically, see batch.rs
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.write_with(batch, &WriteOptions::default())
+    }
+
+    pub fn write_with(&self, batch: WriteBatch, opts: &WriteOptions) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mode = opts.wal_mode.unwrap_or(self.opts.wal_mode);
+        self.apply(resolve_batch(batch), None, mode)
+    }
+
+    // deletes every key in [start, end) with a single range tombstone, an empty range is a no-op
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(start, end);
+        self.write(batch)
+    }
+
+    // the record is logged before it becomes visible in the memtables, a sync write that fails
+    // to reach the disk is reported to the caller and never applied
+    //
+    // the record goes into the segment of the memtables it's applied to, so a segment never has
+    // to outlive the memtables it belongs to
+    //
+    // the record is written under `seq`, or under a sequence number picked for it once it's known
+    // to be valid
+    fn apply(&self, mut record: WalRecord, seq: Option<u64>, mode: WalMode) -> Result<()> {
+        let start = Instant::now();
+        let rotation = self.rotation_lock.read();
+        let memtables = self.memtables.load();
+        let families = record
+            .entries
+            .iter()
+            .map(|entry| entry.cf)
+            .chain(record.range_deletes.iter().map(|(cf, _, _)| *cf));
+        for cf in families {
+            if memtables.get(cf).is_none() {
+                return Err(DbError::ColumnFamily(format!(
+                    "column family {} doesn't exist",
+                    cf
+                )));
+            }
+        }
+        // a key the tables can't store would only fail once its memtable is flushed, and keep
+        // failing every flush after that. the same goes for the bounds of a range delete, and for
+        // values and records the log can't frame
+        let too_long = record
+            .entries
+            .iter()
+            .map(|e| e.key.len())
+            .chain(
+                record
+                    .range_deletes
+                    .iter()
+                    .flat_map(|(_, start, end)| [start.len(), end.len()]),
+            )
+            .find(|len| *len > MAX_KEY_SIZE);
+        if let Some(len) = too_long {
+            return Err(DbError::KeyTooLarge(len));
+        }
+        if let Some(entry) = record.entries.iter().find(|e| e.val.len() > MAX_VALUE_SIZE) {
+            return Err(DbError::ValueTooLarge(entry.val.len()));
+        }
+        let len = encoded_len(&record);
+        if len > MAX_RECORD_SIZE {
+            return Err(DbError::WriteTooLarge(len));
+        }
+        record.seq = match seq {
+            Some(seq) => seq,
+            None => {
+                let mut in_flight = self.in_flight.lock();
+                let seq = self.next_sequence();
+                in_flight.insert(seq);
+                seq
+            }
+        };
 
+        let logged = self.log_write(memtables.segment(), &record, mode);
+        let record_seq = record.seq;
+        if logged.is_ok() {
+            self.count_write(&record);
+            for entry in record.entries {
+                let memtable = memtables.get(entry.cf).expect("family checked above");
+                memtable.insert(entry.key, entry.value_type, entry.val, record.seq);
+            }
+            for (cf, start, end) in record.range_deletes {
+                let memtable = memtables.get(cf).expect("family checked above");
+                memtable.delete_range(start, end, record.seq);
+            }
+        }
+        if seq.is_none() {
+            self.in_flight.lock().remove(&record_seq);
+        }
+        drop(rotation);
+        logged?;
+
+        // flush if needed
         self.flush_if_needed();
 
+        self.stats.record_since(Histogram::Write, start);
+        Ok(())
+    }
+
+    // bumps the write counters for a record that made it past the log
+    fn count_write(&self, record: &WalRecord) {
+        self.stats.add(Ticker::Writes, 1);
+        let mut bytes = 0;
+        for entry in &record.entries {
+            let ticker = match entry.value_type {
+                ValueType::Delete => Ticker::Deletes,
+                ValueType::Merge => Ticker::Merges,
+                ValueType::Put | ValueType::ExpiringPut | ValueType::BlobIndex => Ticker::Puts,
+            };
+            self.stats.add(ticker, 1);
+            bytes += entry.key.len() + entry.val.len();
+        }
+        for (_, start, end) in &record.range_deletes {
+            self.stats.add(Ticker::RangeDeletes, 1);
+            bytes += start.len() + end.len();
+        }
+        self.stats.add(Ticker::BytesWritten, bytes as u64);
+    }
+
+    fn log_write(&self, segment: u64, record: &WalRecord, mode: WalMode) -> Result<()> {
+        if mode == WalMode::Disabled {
+            // not logged, but the subscribers still get to see it
+            if self.subscribers.load(Ordering::Acquire) > 0 {
+                let _ = self.wal_sender.send(WalMessage::Publish(record.clone()));
+            }
+            return Ok(());
+        }
+
+        if mode == WalMode::Async {
+            // an earlier asynchronous write may not have made it to the log, this one isn't
+            // applied so the caller finds out
+            if let Some(e) = self.wal_error.lock().take() {
+                return Err(e.into());
+            }
+        }
+        self.wal_sender
+            .send(WalMessage::Append(segment, record.clone(), mode))
+            .map_err(|_| DbError::Other("wal thread is not running".to_string()))?;
+        if mode == WalMode::Async {
+            return Ok(());
+        }
+
+        // the Sync request queues up behind our Append, so the fsync that answers it covers our
+        // record along with everything other writers appended in the meantime
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        self.wal_sender
+            .send(WalMessage::Sync(segment, ack_tx))
+            .map_err(|_| DbError::Other("wal thread is not running".to_string()))?;
+        ack_rx
+            .recv()
+            .map_err(|_| DbError::Other("wal thread exited before syncing".to_string()))??;
         Ok(())
     }
 
@@ -212,105 +987,243 @@ impl Db {
     // then check the 2 immutable memtable
     // if not found then fallback to SSTs
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        //  mutable memtable
-        let memtable = self.memtable.load();
-        if let Some(val) = memtable.get(key) {
-            if val.is_empty() {
-                return Ok(None);
-            }
-            return Ok(Some(val));
+        self.lookup(&self.default_family, key, u64::MAX)
+    }
+
+    // the value of the key as of the snapshot
+    pub fn get_seq(&self, key: &[u8], snapshot: &Snapshot) -> Result<Option<Vec<u8>>> {
+        self.lookup(&self.default_family, key, snapshot.sequence())
+    }
+
+    // the value of the key in a column family
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.check_live(cf)?;
+        self.lookup(cf, key, u64::MAX)
+    }
+
+    // a point lookup, counted and timed
+    fn lookup(&self, cf: &ColumnFamily, key: &[u8], seq: u64) -> Result<Option<Vec<u8>>> {
+        let start = Instant::now();
+        let value = self.find_value(cf, key, seq)?;
+        self.stats.add(Ticker::Gets, 1);
+        if let Some(value) = &value {
+            self.stats.add(Ticker::BytesRead, value.len() as u64);
         }
+        self.stats.record_since(Histogram::Get, start);
+        Ok(value)
+    }
 
-        //  immutable memtables
-        let immutables = self.immutable_memtables.load();
-        for mt in immutables.iter().rev() {
-            if let Some(val) = mt.get(key) {
-                if val.is_empty() {
-                    return Ok(None);
-                }
-                return Ok(Some(val));
+    // sources are checked from newest to oldest, the first one that has a version of the key
+    // decides: either it's a value or a tombstone, in which case older sources must not be
+    // consulted. range tombstones seen on the way delete every older version, so the version
+    // found is only visible if it's at least as new as the newest of them. a merge operand
+    // doesn't decide on its own, see lookup_merged
+    fn find_value(&self, cf: &ColumnFamily, key: &[u8], seq: u64) -> Result<Option<Vec<u8>>> {
+        let mut deleted_before = 0;
+        // an expired version reads as deleted
+        let now = self.opts.clock.now_millis();
+        // the type and value of a version that isn't deleted, a table resolves a blob pointer
+        let visible = |(version_seq, value_type, val): (u64, ValueType, Vec<u8>),
+                       deleted_before: u64| {
+            (version_seq >= deleted_before
+                && value_type != ValueType::Delete
+                && !is_expired(value_type, &val, now))
+            .then_some((value_type, val))
+        };
+
+        //  mutable memtable, then the immutable memtables newest first
+        for mt in self.family_memtables(cf.id()) {
+            deleted_before = deleted_before.max(mt.covering_seq(key, seq));
+            if let Some(version) = mt.lookup_version(key, seq) {
+                self.stats.add(Ticker::MemtableHits, 1);
+                return match visible(version, deleted_before) {
+                    Some((ValueType::Merge, _)) => self.lookup_merged(cf, key, seq),
+                    Some((value_type, val)) => Ok(Some(inline_value(value_type, val))),
+                    None => Ok(None),
+                };
             }
         }
+        self.stats.add(Ticker::MemtableMisses, 1);
 
-        //  sstables
-        let sstables = self.sstables.load();
+        //  sstables, tables whose key range doesn't cover the key or whose data is all older
+        //  than a range tombstone seen already can be skipped right away
+        let sstables = cf.sstables.load();
         for sst in sstables.iter() {
-            match sst.get(key) {
-                Ok(Some(val)) => {
-                    if val.is_empty() {
-                        return Ok(None);
-                    }
-                    return Ok(Some(val));
-                }
-                Ok(None) => continue,
-                Err(e) => return Err(DbError::SST(e)),
+            if !sst.may_contain_key(key) || sst.max_sequence() < deleted_before {
+                continue;
+            }
+            self.stats.add(Ticker::SstProbes, 1);
+            deleted_before = deleted_before.max(sst.covering_seq(key, seq));
+            if let Some(version) = sst.lookup_version_counted(key, seq, Some(&self.stats))? {
+                return match visible(version, deleted_before) {
+                    Some((ValueType::Merge, _)) => self.lookup_merged(cf, key, seq),
+                    Some((value_type, val)) => Ok(Some(sst.resolve_value(value_type, val)?)),
+                    None => Ok(None),
+                };
             }
         }
 
         Ok(None)
     }
 
-    pub fn get_seq(&self, key: &[u8], seq: u64) -> Result<Option<Vec<u8>>> {
-        let memtable = self.memtable.load();
+    // the value of a key whose newest version is a merge operand: the operands down to the first
+    // version that isn't one, folded on top of it
+    //
+    // writers racing a memtable switch may leave a version in an older memtable than a version
+    // written after it, so the versions of every source are gathered and put in seq order first.
+    // a source is only read down to its first version that isn't an operand, and a version both
+    // in a memtable and in the table it was flushed to only counts once
+    fn lookup_merged(&self, cf: &ColumnFamily, key: &[u8], seq: u64) -> Result<Option<Vec<u8>>> {
+        let memtables = self.family_memtables(cf.id());
+        let sstables = cf.sstables.load();
 
-        if let Some(val) = memtable.get_seq(key, seq) {
-            if val.is_empty() {
-                return Ok(None);
+        let mut deleted_before = 0;
+        // seq, type and value of a version, along with the table it comes from
+        let mut versions = Vec::new();
+        for mt in &memtables {
+            deleted_before = deleted_before.max(mt.covering_seq(key, seq));
+            let mut below = seq;
+            while let Some((version_seq, value_type, val)) = mt.lookup_version(key, below) {
+                below = version_seq;
+                versions.push((version_seq, value_type, val, None));
+                if value_type != ValueType::Merge {
+                    break;
+                }
             }
-            return Ok(Some(val));
         }
-
-        let immutables = self.immutable_memtables.load();
-        for imt in immutables.iter().rev() {
-            if let Some(val) = imt.get_seq(key, seq) {
-                if val.is_empty() {
-                    return Ok(None);
+        for sst in sstables.iter() {
+            if !sst.may_contain_key(key) || sst.max_sequence() < deleted_before {
+                continue;
+            }
+            deleted_before = deleted_before.max(sst.covering_seq(key, seq));
+            let mut below = seq;
+            while let Some((version_seq, value_type, val)) = sst.lookup_version(key, below)? {
+                below = version_seq;
+                versions.push((version_seq, value_type, val, Some(sst)));
+                if value_type != ValueType::Merge {
+                    break;
                 }
-                return Ok(Some(val));
             }
         }
+        versions.sort_by_key(|(version_seq, _, _, _)| std::cmp::Reverse(*version_seq));
+        versions.dedup_by_key(|(version_seq, _, _, _)| *version_seq);
 
-        let ssts = self.sstables.load();
-
-        for sst in ssts.iter() {
-            if let Some(val) = sst.get_seq(key, seq)? {
-                if val.is_empty() {
-                    return Ok(None);
-                }
-                return Ok(Some(val));
+        let now = self.opts.clock.now_millis();
+        let mut operands = Vec::new();
+        let mut existing = None;
+        for (version_seq, value_type, val, sst) in versions {
+            if version_seq < deleted_before
+                || value_type == ValueType::Delete
+                || is_expired(value_type, &val, now)
+            {
+                break;
+            }
+            if value_type == ValueType::Merge {
+                operands.push(val);
+                continue;
             }
+            existing = Some(match sst {
+                Some(sst) => sst.resolve_value(value_type, val)?,
+                None => inline_value(value_type, val),
+            });
+            break;
         }
+        self.fold_operands(cf, key, existing, operands)
+    }
 
-        Ok(None)
+    // the value of the key once the operands, newest first, are folded on top of the value below
+    // them
+    fn fold_operands(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        existing: Option<Vec<u8>>,
+        mut operands: Vec<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        if operands.is_empty() {
+            return Ok(existing);
+        }
+        let operator = cf.opts.merge_operator.as_ref().ok_or_else(|| {
+            DbError::InvalidOptions(
+                "the key holds merge operands but no merge operator is set".to_string(),
+            )
+        })?;
+        operands.reverse();
+        match operator.full_merge(key, existing.as_deref(), &operands) {
+            Some(value) => Ok(Some(value)),
+            None => Err(DbError::Other(format!(
+                "merge operator {} can't apply the operands of the key",
+                operator.name()
+            ))),
+        }
     }
 
-    // deletion is not on spot, rather its like putting a tombstone (i.e. emtpy value) to that
-    // particular key, after compaction the old entries with some value are removed, also the
-    // emtpy value entry is also removed
+    // deletion is not on spot, rather its like putting a tombstone (i.e. a Delete version) to
+    // that particular key, after compaction the old entries with some value are removed, also the
+    // tombstone is also removed
     pub fn del(&self, key: &[u8]) -> Result<()> {
-        self.put(key, &[])
+        self.del_with(key, &WriteOptions::default())
+    }
+
+    pub fn del_with(&self, key: &[u8], opts: &WriteOptions) -> Result<()> {
+        let mode = opts.wal_mode.unwrap_or(self.opts.wal_mode);
+        self.apply(single_entry(0, key, ValueType::Delete, &[]), None, mode)
+    }
+
+    // delete from a column family
+    pub fn del_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.check_live(cf)?;
+        self.apply(
+            single_entry(cf.id(), key, ValueType::Delete, &[]),
+            None,
+            self.opts.wal_mode,
+        )
     }
 
     // deletion with a particular seq
     // used in transactions
     pub fn del_seq(&self, key: &[u8], seq: u64) -> Result<()> {
-        self.put_seq(key, &[], seq)
+        self.apply(
+            single_entry(0, key, ValueType::Delete, &[]),
+            Some(seq),
+            self.opts.wal_mode,
+        )
     }
 
     pub fn flush_if_needed(&self) {
-        let memtable = self.memtable.load();
         // memtables are configured to be of a certain max size to cap the memory usage after that
         // limit is reached the memtables should be freezed and pushed to the flush queue which
-        // will then write the memtable to sst file
-        let should_flush = memtable.size_bytes() >= MEMTABLE_SIZE_THRESHOLD;
+        // will then write the memtable to sst file. once the memtable of any column family is
+        // full the memtables of all of them are frozen, they share the WAL segment
+        if !memtables_full(&self.memtables.load(), &self.families.load()) {
+            return;
+        }
 
-        if should_flush {
-            // replace the memtable with a new empty one so that writes don't have to wait until
-            // the memtable is being flushed to the file sys
-            let new_memtable = Arc::new(Memtable::new());
-            let old_memtable = self.memtable.swap(new_memtable);
+        // a writer that has to wait for another one to freeze the memtables is stalled
+        let _switch = match self.switch_lock.try_lock() {
+            Some(guard) => guard,
+            None => {
+                let start = Instant::now();
+                let guard = self.switch_lock.lock();
+                self.stats.add(Ticker::WriteStalls, 1);
+                self.stats
+                    .add(Ticker::WriteStallMicros, start.elapsed().as_micros() as u64);
+                guard
+            }
+        };
+        // another writer may have frozen them while we were waiting
+        if memtables_full(&self.memtables.load(), &self.families.load()) {
+            // replace the memtables with new empty ones so that writes don't have to wait until
+            // the memtables are being flushed to the file sys, the new ones log into a new WAL
+            // segment
+            let segment = self.next_wal_segment.fetch_add(1, Ordering::SeqCst);
+            let new_memtables = Arc::new(self.memtables.load().fresh(segment));
+            let old_memtables = {
+                let _writers = self.rotation_lock.write();
+                self.memtables.swap(new_memtables)
+            };
 
-            if !old_memtable.is_empty() {
+            if !old_memtables.is_empty() {
                 loop {
                     // keep up to 2 immutable memtables in memory. When a 3rd one is created,
                     // send the oldest to the flush queue. The memtable will remain in the list
@@ -319,15 +1232,19 @@ impl Db {
                     // this ensures data is always available during async flush operations.
                     let current = self.immutable_memtables.load();
                     let mut new_immutables = (**current).clone();
-                    new_immutables.push(old_memtable.clone());
+                    new_immutables.push(old_memtables.clone());
 
                     // only send to flush if we have more than 2 immutable memtables
                     // but dont' remove it from the list yet,
                     // its the job of the flush worker
                     // after successful flushing and creation on SSTable, flush worker will remove
                     // the immutable memtable that just got flushed from the memory
+                    // the one sent is the memtable that just became the 3rd newest, the oldest may
+                    // still be in the list because its flush hasn't finished yet and must not be
+                    // flushed twice, two tables holding the same merge operands would fold them
+                    // twice
                     let should_flush = if new_immutables.len() > 2 {
-                        Some(new_immutables[0].clone()) // send the oldest to flush
+                        Some(new_immutables[new_immutables.len() - 3].clone())
                     } else {
                         None
                     };
@@ -345,38 +1262,119 @@ impl Db {
                         break;
                     }
                 }
+            } else {
+                // nothing to flush, a checkpoint doesn't need to copy the segment either
+                self.manifest.segment_flushed(old_memtables.segment());
             }
 
-            // at a moment only certain number of sstables are allowed after reaching that limit
-            // the sstables are sent for compaction, where they are merged into one big sstable
-            // removing all the duplicates, tombstones
-            let sst_count = self.sstables.load().len();
-            if sst_count >= MAX_SSTABLES {
+            // level 0 may only hold a certain number of sstables and every deeper level has a
+            // size target, once any of them is exceeded the compaction worker pushes data down
+            // to the next level, removing duplicates and tombstones on the way
+            let families = self.families.load();
+            if families
+                .values()
+                .any(|family| needs_compaction(&family.sstables.load(), &family.opts))
+            {
                 let _ = self.compaction_sender.send(CompactionMessage::Compact);
             }
         }
     }
 
     pub fn scan(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> DbIterator {
-        let memtable = Arc::clone(&self.memtable.load());
-        let immutables = (**self.immutable_memtables.load()).clone();
-        let sstables = (**self.sstables.load()).clone();
+        // the memtables are walked lazily, pinning the sequence keeps writes made while the scan is
+        // running out of it, and the ones still in flight when it starts
+        let seq = self.visible_sequence();
+        self.scan_family(&self.default_family, start, end, seq, None)
+    }
 
-        let start_bound = start.map(|s| s.to_vec());
-        let end_bound = end.map(|e| e.to_vec());
+    // the same scan walked from the last key down to the first
+    pub fn scan_rev(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> DbRevIterator {
+        DbRevIterator::new(self.scan(start, end))
+    }
 
-        DbIterator::new(memtable, immutables, sstables, start_bound, end_bound)
+    pub fn scan_seq(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> DbIterator {
+        self.scan_family(&self.default_family, start, end, snapshot.sequence(), None)
     }
 
-    pub fn scan_seq(&self, start: Option<&[u8]>, end: Option<&[u8]>, seq: u64) -> DbIterator {
-        let memtable = Arc::clone(&self.memtable.load());
-        let immutables = (**self.immutable_memtables.load()).clone();
-        let sstables = (**self.sstables.load()).clone();
+    // scan of a column family
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<DbIterator> {
+        self.check_live(cf)?;
+        let seq = self.visible_sequence();
+        Ok(self.scan_family(cf, start, end, seq, None))
+    }
+
+    // every key starting with the prefix, the scan ends right behind the last one. the tables
+    // that can't hold the prefix are skipped, by key range and, if the database has a prefix
+    // extractor, by their prefix filter. see `DbOptions::prefix_extractor`
+    pub fn scan_prefix(&self, prefix: &[u8]) -> DbIterator {
+        let seq = self.visible_sequence();
+        let end = prefix_end(prefix);
+        self.scan_family(
+            &self.default_family,
+            Some(prefix),
+            end.as_deref(),
+            seq,
+            Some(prefix),
+        )
+    }
+
+    // prefix scan of a column family, the family's own prefix extractor decides which tables are
+    // skipped
+    pub fn scan_prefix_cf(&self, cf: &ColumnFamily, prefix: &[u8]) -> Result<DbIterator> {
+        self.check_live(cf)?;
+        let seq = self.visible_sequence();
+        let end = prefix_end(prefix);
+        Ok(self.scan_family(cf, Some(prefix), end.as_deref(), seq, Some(prefix)))
+    }
+
+    // scan of the family between the bounds, a prefix leaves out the tables that can't hold it
+    fn scan_family(
+        &self,
+        cf: &ColumnFamily,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        seq: u64,
+        prefix: Option<&[u8]>,
+    ) -> DbIterator {
+        let mut memtables = self.family_memtables(cf.id());
+        // a family dropped since has no memtable anymore, it reads as empty
+        let memtable = if memtables.is_empty() {
+            Arc::new(Memtable::new())
+        } else {
+            memtables.remove(0)
+        };
+        // the iterator wants the immutable memtables oldest first
+        memtables.reverse();
+        let mut sstables = (**cf.sstables.load()).clone();
+        if let Some(prefix) = prefix {
+            let extractor = cf.opts.prefix_extractor.as_deref();
+            sstables.retain(|table| table.may_contain_prefix(prefix, extractor));
+        }
 
         let start_bound = start.map(|s| s.to_vec());
         let end_bound = end.map(|e| e.to_vec());
 
-        DbIterator::new_with_seq(memtable, immutables, sstables, start_bound, end_bound, Some(seq))
+        let mut iter = DbIterator::new_with_seq(
+            memtable,
+            memtables,
+            sstables,
+            start_bound,
+            end_bound,
+            Some(seq),
+        );
+        iter.set_now(self.opts.clock.now_millis());
+        iter.set_merge_operator(cf.opts.merge_operator.clone());
+        iter
     }
 }
 
@@ -385,43 +1383,33 @@ impl Db {
 // that no data is lost during shutdown
 impl Drop for Db {
     fn drop(&mut self) {
-        let remaining_mt = self.memtable.load_full();
-
-        if !remaining_mt.is_empty() {
-            // flush the mutable memtable with whatever data it has
-            let _ = flush_memtable_to_disk(
-                &remaining_mt,
-                &self.dir,
-                &self.sstables,
-                &self.next_sst_id,
-                self.wal_sender.clone(),
-            );
+        // level 0 tables are ordered by id so the newest data has to get the highest one,
+        // otherwise a range tombstone in the mutable memtable would end up looking older than the
+        // data it deletes. so the flush worker finishes what it has queued first, then the
+        // memtables that are left are flushed oldest first
+        let _ = self.flush_sender.send(FlushMessage::Shutdown);
+        if let Some(handle) = self.flush_thread.take() {
+            let _ = handle.join();
         }
 
         let immutable = self.immutable_memtables.load_full();
 
-        for mt in immutable.iter() {
-            // flush all the immutable memtables to the disk
-            if !mt.is_empty() {
-                let _ = flush_memtable_to_disk(
-                    mt,
-                    &self.dir,
-                    &self.sstables,
-                    &self.next_sst_id,
-                    self.wal_sender.clone(),
-                );
+        let remaining = self.memtables.load_full();
+        let families = self.families.load();
+
+        // flush all the immutable memtables and then the mutable ones with whatever data they
+        // have, a segment is only retired once its memtables made it into tables
+        for set in immutable.iter().chain(std::iter::once(&remaining)) {
+            if flush_memtables(set, &families, &self.manifest).is_ok() {
+                let _ = self.wal_sender.send(WalMessage::Retire(set.segment()));
             }
         }
 
-        // stop the flush and compaction workers
-        let _ = self.flush_sender.send(FlushMessage::Shutdown);
+        // stop the compaction worker and the WAL thread
         let _ = self.compaction_sender.send(CompactionMessage::Shutdown);
         let _ = self.wal_sender.send(WalMessage::Shutdown);
 
         // join the background threads
-        if let Some(handle) = self.flush_thread.take() {
-            let _ = handle.join();
-        }
         if let Some(handle) = self.compaction_thread.take() {
             let _ = handle.join();
         }
@@ -430,3 +1418,67 @@ impl Drop for Db {
         }
     }
 }
+
+// whether the memtable of any column family reached the size its family flushes at
+fn memtables_full(memtables: &MemtableSet, families: &FamilyMap) -> bool {
+    memtables.iter().any(|(cf, memtable)| {
+        families
+            .get(&cf)
+            .is_some_and(|family| memtable.size_bytes() >= family.opts.memtable_size_threshold)
+    })
+}
+
+// the record of a single write, `apply` gives it its sequence number
+fn single_entry(cf: u32, key: &[u8], value_type: ValueType, val: &[u8]) -> WalRecord {
+    WalRecord {
+        seq: 0,
+        entries: vec![WalEntry {
+            cf,
+            key: key.to_vec(),
+            value_type,
+            val: val.to_vec(),
+        }],
+        range_deletes: Vec::new(),
+    }
+}
+
+// turns the batch into the record logged for it: the final version of every key it touches plus
+// its range deletes. everything shares one seq, given by `apply`, and a range
+// tombstone only hides older versions, so the keys the batch wrote inside a range before
+// deleting it are dropped here while the ones it writes after survive
+fn resolve_batch(batch: WriteBatch) -> WalRecord {
+    // keyed by column family and key
+    let mut resolved: BTreeMap<(u32, Vec<u8>), (ValueType, Vec<u8>)> = BTreeMap::new();
+    let mut range_deletes = Vec::new();
+    for op in batch.into_ops() {
+        match op {
+            BatchOp::Put { cf, key, val } => {
+                resolved.insert((cf, key), (ValueType::Put, val));
+            }
+            BatchOp::Delete { cf, key } => {
+                resolved.insert((cf, key), (ValueType::Delete, Vec::new()));
+            }
+            BatchOp::DeleteRange { cf, start, end } => {
+                if start >= end {
+                    continue;
+                }
+                resolved.retain(|(key_cf, key), _| *key_cf != cf || key < &start || key >= &end);
+                range_deletes.push((cf, start, end));
+            }
+        }
+    }
+
+    WalRecord {
+        seq: 0,
+        entries: resolved
+            .into_iter()
+            .map(|((cf, key), (value_type, val))| WalEntry {
+                cf,
+                key,
+                value_type,
+                val,
+            })
+            .collect(),
+        range_deletes,
+    }
+}
@@ -0,0 +1,128 @@
+// column families split the database into keyspaces that share the WAL and the sequence numbers
+// but nothing else: every family has memtables, sstables and options of its own, so the data of
+// one family is never compacted together with the data of another one, and a family is dropped
+// with a single manifest edit however much data it holds
+//
+// a `WriteBatch` may write to several families, it's still logged as a single WAL record under a
+// single sequence number. for that the memtables of all the families are frozen together (see
+// MemtableSet), they always log into the same WAL segment and the segment can go once all of
+// them are flushed
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::config::DbOptions;
+use crate::manifest::TableSet;
+use crate::memtable::Memtable;
+
+/// name of the column family every database has, the plain `Db` methods read and write it
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+// the live column families keyed by id
+pub(crate) type FamilyMap = BTreeMap<u32, Arc<ColumnFamily>>;
+
+/// handle of a column family, see `Db::create_column_family`
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+    pub(crate) opts: Arc<DbOptions>,
+    // shared with the manifest, which swaps in the new table list after every flush and
+    // compaction of the family
+    pub(crate) sstables: TableSet,
+    dropped: AtomicBool,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: &str, opts: Arc<DbOptions>, sstables: TableSet) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            opts,
+            sstables,
+            dropped: AtomicBool::new(false),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn options(&self) -> &DbOptions {
+        &self.opts
+    }
+
+    /// whether the family was dropped, every read and write through the handle fails from then on
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn mark_dropped(&self) {
+        self.dropped.store(true, Ordering::Release);
+    }
+}
+
+// one memtable per column family, all of them logging into the same WAL segment. a set is frozen
+// and flushed as a whole, the families that weren't written in the meantime just have nothing to
+// flush
+pub(crate) struct MemtableSet {
+    segment: u64,
+    memtables: BTreeMap<u32, Arc<Memtable>>,
+}
+
+impl MemtableSet {
+    pub(crate) fn new(segment: u64, families: impl IntoIterator<Item = u32>) -> Self {
+        Self {
+            segment,
+            memtables: families
+                .into_iter()
+                .map(|cf| (cf, Arc::new(Memtable::with_wal_segment(segment))))
+                .collect(),
+        }
+    }
+
+    // empty memtables for the same families, logging into the given segment
+    pub(crate) fn fresh(&self, segment: u64) -> Self {
+        Self::new(segment, self.memtables.keys().copied())
+    }
+
+    // the same memtables plus an empty one for a new family
+    pub(crate) fn with_family(&self, cf: u32) -> Self {
+        let mut memtables = self.memtables.clone();
+        memtables.insert(cf, Arc::new(Memtable::with_wal_segment(self.segment)));
+        Self {
+            segment: self.segment,
+            memtables,
+        }
+    }
+
+    // the same memtables without the one of a dropped family
+    pub(crate) fn without_family(&self, cf: u32) -> Self {
+        let mut memtables = self.memtables.clone();
+        memtables.remove(&cf);
+        Self {
+            segment: self.segment,
+            memtables,
+        }
+    }
+
+    pub(crate) fn segment(&self) -> u64 {
+        self.segment
+    }
+
+    pub(crate) fn get(&self, cf: u32) -> Option<&Arc<Memtable>> {
+        self.memtables.get(&cf)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u32, &Arc<Memtable>)> {
+        self.memtables.iter().map(|(cf, memtable)| (*cf, memtable))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.memtables.values().all(|memtable| memtable.is_empty())
+    }
+}
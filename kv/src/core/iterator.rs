@@ -2,17 +2,33 @@
 // sources: mutable memtable (highest priority), immutable memtable(s) (second highest priority)
 // and the SSTables (least priority)
 //
+// every source is read lazily, only the entry at the head of each source is held in memory, and
+// `seek` repositions all of them at once
+//
+// the iterator is a cursor that sits between two keys and moves either way, `next` returns the
+// key after it and `prev` the key before it. the sources only walk one way at a time, so a change
+// of direction repositions all of them around the cursor
+//
+use super::clock::{Clock, SystemClock};
 use crate::{
     memtable::{skipmap::VersionedKey, Memtable},
+    merge::MergeOperator,
     sst::{SSTIterator, SSTReader},
+    types::{covering_seq, inline_value, is_expired, RangeTombstone, ValueType},
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    ops::Bound,
+    sync::Arc,
 };
-use std::{cmp::Ordering, collections::BinaryHeap, sync::Arc};
 
 #[derive(Clone, Debug)]
 pub struct IterEntry {
     key: Vec<u8>,
     value: Vec<u8>,
     seq: u64,
+    value_type: ValueType,
     priority: usize, // determines source order (mem → immut → sst)
 }
 
@@ -49,6 +65,45 @@ impl std::cmp::PartialEq for IterEntry {
 
 impl std::cmp::Eq for IterEntry {}
 
+// an entry in the heap of a backwards walk, the biggest key is on top and among equal keys the
+// newest version. a source hands out the versions of a key oldest first when walking backwards,
+// so the newest version is only known once the whole key is drained
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RevEntry(IterEntry);
+
+impl std::cmp::Ord for RevEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .key
+            .cmp(&other.0.key)
+            .then(self.0.seq.cmp(&other.0.seq))
+            .then(self.0.priority.cmp(&other.0.priority))
+    }
+}
+
+impl std::cmp::PartialOrd for RevEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Forward,
+    Reverse,
+}
+
+// where a cursor sits, a change of direction picks up from here
+#[derive(Clone, Debug)]
+pub(crate) enum Gap {
+    // in front of the first key of the scan
+    Start,
+    Before(Vec<u8>),
+    After(Vec<u8>),
+    // behind the last key of the scan
+    End,
+}
+
 // we have two types of source for each entry
 // it can be SSTable or Memtable (immutable or mutable)
 // each source will be given some priority based on their age
@@ -59,8 +114,10 @@ impl std::cmp::Eq for IterEntry {}
 // least priority will be given to SST -> newest will have more than oldest
 enum IterSource {
     Memtable {
-        data: Vec<(VersionedKey, Vec<u8>)>,
-        pos: usize,
+        memtable: Arc<Memtable>,
+        // where the next entry is looked up, right past the last entry returned in the direction
+        // of the walk
+        cursor: Bound<VersionedKey>,
         priority: usize,
     },
     Sst {
@@ -70,14 +127,34 @@ enum IterSource {
     },
 }
 
+impl IterSource {
+    fn priority(&self) -> usize {
+        match self {
+            IterSource::Memtable { priority, .. } => *priority,
+            IterSource::Sst { priority, .. } => *priority,
+        }
+    }
+}
+
 pub struct DbIterator {
     heap: BinaryHeap<IterEntry>,
+    // only one of the heaps is filled, depending on the direction
+    rev_heap: BinaryHeap<RevEntry>,
+    direction: Direction,
+    gap: Gap,
     sources: Vec<IterSource>,
     last_key: Option<Vec<u8>>,
     start_bound: Option<Vec<u8>>,
     end_bound: Option<Vec<u8>>,
     max_seq: Option<u64>, // for snapshot isolation, we should only see the values with seq less
-                          // than this
+    // than this
+    // range tombstones of every source that are visible and intersect the bounds
+    range_tombstones: Vec<RangeTombstone>,
+    // values written with a TTL that expired by this time (ms since the unix epoch) are skipped
+    now: u64,
+    // folds the versions of a key whose newest version is a merge operand, without one such a
+    // key is skipped
+    merge_operator: Option<Arc<dyn MergeOperator>>,
 }
 
 impl DbIterator {
@@ -107,93 +184,258 @@ impl DbIterator {
         max_seq: Option<u64>,
     ) -> Self {
         let mut sources = Vec::new();
-        let mut heap = BinaryHeap::new();
+
+        // range tombstones apply across sources, a tombstone in a newer source deletes the
+        // versions an older source holds, so they are gathered up front
+        let start = start_bound.as_deref().unwrap_or(&[]);
+        let range_tombstones = std::iter::once(memtable.range_tombstones())
+            .chain(immutable_memtable.iter().map(|imt| imt.range_tombstones()))
+            .chain(sstables.iter().map(|sst| sst.range_tombstones().to_vec()))
+            .flatten()
+            .filter(|t| max_seq.is_none_or(|max| t.seq < max))
+            .filter(|t| t.overlaps(start, end_bound.as_deref()))
+            .collect();
 
         // memtable priority will be highest
         // i.e. number of sstables + number of immutable memtables
         let memtable_priority = immutable_memtable.len() + sstables.len();
 
-        // collect the data stored in the mutable memtable
-        // iter is implemented for memtable, see /storage/memtable.rs
-        // skipmap iterator is already sorted, no need to sort again
-        let memtable_data: Vec<(VersionedKey, Vec<u8>)> = memtable.iter().collect();
-
-        // add the memtable source in sources
+        // add the mutable memtable in sources, skipmap is already sorted so it's walked in order
+        // right from the skiplist, see memtable/skipmap.rs
         sources.push(IterSource::Memtable {
-            data: memtable_data,
-            pos: 0,
+            memtable,
+            cursor: Bound::Unbounded,
             priority: memtable_priority,
         });
 
-        // collect the data stored in the immutable memtable(s)
-        for (i, imt) in immutable_memtable.iter().enumerate() {
-            let priority = memtable_priority - 1 - i;
-
-            let imt_data: Vec<(VersionedKey, Vec<u8>)> = imt.iter().collect();
-
-            // add immutable_memtables to sources
+        // the immutable memtable(s) are ordered oldest first, so the newest immutable memtable
+        // gets the highest priority right below the mutable one
+        for (i, imt) in immutable_memtable.into_iter().enumerate() {
             sources.push(IterSource::Memtable {
-                data: imt_data,
-                pos: 0,
-                priority,
+                memtable: imt,
+                cursor: Bound::Unbounded,
+                priority: sstables.len() + i,
             });
         }
 
-        for (i, sst) in sstables.iter().enumerate() {
-            let priority = if !immutable_memtable.is_empty() {
-                immutable_memtable.len() - i - 1
-            } else {
-                memtable_priority - i - 1
-            };
-            let iter = SSTIterator::new(sst.clone());
+        // sstables are in lookup order, i.e. newest data first
+        let num_sstables = sstables.len();
+        for (i, sst) in sstables.into_iter().enumerate() {
+            let priority = num_sstables - 1 - i;
+            let iter = SSTIterator::new(sst);
             sources.push(IterSource::Sst { iter, priority });
         }
 
-        // preload one entry from each source
-        for i in 0..sources.len() {
-            if let Some(entry) =
-                Self::advance_source(&mut sources, i, &start_bound, &end_bound, &max_seq)
-            {
-                heap.push(entry);
-            }
-        }
-
-        Self {
-            heap,
+        let mut iter = Self {
+            heap: BinaryHeap::new(),
+            rev_heap: BinaryHeap::new(),
+            direction: Direction::Forward,
+            gap: Gap::Start,
             sources,
             last_key: None,
             start_bound,
             end_bound,
             max_seq,
+            range_tombstones,
+            now: SystemClock.now_millis(),
+            merge_operator: None,
+        };
+        iter.seek_to_first();
+        iter
+    }
+
+    // the time values with a TTL are checked against, the wall clock unless the db has a clock of
+    // its own, see DbOptions::clock
+    pub(crate) fn set_now(&mut self, now: u64) {
+        self.now = now;
+    }
+
+    pub(crate) fn set_merge_operator(&mut self, operator: Option<Arc<dyn MergeOperator>>) {
+        self.merge_operator = operator;
+    }
+
+    /// positions the iterator in front of the first key >= key, a key before the start bound of
+    /// the scan seeks to the start bound
+    pub fn seek(&mut self, key: &[u8]) {
+        self.reposition(Direction::Forward, Gap::Before(key.to_vec()));
+    }
+
+    /// positions the iterator back in front of the first key of the scan
+    pub fn seek_to_first(&mut self) {
+        self.reposition(Direction::Forward, Gap::Start);
+    }
+
+    /// positions the iterator behind the last key <= key, so `prev` returns that key first. a
+    /// key past the end bound of the scan seeks to the end bound
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        self.reposition(Direction::Reverse, Gap::After(key.to_vec()));
+    }
+
+    /// positions the iterator behind the last key of the scan
+    pub fn seek_to_last(&mut self) {
+        self.reposition(Direction::Reverse, Gap::End);
+    }
+
+    /// the key before the cursor, the cursor moves in front of it. a `next` right after returns
+    /// the same key again
+    pub fn prev(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        if self.direction == Direction::Forward {
+            self.reposition(Direction::Reverse, self.gap.clone());
+        }
+
+        loop {
+            let Some(RevEntry(entry)) = self.rev_heap.pop() else {
+                self.gap = Gap::Start;
+                return None;
+            };
+            self.refill(entry.priority);
+
+            // the sources hand out the versions of a key oldest first, the whole key is drained
+            // to find the newest version
+            let mut versions = vec![entry];
+            while self
+                .rev_heap
+                .peek()
+                .is_some_and(|top| top.0.key == versions[0].key)
+            {
+                let RevEntry(older) = self.rev_heap.pop().unwrap();
+                self.refill(older.priority);
+                versions.push(older);
+            }
+            versions.sort_by_key(|v| Reverse((v.seq, v.priority)));
+            let entry = versions.remove(0);
+
+            if let Some(item) = self.resolve(entry, versions) {
+                self.gap = Gap::Before(item.0.clone());
+                return Some(item);
+            }
+        }
+    }
+
+    // puts every source right at the gap, walking in the given direction. a gap right after a key
+    // in the direction of the walk starts at the key and skips it as if it was just returned
+    pub(crate) fn reposition(&mut self, direction: Direction, gap: Gap) {
+        self.direction = direction;
+        self.gap = gap.clone();
+        self.heap.clear();
+        self.rev_heap.clear();
+        self.last_key = None;
+
+        let target = match (direction, gap) {
+            (Direction::Forward, Gap::Start) => self.start_bound.clone(),
+            (Direction::Forward, Gap::Before(key)) => Some(key),
+            (Direction::Forward, Gap::After(key)) => {
+                self.last_key = Some(key.clone());
+                Some(key)
+            }
+            (Direction::Reverse, Gap::End) => None,
+            (Direction::Reverse, Gap::After(key)) => Some(key),
+            (Direction::Reverse, Gap::Before(key)) => {
+                self.last_key = Some(key.clone());
+                Some(key)
+            }
+            // nothing left in that direction
+            (Direction::Forward, Gap::End) | (Direction::Reverse, Gap::Start) => return,
+        };
+
+        // the bounds of the scan clamp the target, keys beyond them are filtered out anyway
+        let target = match (direction, target) {
+            (Direction::Forward, Some(key)) => match &self.start_bound {
+                Some(start) if start > &key => Some(start.clone()),
+                _ => Some(key),
+            },
+            (Direction::Reverse, Some(key)) => match &self.end_bound {
+                Some(end) if end < &key => Some(end.clone()),
+                _ => Some(key),
+            },
+            (_, None) => None,
+        };
+
+        for source in self.sources.iter_mut() {
+            match source {
+                // versions are ordered newest first, so u64::MAX puts the cursor in front of all
+                // the versions of the target and 0 behind them
+                IterSource::Memtable { cursor, .. } => {
+                    *cursor = match (&target, direction) {
+                        (Some(key), Direction::Forward) => Bound::Included(VersionedKey {
+                            key: key.clone(),
+                            seq: u64::MAX,
+                        }),
+                        (Some(key), Direction::Reverse) => Bound::Included(VersionedKey {
+                            key: key.clone(),
+                            seq: 0,
+                        }),
+                        (None, _) => Bound::Unbounded,
+                    };
+                }
+                IterSource::Sst { iter, .. } => match (&target, direction) {
+                    (Some(key), Direction::Forward) => iter.seek(key),
+                    (None, Direction::Forward) => iter.seek_to_first(),
+                    (Some(key), Direction::Reverse) => iter.seek_for_prev(key),
+                    (None, Direction::Reverse) => iter.seek_to_last(),
+                },
+            }
+        }
+
+        // preload one entry from each source
+        for i in 0..self.sources.len() {
+            let priority = self.sources[i].priority();
+            self.refill(priority);
+        }
+    }
+
+    // pushes the next entry of the source with the given priority into the heap of the direction
+    fn refill(&mut self, priority: usize) {
+        let Some(idx) = self.sources.iter().position(|s| s.priority() == priority) else {
+            return;
+        };
+        let entry = Self::advance_source(
+            &mut self.sources[idx],
+            self.direction,
+            &self.start_bound,
+            &self.end_bound,
+            &self.max_seq,
+        );
+        if let Some(entry) = entry {
+            match self.direction {
+                Direction::Forward => self.heap.push(entry),
+                Direction::Reverse => self.rev_heap.push(RevEntry(entry)),
+            }
         }
     }
 
     fn advance_source(
-        sources: &mut [IterSource],
-        source_idx: usize,
+        source: &mut IterSource,
+        direction: Direction,
         start_bound: &Option<Vec<u8>>,
         end_bound: &Option<Vec<u8>>,
         max_seq: &Option<u64>,
     ) -> Option<IterEntry> {
         loop {
-            let (key, value, seq, priority) = match &mut sources[source_idx] {
+            let (key, value, seq, value_type, priority) = match source {
                 IterSource::Memtable {
-                    data,
-                    pos,
+                    memtable,
+                    cursor,
                     priority,
                 } => {
-                    if *pos >= data.len() {
-                        return None;
-                    }
-                    let (vk, v) = data[*pos].clone();
-                    *pos += 1;
+                    let (vk, value_type, v) = match direction {
+                        Direction::Forward => memtable.entry_from(cursor.as_ref())?,
+                        Direction::Reverse => memtable.entry_before(cursor.as_ref())?,
+                    };
+                    *cursor = Bound::Excluded(vk.clone());
 
-                    (vk.key, v, vk.seq, *priority)
+                    (vk.key, v, vk.seq, value_type, *priority)
+                }
+                IterSource::Sst { iter, priority } => {
+                    let next = match direction {
+                        Direction::Forward => iter.next()?,
+                        Direction::Reverse => iter.prev()?,
+                    };
+                    match next {
+                        Ok((k, v, seq, value_type)) => (k, v, seq, value_type, *priority),
+                        Err(_) => return None,
+                    }
                 }
-                IterSource::Sst { iter, priority } => match iter.next()? {
-                    Ok((k, v, seq)) => (k, v, seq, *priority),
-                    Err(_) => return None,
-                },
             };
 
             // kkip entries with sequence numbers >= max_seq, for snapshot isolation
@@ -204,26 +446,114 @@ impl DbIterator {
                 }
             }
 
-            if let Some(start) = start_bound {
-                if &key < start {
-                    continue;
-                }
-            }
-
-            if let Some(end) = end_bound {
-                if &key >= end {
-                    return None;
-                }
+            // a key beyond the bound the walk is heading to ends the source, one beyond the
+            // other bound is skipped
+            let before_start = start_bound.as_ref().is_some_and(|start| &key < start);
+            let past_end = end_bound.as_ref().is_some_and(|end| &key >= end);
+            match direction {
+                Direction::Forward if past_end => return None,
+                Direction::Reverse if before_start => return None,
+                _ if before_start || past_end => continue,
+                _ => {}
             }
 
             return Some(IterEntry {
                 key,
                 value,
                 seq,
+                value_type,
                 priority,
             });
         }
     }
+
+    // the newest visible version of a key, None if it's a duplicate of the key returned last or
+    // deleted. a merge operand is folded on top of the older versions, newest first, which only
+    // have to be gathered when the newest version is one
+    fn resolve(&mut self, entry: IterEntry, older: Vec<IterEntry>) -> Option<(Vec<u8>, Vec<u8>)> {
+        // if the entry's key is same as the last key, hence it's duplicate
+        // and one key with same value has already been pushed into the Iterator
+        if self.last_key.as_ref() == Some(&entry.key) {
+            return None;
+        }
+
+        // change the last_key to the key we're about to return
+        self.last_key = Some(entry.key.clone());
+
+        // the newest version is a tombstone or a value that expired
+        if entry.value_type == ValueType::Delete
+            || is_expired(entry.value_type, &entry.value, self.now)
+        {
+            return None;
+        }
+
+        // the newest version is older than a range tombstone covering the key
+        let deleted_before = covering_seq(&self.range_tombstones, &entry.key, u64::MAX);
+        if entry.seq < deleted_before {
+            return None;
+        }
+
+        if entry.value_type == ValueType::Merge {
+            let key = entry.key.clone();
+            let value = self.fold_operands(entry, older, deleted_before)?;
+            return Some((key, value));
+        }
+
+        let key = entry.key.clone();
+        Some((key, self.value_of(entry)?))
+    }
+
+    // the value a version sets its key to. a blob pointer can only come from a table, the value
+    // is read from its blob file. a value that can't be read is skipped
+    fn value_of(&self, entry: IterEntry) -> Option<Vec<u8>> {
+        if entry.value_type == ValueType::BlobIndex {
+            let reader = self.sources.iter().find_map(|source| match source {
+                IterSource::Sst { iter, priority } if *priority == entry.priority => {
+                    Some(iter.reader())
+                }
+                _ => None,
+            })?;
+            return reader.resolve_value(entry.value_type, entry.value).ok();
+        }
+        Some(inline_value(entry.value_type, entry.value))
+    }
+
+    // the operands from the newest version down to the first version that isn't one, folded on
+    // top of the value of that version. operands that can't be folded are skipped like a value
+    // that can't be read
+    fn fold_operands(
+        &self,
+        entry: IterEntry,
+        older: Vec<IterEntry>,
+        deleted_before: u64,
+    ) -> Option<Vec<u8>> {
+        let operator = self.merge_operator.as_ref()?;
+        let key = entry.key.clone();
+        let mut operands = Vec::new();
+        let mut existing = None;
+        let mut below = u64::MAX;
+        for version in std::iter::once(entry).chain(older) {
+            // a version left in a memtable and in the table it was flushed to shows up twice
+            if version.seq >= below {
+                continue;
+            }
+            below = version.seq;
+            if version.seq < deleted_before
+                || version.value_type == ValueType::Delete
+                || is_expired(version.value_type, &version.value, self.now)
+            {
+                break;
+            }
+            if version.value_type == ValueType::Merge {
+                operands.push(version.value);
+                continue;
+            }
+            existing = Some(self.value_of(version)?);
+            break;
+        }
+        operands.reverse();
+        operator.full_merge(&key, existing.as_deref(), &operands)
+    }
 }
 
 impl Iterator for DbIterator {
@@ -231,47 +561,67 @@ impl Iterator for DbIterator {
 
     // the next implementation for the DbIterator
     fn next(&mut self) -> Option<Self::Item> {
+        if self.direction == Direction::Reverse {
+            self.reposition(Direction::Forward, self.gap.clone());
+        }
+
         loop {
-            let entry = self.heap.pop()?;
+            let Some(entry) = self.heap.pop() else {
+                self.gap = Gap::End;
+                return None;
+            };
 
             // advance the source that produced this entry
-            for idx in 0..self.sources.len() {
-                let source_priority = match &self.sources[idx] {
-                    IterSource::Memtable { priority, .. } => *priority,
-                    IterSource::Sst { priority, .. } => *priority,
-                };
-
-                if source_priority == entry.priority {
-                    if let Some(next_entry) = Self::advance_source(
-                        &mut self.sources,
-                        idx,
-                        &self.start_bound,
-                        &self.end_bound,
-                        &self.max_seq,
-                    ) {
-                        self.heap.push(next_entry);
-                    }
-                    break;
+            self.refill(entry.priority);
+
+            // the older versions of the key come next, newest first, they're only needed to fold
+            // a merge operand
+            let mut older = Vec::new();
+            if entry.value_type == ValueType::Merge && self.last_key.as_ref() != Some(&entry.key) {
+                while self.heap.peek().is_some_and(|top| top.key == entry.key) {
+                    let version = self.heap.pop().unwrap();
+                    self.refill(version.priority);
+                    older.push(version);
                 }
             }
 
-            // if the entry's key is same as the last key, hence it's duplicate
-            // and one key with same value has already been pushed into the Iterator
-            if let Some(ref last) = self.last_key {
-                if &entry.key == last {
-                    continue;
-                }
+            if let Some(item) = self.resolve(entry, older) {
+                self.gap = Gap::After(item.0.clone());
+                return Some(item);
             }
+        }
+    }
+}
 
-            // change the last_key to the key we're about to return
-            self.last_key = Some(entry.key.clone());
+// walks a DbIterator backwards, so a reverse scan reads like any other iterator. see `Db::scan_rev`
+pub struct DbRevIterator {
+    inner: DbIterator,
+}
 
-            // value is empty hence it's tombstoned
-            if entry.value.is_empty() {
-                continue;
-            }
+impl DbRevIterator {
+    pub(crate) fn new(mut inner: DbIterator) -> Self {
+        inner.seek_to_last();
+        Self { inner }
+    }
 
-            return Some((entry.key, entry.value));
-        }
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        self.inner.seek_for_prev(key);
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.inner.seek_to_last();
+    }
+
+    // the underlying cursor, still positioned where the reverse scan left off
+    pub fn into_inner(self) -> DbIterator {
+        self.inner
+    }
+}
+
+impl Iterator for DbRevIterator {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.prev()
     }
 }
@@ -1,7 +1,19 @@
+mod clock;
 pub mod config;
 mod db;
+mod family;
 mod iterator;
+mod snapshot;
 
-pub use config::{MAX_SSTABLES, MEMTABLE_SIZE_THRESHOLD};
+pub use crate::batch::WriteBatch;
+pub use crate::sst::CompressionType;
+pub use crate::wal::WalMode;
+pub use clock::{Clock, SystemClock};
+pub use config::{DbOptions, WriteOptions, MAX_SSTABLES, MEMTABLE_SIZE_THRESHOLD};
 pub use db::Db;
-pub use iterator::DbIterator;
+pub use family::{ColumnFamily, DEFAULT_COLUMN_FAMILY};
+pub(crate) use family::{FamilyMap, MemtableSet};
+pub use iterator::{DbIterator, DbRevIterator};
+pub(crate) use iterator::{Direction, Gap};
+pub use snapshot::Snapshot;
+pub(crate) use snapshot::SnapshotList;
@@ -0,0 +1,80 @@
+// snapshots pin a sequence number: reads through them only see versions with a lower seq, and
+// compaction keeps, for every live snapshot, the version of each key the snapshot sees
+//
+// the live snapshots are tracked in a SnapshotList shared between the db and the compaction
+// worker, a snapshot is released once its last clone is dropped
+
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// a consistent view of the database as of the moment it was taken, see `Db::snapshot`
+///
+/// ```
+/// use keylite_kv::core::Db;
+///
+/// let db = Db::open("/tmp/keylite_doc_snapshot").unwrap();
+/// db.put(b"key", b"old").unwrap();
+/// let snapshot = db.snapshot();
+/// db.put(b"key", b"new").unwrap();
+///
+/// assert_eq!(db.get_seq(b"key", &snapshot).unwrap(), Some(b"old".to_vec()));
+/// assert_eq!(db.get(b"key").unwrap(), Some(b"new".to_vec()));
+/// # drop(db);
+/// # let _ = std::fs::remove_dir_all("/tmp/keylite_doc_snapshot");
+/// ```
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: Arc<SnapshotInner>,
+}
+
+struct SnapshotInner {
+    seq: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// versions with a seq lower than this one are visible through the snapshot
+    pub fn sequence(&self) -> u64 {
+        self.inner.seq
+    }
+}
+
+impl Drop for SnapshotInner {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
+
+// live snapshots, several snapshots can share a seq so each one is counted
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    live: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    pub(crate) fn acquire(self: &Arc<Self>, seq: u64) -> Snapshot {
+        *self.live.lock().entry(seq).or_insert(0) += 1;
+        Snapshot {
+            inner: Arc::new(SnapshotInner {
+                seq,
+                list: Arc::clone(self),
+            }),
+        }
+    }
+
+    fn release(&self, seq: u64) {
+        let mut live = self.live.lock();
+        if let Some(count) = live.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&seq);
+            }
+        }
+    }
+
+    // seqs of the live snapshots, ascending
+    pub(crate) fn sequences(&self) -> Vec<u64> {
+        self.live.lock().keys().copied().collect()
+    }
+}
@@ -10,6 +10,22 @@ pub enum DbError {
     Other(String),
     #[error("data corruption: {0}")]
     DataCorruption(String),
+    #[error("invalid options: {0}")]
+    InvalidOptions(String),
+    #[error("transaction conflict: {0}")]
+    Conflict(String),
+    #[error("column family: {0}")]
+    ColumnFamily(String),
+    #[error("backup: {0}")]
+    Backup(String),
+    #[error("key of {0} bytes is longer than {max} bytes", max = crate::sst::MAX_KEY_SIZE)]
+    KeyTooLarge(usize),
+    #[error("value of {0} bytes is longer than {max} bytes", max = crate::sst::MAX_VALUE_SIZE)]
+    ValueTooLarge(usize),
+    #[error("write of {0} bytes is larger than a WAL record can hold")]
+    WriteTooLarge(usize),
+    #[error("the WAL no longer has the batches before sequence {0}")]
+    SequenceGap(u64),
 }
 
 pub type Result<T> = std::result::Result<T, crate::error::DbError>;
@@ -2,4 +2,4 @@ pub mod queue;
 pub mod worker;
 
 pub use queue::{FlushMessage, FlushQueue};
-pub use worker::{flush_memtable_to_disk, flush_worker};
+pub use worker::{flush_memtables, flush_worker};
@@ -1,9 +1,10 @@
-use crate::memtable::Memtable;
+use crate::core::MemtableSet;
 use crossbeam_channel::{Receiver, Sender};
 use std::sync::Arc;
 
 pub enum FlushMessage {
-    Flush(Arc<Memtable>),
+    // the memtables of every column family frozen together
+    Flush(Arc<MemtableSet>),
     Shutdown,
 }
 
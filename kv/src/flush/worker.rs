@@ -2,16 +2,24 @@
 // remove the memtables that are flushed from memory, ONLY AND ONLY after successfull addition of a
 // new SSTable
 // while the process is running flush worked only flushes the oldes immutable memtable
+//
+// the memtables of all the column families are frozen together and share a WAL segment, so they
+// are flushed together as well: one table per family that has data, all of them added to the
+// manifest in a single edit
 
 use arc_swap::ArcSwap;
 use crossbeam_channel::{Receiver, Sender};
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::blob::BlobOutput;
+use crate::core::{DbOptions, FamilyMap, MemtableSet};
 use crate::error::DbError;
+use crate::manifest::Manifest;
 use crate::memtable::Memtable;
 use crate::sst::{SSTReader, SSTWriter};
+use crate::stats::{Histogram, Statistics, Ticker};
 use crate::wal::thread::WalMessage;
 
 use super::queue::FlushMessage;
@@ -20,59 +28,69 @@ type Result<T> = std::result::Result<T, DbError>;
 
 pub fn flush_worker(
     receiver: Receiver<FlushMessage>,
-    dir: std::path::PathBuf,
-    sstables: Arc<ArcSwap<Vec<SSTReader>>>,
-    immutable_memtables: Arc<ArcSwap<Vec<Arc<Memtable>>>>,
-    next_sst_id: Arc<AtomicU64>,
+    manifest: Arc<Manifest>,
+    immutable_memtables: Arc<ArcSwap<Vec<Arc<MemtableSet>>>>,
+    families: Arc<ArcSwap<FamilyMap>>,
     wal_tx: Sender<WalMessage>,
+    stats: Arc<Statistics>,
 ) {
-    loop {
-        match receiver.recv() {
-            Ok(FlushMessage::Flush(memtable)) => {
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            FlushMessage::Flush(memtables) => {
                 // println!("[FLUSH] Starting flush of immutable memtable ({} entries, {} bytes)",
                 //     memtable.len(), memtable.size_bytes());
-                if let Err(e) = flush_and_remove_memtable(
-                    &memtable,
-                    &dir,
-                    &sstables,
+                let start = Instant::now();
+                match flush_and_remove_memtables(
+                    &memtables,
+                    &manifest,
                     &immutable_memtables,
-                    &next_sst_id,
+                    &families,
                     wal_tx.clone(),
                 ) {
-                    eprintln!("Error flushing memtable: {}", e);
-                } else {
-                    // println!("[FLUSH] Completed flush of immutable memtable");
+                    Ok(bytes) => {
+                        // println!("[FLUSH] Completed flush of immutable memtable");
+                        stats.add(Ticker::Flushes, 1);
+                        stats.add(Ticker::BytesFlushed, bytes);
+                        stats.record_since(Histogram::Flush, start);
+                    }
+                    Err(e) => {
+                        stats.add(Ticker::FlushErrors, 1);
+                        eprintln!("Error flushing memtable: {}", e);
+                    }
                 }
             }
-            Ok(FlushMessage::Shutdown) | Err(_) => break,
+            FlushMessage::Shutdown => break,
         }
     }
 }
 
-// flush a memtable and then remove it from the immutable memtables list onlfy after successfull
-// flush and creation of SSTable
-fn flush_and_remove_memtable(
-    memtable: &Arc<Memtable>,
-    dir: &Path,
-    sstables: &Arc<ArcSwap<Vec<SSTReader>>>,
-    immutable_memtables: &Arc<ArcSwap<Vec<Arc<Memtable>>>>,
-    next_sst_id: &Arc<AtomicU64>,
+// flush the memtables and then remove them from the immutable memtables list onlfy after
+// successfull flush and creation of the SSTables, returns the number of bytes written
+fn flush_and_remove_memtables(
+    memtables: &Arc<MemtableSet>,
+    manifest: &Manifest,
+    immutable_memtables: &Arc<ArcSwap<Vec<Arc<MemtableSet>>>>,
+    families: &ArcSwap<FamilyMap>,
     wal_tx: Sender<WalMessage>,
-) -> Result<()> {
-    // flush the memtable to disk
-    flush_memtable_to_disk(memtable, dir, sstables, next_sst_id, wal_tx)?;
+) -> Result<u64> {
+    // flush the memtables to disk
+    let bytes = flush_memtables(memtables, &families.load(), manifest)?;
+
+    // the tables are durable and referenced by the manifest, the log of these memtables (and of
+    // these memtables only, the newer ones aren't flushed yet) can go
+    let _ = wal_tx.send(WalMessage::Retire(memtables.segment()));
 
-    // now that the SST is added
-    // we can remove the immutable memtable from the list
-    // find and remove this specific immutable memtable from the immutable memtables list
+    // now that the SSTs are added
+    // we can remove the immutable memtables from the list
+    // find and remove this specific set of immutable memtables from the immutable memtables list
     loop {
         let current = immutable_memtables.load();
         let mut new_immutables = (**current).clone();
 
-        // find the index of this memtable (comparing by pointer)
+        // find the index of this set (comparing by pointer)
         if let Some(pos) = new_immutables
             .iter()
-            .position(|mt| Arc::ptr_eq(mt, memtable))
+            .position(|set| Arc::ptr_eq(set, memtables))
         {
             new_immutables.remove(pos);
 
@@ -81,58 +99,80 @@ fn flush_and_remove_memtable(
                 break;
             }
         } else {
-            // memtable not found in the list, might have been already removed
+            // set not found in the list, might have been already removed
             break;
         }
     }
 
-    Ok(())
+    Ok(bytes)
+}
+
+// writes every memtable of the set into a new level 0 table of its column family and adds all of
+// them to the manifest in one edit, retiring the WAL segment is up to the caller. the memtables
+// of families that were dropped in the meantime are skipped. returns the size of the new tables
+pub fn flush_memtables(
+    memtables: &MemtableSet,
+    families: &FamilyMap,
+    manifest: &Manifest,
+) -> Result<u64> {
+    let mut tables = Vec::new();
+    for (cf, memtable) in memtables.iter() {
+        let Some(family) = families.get(&cf) else {
+            continue;
+        };
+        if let Some(table) = write_memtable(memtable, manifest, &family.opts)? {
+            tables.push((cf, table));
+        }
+    }
+    if tables.is_empty() {
+        manifest.segment_flushed(memtables.segment());
+        return Ok(0);
+    }
+    let bytes = tables.iter().map(|(_, table)| table.file_size()).sum();
+
+    // the tables only become part of the database once the manifest says so, a crash before this
+    // point leaves orphan files that are cleaned up at the next open and the data is still in
+    // the WAL
+    manifest.log_flush(tables, memtables.segment())?;
+    Ok(bytes)
 }
 
-pub fn flush_memtable_to_disk(
+// writes the memtable into a new level 0 table, None if there's nothing to write
+fn write_memtable(
     memtable: &Memtable,
-    dir: &Path,
-    sstables: &Arc<ArcSwap<Vec<SSTReader>>>,
-    next_sst_id: &Arc<AtomicU64>,
-    wal_tx: Sender<WalMessage>,
-) -> Result<()> {
+    manifest: &Manifest,
+    opts: &DbOptions,
+) -> Result<Option<SSTReader>> {
     // if memtable is empty there is nothing to flush
     if memtable.is_empty() {
-        return Ok(());
+        return Ok(None);
     }
 
-    // get the next sst_id that the worker gonna write to the disk
-    let sst_id = next_sst_id.fetch_add(1, Ordering::Relaxed);
-    let sst_path = dir.join(format!("sst-{}.db", sst_id));
+    // reserve the next sst_id that the worker gonna write to the disk
+    let sst_path = manifest.new_table_path();
 
     // create new SSTWriter, implemented in /sst/writer.rs
-    let mut writer = SSTWriter::new(&sst_path)?;
+    let mut writer = SSTWriter::with_options(&sst_path, opts.writer_options())?;
+
+    // large values go into blob files, the table only keeps pointers to them, see blob/mod.rs
+    let mut blobs = BlobOutput::new(manifest, opts, HashSet::new());
 
     // iterate over memtable entries in sorted order (skipmap is already sorted)
-    for (vk, val) in memtable.iter() {
-        // writer.add method adds the entry in the buffer
-        writer.add(&vk.key, &val, vk.seq)?;
+    for (vk, value_type, val) in memtable.iter() {
+        let (value_type, val) = blobs.separate(&vk.key, value_type, val)?;
+        // writer.add_entry method adds the entry in the buffer
+        writer.add_entry(&vk.key, value_type, &val, vk.seq)?;
     }
+    for tombstone in memtable.range_tombstones() {
+        writer.add_range_tombstone(tombstone);
+    }
+
+    // the blob files must be durable before the table pointing into them
+    blobs.finish()?;
 
     // writer.finish method wrties all the entries is has in the buffer, with the bloom filters,
     // block indexes and the footer
     writer.finish()?;
 
-    let reader = SSTReader::open(&sst_path)?;
-
-    // add the newly created SST to the sstables list
-    loop {
-        let current = sstables.load();
-        let mut new_sstables = (**current).clone();
-        new_sstables.insert(0, reader.clone());
-
-        // insert the newly created sstable in the global sst list
-        let prev = sstables.compare_and_swap(&current, Arc::new(new_sstables));
-        if Arc::ptr_eq(&*prev, &*current) {
-            break;
-        }
-    }
-
-    let _ = wal_tx.send(WalMessage::Truncate);
-    Ok(())
+    Ok(Some(manifest.open_table(&sst_path)?))
 }
@@ -1,8 +1,17 @@
+pub mod backup;
+pub mod batch;
+pub mod blob;
+pub mod cdc;
 pub mod core;
 pub mod error;
+pub mod manifest;
 pub mod memtable;
+pub mod merge;
+pub mod prefix;
 pub mod sst;
+pub mod stats;
 pub mod transaction;
+pub mod types;
 pub mod wal;
 
 mod compaction;
@@ -0,0 +1,199 @@
+// a version edit describes one atomic change to the set of live sstables and column families
+//
+// edits are encoded as a list of tagged fields:
+//
+// | tag (u8) | len (u32) | body (len bytes) |
+//
+// - ADD_TABLE:     id (u64) | level (u32) | smallest_len (u32) | smallest | largest_len (u32) |
+//                  largest | min_seq (u64) | max_seq (u64) | column family (u32)
+// - REMOVE_TABLE:  id (u64)
+// - NEXT_FILE_ID:  id (u64)
+// - LAST_SEQUENCE: seq (u64)
+// - ADD_FAMILY:    id (u32) | name
+// - DROP_FAMILY:   id (u32)
+// - NEXT_FAMILY_ID: id (u32)
+//
+// unknown tags are skipped, this leaves room for new kinds of edits. tables added before column
+// families existed have no column family and belong to the default one
+
+use crate::error::{DbError, Result};
+
+const TAG_ADD_TABLE: u8 = 1;
+const TAG_REMOVE_TABLE: u8 = 2;
+const TAG_NEXT_FILE_ID: u8 = 3;
+const TAG_LAST_SEQUENCE: u8 = 4;
+const TAG_ADD_FAMILY: u8 = 5;
+const TAG_DROP_FAMILY: u8 = 6;
+const TAG_NEXT_FAMILY_ID: u8 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableMeta {
+    pub id: u64,
+    pub level: u32,
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    pub min_sequence: u64,
+    pub max_sequence: u64,
+    // id of the column family the table belongs to
+    pub cf: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    pub added: Vec<TableMeta>,
+    pub removed: Vec<u64>,
+    pub next_file_id: Option<u64>,
+    pub last_sequence: Option<u64>,
+    // id and name of every column family created by the edit
+    pub added_families: Vec<(u32, String)>,
+    // a dropped column family takes all of its tables with it
+    pub dropped_families: Vec<u32>,
+    pub next_family_id: Option<u32>,
+}
+
+impl VersionEdit {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        for table in &self.added {
+            let mut body = Vec::new();
+            body.extend_from_slice(&table.id.to_le_bytes());
+            body.extend_from_slice(&table.level.to_le_bytes());
+            body.extend_from_slice(&(table.smallest_key.len() as u32).to_le_bytes());
+            body.extend_from_slice(&table.smallest_key);
+            body.extend_from_slice(&(table.largest_key.len() as u32).to_le_bytes());
+            body.extend_from_slice(&table.largest_key);
+            body.extend_from_slice(&table.min_sequence.to_le_bytes());
+            body.extend_from_slice(&table.max_sequence.to_le_bytes());
+            body.extend_from_slice(&table.cf.to_le_bytes());
+            put_field(&mut buf, TAG_ADD_TABLE, &body);
+        }
+
+        for id in &self.removed {
+            put_field(&mut buf, TAG_REMOVE_TABLE, &id.to_le_bytes());
+        }
+
+        if let Some(id) = self.next_file_id {
+            put_field(&mut buf, TAG_NEXT_FILE_ID, &id.to_le_bytes());
+        }
+
+        if let Some(seq) = self.last_sequence {
+            put_field(&mut buf, TAG_LAST_SEQUENCE, &seq.to_le_bytes());
+        }
+
+        for (id, name) in &self.added_families {
+            let mut body = id.to_le_bytes().to_vec();
+            body.extend_from_slice(name.as_bytes());
+            put_field(&mut buf, TAG_ADD_FAMILY, &body);
+        }
+
+        for id in &self.dropped_families {
+            put_field(&mut buf, TAG_DROP_FAMILY, &id.to_le_bytes());
+        }
+
+        if let Some(id) = self.next_family_id {
+            put_field(&mut buf, TAG_NEXT_FAMILY_ID, &id.to_le_bytes());
+        }
+
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut edit = VersionEdit::default();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let tag = data[pos];
+            let len = read_u32(data, pos + 1)? as usize;
+            pos += 5;
+
+            let body = data
+                .get(pos..pos + len)
+                .ok_or_else(|| corrupt("field extends past the end of the edit"))?;
+            pos += len;
+
+            match tag {
+                TAG_ADD_TABLE => edit.added.push(decode_table(body)?),
+                TAG_REMOVE_TABLE => edit.removed.push(read_u64(body, 0)?),
+                TAG_NEXT_FILE_ID => edit.next_file_id = Some(read_u64(body, 0)?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(read_u64(body, 0)?),
+                TAG_ADD_FAMILY => {
+                    let id = read_u32(body, 0)?;
+                    let name = String::from_utf8(body[4..].to_vec())
+                        .map_err(|_| corrupt("column family name isn't utf-8"))?;
+                    edit.added_families.push((id, name));
+                }
+                TAG_DROP_FAMILY => edit.dropped_families.push(read_u32(body, 0)?),
+                TAG_NEXT_FAMILY_ID => edit.next_family_id = Some(read_u32(body, 0)?),
+                _ => {}
+            }
+        }
+
+        Ok(edit)
+    }
+}
+
+fn decode_table(body: &[u8]) -> Result<TableMeta> {
+    let id = read_u64(body, 0)?;
+    let level = read_u32(body, 8)?;
+
+    let mut pos = 12;
+    let smallest_len = read_u32(body, pos)? as usize;
+    pos += 4;
+    let smallest_key = body
+        .get(pos..pos + smallest_len)
+        .ok_or_else(|| corrupt("truncated smallest key"))?
+        .to_vec();
+    pos += smallest_len;
+
+    let largest_len = read_u32(body, pos)? as usize;
+    pos += 4;
+    let largest_key = body
+        .get(pos..pos + largest_len)
+        .ok_or_else(|| corrupt("truncated largest key"))?
+        .to_vec();
+    pos += largest_len;
+
+    let min_sequence = read_u64(body, pos)?;
+    let max_sequence = read_u64(body, pos + 8)?;
+    pos += 16;
+    let cf = if body.len() > pos {
+        read_u32(body, pos)?
+    } else {
+        0
+    };
+
+    Ok(TableMeta {
+        id,
+        level,
+        smallest_key,
+        largest_key,
+        min_sequence,
+        max_sequence,
+        cf,
+    })
+}
+
+fn put_field(buf: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    buf.push(tag);
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(body);
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| corrupt("truncated u32"))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Result<u64> {
+    data.get(pos..pos + 8)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| corrupt("truncated u64"))
+}
+
+fn corrupt(msg: &str) -> DbError {
+    DbError::DataCorruption(format!("manifest: {}", msg))
+}
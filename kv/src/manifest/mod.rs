@@ -0,0 +1,715 @@
+// MANIFEST - the persistent record of which sstables make up the database
+//
+// the manifest is an append-only log of version edits (see manifest/edit.rs), every flush and
+// compaction appends exactly one edit describing the tables it added and removed, and the edit is
+// fsynced before the new tables become visible in memory. replaying the log at open gives back
+// the exact set of live tables, so the directory listing is never trusted: a table written by a
+// flush or compaction that crashed before committing, or an input of a compaction that crashed
+// before deleting it, is simply not part of the manifest and gets garbage collected
+//
+// each record is stored as:
+//
+// | payload len (u32) | crc32 of payload (u32) | payload (edit) |
+//
+// a record cut short at the end of the file is the result of a crash in the middle of an append,
+// it was never acknowledged so it's ignored
+//
+// on every open the log is rewritten as a single snapshot edit (written to MANIFEST.tmp and
+// renamed over MANIFEST), the same happens whenever the log grows past MAX_MANIFEST_SIZE
+//
+// the manifest also records the column families of the database. every table belongs to exactly
+// one of them, and dropping a family is a single edit that takes all of its tables along
+
+pub mod edit;
+
+use arc_swap::ArcSwap;
+use crc32fast::Hasher;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::blob::{blob_path, parse_blob_id, BlobFiles, BlobPointer};
+use crate::compaction::sort_tables;
+use crate::core::DEFAULT_COLUMN_FAMILY;
+use crate::error::{DbError, Result};
+use crate::sst::reader::{parse_sst_id, table_path};
+use crate::sst::{BlockCache, SSTReader};
+
+pub use edit::{TableMeta, VersionEdit};
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+const MANIFEST_TMP_FILE: &str = "MANIFEST.tmp";
+const MAX_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+struct ManifestLog {
+    file: File,
+    size: u64,
+}
+
+// the live tables of a column family in lookup order, shared with the read path
+pub type TableSet = Arc<ArcSwap<Vec<SSTReader>>>;
+
+struct LiveFamily {
+    name: String,
+    sstables: TableSet,
+}
+
+pub struct Manifest {
+    dir: PathBuf,
+    log: Mutex<ManifestLog>,
+    // every column family that isn't dropped, keyed by id. the default family (id 0) always
+    // exists. only changed while the log is locked
+    families: RwLock<BTreeMap<u32, LiveFamily>>,
+    next_file_id: AtomicU64,
+    last_sequence: AtomicU64,
+    next_family_id: AtomicU32,
+    // the WAL segments whose memtables made it into tables, a segment may still be on disk until
+    // the WAL thread retires it. only kept in memory, see `checkpoint`
+    flushed_segments: Mutex<FlushedSegments>,
+    block_cache: Option<Arc<BlockCache>>,
+    blob_files: BlobFiles,
+}
+
+// every segment below `below` is flushed, or was retired before the database was opened, one at
+// or above it only if it's listed in `above`. segments are flushed oldest first, so `above` only
+// holds the ones that got ahead of an older segment whose flush failed
+#[derive(Default)]
+pub struct FlushedSegments {
+    below: u64,
+    above: BTreeSet<u64>,
+}
+
+impl FlushedSegments {
+    pub fn contains(&self, segment: u64) -> bool {
+        segment < self.below || self.above.contains(&segment)
+    }
+
+    fn insert(&mut self, segment: u64) {
+        if segment >= self.below {
+            self.above.insert(segment);
+        }
+        self.advance();
+    }
+
+    fn skip_below(&mut self, segment: u64) {
+        self.below = self.below.max(segment);
+        self.above.retain(|s| *s >= segment);
+        self.advance();
+    }
+
+    fn advance(&mut self) {
+        while self.above.remove(&self.below) {
+            self.below += 1;
+        }
+    }
+}
+
+// state rebuilt by replaying the manifest log
+#[derive(Default)]
+struct ManifestState {
+    tables: BTreeMap<u64, TableMeta>,
+    next_file_id: u64,
+    last_sequence: u64,
+    // the column families besides the default one
+    families: BTreeMap<u32, String>,
+    next_family_id: u32,
+}
+
+impl ManifestState {
+    fn apply(&mut self, edit: VersionEdit) {
+        for id in edit.removed {
+            self.tables.remove(&id);
+        }
+        for table in edit.added {
+            self.tables.insert(table.id, table);
+        }
+        if let Some(id) = edit.next_file_id {
+            self.next_file_id = self.next_file_id.max(id);
+        }
+        if let Some(seq) = edit.last_sequence {
+            self.last_sequence = self.last_sequence.max(seq);
+        }
+        for (id, name) in edit.added_families {
+            self.families.insert(id, name);
+        }
+        for id in edit.dropped_families {
+            self.families.remove(&id);
+            self.tables.retain(|_, table| table.cf != id);
+        }
+        if let Some(id) = edit.next_family_id {
+            self.next_family_id = self.next_family_id.max(id);
+        }
+    }
+}
+
+impl Manifest {
+    // recovers the set of live tables of the database in dir
+    //
+    // if the directory has no MANIFEST yet (a new database, or one written before the manifest
+    // existed) the tables found in the directory are adopted and a manifest is created for them
+    pub fn open(dir: &Path, block_cache: Option<Arc<BlockCache>>) -> Result<Self> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let blob_files = BlobFiles::new(dir);
+
+        let (state, tables) = if manifest_path.exists() {
+            let state = replay(&manifest_path)?;
+            let mut tables = Vec::with_capacity(state.tables.len());
+            for meta in state.tables.values() {
+                // a table can't outlive its column family, this one is left over
+                if meta.cf != 0 && !state.families.contains_key(&meta.cf) {
+                    continue;
+                }
+                let path = table_path(dir, meta.id);
+                let mut reader =
+                    SSTReader::open_with_blobs(&path, block_cache.clone(), &blob_files).map_err(
+                        |e| {
+                            DbError::DataCorruption(format!(
+                                "table {:?} listed in the manifest can't be opened: {}",
+                                path, e
+                            ))
+                        },
+                    )?;
+                reader.set_level(meta.level);
+                tables.push((meta.cf, reader));
+            }
+            (state, tables)
+        } else {
+            bootstrap(dir, &block_cache, &blob_files)?
+        };
+
+        // anything that looks like a table but isn't live is left over from a flush or compaction
+        // that didn't finish, or from a compaction that committed but crashed before cleaning up.
+        // the same goes for a blob file no live table points into
+        let live: HashSet<u64> = tables.iter().map(|(_, t)| t.id()).collect();
+        let live_blobs: HashSet<u64> = tables.iter().flat_map(|(_, t)| t.blob_file_ids()).collect();
+        let mut max_seen_id = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(id) = parse_sst_id(&path) {
+                max_seen_id = max_seen_id.max(id);
+                if !live.contains(&id) {
+                    fs::remove_file(&path)?;
+                }
+            } else if let Some(id) = parse_blob_id(&path) {
+                // blob files take their ids from the same counter as the tables
+                max_seen_id = max_seen_id.max(id);
+                if !live_blobs.contains(&id) {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        let _ = fs::remove_file(dir.join(MANIFEST_TMP_FILE));
+
+        let next_file_id = state.next_file_id.max(max_seen_id + 1).max(1);
+        let last_sequence = tables
+            .iter()
+            .map(|(_, t)| t.max_sequence())
+            .fold(state.last_sequence, u64::max);
+
+        let mut grouped: BTreeMap<u32, (String, Vec<SSTReader>)> = BTreeMap::new();
+        grouped.insert(0, (DEFAULT_COLUMN_FAMILY.to_string(), Vec::new()));
+        for (id, name) in state.families {
+            grouped.insert(id, (name, Vec::new()));
+        }
+        for (cf, table) in tables {
+            if let Some((_, family_tables)) = grouped.get_mut(&cf) {
+                family_tables.push(table);
+            }
+        }
+        let families: BTreeMap<u32, LiveFamily> = grouped
+            .into_iter()
+            .map(|(id, (name, mut tables))| {
+                sort_tables(&mut tables);
+                let sstables = Arc::new(ArcSwap::from_pointee(tables));
+                (id, LiveFamily { name, sstables })
+            })
+            .collect();
+        let next_family_id = families
+            .keys()
+            .map(|id| id + 1)
+            .fold(state.next_family_id, u32::max);
+
+        let log = write_snapshot(dir, &families, next_file_id, last_sequence, next_family_id)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            log: Mutex::new(log),
+            families: RwLock::new(families),
+            next_file_id: AtomicU64::new(next_file_id),
+            last_sequence: AtomicU64::new(last_sequence),
+            next_family_id: AtomicU32::new(next_family_id),
+            flushed_segments: Mutex::new(FlushedSegments::default()),
+            block_cache,
+            blob_files,
+        })
+    }
+
+    // the live tables of the column family, None if there's no such family (anymore)
+    pub fn sstables(&self, cf: u32) -> Option<TableSet> {
+        self.families
+            .read()
+            .get(&cf)
+            .map(|family| Arc::clone(&family.sstables))
+    }
+
+    // id and name of every live column family, the default one first
+    pub fn families(&self) -> Vec<(u32, String)> {
+        self.families
+            .read()
+            .iter()
+            .map(|(id, family)| (*id, family.name.clone()))
+            .collect()
+    }
+
+    // records a new, empty column family and returns its id. ids are never reused, the WAL may
+    // still hold writes of a dropped family under its id
+    pub fn create_family(&self, name: &str) -> Result<u32> {
+        let mut log = self.log.lock();
+        if self.families.read().values().any(|f| f.name == name) {
+            return Err(DbError::ColumnFamily(format!(
+                "column family {:?} already exists",
+                name
+            )));
+        }
+
+        let id = self.next_family_id.load(Ordering::Relaxed);
+        let edit = VersionEdit {
+            added_families: vec![(id, name.to_string())],
+            next_family_id: Some(id + 1),
+            ..VersionEdit::default()
+        };
+        append_record(&mut log, &edit.encode())?;
+
+        self.next_family_id.store(id + 1, Ordering::Relaxed);
+        self.families.write().insert(
+            id,
+            LiveFamily {
+                name: name.to_string(),
+                sstables: Arc::new(ArcSwap::from_pointee(Vec::new())),
+            },
+        );
+        self.rewrite_if_large(&mut log)?;
+        Ok(id)
+    }
+
+    // drops the column family along with all of its tables in a single edit, however many tables
+    // it has. the tables are deleted once nobody reads from them anymore
+    pub fn drop_family(&self, cf: u32) -> Result<()> {
+        if cf == 0 {
+            return Err(DbError::ColumnFamily(
+                "the default column family can't be dropped".to_string(),
+            ));
+        }
+        let mut log = self.log.lock();
+        if !self.families.read().contains_key(&cf) {
+            return Err(DbError::ColumnFamily(format!(
+                "column family {} doesn't exist",
+                cf
+            )));
+        }
+
+        let edit = VersionEdit {
+            dropped_families: vec![cf],
+            ..VersionEdit::default()
+        };
+        append_record(&mut log, &edit.encode())?;
+
+        let mut families = self.families.write();
+        let family = families.remove(&cf).expect("family checked above");
+        let tables = family.sstables.swap(Arc::new(Vec::new()));
+        let still_live = live_blobs(&families);
+        drop(families);
+
+        let dead_blobs: HashSet<u64> = tables
+            .iter()
+            .flat_map(|t| t.blob_file_ids())
+            .filter(|id| !still_live.contains(id))
+            .collect();
+        for table in tables.iter() {
+            table.evict_blocks();
+            table.mark_obsolete();
+        }
+        self.blob_files.retire(dead_blobs);
+
+        self.rewrite_if_large(&mut log)
+    }
+
+    // highest sequence number known to be persisted in a table
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence.load(Ordering::Acquire)
+    }
+
+    // reserves a file id and returns the path the new table should be written to
+    pub fn new_table_path(&self) -> PathBuf {
+        let id = self.next_file_id.fetch_add(1, Ordering::Relaxed);
+        table_path(&self.dir, id)
+    }
+
+    // reserves a file id and returns the path the new blob file should be written to
+    pub fn new_blob_path(&self) -> PathBuf {
+        let id = self.next_file_id.fetch_add(1, Ordering::Relaxed);
+        blob_path(&self.dir, id)
+    }
+
+    // opens a table written by a flush or compaction, sharing the database's block cache
+    pub fn open_table(&self, path: &Path) -> Result<SSTReader> {
+        Ok(SSTReader::open_with_blobs(
+            path,
+            self.block_cache.clone(),
+            &self.blob_files,
+        )?)
+    }
+
+    // reads the value a pointer of a live table refers to
+    pub fn read_blob(&self, pointer: &BlobPointer) -> Result<Vec<u8>> {
+        Ok(self
+            .blob_files
+            .get_or_open(pointer.file_id)?
+            .read(pointer)?)
+    }
+
+    // ids of the blob files live tables point into, oldest first
+    pub fn live_blob_ids(&self) -> Vec<u64> {
+        let ids: BTreeSet<u64> = live_blobs(&self.families.read()).into_iter().collect();
+        ids.into_iter().collect()
+    }
+
+    // atomically replaces `removed` with `added` in the set of live tables, every added table
+    // comes with the id of its column family. the tables of one edit may belong to several
+    // families, a flush adds a table to every family that had data in the memtables
+    //
+    // the edit is fsynced to the manifest before the in-memory table lists are swapped, if this
+    // returns an error the edit may or may not be durable but the in-memory state is untouched
+    pub fn log_and_apply(&self, added: Vec<(u32, SSTReader)>, removed: &[SSTReader]) -> Result<()> {
+        self.commit(added, removed, None)
+    }
+
+    // adds the tables a flush wrote from the memtables of the given WAL segment
+    pub fn log_flush(&self, added: Vec<(u32, SSTReader)>, segment: u64) -> Result<()> {
+        self.commit(added, &[], Some(segment))
+    }
+
+    // the memtables of the segment held nothing to write, there's no table to add but its data
+    // isn't only in the WAL either
+    pub fn segment_flushed(&self, segment: u64) {
+        self.flushed_segments.lock().insert(segment);
+    }
+
+    // the segments below the given one were all replayed into tables while opening
+    pub fn segments_flushed_below(&self, segment: u64) {
+        self.flushed_segments.lock().skip_below(segment);
+    }
+
+    fn commit(
+        &self,
+        added: Vec<(u32, SSTReader)>,
+        removed: &[SSTReader],
+        flushed_segment: Option<u64>,
+    ) -> Result<()> {
+        let mut log = self.log.lock();
+        let families = self.families.read();
+
+        // a family dropped while its tables were written doesn't get them, they're deleted right
+        // away instead
+        let (added, orphaned): (Vec<_>, Vec<_>) = added
+            .into_iter()
+            .partition(|(cf, _)| families.contains_key(cf));
+        for (_, table) in &orphaned {
+            table.mark_obsolete();
+        }
+
+        let max_added_seq = added
+            .iter()
+            .map(|(_, t)| t.max_sequence())
+            .max()
+            .unwrap_or(0);
+        let last_sequence = self.last_sequence().max(max_added_seq);
+
+        let edit = VersionEdit {
+            added: added.iter().map(|(cf, t)| table_meta(*cf, t)).collect(),
+            removed: removed.iter().map(|t| t.id()).collect(),
+            next_file_id: Some(self.next_file_id.load(Ordering::Relaxed)),
+            last_sequence: Some(last_sequence),
+            ..VersionEdit::default()
+        };
+        append_record(&mut log, &edit.encode())?;
+
+        for (id, family) in families.iter() {
+            let current = family.sstables.load();
+            let touched = added.iter().any(|(cf, _)| cf == id)
+                || current
+                    .iter()
+                    .any(|sst| removed.iter().any(|old| old.id() == sst.id()));
+            if !touched {
+                continue;
+            }
+            let mut updated: Vec<SSTReader> = current
+                .iter()
+                .filter(|sst| !removed.iter().any(|old| old.id() == sst.id()))
+                .cloned()
+                .collect();
+            updated.extend(
+                added
+                    .iter()
+                    .filter(|(cf, _)| cf == id)
+                    .map(|(_, t)| t.clone()),
+            );
+            sort_tables(&mut updated);
+            family.sstables.store(Arc::new(updated));
+        }
+
+        // a blob file is live as long as a live table points into it. only the blob files of the
+        // removed tables are candidates, a flush or compaction that hasn't committed yet only
+        // writes pointers into blob files of its own or of tables that are still live
+        let still_live = live_blobs(&families);
+        let dead_blobs: HashSet<u64> = removed
+            .iter()
+            .chain(orphaned.iter().map(|(_, t)| t))
+            .flat_map(|t| t.blob_file_ids())
+            .filter(|id| !still_live.contains(id))
+            .collect();
+        drop(families);
+
+        self.last_sequence.store(last_sequence, Ordering::Release);
+        if let Some(segment) = flushed_segment {
+            self.flushed_segments.lock().insert(segment);
+        }
+        self.blob_files.retire(dead_blobs);
+
+        self.rewrite_if_large(&mut log)
+    }
+
+    // writes the live state of the database into dest: every live table and blob file is hard
+    // linked (copied where that's not possible) and a MANIFEST listing them is written
+    //
+    // the log stays locked until `wal` returns, so meanwhile no flush or compaction commits and
+    // none of the files goes away. `wal` gets the segments whose memtables are in the tables, the
+    // data of every other segment is only in the WAL
+    pub fn checkpoint<T>(
+        &self,
+        dest: &Path,
+        wal: impl FnOnce(&FlushedSegments) -> Result<T>,
+    ) -> Result<T> {
+        let log = self.log.lock();
+        let families = self.families.read();
+
+        for family in families.values() {
+            for table in family.sstables.load().iter() {
+                link_or_copy(table.path(), &table_path(dest, table.id()))?;
+            }
+        }
+        for id in live_blobs(&families) {
+            link_or_copy(&blob_path(&self.dir, id), &blob_path(dest, id))?;
+        }
+        write_snapshot(
+            dest,
+            &families,
+            self.next_file_id.load(Ordering::Relaxed),
+            self.last_sequence(),
+            self.next_family_id.load(Ordering::Relaxed),
+        )?;
+
+        let result = wal(&self.flushed_segments.lock());
+        drop(families);
+        drop(log);
+        result
+    }
+
+    // directory of the database
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // keeps the log from growing forever, the snapshot replaces it atomically
+    fn rewrite_if_large(&self, log: &mut ManifestLog) -> Result<()> {
+        if log.size > MAX_MANIFEST_SIZE {
+            *log = write_snapshot(
+                &self.dir,
+                &self.families.read(),
+                self.next_file_id.load(Ordering::Relaxed),
+                self.last_sequence(),
+                self.next_family_id.load(Ordering::Relaxed),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// the blob files the live tables of every column family point into
+fn live_blobs(families: &BTreeMap<u32, LiveFamily>) -> HashSet<u64> {
+    let mut ids = HashSet::new();
+    for family in families.values() {
+        ids.extend(
+            family
+                .sstables
+                .load()
+                .iter()
+                .flat_map(|t| t.blob_file_ids()),
+        );
+    }
+    ids
+}
+
+fn table_meta(cf: u32, table: &SSTReader) -> TableMeta {
+    TableMeta {
+        id: table.id(),
+        level: table.level(),
+        smallest_key: table.smallest_key().to_vec(),
+        largest_key: table.largest_key().to_vec(),
+        min_sequence: table.min_sequence(),
+        max_sequence: table.max_sequence(),
+        cf,
+    }
+}
+
+fn replay(path: &Path) -> Result<ManifestState> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut state = ManifestState::default();
+    let mut pos = 0;
+
+    while pos + 8 <= data.len() {
+        let len = u32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 bytes")) as usize;
+        let crc = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().expect("4 bytes"));
+        let end = pos + 8 + len;
+
+        // torn write at the tail, the edit was never acknowledged
+        if end > data.len() {
+            break;
+        }
+
+        let payload = &data[pos + 8..end];
+        let mut hasher = Hasher::new();
+        hasher.update(payload);
+        if hasher.finalize() != crc {
+            // a bad checksum on the very last record is a torn write as well, anywhere else the
+            // manifest is damaged and guessing the table set could lose or resurrect data
+            if end == data.len() {
+                break;
+            }
+            return Err(DbError::DataCorruption(
+                "manifest record checksum mismatch".to_string(),
+            ));
+        }
+
+        state.apply(VersionEdit::decode(payload)?);
+        pos = end;
+    }
+
+    Ok(state)
+}
+
+// builds the initial state of a database that has no manifest from the tables in the directory,
+// they all belong to the default column family
+fn bootstrap(
+    dir: &Path,
+    block_cache: &Option<Arc<BlockCache>>,
+    blob_files: &BlobFiles,
+) -> Result<(ManifestState, Vec<(u32, SSTReader)>)> {
+    let mut tables = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if parse_sst_id(&path).is_none() {
+            continue;
+        }
+        // skipping the table would get it collected as an orphan right after, so a database with
+        // a damaged table doesn't open rather than losing the table for good
+        let reader =
+            SSTReader::open_with_blobs(&path, block_cache.clone(), blob_files).map_err(|e| {
+                DbError::DataCorruption(format!("table {:?} can't be opened: {}", path, e))
+            })?;
+        tables.push((0, reader));
+    }
+    Ok((ManifestState::default(), tables))
+}
+
+fn append_record(log: &mut ManifestLog, payload: &[u8]) -> Result<()> {
+    let mut hasher = Hasher::new();
+    hasher.update(payload);
+    let crc = hasher.finalize();
+
+    let mut record = Vec::with_capacity(8 + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc.to_le_bytes());
+    record.extend_from_slice(payload);
+
+    // a record that didn't make it whole is cut off again, the next one would otherwise land
+    // behind it and a later checksum mismatch in the middle of the log fails the replay
+    if let Err(e) = log
+        .file
+        .write_all(&record)
+        .and_then(|_| log.file.sync_data())
+    {
+        log.file.set_len(log.size)?;
+        log.file.seek(SeekFrom::Start(log.size))?;
+        return Err(e.into());
+    }
+    log.size += record.len() as u64;
+
+    Ok(())
+}
+
+// writes the full set of column families and tables as a single edit into a fresh manifest and
+// atomically swaps it in
+fn write_snapshot(
+    dir: &Path,
+    families: &BTreeMap<u32, LiveFamily>,
+    next_file_id: u64,
+    last_sequence: u64,
+    next_family_id: u32,
+) -> Result<ManifestLog> {
+    let tmp_path = dir.join(MANIFEST_TMP_FILE);
+    let manifest_path = dir.join(MANIFEST_FILE);
+
+    let mut edit = VersionEdit {
+        next_file_id: Some(next_file_id),
+        last_sequence: Some(last_sequence),
+        next_family_id: Some(next_family_id),
+        ..VersionEdit::default()
+    };
+    for (id, family) in families {
+        if *id != 0 {
+            edit.added_families.push((*id, family.name.clone()));
+        }
+        edit.added
+            .extend(family.sstables.load().iter().map(|t| table_meta(*id, t)));
+    }
+
+    let mut log = ManifestLog {
+        file: File::create(&tmp_path)?,
+        size: 0,
+    };
+    append_record(&mut log, &edit.encode())?;
+    drop(log);
+
+    fs::rename(&tmp_path, &manifest_path)?;
+    sync_dir(dir)?;
+
+    let file = OpenOptions::new().append(true).open(&manifest_path)?;
+    let size = file.metadata()?.len();
+    Ok(ManifestLog { file, size })
+}
+
+// hard links src to dst, tables and blob files are never modified so the checkpoint can share
+// them with the database. a copy is made if the link fails, e.g. across file systems
+fn link_or_copy(src: &Path, dst: &Path) -> Result<()> {
+    if fs::hard_link(src, dst).is_err() {
+        fs::copy(src, dst)?;
+        File::open(dst)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// makes renames and newly created files in the directory durable
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
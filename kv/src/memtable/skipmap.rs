@@ -1,6 +1,9 @@
 use crossbeam_skiplist::SkipMap;
+use std::ops::Bound;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::types::{covering_seq, inline_value, Lookup, RangeTombstone, ValueType};
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct VersionedKey {
     pub key: Vec<u8>,
@@ -25,32 +28,82 @@ impl PartialOrd for VersionedKey {
 /// look up heirerchy:
 /// memtable -> immutable memtable -> sst
 pub struct Memtable {
-    data: SkipMap<VersionedKey, Vec<u8>>,
+    data: SkipMap<VersionedKey, (ValueType, Vec<u8>)>,
+    // range tombstones keyed by (start, seq), the value is the exclusive end of the range
+    range_tombstones: SkipMap<VersionedKey, Vec<u8>>,
     size_bytes: AtomicUsize,
+    // WAL segment the writes of this memtable are logged to, see wal/mod.rs
+    wal_segment: u64,
 }
 
 impl Memtable {
     pub fn new() -> Self {
+        Self::with_wal_segment(0)
+    }
+
+    pub fn with_wal_segment(wal_segment: u64) -> Self {
         Self {
             data: SkipMap::new(),
+            range_tombstones: SkipMap::new(),
             size_bytes: AtomicUsize::new(0),
+            wal_segment,
         }
     }
 
+    pub fn wal_segment(&self) -> u64 {
+        self.wal_segment
+    }
+
     pub fn put(&self, key: Vec<u8>, value: Vec<u8>, seq: u64) {
+        self.insert(key, ValueType::Put, value, seq);
+    }
+
+    pub fn delete(&self, key: Vec<u8>, seq: u64) {
+        self.insert(key, ValueType::Delete, Vec::new(), seq);
+    }
+
+    pub fn insert(&self, key: Vec<u8>, value_type: ValueType, value: Vec<u8>, seq: u64) {
         let key_size = key.len();
         let val_size = value.len();
 
         let vk = VersionedKey { key, seq };
 
+        // the tag is counted along with the seq
+        self.size_bytes
+            .fetch_add(key_size + val_size + 9, Ordering::Relaxed);
+
+        self.data.insert(vk, (value_type, value));
+    }
+
+    // deletes every version of the keys in [start, end) older than seq
+    pub fn delete_range(&self, start: Vec<u8>, end: Vec<u8>, seq: u64) {
         self.size_bytes
-            .fetch_add(key_size + val_size + 8, Ordering::Relaxed);
+            .fetch_add(start.len() + end.len() + 8, Ordering::Relaxed);
 
-        self.data.insert(vk, value);
+        let vk = VersionedKey { key: start, seq };
+        // two ranges of the same batch starting at the same key, the wider one covers both
+        let end = match self.range_tombstones.get(&vk) {
+            Some(existing) if existing.value() > &end => existing.value().clone(),
+            _ => end,
+        };
+        self.range_tombstones.insert(vk, end);
     }
 
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let mut range = self.data.range(
+        self.lookup(key, u64::MAX).into_value()
+    }
+
+    pub fn get_seq(&self, key: &[u8], snapshot_seq: u64) -> Option<Vec<u8>> {
+        self.lookup(key, snapshot_seq).into_value()
+    }
+
+    // finds the latest version of the key with seq < snapshot_seq (strict inequality for snapshot
+    // isolation), a tombstone is reported as Lookup::Deleted so the caller knows it must not look
+    // any further into older memtables or sstables
+    pub fn lookup(&self, key: &[u8], snapshot_seq: u64) -> Lookup {
+        // VersionedKey is ordered by (key ASC, seq DESC), so the range walks the versions of the
+        // key from the highest seq down
+        let range = self.data.range(
             VersionedKey {
                 key: key.to_vec(),
                 seq: u64::MAX,
@@ -59,58 +112,111 @@ impl Memtable {
                 seq: 0,
             },
         );
-        // for entry in range {
-        //     if entry.key().key == key {
-        //         let val = entry.value();
-        //         if val.is_empty() {
-        //             return None;
-        //         }
-        //         return Some(val.clone());
-        //     } else {
-        //         break;
-        //     }
-        // }
-        //
-        if let Some(entry) = range.next() {
-            if entry.key().key == key {
-                let val = entry.value();
-                if val.is_empty() {
-                    return None;
-                }
-                return Some(val.clone());
+        for entry in range {
+            if entry.key().seq < snapshot_seq {
+                return match entry.value() {
+                    (ValueType::Delete, _) => Lookup::Deleted,
+                    (ValueType::Put, val) => Lookup::Value(val.clone()),
+                    // expiry is checked by the db, which knows the time
+                    (ValueType::ExpiringPut, val) => {
+                        Lookup::Value(inline_value(ValueType::ExpiringPut, val.clone()))
+                    }
+                    // folding the operand into the older versions is up to the db as well
+                    (ValueType::Merge, val) => Lookup::Value(val.clone()),
+                    // blob pointers are only written by flushes and compactions
+                    (ValueType::BlobIndex, _) => unreachable!("blob pointer in a memtable"),
+                };
             }
         }
-        None
+        Lookup::Absent
     }
 
-    pub fn get_seq(&self, key: &[u8], snapshot_seq: u64) -> Option<Vec<u8>> {
-        // For snapshot isolation, we need to find the latest version with seq < snapshot_seq
-        // VersionedKey is ordered by (key ASC, seq DESC), so we iterate from highest seq down
+    // seq, type and value of the newest version of the key with seq < snapshot_seq, unlike lookup
+    // this ignores range tombstones, see covering_seq
+    pub fn lookup_version(
+        &self,
+        key: &[u8],
+        snapshot_seq: u64,
+    ) -> Option<(u64, ValueType, Vec<u8>)> {
+        self.data
+            .range(
+                VersionedKey {
+                    key: key.to_vec(),
+                    seq: u64::MAX,
+                }..=VersionedKey {
+                    key: key.to_vec(),
+                    seq: 0,
+                },
+            )
+            .find(|entry| entry.key().seq < snapshot_seq)
+            .map(|entry| {
+                let (value_type, value) = entry.value();
+                (entry.key().seq, *value_type, value.clone())
+            })
+    }
+
+    // seq of the newest range tombstone visible at snapshot_seq that covers the key, 0 if none
+    pub fn covering_seq(&self, key: &[u8], snapshot_seq: u64) -> u64 {
+        // only ranges starting at or before the key can cover it
+        let candidates: Vec<RangeTombstone> = self
+            .range_tombstones
+            .range(
+                ..=VersionedKey {
+                    key: key.to_vec(),
+                    seq: 0,
+                },
+            )
+            .map(|entry| RangeTombstone {
+                start: entry.key().key.clone(),
+                end: entry.value().clone(),
+                seq: entry.key().seq,
+            })
+            .collect();
+        covering_seq(&candidates, key, snapshot_seq)
+    }
+
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones
+            .iter()
+            .map(|entry| RangeTombstone {
+                start: entry.key().key.clone(),
+                end: entry.value().clone(),
+                seq: entry.key().seq,
+            })
+            .collect()
+    }
+
+    // sequence number of the newest version of the key, tombstones included
+    pub fn newest_sequence(&self, key: &[u8]) -> Option<u64> {
+        self.data
+            .lower_bound(Bound::Included(&VersionedKey {
+                key: key.to_vec(),
+                seq: u64::MAX,
+            }))
+            .filter(|entry| entry.key().key == key)
+            .map(|entry| entry.key().seq)
+    }
+
+    // whether any key in [start, end) has a version or is covered by a range tombstone with
+    // seq >= since_seq, an unbounded end runs to the last key
+    pub fn written_since(&self, start: &[u8], end: Option<&[u8]>, since_seq: u64) -> bool {
         let range = self.data.range(
             VersionedKey {
-                key: key.to_vec(),
-                seq: u64::MAX, // Start from highest possible sequence
-            }..=VersionedKey {
-                key: key.to_vec(),
-                seq: 0,
-            },
+                key: start.to_vec(),
+                seq: u64::MAX,
+            }..,
         );
         for entry in range {
-            if entry.key().key == key {
-                // Only return entries with seq < snapshot_seq (strict inequality for snapshot isolation)
-                if entry.key().seq < snapshot_seq {
-                    let val = entry.value();
-                    if val.is_empty() {
-                        return None;
-                    }
-                    return Some(val.clone());
-                }
-                // Continue searching for older versions
-            } else {
+            if end.is_some_and(|end| entry.key().key.as_slice() >= end) {
                 break;
             }
+            if entry.key().seq >= since_seq {
+                return true;
+            }
         }
-        None
+        self.range_tombstones()
+            .iter()
+            .any(|t| t.seq >= since_seq && t.overlaps(start, end))
     }
 
     pub fn size_bytes(&self) -> usize {
@@ -122,17 +228,42 @@ impl Memtable {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.data.is_empty() && self.range_tombstones.is_empty()
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (VersionedKey, Vec<u8>)> + '_ {
-        self.data
-            .iter()
-            .map(|entry| (entry.key().clone(), entry.value().clone()))
+    // the first entry at or after the bound. lets a cursor walk the memtable while only holding
+    // on to the last key it returned instead of a borrow of the skiplist
+    pub fn entry_from(
+        &self,
+        from: Bound<&VersionedKey>,
+    ) -> Option<(VersionedKey, ValueType, Vec<u8>)> {
+        self.data.lower_bound(from).map(|entry| {
+            let (value_type, value) = entry.value();
+            (entry.key().clone(), *value_type, value.clone())
+        })
+    }
+
+    // the last entry at or before the bound, the backwards counterpart of entry_from
+    pub fn entry_before(
+        &self,
+        to: Bound<&VersionedKey>,
+    ) -> Option<(VersionedKey, ValueType, Vec<u8>)> {
+        self.data.upper_bound(to).map(|entry| {
+            let (value_type, value) = entry.value();
+            (entry.key().clone(), *value_type, value.clone())
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (VersionedKey, ValueType, Vec<u8>)> + '_ {
+        self.data.iter().map(|entry| {
+            let (value_type, value) = entry.value();
+            (entry.key().clone(), *value_type, value.clone())
+        })
     }
 
     pub fn clear(&self) {
         self.data.clear();
+        self.range_tombstones.clear();
         self.size_bytes.store(0, Ordering::Relaxed);
     }
 }
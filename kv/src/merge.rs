@@ -0,0 +1,110 @@
+// merge operators let a write describe a change to the value of a key instead of the new value
+// itself, e.g. "add 1" to a counter. `Db::merge` stores the operand as a Merge version of the key
+// without reading anything, the operands are only folded into a value when the key is read, and
+// compaction folds them for good once it sees every version they apply to
+//
+// the operator is registered through `DbOptions::merge_operator`, a database holding operands has
+// to be opened with the same operator again to read them
+
+use std::fmt;
+
+/// folds the merge operands of a key into its value, see `Db::merge`
+///
+/// reads and compaction may fold the operands of a key in several steps, so folding the operands
+/// one at a time has to give the same value as folding all of them at once
+pub trait MergeOperator: fmt::Debug + Send + Sync {
+    /// name of the operator, shows up in errors
+    fn name(&self) -> &str;
+
+    /// the value of the key after applying the operands, oldest first, on top of the existing
+    /// value. `existing` is None if the key has no value below the operands, e.g. it was never
+    /// written or deleted. None if the operands can't be applied
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Vec<u8>],
+    ) -> Option<Vec<u8>>;
+}
+
+/// adds up little endian u64 operands, a missing value counts as 0 and the sum wraps around on
+/// overflow
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    pub fn encode(value: u64) -> Vec<u8> {
+        value.to_le_bytes().to_vec()
+    }
+
+    pub fn decode(value: &[u8]) -> Option<u64> {
+        Some(u64::from_le_bytes(value.try_into().ok()?))
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "u64_add"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Vec<u8>],
+    ) -> Option<Vec<u8>> {
+        let mut sum = match existing {
+            Some(value) => Self::decode(value)?,
+            None => 0,
+        };
+        for operand in operands {
+            sum = sum.wrapping_add(Self::decode(operand)?);
+        }
+        Some(Self::encode(sum))
+    }
+}
+
+/// appends every operand to the value, separated by a delimiter
+#[derive(Debug, Clone)]
+pub struct ListAppendOperator {
+    delimiter: Vec<u8>,
+}
+
+impl ListAppendOperator {
+    pub fn new(delimiter: &[u8]) -> Self {
+        Self {
+            delimiter: delimiter.to_vec(),
+        }
+    }
+}
+
+impl Default for ListAppendOperator {
+    fn default() -> Self {
+        Self::new(b",")
+    }
+}
+
+impl MergeOperator for ListAppendOperator {
+    fn name(&self) -> &str {
+        "list_append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Vec<u8>],
+    ) -> Option<Vec<u8>> {
+        let mut list = existing.map(|value| value.to_vec());
+        for operand in operands {
+            match &mut list {
+                Some(list) => {
+                    list.extend_from_slice(&self.delimiter);
+                    list.extend_from_slice(operand);
+                }
+                None => list = Some(operand.clone()),
+            }
+        }
+        Some(list.unwrap_or_default())
+    }
+}
@@ -0,0 +1,113 @@
+// prefix extractors map a key to the prefix it's grouped under, e.g. the collection part of a
+// document key. every sstable written with an extractor carries a bloom filter over the prefixes
+// of its keys, so `Db::scan_prefix` can skip the tables that hold nothing under a prefix without
+// reading a single data block
+//
+// the extractor is registered through `DbOptions::prefix_extractor`. its name is stored in every
+// table, a table written with another extractor (or none) is never skipped, so the extractor can
+// be changed between opens, the old tables only stop benefiting from their filters
+
+use std::fmt;
+
+/// maps keys to the prefix the prefix filter of a table is built from, see `Db::scan_prefix`
+///
+/// the prefixes have to be consistent: if a key is in the domain, every key that starts with its
+/// prefix is in the domain as well and has the same prefix
+pub trait PrefixExtractor: fmt::Debug + Send + Sync {
+    /// name of the extractor, stored in every table written with it
+    fn name(&self) -> &str;
+
+    /// whether the key has a prefix, the keys outside the domain are left out of the filter
+    fn in_domain(&self, key: &[u8]) -> bool;
+
+    /// the prefix of a key in the domain
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+}
+
+/// the first `len` bytes of a key, shorter keys have no prefix
+#[derive(Debug, Clone)]
+pub struct FixedPrefix {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefix {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("fixed:{}", len),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+}
+
+/// everything up to and including the `count`th separator of a key, e.g. `col:users:` for
+/// `col:users:doc:42` with ':' and 2. keys with fewer separators have no prefix
+#[derive(Debug, Clone)]
+pub struct SeparatorPrefix {
+    separator: u8,
+    count: usize,
+    name: String,
+}
+
+impl SeparatorPrefix {
+    pub fn new(separator: u8, count: usize) -> Self {
+        Self {
+            separator,
+            count,
+            name: format!("separator:{}:{}", separator, count),
+        }
+    }
+
+    // length of the prefix, None if the key has too few separators
+    fn prefix_len(&self, key: &[u8]) -> Option<usize> {
+        if self.count == 0 {
+            return Some(0);
+        }
+        key.iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == self.separator)
+            .nth(self.count - 1)
+            .map(|(pos, _)| pos + 1)
+    }
+}
+
+impl PrefixExtractor for SeparatorPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool {
+        self.prefix_len(key).is_some()
+    }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.prefix_len(key).unwrap_or(key.len())]
+    }
+}
+
+// the smallest key greater than every key starting with the prefix, None if there is none, i.e.
+// the prefix is empty or all 0xff
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
@@ -1,107 +1,214 @@
-// a simple bloom filter implementation used inside sstables for negative lookups
+// bloom filters used inside sstables for negative lookups
 //
-// this implementation uses:
-// - 3 hash bit indexes (H1, H2, H1+H2)
-// - crc32fast for hashing
-// - simple bit vector as the bloom data
+// from format version 7 on the filter is sized from the number of keys in the table: every key
+// gets `bits_per_key` bits and k = bits_per_key * ln 2 probes, the false positive rate stays
+// around 1% at 10 bits per key however big the table gets. the probes are derived from a single
+// 64 bit hash of the key by double hashing (h1 + i * h2)
+//
+// the filter block starts with the kind of the filter and its parameters so the reader doesn't
+// need to know how the writer was configured:
+//
+// | kind | num probes | bits      |
+// |  u8  |    u8      | [u8; len] |
+//
+// tables older than version 7 carry a fixed size legacy filter without the header: 3 probes
+// (H1, H2, H1+H2) derived from two seeded crc32 hashes
 
 use crc32fast::Hasher;
 use memmap2::Mmap;
 
 use super::{Result, SSTError};
 
+// kind byte of a filter block, only one kind is written so far
+const FILTER_BLOOM64: u8 = 1;
+
+// the smallest filter written, so a table without any keys still has bits to probe
+const MIN_FILTER_BYTES: usize = 8;
+
+const MAX_PROBES: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKind {
+    // the fixed size filter of tables before version 7
+    LegacyCrc32,
+    Bloom64 { num_probes: u32 },
+}
+
 pub struct BloomFilter {
+    kind: FilterKind,
     data: Vec<u8>,
 }
 
 impl BloomFilter {
+    /// legacy filter over the raw bits of a table older than version 7
     pub fn new(data: Vec<u8>) -> Self {
-        Self { data }
+        Self {
+            kind: FilterKind::LegacyCrc32,
+            data,
+        }
+    }
+
+    /// builds a filter over the given key hashes (see `hash_key`) with `bits_per_key` bits for
+    /// every key, but never more than `max_bytes` bytes. a filter that hit the limit uses fewer
+    /// probes to make up for the missing bits
+    pub fn build(key_hashes: &[u64], bits_per_key: usize, max_bytes: usize) -> Self {
+        let wanted = key_hashes.len().saturating_mul(bits_per_key).div_ceil(8);
+        let bytes = wanted.min(max_bytes).max(MIN_FILTER_BYTES);
+        let num_bits = (bytes * 8) as u64;
+
+        // k = ln 2 * bits per key is what minimizes the false positive rate
+        let actual_bits_per_key = num_bits as f64 / key_hashes.len().max(1) as f64;
+        let num_probes = ((actual_bits_per_key * std::f64::consts::LN_2).round() as usize)
+            .clamp(1, MAX_PROBES) as u32;
+
+        let mut data = vec![0u8; bytes];
+        for &hash in key_hashes {
+            for bit in probes(hash, num_probes, num_bits) {
+                data[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+
+        Self {
+            kind: FilterKind::Bloom64 { num_probes },
+            data,
+        }
     }
 
     // returns true if the key MIGHT be present in the SSTable
     //
-    // example:
-    //
-    // lets say the BloomFiler data looks like:
-    // [01101010, 10000001] // vector of u8 with 2 elements
-    //
-    // bits_len = 8*2 = 16 // we have that many bits
-    //
-    // let's say we look for the key "apple" and the h1, h2, h3 comes out to be 3, 6, and 16
-    // respectively
-    //
-    // so the bit_index1 = 3 % 16 = 3,
-    //
-    // bit_index2 = 6 % 16 = 6
-    //
-    // bit_index3 = 16 % 16 = 0
-    //
-    // not let's check for bit_index1
-    //
-    // bit_index1 / 8 = 3/8 = 0 hence the first bit is present in the first byte (01101010),
-    // self.data[0]
-    //
-    // bit_index1 % 8 = 3 % 8 = 3, hence the bit to check is the third bit in the first byte
-    //
-    // now if we do 01101010 >> 3, means right shift 3 times
-    // so it becomes 00001101
-    //
-    // now it we take & with 1 it will result in 1, hence the bit_index1 is correct
-    //
-    // similarly check other 2 indexes and they'll also be 1 hence this sst MIGHT contain the given
-    // key
+    // every probe of the key names a bit, bit i lives in byte i / 8 at position i % 8. the key
+    // can only be in the table if all of its bits are set, a single 0 rules it out
     pub fn might_contain(&self, key: &[u8]) -> bool {
-        let hash1 = self.hash_key(key, 0);
-        let hash2 = self.hash_key(key, 1);
-
-        // self.data is Vec<u8>
-        // each byte has 8 bits
-        // hence total bits_len = bytes * 8
-        let bits_len = (self.data.len() * 8) as u64;
-
-        // compute the 3 bit positions that has to be 1 for that particular key to exist
-        let bit_index1 = (hash1 % bits_len) as usize;
-        let bit_index2 = (hash2 % bits_len) as usize;
-        let bit_index3 = ((hash1.wrapping_add(hash2)) % bits_len) as usize;
-
-        // shift the targe bit to the LSB to check if it's 1 or not
-        if (self.data[bit_index1 / 8] >> (bit_index1 % 8)) & 1 == 0 {
-            return false;
+        let num_bits = (self.data.len() * 8) as u64;
+        if num_bits == 0 {
+            return true;
         }
-
-        if (self.data[bit_index2 / 8] >> (bit_index2 % 8)) & 1 == 0 {
-            return false;
+        let is_set = |bit: usize| (self.data[bit / 8] >> (bit % 8)) & 1 == 1;
+
+        match self.kind {
+            FilterKind::LegacyCrc32 => {
+                let hash1 = legacy_hash(key, 0);
+                let hash2 = legacy_hash(key, 1);
+                is_set((hash1 % num_bits) as usize)
+                    && is_set((hash2 % num_bits) as usize)
+                    && is_set((hash1.wrapping_add(hash2) % num_bits) as usize)
+            }
+            FilterKind::Bloom64 { num_probes } => {
+                probes(hash_key(key), num_probes, num_bits).all(is_set)
+            }
         }
+    }
 
-        (self.data[bit_index3 / 8] >> (bit_index3 % 8)) & 1 == 1
+    /// number of probes per key, 3 for a legacy filter
+    pub fn num_probes(&self) -> u32 {
+        match self.kind {
+            FilterKind::LegacyCrc32 => 3,
+            FilterKind::Bloom64 { num_probes } => num_probes,
+        }
     }
 
-    // computes crc32 based hash for the given key using the given seed
-    fn hash_key(&self, key: &[u8], seed: u32) -> u64 {
-        let mut hasher = Hasher::new();
-        hasher.update(&seed.to_le_bytes());
-        hasher.update(key);
-        hasher.finalize() as u64
+    pub fn num_bits(&self) -> usize {
+        self.data.len() * 8
     }
 
     pub fn data(&self) -> &[u8] {
         &self.data
     }
+
+    // the filter block body as written by a version 7+ table, see the top of this file
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        match self.kind {
+            FilterKind::LegacyCrc32 => self.data.clone(),
+            FilterKind::Bloom64 { num_probes } => {
+                let mut block = Vec::with_capacity(2 + self.data.len());
+                block.push(FILTER_BLOOM64);
+                block.push(num_probes as u8);
+                block.extend_from_slice(&self.data);
+                block
+            }
+        }
+    }
+
+    fn decode(block: &[u8], version: u32) -> Result<Self> {
+        if version < 7 {
+            return Ok(Self::new(block.to_vec()));
+        }
+        match block {
+            [FILTER_BLOOM64, num_probes, data @ ..] if *num_probes > 0 => Ok(Self {
+                kind: FilterKind::Bloom64 {
+                    num_probes: *num_probes as u32,
+                },
+                data: data.to_vec(),
+            }),
+            _ => Err(SSTError::Corrupt),
+        }
+    }
 }
 
-// reads the bloom data from the SSTable using mmap
+/// 64 bit hash the filter probes of a key are derived from, MurmurHash64A with a fixed seed
+///
+/// the hash ends up in the tables on disk, so it must never change
+pub fn hash_key(key: &[u8]) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    const SEED: u64 = 0x6b65_796c_6974_6521;
+
+    let mut h = SEED ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut words = key.chunks_exact(8);
+    for word in &mut words {
+        let mut k = u64::from_le_bytes(word.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = words.remainder();
+    if !tail.is_empty() {
+        let mut buf = [0u8; 8];
+        buf[..tail.len()].copy_from_slice(tail);
+        h ^= u64::from_le_bytes(buf);
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// bit positions of the probes for a key hash, h2 is made odd so that consecutive probes never
+// land on the same bit over and over
+fn probes(hash: u64, num_probes: u32, num_bits: u64) -> impl Iterator<Item = usize> {
+    let h2 = hash.rotate_left(32) | 1;
+    (0..num_probes as u64).map(move |i| (hash.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+}
+
+// crc32 based hash of the legacy filters, using the given seed
+fn legacy_hash(key: &[u8], seed: u32) -> u64 {
+    let mut hasher = Hasher::new();
+    hasher.update(&seed.to_le_bytes());
+    hasher.update(key);
+    hasher.finalize() as u64
+}
+
+// reads the bloom filter of a table using mmap
 //
-// the bloom filter data is stored in the file as follows:
+// the filter block is stored in the file as follows:
 //
-// | bloom len | bloom data in bytes | crc32 |
-// |  u32      |    [u8; len]        |  u32  |
+// | bloom len | filter block in bytes | crc32 |
+// |  u32      |    [u8; len]          |  u32  |
 //
 // first read the bloom len which is 4 bytes long
-// then read the bloom data which is len bytes long
+// then read the filter block which is len bytes long
 // then read and verify the crc32 which is 4 bytes long
 // if the crc32 doesn't match then we can tell that the bloom filter is corrupted
-pub fn read_bloom_filter(mmap: &Mmap, offset: u64) -> Result<BloomFilter> {
+//
+// the table version tells whether the block starts with the filter parameters (v7+) or holds the
+// bits of a legacy filter
+pub fn read_bloom_filter(mmap: &Mmap, offset: u64, version: u32) -> Result<BloomFilter> {
     let mut pos = offset as usize;
 
     let bloom_len = super::to_u32(&mmap[pos..pos + 4])? as usize;
@@ -117,5 +224,5 @@ pub fn read_bloom_filter(mmap: &Mmap, offset: u64) -> Result<BloomFilter> {
         return Err(SSTError::Corrupt);
     }
 
-    Ok(BloomFilter::new(bloom_data.to_vec()))
+    BloomFilter::decode(bloom_data, version)
 }
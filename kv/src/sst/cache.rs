@@ -0,0 +1,68 @@
+// block cache, shared by every sstable of a database
+//
+// holds data blocks that were already read from the mmap, checksummed and decompressed, keyed by
+// (sst id, block offset). sst ids are never reused so an entry can't be confused with a block of
+// a newer table, entries of tables removed by compaction are evicted right away to free the room
+// for live data
+//
+// blocks are handed out as Arc<[u8]> so a hit is just a refcount bump, no copy and no crc check
+
+use quick_cache::sync::Cache;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub type BlockKey = (u64, u64);
+
+pub struct BlockCache {
+    blocks: Cache<BlockKey, Arc<[u8]>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // number of blocks currently cached
+    pub entries: usize,
+}
+
+impl BlockCache {
+    // capacity is the number of blocks the cache may hold
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            blocks: Cache::new(capacity.max(1)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // returns the cached block or loads it with `load` and caches it
+    pub fn get_or_load<E>(
+        &self,
+        key: BlockKey,
+        load: impl FnOnce() -> Result<Arc<[u8]>, E>,
+    ) -> Result<Arc<[u8]>, E> {
+        if let Some(block) = self.blocks.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(block);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let block = load()?;
+        self.blocks.insert(key, Arc::clone(&block));
+        Ok(block)
+    }
+
+    pub fn remove(&self, key: BlockKey) {
+        self.blocks.remove(&key);
+    }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.blocks.len(),
+        }
+    }
+}
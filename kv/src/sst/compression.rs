@@ -0,0 +1,81 @@
+// block compression (format version 3+)
+//
+// every data block records the codec it was written with in a single byte right after the block
+// length, so the codec can be changed per database without rewriting existing tables and tables
+// written with different codecs can be merged by compaction:
+//
+// | block len (u32) | codec (u8) | block data (len bytes, compressed) | crc32 (u32) |
+//
+// the crc covers the codec byte and the stored (compressed) bytes, so corruption is detected
+// before anything is handed to a decompressor
+//
+// a block that doesn't get smaller is stored as is with codec None, this keeps already compressed
+// or random values from paying the decompression cost for nothing
+
+use std::borrow::Cow;
+
+use super::{Result, SSTError};
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionType {
+    None,
+    #[default]
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+impl CompressionType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Zstd => 2,
+            CompressionType::Snappy => 3,
+        }
+    }
+
+    pub fn from_u8(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Lz4),
+            2 => Ok(CompressionType::Zstd),
+            3 => Ok(CompressionType::Snappy),
+            _ => Err(SSTError::Corrupt),
+        }
+    }
+
+    // compresses a block, returns the codec that was actually used together with the bytes to
+    // store, which is None whenever compression didn't pay off
+    pub fn compress(self, data: &[u8]) -> (CompressionType, Cow<'_, [u8]>) {
+        let compressed = match self {
+            CompressionType::None => None,
+            CompressionType::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+            CompressionType::Snappy => snap::raw::Encoder::new().compress_vec(data).ok(),
+        };
+
+        match compressed {
+            Some(bytes) if bytes.len() < data.len() => (self, Cow::Owned(bytes)),
+            _ => (CompressionType::None, Cow::Borrowed(data)),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Cow<'_, [u8]>> {
+        match self {
+            CompressionType::None => Ok(Cow::Borrowed(data)),
+            CompressionType::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map(Cow::Owned)
+                .map_err(|_| SSTError::Corrupt),
+            CompressionType::Zstd => zstd::stream::decode_all(data)
+                .map(Cow::Owned)
+                .map_err(|_| SSTError::Corrupt),
+            CompressionType::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map(Cow::Owned)
+                .map_err(|_| SSTError::Corrupt),
+        }
+    }
+}
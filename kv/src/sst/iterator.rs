@@ -1,35 +1,163 @@
 // iterator implementation for for SSTables which will return key/val pairs
-// SSTIterator provides a forward only iterator over a single sstable file
+// SSTIterator walks a single sstable file forward through `next` and backwards through `prev`,
+// both directions keep their own position
 // the sst file is stored memory mapped and further divided into blocks currently each block is of
 // size 16 kB
 //
-// each block look something like this:
+// each block look something like this (the codec byte only exists from format version 3 on, see
+// sst/compression.rs):
 //
-// | block len (u32) | block_data (len bytes) | block crc32 (u32) |
+// | block len (u32) | codec (u8) | block_data (len bytes) | block crc32 (u32) |
 //
 // the block_data looks something like this:
 //
-// | key len (u16) | val len (u32) | key (key_len bytes) | val (val_len bytes) |
+// | key len (u16) | val len (u32) | key (key_len bytes) | seq (u64) | type (u8) | val (val_len bytes) |
 //
+// the type byte only exists from format version 5 on
 
-use crc32fast::Hasher;
+use std::sync::Arc;
 
-use super::{Result, SSTError, SSTReader};
+use super::{decode_value_type, value_type_len, Result, SSTReader};
+use crate::types::ValueType;
+
+type Entry = (Vec<u8>, Vec<u8>, u64, ValueType);
+
+// where the parts of the entry starting at some offset of a block are
+struct EntryLayout {
+    key_start: usize,
+    seq_start: usize,
+    val_start: usize,
+    val_end: usize,
+}
+
+impl EntryLayout {
+    // None once the rest of the block can't hold another entry
+    fn at(data: &[u8], idx: usize, version: u32) -> Result<Option<Self>> {
+        if idx + 6 > data.len() {
+            return Ok(None);
+        }
+
+        let key_len = super::to_u16(&data[idx..idx + 2])? as usize;
+        let val_len = super::to_u32(&data[idx + 2..idx + 6])? as usize;
+
+        let key_start = idx + 6;
+        let seq_start = key_start + key_len;
+        let val_start = seq_start + 8 + value_type_len(version);
+
+        if val_start + val_len > data.len() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            key_start,
+            seq_start,
+            val_start,
+            val_end: val_start + val_len,
+        }))
+    }
+
+    fn key<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.key_start..self.seq_start]
+    }
+
+    fn decode(&self, data: &[u8], version: u32) -> Result<Entry> {
+        let key = self.key(data).to_vec();
+        let seq = super::to_u64(&data[self.seq_start..self.seq_start + 8])?;
+        let value = data[self.val_start..self.val_end].to_vec();
+        let value_type = decode_value_type(version, data[self.val_start - 1], &value)?;
+        Ok((key, value, seq, value_type))
+    }
+}
 
 pub struct SSTIterator {
     reader: SSTReader,
     block_idx: usize,
-    current_block_data: Vec<u8>,
+    current_block_data: Arc<[u8]>,
     current_block_pos: usize,
+    // set by seek, entries with a smaller key are skipped until the first one at or after it
+    seek_key: Option<Vec<u8>>,
+    // the backwards walk decodes a whole block at a time and hands its entries out from the back,
+    // rev_block_idx is the number of blocks in front of the ones already decoded
+    rev_entries: Vec<Entry>,
+    rev_block_idx: usize,
+    // set by seek_for_prev, entries with a bigger key are left out of the first block decoded
+    rev_seek_key: Option<Vec<u8>>,
 }
 
 impl SSTIterator {
     pub fn new(reader: SSTReader) -> Self {
+        let rev_block_idx = reader.block_indexes.len();
         Self {
             reader,
             block_idx: 0,
-            current_block_data: Vec::new(),
+            current_block_data: Arc::from(Vec::new()),
             current_block_pos: 0,
+            seek_key: None,
+            rev_entries: Vec::new(),
+            rev_block_idx,
+            rev_seek_key: None,
+        }
+    }
+
+    // the table being walked, e.g. to resolve the blob pointers it returns
+    pub fn reader(&self) -> &SSTReader {
+        &self.reader
+    }
+
+    // positions the iterator at the first entry with a key >= key, nothing is read until the
+    // next call to next
+    pub fn seek(&mut self, key: &[u8]) {
+        // the versions of a key can start in the block before the first one whose first key is
+        // >= key, so the walk starts at the last block whose first key is smaller
+        self.block_idx = self
+            .reader
+            .block_indexes
+            .partition_point(|idx| idx.first_key.as_ref() < key)
+            .saturating_sub(1);
+        self.current_block_data = Arc::from(Vec::new());
+        self.current_block_pos = 0;
+        self.seek_key = Some(key.to_vec());
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.block_idx = 0;
+        self.current_block_data = Arc::from(Vec::new());
+        self.current_block_pos = 0;
+        self.seek_key = None;
+    }
+
+    // positions the backwards walk at the last entry with a key <= key, so the oldest version of
+    // the key comes first. nothing is read until the next call to prev
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        // every block after the last one whose first key is <= key only holds bigger keys
+        self.rev_block_idx = self
+            .reader
+            .block_indexes
+            .partition_point(|idx| idx.first_key.as_ref() <= key);
+        self.rev_entries.clear();
+        self.rev_seek_key = Some(key.to_vec());
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.rev_block_idx = self.reader.block_indexes.len();
+        self.rev_entries.clear();
+        self.rev_seek_key = None;
+    }
+
+    // the entry before the last one prev returned, entries come in the exact reverse order of
+    // next, i.e. descending keys and the versions of a key oldest first
+    pub fn prev(&mut self) -> Option<Result<Entry>> {
+        loop {
+            if let Some(entry) = self.rev_entries.pop() {
+                return Some(Ok(entry));
+            }
+            if self.rev_block_idx == 0 {
+                return None;
+            }
+            self.rev_block_idx -= 1;
+            if let Err(e) = self.load_prev_block() {
+                return Some(Err(e));
+            }
         }
     }
 
@@ -38,31 +166,41 @@ impl SSTIterator {
             return Ok(false);
         }
 
+        // verified and decompressed by the reader, possibly straight from the block cache
         let offset = self.reader.block_indexes[self.block_idx].offset;
-        let mut pos = offset as usize;
-
-        let block_len = super::to_u32(&self.reader.mmap[pos..pos + 4])? as usize;
-        pos += 4;
-
-        self.current_block_data = self.reader.mmap[pos..pos + block_len].to_vec();
-        pos += block_len;
-
-        let crc = super::to_u32(&self.reader.mmap[pos..pos + 4])?;
-        let mut hasher = Hasher::new();
-        hasher.update(&self.current_block_data);
-        if hasher.finalize() != crc {
-            return Err(SSTError::Corrupt);
-        }
+        self.current_block_data = self.reader.read_block(offset)?;
 
         self.block_idx += 1;
         self.current_block_pos = 0;
 
         Ok(true)
     }
+
+    fn load_prev_block(&mut self) -> Result<()> {
+        let offset = self.reader.block_indexes[self.rev_block_idx].offset;
+        let data = self.reader.read_block(offset)?;
+        let version = self.reader.version();
+
+        let mut pos = 0;
+        while let Some(layout) = EntryLayout::at(&data, pos, version)? {
+            // keys are sorted within the block, the rest of it is past the key seeked to
+            if let Some(seek_key) = &self.rev_seek_key {
+                if layout.key(&data) > seek_key.as_slice() {
+                    break;
+                }
+            }
+            self.rev_entries.push(layout.decode(&data, version)?);
+            pos = layout.val_end;
+        }
+
+        // only the block seeked into can hold bigger keys, the ones in front of it can't
+        self.rev_seek_key = None;
+        Ok(())
+    }
 }
 
 impl Iterator for SSTIterator {
-    type Item = Result<(Vec<u8>, Vec<u8>, u64)>;
+    type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -74,45 +212,29 @@ impl Iterator for SSTIterator {
                 }
             }
 
-            let idx = self.current_block_pos;
             let data = &self.current_block_data;
+            let version = self.reader.version();
 
-            if idx + 6 > data.len() {
-                self.current_block_pos = data.len();
-                continue;
-            }
-
-            let key_len = match super::to_u16(&data[idx..idx + 2]) {
-                Ok(len) => len as usize,
-                Err(e) => return Some(Err(e)),
-            };
-
-            let val_len = match super::to_u32(&data[idx + 2..idx + 6]) {
-                Ok(len) => len as usize,
+            let layout = match EntryLayout::at(data, self.current_block_pos, version) {
+                Ok(Some(layout)) => layout,
+                Ok(None) => {
+                    self.current_block_pos = data.len();
+                    continue;
+                }
                 Err(e) => return Some(Err(e)),
             };
 
-            let key_start = idx + 6;
-            let seq_start = key_start + key_len;
-            let val_start = seq_start + 8;
-
-            if val_start + val_len > data.len() {
-                self.current_block_pos = data.len();
-                continue;
+            // still short of the key seeked to, skip the entry without decoding it
+            if let Some(seek_key) = &self.seek_key {
+                if layout.key(data) < seek_key.as_slice() {
+                    self.current_block_pos = layout.val_end;
+                    continue;
+                }
+                self.seek_key = None;
             }
 
-            let key = data[key_start..key_start + key_len].to_vec();
-
-            let seq = match super::to_u64(&data[seq_start..seq_start + 8]) {
-                Ok(s) => s,
-                Err(e) => return Some(Err(e)),
-            };
-
-            let value = data[val_start..val_start + val_len].to_vec();
-
-            self.current_block_pos = val_start + val_len;
-
-            return Some(Ok((key, value, seq)));
+            self.current_block_pos = layout.val_end;
+            return Some(layout.decode(data, version));
         }
     }
 }
@@ -0,0 +1,140 @@
+// meta block, a small property block written right after the bloom filter (format version 2+)
+//
+// it holds the per table information that is needed without touching the data blocks, e.g. which
+// level of the LSM tree the table belongs to and the key range it covers
+//
+// the block is stored like every other block:
+//
+// | meta len (u32) | meta data (len bytes) | crc32 (u32) |
+//
+// meta data is a list of tagged properties:
+//
+// | tag (u8) | len (u32) | value (len bytes) |
+//
+// unknown tags are skipped by the reader so newer writers can add properties without breaking
+// older readers
+
+use crc32fast::Hasher;
+use memmap2::Mmap;
+
+use super::{to_u32, to_u64, Result, SSTError};
+
+const TAG_LEVEL: u8 = 1;
+const TAG_SMALLEST_KEY: u8 = 2;
+const TAG_LARGEST_KEY: u8 = 3;
+const TAG_RANGE_DEL_OFFSET: u8 = 4;
+const TAG_BLOB_FILES: u8 = 5;
+const TAG_PREFIX_EXTRACTOR: u8 = 6;
+const TAG_PREFIX_FILTER_OFFSET: u8 = 7;
+
+#[derive(Debug, Clone, Default)]
+pub struct TableProperties {
+    pub level: u32,
+    // key range of the table, range tombstones included
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    // offset of the range tombstone block, only set if the table has one (v4+)
+    pub range_del_offset: Option<u64>,
+    // ids of the blob files the table points into (v6+), see blob/mod.rs
+    pub blob_files: Vec<u64>,
+    // name of the prefix extractor the table was written with and the offset of the prefix
+    // filter built from it, see prefix.rs
+    pub prefix_extractor: Option<String>,
+    pub prefix_filter_offset: Option<u64>,
+}
+
+impl TableProperties {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_property(&mut buf, TAG_LEVEL, &self.level.to_le_bytes());
+        put_property(&mut buf, TAG_SMALLEST_KEY, &self.smallest_key);
+        put_property(&mut buf, TAG_LARGEST_KEY, &self.largest_key);
+        if let Some(offset) = self.range_del_offset {
+            put_property(&mut buf, TAG_RANGE_DEL_OFFSET, &offset.to_le_bytes());
+        }
+        if !self.blob_files.is_empty() {
+            let ids: Vec<u8> = self
+                .blob_files
+                .iter()
+                .flat_map(|id| id.to_le_bytes())
+                .collect();
+            put_property(&mut buf, TAG_BLOB_FILES, &ids);
+        }
+        if let Some(name) = &self.prefix_extractor {
+            put_property(&mut buf, TAG_PREFIX_EXTRACTOR, name.as_bytes());
+        }
+        if let Some(offset) = self.prefix_filter_offset {
+            put_property(&mut buf, TAG_PREFIX_FILTER_OFFSET, &offset.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut props = TableProperties::default();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            if pos + 5 > data.len() {
+                return Err(SSTError::Corrupt);
+            }
+            let tag = data[pos];
+            let len = to_u32(&data[pos + 1..pos + 5])? as usize;
+            pos += 5;
+
+            if pos + len > data.len() {
+                return Err(SSTError::Corrupt);
+            }
+            let value = &data[pos..pos + len];
+            pos += len;
+
+            match tag {
+                TAG_LEVEL => props.level = to_u32(value)?,
+                TAG_SMALLEST_KEY => props.smallest_key = value.to_vec(),
+                TAG_LARGEST_KEY => props.largest_key = value.to_vec(),
+                TAG_RANGE_DEL_OFFSET => props.range_del_offset = Some(to_u64(value)?),
+                TAG_BLOB_FILES => {
+                    props.blob_files = value.chunks(8).map(to_u64).collect::<Result<_>>()?
+                }
+                TAG_PREFIX_EXTRACTOR => {
+                    props.prefix_extractor = Some(String::from_utf8_lossy(value).into_owned())
+                }
+                TAG_PREFIX_FILTER_OFFSET => props.prefix_filter_offset = Some(to_u64(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(props)
+    }
+}
+
+fn put_property(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    buf.push(tag);
+    buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buf.extend_from_slice(value);
+}
+
+// reads and verifies the meta block stored at the given offset
+pub fn read_properties(mmap: &Mmap, offset: u64) -> Result<TableProperties> {
+    let mut pos = offset as usize;
+    if pos + 4 > mmap.len() {
+        return Err(SSTError::Corrupt);
+    }
+
+    let meta_len = to_u32(&mmap[pos..pos + 4])? as usize;
+    pos += 4;
+
+    if pos + meta_len + 4 > mmap.len() {
+        return Err(SSTError::Corrupt);
+    }
+    let meta_data = &mmap[pos..pos + meta_len];
+    pos += meta_len;
+
+    let crc = to_u32(&mmap[pos..pos + 4])?;
+    let mut hasher = Hasher::new();
+    hasher.update(meta_data);
+    if hasher.finalize() != crc {
+        return Err(SSTError::Corrupt);
+    }
+
+    TableProperties::decode(meta_data)
+}
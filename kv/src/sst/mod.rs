@@ -5,11 +5,13 @@
 // │            data Blocks (N)              │
 // │ each block:                             │
 // │  block_len (u32)                        │
-// │  entries:                               │
+// │  codec (u8) (v3+)                       │
+// │  entries (compressed with codec):       │
 // │    key_len (u16)                        │
 // │    val_len (u32)                        │
 // │    key bytes                            │
 // │    seq (u64)                            │
+// │    value type (u8) (v5+)                │
 // │    value bytes                          │
 // │  block_crc32 (u32)                      │
 // ├─────────────────────────────────────────┤
@@ -20,9 +22,28 @@
 // ├─────────────────────────────────────────┤
 // │              bloom Filter               │
 // │  bloom_len (u32)                        │
+// │  kind (u8) | num_probes (u8) (v7+)      │
 // │  bloom_data[...]                        │
 // │  crc32                                  │
 // ├─────────────────────────────────────────┤
+// │      range tombstone block (v4+)        │
+// │  only present if the table has any      │
+// │  len (u32)                              │
+// │  repeated: start | end | seq            │
+// │  crc32                                  │
+// ├─────────────────────────────────────────┤
+// │          prefix filter (v7+)            │
+// │  only present if the table was written  │
+// │  with a prefix extractor, laid out like │
+// │  the bloom filter                       │
+// ├─────────────────────────────────────────┤
+// │           meta Block (v2+)              │
+// │  meta_len (u32)                         │
+// │  repeated: tag | len | value            │
+// │  crc32                                  │
+// ├─────────────────────────────────────────┤
+// │  meta_offset (u64) (v2+)                │
+// ├─────────────────────────────────────────┤
 // │                 footer                  │
 // │  magic (u64)                            │
 // │  version (u32)                          │
@@ -32,23 +53,56 @@
 // │  max_sequence (u64)                     │
 // └─────────────────────────────────────────┘
 //
+// the footer itself never changes size, newer versions only add data in front of it so a reader
+// can always parse the last FOOTER_SIZE bytes first and then decide what else to read based on
+// the version
+//
+// version 1: no meta block
+// version 2: meta block with level and key range, see sst/meta.rs
+// version 3: every data block carries the codec it's compressed with, see sst/compression.rs
+// version 4: range tombstones, see sst/range_del.rs
+// version 5: every entry carries its ValueType, older tables store a tombstone as an empty value
+// version 6: BlobIndex entries pointing into blob files, listed in the meta block, see
+//            blob/mod.rs
+// version 7: the bloom filter is sized by key count and starts with its kind and number of
+//            probes, see sst/bloom.rs
 //
+// tables with a version newer than FORMAT_VERSION are refused, an older reader would silently
+// miss whatever the new version added (e.g. the range tombstones of v4 and resurrect deleted data)
 
 pub mod bloom;
+pub mod cache;
+pub mod compression;
 pub mod iterator;
+pub mod meta;
+pub mod range_del;
 pub mod reader;
 pub mod writer;
 
 use std::io;
 use thiserror::Error;
 
+use crate::types::ValueType;
+
+pub use bloom::BloomFilter;
+pub use cache::{BlockCache, BlockCacheStats};
+pub use compression::CompressionType;
 pub use iterator::SSTIterator;
+pub use meta::TableProperties;
 pub use reader::SSTReader;
 pub use writer::SSTWriter;
 
 pub const BLOCK_SIZE: usize = 16 * 1024;
+// upper bound of a table's bloom filter, enough for ~13M keys at the default bits per key
+pub const BLOOM_SIZE: usize = 16 * 1024 * 1024;
+pub const BLOOM_BITS_PER_KEY: usize = 10;
 pub const FOOTER_SIZE: usize = 52;
 pub const MAGIC: u64 = 0x4B45594C54_u64;
+pub const FORMAT_VERSION: u32 = 7;
+// the data and index blocks store key lengths as u16
+pub const MAX_KEY_SIZE: usize = u16::MAX as usize;
+// and value lengths as u32, the WAL does the same
+pub const MAX_VALUE_SIZE: usize = u32::MAX as usize;
 
 #[derive(Debug, Error)]
 pub enum SSTError {
@@ -58,6 +112,8 @@ pub enum SSTError {
     Corrupt,
     #[error("invalid magic number")]
     InvalidMagic,
+    #[error("unsupported format version {0}")]
+    UnsupportedVersion(u32),
     #[error("key not found")]
     NotFound,
     #[error("data conversion error: {0}")]
@@ -90,6 +146,27 @@ fn to_u64(bytes: &[u8]) -> Result<u64> {
         .map_err(|_| SSTError::ConversionError("Failed to convert bytes to u64".to_string()))
 }
 
+// from v5 on the seq of an entry is followed by its value type, older tables store a tombstone as
+// an empty value instead
+#[inline]
+fn value_type_len(version: u32) -> usize {
+    if version >= 5 {
+        1
+    } else {
+        0
+    }
+}
+
+// value type of an entry, `tag` is the byte right after the seq for v5+ tables
+#[inline]
+fn decode_value_type(version: u32, tag: u8, value: &[u8]) -> Result<ValueType> {
+    if version >= 5 {
+        ValueType::from_u8(tag).ok_or(SSTError::Corrupt)
+    } else {
+        Ok(ValueType::from_untagged(value))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Footer {
     pub magic: u64,
@@ -99,6 +176,8 @@ pub struct Footer {
     pub num_entries: u64,
     pub min_sequence: u64,
     pub max_sequence: u64,
+    // offset of the meta block, 0 for version 1 tables that don't have one
+    pub meta_offset: u64,
 }
 
 #[derive(Debug, Clone)]
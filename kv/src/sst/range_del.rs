@@ -0,0 +1,74 @@
+// range tombstone block (format version 4+), written between the bloom filter and the meta block
+// of tables that hold range tombstones, its offset is a property of the meta block
+//
+// | block len (u32) | block data (len bytes) | crc32 (u32) |
+//
+// block data:
+//
+// | count (u32) | (start len (u32) | end len (u32) | seq (u64) | start | end)* |
+//
+// tables are expected to carry few range tombstones, the reader keeps all of them in memory
+
+use crc32fast::Hasher;
+use memmap2::Mmap;
+
+use crate::types::RangeTombstone;
+
+use super::{to_u32, to_u64, Result, SSTError};
+
+pub fn encode(tombstones: &[RangeTombstone]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(tombstones.len() as u32).to_le_bytes());
+    for t in tombstones {
+        buf.extend_from_slice(&(t.start.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(t.end.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&t.seq.to_le_bytes());
+        buf.extend_from_slice(&t.start);
+        buf.extend_from_slice(&t.end);
+    }
+    buf
+}
+
+// reads and verifies the range tombstone block stored at the given offset
+pub fn read_range_tombstones(mmap: &Mmap, offset: u64) -> Result<Vec<RangeTombstone>> {
+    let mut pos = offset as usize;
+    if pos + 4 > mmap.len() {
+        return Err(SSTError::Corrupt);
+    }
+
+    let len = to_u32(&mmap[pos..pos + 4])? as usize;
+    pos += 4;
+
+    if pos + len + 4 > mmap.len() {
+        return Err(SSTError::Corrupt);
+    }
+    let data = &mmap[pos..pos + len];
+    pos += len;
+
+    let crc = to_u32(&mmap[pos..pos + 4])?;
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    if hasher.finalize() != crc {
+        return Err(SSTError::Corrupt);
+    }
+
+    let mut pos = 0;
+    let mut take = |n: usize| -> Result<&[u8]> {
+        let bytes = data.get(pos..pos + n).ok_or(SSTError::Corrupt)?;
+        pos += n;
+        Ok(bytes)
+    };
+
+    let count = to_u32(take(4)?)?;
+    let mut tombstones = Vec::new();
+    for _ in 0..count {
+        let start_len = to_u32(take(4)?)? as usize;
+        let end_len = to_u32(take(4)?)? as usize;
+        let seq = to_u64(take(8)?)?;
+        let start = take(start_len)?.to_vec();
+        let end = take(end_len)?.to_vec();
+        tombstones.push(RangeTombstone { start, end, seq });
+    }
+
+    Ok(tombstones)
+}
@@ -1,22 +1,93 @@
 use crc32fast::Hasher;
 use memmap2::Mmap;
+use std::borrow::Cow;
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use super::{bloom::BloomFilter, BlockIndex, Footer, Result, SSTError, FOOTER_SIZE, MAGIC, to_u16, to_u32, to_u64};
+use crate::blob::{BlobFile, BlobFiles, BlobPointer};
+use crate::prefix::{prefix_end, PrefixExtractor};
+use crate::stats::{Statistics, Ticker};
+use crate::types::{covering_seq, inline_value, Lookup, RangeTombstone, ValueType};
+
+use super::{
+    bloom::BloomFilter, cache::BlockCache, decode_value_type, meta::read_properties,
+    range_del::read_range_tombstones, to_u16, to_u32, to_u64, value_type_len, BlockIndex,
+    CompressionType, Footer, Result, SSTError, FOOTER_SIZE, FORMAT_VERSION, MAGIC,
+};
+
+// outcome of searching a single block for a key
+enum BlockSearch {
+    // seq, type and value of the version
+    Found(u64, ValueType, Vec<u8>),
+    // an entry with a bigger key was reached, no later block can contain the key either
+    Passed,
+    // the block ended before passing the key, the next block may still hold it
+    Continue,
+}
 
-pub struct SSTReader {
+// the mapped file of a table, shared by every handle to it
+//
+// compaction can't delete the file of an input table while a scan or a snapshot read still holds
+// a handle to it, it marks the file obsolete instead and the last handle to go away deletes it
+struct TableFile {
     path: PathBuf,
-    pub(super) mmap: Mmap,
+    mmap: Mmap,
+    obsolete: AtomicBool,
+}
+
+impl Drop for TableFile {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::Acquire) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// cloning a reader is cheap, every clone shares the mapped file and the decoded index, bloom
+// filter and tombstones
+#[derive(Clone)]
+pub struct SSTReader {
+    file: Arc<TableFile>,
     pub(super) block_indexes: Arc<Vec<BlockIndex>>,
     bloom_filter: Arc<BloomFilter>,
+    // filter over the key prefixes and the name of the extractor they come from (v7+), only
+    // there if the table was written with a prefix extractor
+    prefix_filter: Option<Arc<(String, BloomFilter)>>,
     min_sequence: u64,
     max_sequence: u64,
+    // format version from the footer, decides how data blocks are laid out
+    version: u32,
+    id: u64,
+    level: u32,
+    smallest_key: Arc<[u8]>,
+    largest_key: Arc<[u8]>,
+    range_tombstones: Arc<Vec<RangeTombstone>>,
+    // blob files the BlobIndex entries of this table point into (v6+)
+    blob_files: Arc<Vec<Arc<BlobFile>>>,
+    cache: Option<Arc<BlockCache>>,
 }
 
 impl SSTReader {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_cache(path, None)
+    }
+
+    // same as open, but data blocks are served from and added to the given block cache
+    pub fn open_with_cache(path: impl AsRef<Path>, cache: Option<Arc<BlockCache>>) -> Result<Self> {
+        let path = path.as_ref();
+        let blobs = BlobFiles::new(path.parent().unwrap_or(Path::new(".")));
+        Self::open_with_blobs(path, cache, &blobs)
+    }
+
+    // same as open_with_cache, the blob files the table points into are taken from the given
+    // registry so every table of a database shares a single mapping of each of them
+    pub fn open_with_blobs(
+        path: impl AsRef<Path>,
+        cache: Option<Arc<BlockCache>>,
+        blobs: &BlobFiles,
+    ) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
         let file = File::open(&path)?;
         let mmap = unsafe { Mmap::map(&file)? };
@@ -26,25 +97,158 @@ impl SSTReader {
             return Err(SSTError::Corrupt);
         }
 
-        let footer_bytes = &mmap[mmap.len() - FOOTER_SIZE..];
-        let footer = Self::parse_footer(footer_bytes)?;
+        let footer = Self::parse_footer(&mmap)?;
 
         let block_indexes = Self::read_index_block(&mmap, footer.index_offset)?;
-        let bloom_filter = super::bloom::read_bloom_filter(&mmap, footer.bloom_offset)?;
+        let bloom_filter =
+            super::bloom::read_bloom_filter(&mmap, footer.bloom_offset, footer.version)?;
         let min_sequence = footer.min_sequence;
         let max_sequence = footer.max_sequence;
 
+        // version 1 tables don't carry a meta block, they were written before levels existed so
+        // they are treated as level 0 and their key range is recovered from the data itself
+        let mut range_tombstones = Vec::new();
+        let mut blob_files = Vec::new();
+        let mut prefix_filter = None;
+        let (level, smallest_key, largest_key) = if footer.version >= 2 {
+            let props = read_properties(&mmap, footer.meta_offset)?;
+            if let Some(offset) = props.range_del_offset {
+                range_tombstones = read_range_tombstones(&mmap, offset)?;
+            }
+            if let (Some(name), Some(offset)) = (props.prefix_extractor, props.prefix_filter_offset)
+            {
+                let filter = super::bloom::read_bloom_filter(&mmap, offset, footer.version)?;
+                prefix_filter = Some(Arc::new((name, filter)));
+            }
+            for id in &props.blob_files {
+                blob_files.push(blobs.get_or_open(*id)?);
+            }
+            (props.level, props.smallest_key, props.largest_key)
+        } else {
+            let smallest = block_indexes
+                .first()
+                .map(|idx| idx.first_key.to_vec())
+                .unwrap_or_default();
+            let largest = Self::last_key_in_block(&mmap, footer.version, block_indexes.last())?;
+            (0, smallest, largest)
+        };
+
         Ok(Self {
-            path,
-            mmap,
+            id: parse_sst_id(&path).unwrap_or(0),
+            file: Arc::new(TableFile {
+                path,
+                mmap,
+                obsolete: AtomicBool::new(false),
+            }),
             block_indexes: Arc::new(block_indexes),
             bloom_filter: Arc::new(bloom_filter),
+            prefix_filter,
             min_sequence,
             max_sequence,
+            version: footer.version,
+            level,
+            smallest_key: smallest_key.into(),
+            largest_key: largest_key.into(),
+            range_tombstones: Arc::new(range_tombstones),
+            blob_files: Arc::new(blob_files),
+            cache,
         })
     }
 
-    fn parse_footer(bytes: &[u8]) -> Result<Footer> {
+    // walks the entries of the given block and returns the key of the last one
+    fn last_key_in_block(mmap: &Mmap, version: u32, block: Option<&BlockIndex>) -> Result<Vec<u8>> {
+        let Some(block) = block else {
+            return Ok(Vec::new());
+        };
+
+        let data = Self::read_block_at(mmap, version, block.offset)?;
+
+        let mut last_key = Vec::new();
+        let mut idx = 0;
+        while idx + 6 <= data.len() {
+            let key_len = to_u16(&data[idx..idx + 2])? as usize;
+            let val_len = to_u32(&data[idx + 2..idx + 6])? as usize;
+            let key_start = idx + 6;
+            let next = key_start + key_len + 8 + value_type_len(version) + val_len;
+            if next > data.len() {
+                break;
+            }
+            last_key.clear();
+            last_key.extend_from_slice(&data[key_start..key_start + key_len]);
+            idx = next;
+        }
+
+        Ok(last_key)
+    }
+
+    // reads, verifies and decompresses the data block at the given offset:
+    // v1/v2: [block_len: u32][block_data...][crc32: u32]
+    // v3+:   [block_len: u32][codec: u8][block_data...][crc32: u32]
+    fn read_block_at(mmap: &Mmap, version: u32, offset: u64) -> Result<Cow<'_, [u8]>> {
+        let mut pos = offset as usize;
+        if pos + 4 > mmap.len() {
+            return Err(SSTError::Corrupt);
+        }
+
+        let block_len = to_u32(&mmap[pos..pos + 4])? as usize;
+        pos += 4;
+
+        let codec = if version >= 3 {
+            let codec = *mmap.get(pos).ok_or(SSTError::Corrupt)?;
+            pos += 1;
+            Some(codec)
+        } else {
+            None
+        };
+
+        if pos + block_len + 4 > mmap.len() {
+            return Err(SSTError::Corrupt);
+        }
+        let block_data = &mmap[pos..pos + block_len];
+        pos += block_len;
+
+        let crc = to_u32(&mmap[pos..pos + 4])?;
+        let mut hasher = Hasher::new();
+        if let Some(codec) = codec {
+            hasher.update(&[codec]);
+        }
+        hasher.update(block_data);
+        if hasher.finalize() != crc {
+            return Err(SSTError::Corrupt);
+        }
+
+        match codec {
+            Some(codec) => CompressionType::from_u8(codec)?.decompress(block_data),
+            None => Ok(Cow::Borrowed(block_data)),
+        }
+    }
+
+    // data block at the given offset, from the block cache if this table has one
+    pub(super) fn read_block(&self, offset: u64) -> Result<Arc<[u8]>> {
+        let load = || Self::read_block_at(&self.file.mmap, self.version, offset).map(Arc::from);
+        match &self.cache {
+            Some(cache) => cache.get_or_load((self.id, offset), load),
+            None => load(),
+        }
+    }
+
+    // drops every block of this table from the block cache, called once the table is deleted
+    pub fn evict_blocks(&self) {
+        if let Some(cache) = &self.cache {
+            for block in self.block_indexes.iter() {
+                cache.remove((self.id, block.offset));
+            }
+        }
+    }
+
+    // the table is no longer part of the database, its file is deleted as soon as the last
+    // handle to it is dropped, possibly right away
+    pub fn mark_obsolete(&self) {
+        self.file.obsolete.store(true, Ordering::Release);
+    }
+
+    fn parse_footer(mmap: &Mmap) -> Result<Footer> {
+        let bytes = &mmap[mmap.len() - FOOTER_SIZE..];
         // footer layout (must match writer):
         // 0..8    magic (u64) = "KEYLT"
         // 8..12   version (u32)
@@ -61,12 +265,26 @@ impl SSTReader {
         }
 
         let version = to_u32(&bytes[8..12])?;
+        if version > FORMAT_VERSION {
+            return Err(SSTError::UnsupportedVersion(version));
+        }
         let index_offset = to_u64(&bytes[12..20])?;
         let bloom_offset = to_u64(&bytes[20..28])?;
         let num_entries = to_u64(&bytes[28..36])?;
         let min_sequence = to_u64(&bytes[36..44])?;
         let max_sequence = to_u64(&bytes[44..52])?;
 
+        // version 2+ stores the meta block offset right in front of the footer
+        let meta_offset = if version >= 2 {
+            let end = mmap.len() - FOOTER_SIZE;
+            if end < 8 {
+                return Err(SSTError::Corrupt);
+            }
+            to_u64(&mmap[end - 8..end])?
+        } else {
+            0
+        };
+
         Ok(Footer {
             magic,
             version,
@@ -75,6 +293,7 @@ impl SSTReader {
             num_entries,
             min_sequence,
             max_sequence,
+            meta_offset,
         })
     }
 
@@ -124,390 +343,302 @@ impl SSTReader {
 
         Ok(indexes)
     }
-
-    /// simple point lookup (no version merging yet).
+    /// point lookup of the newest version of the key
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        // fast negative path via bloom filter
-        if !self.bloom_filter.might_contain(key) {
-            return Ok(None);
-        }
+        Ok(self.lookup(key, u64::MAX)?.into_value())
+    }
 
-        // find the block whose first_key <= key
-        let block_idx = match self
-            .block_indexes
-            .binary_search_by(|idx| idx.first_key.as_ref().cmp(key))
-        {
-            Ok(i) => i,
-            Err(0) => return Ok(None),
-            Err(i) => i - 1,
-        };
+    /// point lookup of the newest version of the key with seq < snapshot_seq
+    pub fn get_seq(&self, key: &[u8], snapshot_seq: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.lookup(key, snapshot_seq)?.into_value())
+    }
 
-        // IMPORTANT: Because block boundaries are determined by size (not by key changes),
-        // entries for the same key with different sequence numbers can span multiple blocks.
-        // The entry with the highest sequence number will appear first in the file.
-        //
-        // Simple solution: Check the found block and the previous block (if it exists).
-        // This handles the common case where a key's versions span at most 2 blocks.
-
-        let start_idx = if block_idx > 0 { block_idx - 1 } else { 0 };
-
-        // Search from start_idx to block_idx, returning the FIRST result found.
-        // IMPORTANT: If we find a tombstone (Ok(None)), we must return it immediately,
-        // not continue searching, because the tombstone has the highest sequence number
-        // and represents the most recent state of the key.
-        for idx in start_idx..=block_idx {
-            match self.search_block(self.block_indexes[idx].offset, key) {
-                Ok(val) => {
-                    // Found the key (either with a value or as a tombstone)
-                    return Ok(val);
-                }
-                Err(super::SSTError::NotFound) => {
-                    // Key not in this block, continue to next block
-                    continue;
-                }
-                Err(e) => {
-                    // Other error
-                    return Err(e);
-                }
-            }
+    /// looks up the newest version of the key with seq < snapshot_seq
+    ///
+    /// unlike get this tells a tombstone (`Lookup::Deleted`) apart from a key this table knows
+    /// nothing about (`Lookup::Absent`), a tombstone hides every older version stored in older
+    /// tables so the caller must stop searching
+    pub fn lookup(&self, key: &[u8], snapshot_seq: u64) -> Result<Lookup> {
+        Ok(match self.lookup_version(key, snapshot_seq)? {
+            Some((_, ValueType::Delete, _)) => Lookup::Deleted,
+            Some((_, value_type, val)) => Lookup::Value(self.resolve_value(value_type, val)?),
+            None => Lookup::Absent,
+        })
+    }
+
+    /// the value a Put, ExpiringPut or BlobIndex version of this table stands for, a BlobIndex
+    /// version's value is read from the blob file it points into. whether an ExpiringPut version
+    /// expired is up to the caller
+    pub fn resolve_value(&self, value_type: ValueType, value: Vec<u8>) -> Result<Vec<u8>> {
+        if value_type != ValueType::BlobIndex {
+            return Ok(inline_value(value_type, value));
         }
+        let pointer = BlobPointer::decode(&value)?;
+        let file = self
+            .blob_files
+            .iter()
+            .find(|f| f.id() == pointer.file_id)
+            .ok_or(SSTError::Corrupt)?;
+        Ok(file.read(&pointer)?)
+    }
 
-        Ok(None)
+    /// ids of the blob files this table points into
+    pub fn blob_file_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.blob_files.iter().map(|f| f.id())
     }
 
-    pub fn get_seq(&self, key: &[u8], snapshot_seq: u64) -> Result<Option<Vec<u8>>> {
-        // Quick check: if snapshot is before this SST's min sequence, no data visible
+    /// sequence number of the newest version of the key in this table, tombstones included
+    pub fn newest_sequence(&self, key: &[u8]) -> Result<Option<u64>> {
+        Ok(self.lookup_version(key, u64::MAX)?.map(|(seq, _, _)| seq))
+    }
+
+    /// bloom filter of the table, consulted before any data block is read
+    pub fn bloom_filter(&self) -> &BloomFilter {
+        &self.bloom_filter
+    }
+
+    /// range tombstones stored in this table
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// seq of the newest range tombstone of this table covering the key with seq < snapshot_seq,
+    /// 0 if there is none
+    pub fn covering_seq(&self, key: &[u8], snapshot_seq: u64) -> u64 {
+        covering_seq(self.range_tombstones.iter(), key, snapshot_seq)
+    }
+
+    // seq, type and value of the newest version of the key with seq < snapshot_seq, range
+    // tombstones are not taken into account
+    pub(crate) fn lookup_version(
+        &self,
+        key: &[u8],
+        snapshot_seq: u64,
+    ) -> Result<Option<(u64, ValueType, Vec<u8>)>> {
+        self.lookup_version_counted(key, snapshot_seq, None)
+    }
+
+    /// the same, counting what the bloom filter did for the lookup into `stats`
+    pub(crate) fn lookup_version_counted(
+        &self,
+        key: &[u8],
+        snapshot_seq: u64,
+        stats: Option<&Statistics>,
+    ) -> Result<Option<(u64, ValueType, Vec<u8>)>> {
+        // quick check: if snapshot is before this SST's min sequence, no data visible
         if snapshot_seq <= self.min_sequence {
             return Ok(None);
         }
 
+        // fast negative path via bloom filter
         if !self.bloom_filter.might_contain(key) {
+            if let Some(stats) = stats {
+                stats.add(Ticker::BloomUseful, 1);
+            }
             return Ok(None);
         }
 
-        // If snapshot is after all data in this SST, use regular get
-        if snapshot_seq > self.max_sequence {
-            return self.get(key);
+        let found = self.search_blocks(key, snapshot_seq)?;
+        if let Some(stats) = stats {
+            // a version hidden from the snapshot counts as a false positive too, the key is in
+            // the table but the lookup gained nothing from reading it
+            let ticker = match found {
+                Some(_) => Ticker::BloomTruePositive,
+                None => Ticker::BloomFalsePositive,
+            };
+            stats.add(ticker, 1);
         }
+        Ok(found)
+    }
 
-        let block_idx = match self
+    fn search_blocks(
+        &self,
+        key: &[u8],
+        snapshot_seq: u64,
+    ) -> Result<Option<(u64, ValueType, Vec<u8>)>> {
+        // because block boundaries are determined by size (not by key changes), the versions of
+        // a key can span multiple blocks, the newest one comes first in the file
+        // so start at the last block whose first key is smaller than the key (it may hold the
+        // first versions) and walk forward while blocks can still contain the key
+        let start = self
             .block_indexes
-            .binary_search_by(|idx| idx.first_key.as_ref().cmp(key))
-        {
-            Ok(i) => i,
-            Err(0) => return Ok(None),
-            Err(i) => i - 1,
-        };
-
-        let stard_idx = if block_idx > 0 { block_idx - 1 } else { 0 };
+            .partition_point(|idx| idx.first_key.as_ref() < key)
+            .saturating_sub(1);
 
-        let mut best: Option<(u64, Option<Vec<u8>>)> = None;
+        for idx in start..self.block_indexes.len() {
+            let block = &self.block_indexes[idx];
+            if idx > start && block.first_key.as_ref() > key {
+                break;
+            }
 
-        for idx in stard_idx..=block_idx {
-            if let Some((ent_seq, ent_val)) =
-                self.search_block_seq(self.block_indexes[idx].offset, key, snapshot_seq)?
-            {
-                if best.is_none() || ent_seq > best.as_ref().map(|(s, _)| *s).unwrap_or(0) {
-                    best = Some((ent_seq, ent_val));
+            match self.search_block(block.offset, key, snapshot_seq)? {
+                BlockSearch::Found(seq, value_type, val) => {
+                    return Ok(Some((seq, value_type, val)))
                 }
+                BlockSearch::Passed => break,
+                BlockSearch::Continue => continue,
             }
         }
-        match best {
-            Some((_, Some(v))) => Ok(Some(v)),
-            Some((_, None)) => Ok(None),
-            None => Ok(None),
-        }
+
+        Ok(None)
     }
 
-    /// search for a key within a specific block.
+    /// search for the newest visible version of a key within a specific block
     ///
-    /// returns:
-    /// - `Ok(Some(val))` if key found with a non-empty value
-    /// - `Ok(None)` if key found with empty value (tombstone/deleted)
-    /// - `Err(SSTError::NotFound)` if key not in this block
-    /// - `Err(other)` for other errors
-    fn search_block(&self, offset: u64, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        // read and verify block at given offset:
-        // [block_len: u32][block_data...][crc32: u32]
-        let block_data = {
-            let mut pos = offset as usize;
-
-            let block_len = to_u32(&self.mmap[pos..pos + 4])? as usize;
-            pos += 4;
-
-            let block_data = &self.mmap[pos..pos + block_len];
-            pos += block_len;
-
-            let crc = to_u32(&self.mmap[pos..pos + 4])?;
-            let mut hasher = Hasher::new();
-            hasher.update(block_data);
-            if hasher.finalize() != crc {
-                return Err(SSTError::Corrupt);
-            }
-
-            Arc::new(block_data.to_vec())
-        };
+    /// entries are sorted by key and then by seq descending, so the first entry with a matching
+    /// key and seq < snapshot_seq is the one we're after
+    fn search_block(&self, offset: u64, key: &[u8], snapshot_seq: u64) -> Result<BlockSearch> {
+        let block = self.read_block(offset)?;
+        let block_data = &block[..];
 
         // entry format in block_data (must match writer) :
         // key_len (u16)
         // val_len (u32)
         // key [key_len]
         // seq (u64)
+        // value type (u8) (v5+)
         // value [val_len]
-
-        #[derive(Debug)]
-        struct EntryMeta {
-            key_start: usize,
-            key_len: usize,
-            val_start: usize,
-            val_len: usize,
-        }
-
-        let mut entries: Vec<EntryMeta> = Vec::new();
         let mut idx = 0;
         let len = block_data.len();
+        let type_len = value_type_len(self.version);
 
-        while idx < len {
-            // need at least key_len + val_len
-            if idx + 6 > len {
-                break;
-            }
-
+        while idx + 6 <= len {
             let key_len = to_u16(&block_data[idx..idx + 2])? as usize;
-            idx += 2;
+            let val_len = to_u32(&block_data[idx + 2..idx + 6])? as usize;
+            idx += 6;
 
-            let val_len = to_u32(&block_data[idx..idx + 4])? as usize;
-            idx += 4;
-
-            // now we need: key_len + 8(seq) + val_len bytes
-            if idx + key_len + 8 + val_len > len {
-                break;
+            // now we need: key_len + 8(seq) + type + val_len bytes
+            if idx + key_len + 8 + type_len + val_len > len {
+                return Err(SSTError::Corrupt);
             }
 
-            let key_start = idx;
+            let entry_key = &block_data[idx..idx + key_len];
             idx += key_len;
 
-            // nead seq but we don't use it yet in get()
-            idx += 8;
+            let seq = to_u64(&block_data[idx..idx + 8])?;
+            idx += 8 + type_len;
 
             let val_start = idx;
             idx += val_len;
 
-            entries.push(EntryMeta {
-                key_start,
-                key_len,
-                val_start,
-                val_len,
-            });
-        }
-
-        // binary search over keys inside the block, then find the entry with highest seq
-        match entries.binary_search_by(|entry| {
-            let entry_key = &block_data[entry.key_start..entry.key_start + entry.key_len];
-            entry_key.cmp(key)
-        }) {
-            Ok(mut pos) => {
-                // found a match, but there might be multiple entries for the same key
-                // with different sequence numbers within this block, find the one with the
-                // highest sequence number
-                // entries are sorted by key first, then by sequence (descending), so we need
-                // to find the first occurrence of this key (which has the highest seq).
-
-                // move backward to find the first occurrence of this key
-                while pos > 0 {
-                    let prev_entry = &entries[pos - 1];
-                    let prev_key = &block_data
-                        [prev_entry.key_start..prev_entry.key_start + prev_entry.key_len];
-                    if prev_key == key {
-                        pos -= 1;
-                    } else {
-                        break;
+            match entry_key.cmp(key) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Greater => return Ok(BlockSearch::Passed),
+                std::cmp::Ordering::Equal => {
+                    // for snapshot isolation
+                    // only return entries with seq < snapshot_seq (strict inequality)
+                    if seq < snapshot_seq {
+                        let value = block_data[val_start..val_start + val_len].to_vec();
+                        let value_type =
+                            decode_value_type(self.version, block_data[val_start - 1], &value)?;
+                        return Ok(BlockSearch::Found(seq, value_type, value));
                     }
                 }
-
-                let entry = &entries[pos];
-                let entry_val = &block_data[entry.val_start..entry.val_start + entry.val_len];
-
-                // tombstone: empty value represents deletion
-                if entry_val.is_empty() {
-                    return Ok(None);
-                }
-
-                Ok(Some(entry_val.to_vec()))
             }
-            Err(_) => Err(super::SSTError::NotFound),
         }
+
+        Ok(BlockSearch::Continue)
     }
 
-    fn search_block_seq(
-        &self,
-        offset: u64,
-        key: &[u8],
-        snapshot_seq: u64,
-    ) -> Result<Option<(u64, Option<Vec<u8>>)>> {
-        let block_data = {
-            let mut pos = offset as usize;
-            let block_len = to_u32(&self.mmap[pos..pos + 4])? as usize;
-            pos += 4;
-
-            let block_data = &self.mmap[pos..pos + block_len];
-            pos += block_len;
-
-            let crc = to_u32(&self.mmap[pos..pos + 4])?;
-            let mut hasher = Hasher::new();
-            hasher.update(block_data);
-            if hasher.finalize() != crc {
-                return Err(SSTError::Corrupt);
-            }
+    pub fn path(&self) -> &Path {
+        &self.file.path
+    }
 
-            block_data
-        };
+    pub fn min_sequence(&self) -> u64 {
+        self.min_sequence
+    }
+    pub fn max_sequence(&self) -> u64 {
+        self.max_sequence
+    }
 
-        #[derive(Clone, Debug)]
-        struct Entry {
-            seq: u64,
-            key_start: usize,
-            key_len: usize,
-            val_start: usize,
-            val_len: usize,
-        }
+    // file id, i.e. the N in sst-N.db
+    pub fn id(&self) -> u64 {
+        self.id
+    }
 
-        let mut entries = Vec::new();
-        let mut idx = 0;
-        let len = block_data.len();
+    pub fn level(&self) -> u32 {
+        self.level
+    }
 
-        while idx < len {
-            if idx + 6 > len {
-                break;
-            }
+    // format version from the footer
+    pub fn version(&self) -> u32 {
+        self.version
+    }
 
-            let key_len = to_u16(&block_data[idx..idx + 2])? as usize;
-            idx += 2;
+    // the manifest is the source of truth for the level of a table
+    pub(crate) fn set_level(&mut self, level: u32) {
+        self.level = level;
+    }
 
-            let val_len = to_u32(&block_data[idx..idx + 4])? as usize;
-            idx += 4;
+    pub fn smallest_key(&self) -> &[u8] {
+        &self.smallest_key
+    }
 
-            if idx + key_len + 8 + val_len > len {
-                break;
-            }
+    pub fn largest_key(&self) -> &[u8] {
+        &self.largest_key
+    }
 
-            let key_start = idx;
-            idx += key_len;
+    pub fn file_size(&self) -> u64 {
+        self.file.mmap.len() as u64
+    }
 
-            let seq = to_u64(&block_data[idx..idx + 8])?;
-            idx += 8;
+    // true if the key falls inside the key range covered by this table
+    pub fn may_contain_key(&self, key: &[u8]) -> bool {
+        key >= self.smallest_key() && key <= self.largest_key()
+    }
 
-            let val_start = idx;
-            idx += val_len;
+    // true if the table's key range intersects the inclusive range [smallest, largest]
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.smallest_key() <= largest && self.largest_key() >= smallest
+    }
 
-            entries.push(Entry {
-                seq,
-                key_start,
-                key_len,
-                val_start,
-                val_len,
-            });
+    /// false if the table can't hold any key starting with the prefix, neither a version nor a
+    /// range tombstone. the prefix filter is only consulted if the table was written with the
+    /// same extractor and the prefix is in its domain
+    pub fn may_contain_prefix(
+        &self,
+        prefix: &[u8],
+        extractor: Option<&dyn PrefixExtractor>,
+    ) -> bool {
+        let end = prefix_end(prefix);
+        if self.largest_key() < prefix {
+            return false;
         }
-
-        // binary search for key
-        let pos = match entries.binary_search_by(|e| {
-            let entry_key = &block_data[e.key_start..e.key_start + e.key_len];
-            entry_key.cmp(key)
-        }) {
-            Ok(pos) => pos,
-            Err(_) => return Ok(None),
-        };
-
-        let mut first = pos;
-        while first > 0 {
-            let pk = &block_data[entries[first - 1].key_start
-                ..entries[first - 1].key_start + entries[first - 1].key_len];
-            if pk == key {
-                first -= 1;
-            } else {
-                break;
-            }
+        if end.as_deref().is_some_and(|end| self.smallest_key() >= end) {
+            return false;
         }
 
-        // iterate entries for this key for seq to be in descending order
-        let mut i = first;
-        while i < entries.len() {
-            let e = &entries[i];
-            let entry_key = &block_data[e.key_start..e.key_start + e.key_len];
-            if entry_key != key {
-                break;
-            }
-
-            // for snapshot isolation
-            // only return entries with seq < snapshot_seq (strict inequality)
-            if e.seq < snapshot_seq {
-                let val_slice = &block_data[e.val_start..e.val_start + e.val_len];
-                let val_opt = if val_slice.is_empty() {
-                    None
-                } else {
-                    Some(val_slice.to_vec())
-                };
-                return Ok(Some((e.seq, val_opt)));
-            }
-
-            i += 1;
+        // a tombstone reaching into the prefix has to be seen by the scan whatever the filter says
+        if self
+            .range_tombstones
+            .iter()
+            .any(|t| t.overlaps(prefix, end.as_deref()))
+        {
+            return true;
         }
 
-        Ok(None)
-    }
-
-    pub fn path(&self) -> &Path {
-        &self.path
-    }
-
-    pub fn min_sequence(&self) -> u64 {
-        self.min_sequence
-    }
-    pub fn max_sequence(&self) -> u64 {
-        self.max_sequence
+        let (Some(extractor), Some(filter)) = (extractor, &self.prefix_filter) else {
+            return true;
+        };
+        let (name, filter) = filter.as_ref();
+        if name != extractor.name() || !extractor.in_domain(prefix) {
+            return true;
+        }
+        filter.might_contain(extractor.transform(prefix))
     }
 }
 
-impl Clone for SSTReader {
-    fn clone(&self) -> Self {
-        // cheap clone: reuse the same mmap and indexes via Arc
-        match Self::try_clone(self) {
-            Ok(reader) => reader,
-            Err(e) => {
-                // if cloning fails, log the error and try to open the file again
-                eprintln!("Failed to clone SSTReader: {}. Attempting to reopen file.", e);
-                // fallback: try to open the file again
-                match Self::open(&self.path) {
-                    Ok(reader) => reader,
-                    Err(e) => {
-                        // if even fallback fails, this is a critical error
-                        // the database is likely in an inconsistent state
-                        eprintln!(
-                            "CRITICAL: Failed to clone SSTReader at {:?} and fallback also failed: {}",
-                            self.path, e
-                        );
-                        eprintln!("This indicates a serious filesystem or memory issue.");
-                        // panic on the moment as we can't move ahead
-                        panic!("Failed to clone SSTReader and fallback also failed: {}", e)
-                    }
-                }
-            }
-        }
-    }
+// path of the table with the given id inside the database directory
+pub fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("sst-{}.db", id))
 }
 
-impl SSTReader {
-    fn try_clone(&self) -> Result<Self> {
-        let file = File::open(&self.path)?;
-        let mmap = unsafe { Mmap::map(&file)? };
-        
-        Ok(Self {
-            path: self.path.clone(),
-            mmap,
-            block_indexes: Arc::clone(&self.block_indexes),
-            bloom_filter: Arc::clone(&self.bloom_filter),
-            min_sequence: self.min_sequence,
-            max_sequence: self.max_sequence,
-        })
-    }
+// extracts N from a path of the form .../sst-N.db
+pub fn parse_sst_id(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("sst-")?
+        .strip_suffix(".db")?
+        .parse()
+        .ok()
 }
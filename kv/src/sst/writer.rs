@@ -1,13 +1,46 @@
 use crc32fast::Hasher;
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
-use std::u64;
-
-use super::{BlockIndex, Footer, BLOCK_SIZE, FOOTER_SIZE, MAGIC};
+use std::sync::Arc;
+
+use super::bloom::{self, BloomFilter};
+use super::{
+    range_del, BlockIndex, CompressionType, Footer, TableProperties, BLOCK_SIZE,
+    BLOOM_BITS_PER_KEY, BLOOM_SIZE, FOOTER_SIZE, FORMAT_VERSION, MAGIC, MAX_KEY_SIZE,
+    MAX_VALUE_SIZE,
+};
+use crate::blob::BlobPointer;
+use crate::prefix::PrefixExtractor;
+use crate::types::{RangeTombstone, ValueType};
 
 pub type Result<T> = std::result::Result<T, std::io::Error>;
 
+// knobs that shape the sstable being written, the defaults match the constants in sst/mod.rs
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    pub block_size: usize,
+    // upper bound in bytes of the bloom filter, below it the filter is sized by bloom_bits_per_key
+    pub bloom_size: usize,
+    pub bloom_bits_per_key: usize,
+    pub compression: CompressionType,
+    // the prefixes it extracts are added to a prefix filter of their own, see prefix.rs
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            block_size: BLOCK_SIZE,
+            bloom_size: BLOOM_SIZE,
+            bloom_bits_per_key: BLOOM_BITS_PER_KEY,
+            compression: CompressionType::default(),
+            prefix_extractor: None,
+        }
+    }
+}
+
 pub struct SSTWriter {
     file: BufWriter<File>,
     current_block: Vec<u8>,
@@ -15,13 +48,32 @@ pub struct SSTWriter {
     current_block_offset: u64,
     total_bytes_written: u64,
     num_entries: u64,
-    bloom_filter: Vec<u8>,
+    // hash of every distinct key, the filter is only built in finish once the key count is known
+    key_hashes: Vec<u64>,
+    bloom_size: usize,
+    bloom_bits_per_key: usize,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    // hash of every distinct prefix and the prefix of the last key added, the keys come in order
+    // so the keys sharing a prefix are next to each other
+    prefix_hashes: Vec<u64>,
+    last_prefix: Option<Vec<u8>>,
     min_sequence: u64,
     max_sequence: u64,
+    block_size: usize,
+    compression: CompressionType,
+    level: u32,
+    last_key: Vec<u8>,
+    range_tombstones: Vec<RangeTombstone>,
+    // blob files the BlobIndex entries point into, recorded in the meta block
+    blob_files: BTreeSet<u64>,
 }
 
 impl SSTWriter {
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_options(path, WriterOptions::default())
+    }
+
+    pub fn with_options(path: impl AsRef<Path>, opts: WriterOptions) -> Result<Self> {
         let file = File::create(path)?;
         Ok(Self {
             file: BufWriter::new(file),
@@ -30,13 +82,55 @@ impl SSTWriter {
             current_block_offset: 0,
             total_bytes_written: 0,
             num_entries: 0,
-            bloom_filter: vec![0u8; 16384],
+            key_hashes: Vec::new(),
+            bloom_size: opts.bloom_size,
+            bloom_bits_per_key: opts.bloom_bits_per_key,
+            prefix_extractor: opts.prefix_extractor,
+            prefix_hashes: Vec::new(),
+            last_prefix: None,
             min_sequence: u64::MAX,
             max_sequence: u64::MIN,
+            block_size: opts.block_size,
+            compression: opts.compression,
+            level: 0,
+            last_key: Vec::new(),
+            range_tombstones: Vec::new(),
+            blob_files: BTreeSet::new(),
         })
     }
 
     pub fn add(&mut self, key: &[u8], value: &[u8], seq: u64) -> Result<()> {
+        self.add_entry(key, ValueType::Put, value, seq)
+    }
+
+    // adds a version of any type, a tombstone is added with an empty value
+    pub fn add_entry(
+        &mut self,
+        key: &[u8],
+        value_type: ValueType,
+        value: &[u8],
+        seq: u64,
+    ) -> Result<()> {
+        if key.len() > MAX_KEY_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "key of {} bytes is longer than {} bytes",
+                    key.len(),
+                    MAX_KEY_SIZE
+                ),
+            ));
+        }
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "value of {} bytes is longer than {} bytes",
+                    value.len(),
+                    MAX_VALUE_SIZE
+                ),
+            ));
+        }
         if self.current_block.is_empty() {
             self.block_indexes.push(BlockIndex {
                 first_key: key.to_vec().into_boxed_slice(),
@@ -44,7 +138,14 @@ impl SSTWriter {
             });
         }
 
-        self.add_to_bloom_filter(key);
+        // the versions of a key are added one after the other, one hash covers all of them
+        if self.num_entries == 0 || self.last_key != key {
+            self.key_hashes.push(bloom::hash_key(key));
+            self.add_prefix(key);
+        }
+        if value_type == ValueType::BlobIndex {
+            self.blob_files.insert(BlobPointer::decode(value)?.file_id);
+        }
 
         self.current_block
             .extend_from_slice(&(key.len() as u16).to_le_bytes());
@@ -52,38 +153,60 @@ impl SSTWriter {
             .extend_from_slice(&(value.len() as u32).to_le_bytes());
         self.current_block.extend_from_slice(key);
         self.current_block.extend_from_slice(&(seq).to_le_bytes());
+        self.current_block.push(value_type as u8);
         self.current_block.extend_from_slice(value);
 
         self.num_entries += 1;
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
         self.min_sequence = self.min_sequence.min(seq);
         self.max_sequence = self.max_sequence.max(seq);
 
-        if self.current_block.len() >= BLOCK_SIZE {
+        if self.current_block.len() >= self.block_size {
             self.flush_block()?;
         }
 
         Ok(())
     }
 
-    fn add_to_bloom_filter(&mut self, key: &[u8]) {
-        let hash1 = self.hash_key(key, 0);
-        let hash2 = self.hash_key(key, 1);
+    // range tombstones don't go through the data blocks, they can be added in any order and end up
+    // in their own block, see sst/range_del.rs
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.min_sequence = self.min_sequence.min(tombstone.seq);
+        self.max_sequence = self.max_sequence.max(tombstone.seq);
+        self.range_tombstones.push(tombstone);
+    }
+
+    // level of the LSM tree this table is written for, recorded in the meta block
+    pub fn set_level(&mut self, level: u32) {
+        self.level = level;
+    }
 
-        let bits_len = (self.bloom_filter.len() * 8) as u64;
-        let bit_index1 = (hash1 % bits_len) as usize;
-        let bit_index2 = (hash2 % bits_len) as usize;
-        let bit_index3 = ((hash1.wrapping_add(hash2)) % bits_len) as usize;
+    // bytes written so far plus the pending block, used by compaction to cut output files
+    pub fn estimated_size(&self) -> u64 {
+        self.total_bytes_written + self.current_block.len() as u64
+    }
 
-        self.bloom_filter[bit_index1 / 8] |= 1 << (bit_index1 % 8);
-        self.bloom_filter[bit_index2 / 8] |= 1 << (bit_index2 % 8);
-        self.bloom_filter[bit_index3 / 8] |= 1 << (bit_index3 % 8);
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
     }
 
-    fn hash_key(&self, key: &[u8], seed: u32) -> u64 {
-        let mut hasher = Hasher::new();
-        hasher.update(&seed.to_le_bytes());
-        hasher.update(key);
-        hasher.finalize() as u64
+    pub fn num_range_tombstones(&self) -> usize {
+        self.range_tombstones.len()
+    }
+
+    fn add_prefix(&mut self, key: &[u8]) {
+        let Some(extractor) = &self.prefix_extractor else {
+            return;
+        };
+        if !extractor.in_domain(key) {
+            return;
+        }
+        let prefix = extractor.transform(key);
+        if self.last_prefix.as_deref() != Some(prefix) {
+            self.prefix_hashes.push(bloom::hash_key(prefix));
+            self.last_prefix = Some(prefix.to_vec());
+        }
     }
 
     fn flush_block(&mut self) -> Result<()> {
@@ -91,16 +214,21 @@ impl SSTWriter {
             return Ok(());
         }
 
+        // the codec that was actually used is recorded per block, see sst/compression.rs
+        let (codec, stored) = self.compression.compress(&self.current_block);
+        let codec = codec.to_u8();
+
         let mut hasher = Hasher::new();
-        hasher.update(&self.current_block);
+        hasher.update(&[codec]);
+        hasher.update(&stored);
         let crc = hasher.finalize();
 
-        self.file
-            .write_all(&(self.current_block.len() as u32).to_le_bytes())?;
-        self.file.write_all(&self.current_block)?;
+        self.file.write_all(&(stored.len() as u32).to_le_bytes())?;
+        self.file.write_all(&[codec])?;
+        self.file.write_all(&stored)?;
         self.file.write_all(&crc.to_le_bytes())?;
 
-        let block_total_size = 4 + self.current_block.len() + 4;
+        let block_total_size = 4 + 1 + stored.len() + 4;
         self.total_bytes_written += block_total_size as u64;
         self.current_block_offset = self.total_bytes_written;
 
@@ -135,25 +263,103 @@ impl SSTWriter {
         self.total_bytes_written += 4 + index_block.len() as u64 + 4;
 
         let bloom_offset = self.total_bytes_written;
+        let filter = BloomFilter::build(&self.key_hashes, self.bloom_bits_per_key, self.bloom_size);
+        let filter_block = filter.encode();
         let mut hasher = Hasher::new();
-        hasher.update(&self.bloom_filter);
+        hasher.update(&filter_block);
         let bloom_crc = hasher.finalize();
 
         self.file
-            .write_all(&(self.bloom_filter.len() as u32).to_le_bytes())?;
-        self.file.write_all(&self.bloom_filter)?;
+            .write_all(&(filter_block.len() as u32).to_le_bytes())?;
+        self.file.write_all(&filter_block)?;
         self.file.write_all(&bloom_crc.to_le_bytes())?;
 
-        self.total_bytes_written += 4 + self.bloom_filter.len() as u64 + 4;
+        self.total_bytes_written += 4 + filter_block.len() as u64 + 4;
+
+        let mut range_del_offset = None;
+        if !self.range_tombstones.is_empty() {
+            range_del_offset = Some(self.total_bytes_written);
+            let block = range_del::encode(&self.range_tombstones);
+            let mut hasher = Hasher::new();
+            hasher.update(&block);
+            let crc = hasher.finalize();
+
+            self.file.write_all(&(block.len() as u32).to_le_bytes())?;
+            self.file.write_all(&block)?;
+            self.file.write_all(&crc.to_le_bytes())?;
+
+            self.total_bytes_written += 4 + block.len() as u64 + 4;
+        }
+
+        let mut prefix_filter_offset = None;
+        if self.prefix_extractor.is_some() {
+            prefix_filter_offset = Some(self.total_bytes_written);
+            let filter = BloomFilter::build(
+                &self.prefix_hashes,
+                self.bloom_bits_per_key,
+                self.bloom_size,
+            );
+            let block = filter.encode();
+            let mut hasher = Hasher::new();
+            hasher.update(&block);
+            let crc = hasher.finalize();
+
+            self.file.write_all(&(block.len() as u32).to_le_bytes())?;
+            self.file.write_all(&block)?;
+            self.file.write_all(&crc.to_le_bytes())?;
+
+            self.total_bytes_written += 4 + block.len() as u64 + 4;
+        }
+
+        // the key range covers the range tombstones too so that compaction picks up every table a
+        // tombstone may shadow data in. a tombstone's end is exclusive, so the largest key can be
+        // one the table doesn't actually cover, that only costs an extra table in a compaction
+        let mut smallest_key = self.block_indexes.first().map(|idx| idx.first_key.to_vec());
+        let mut largest_key = (self.num_entries > 0).then(|| std::mem::take(&mut self.last_key));
+        for t in &self.range_tombstones {
+            if smallest_key.as_ref().is_none_or(|k| t.start < *k) {
+                smallest_key = Some(t.start.clone());
+            }
+            if largest_key.as_ref().is_none_or(|k| t.end > *k) {
+                largest_key = Some(t.end.clone());
+            }
+        }
+
+        let meta_offset = self.total_bytes_written;
+        let props = TableProperties {
+            level: self.level,
+            smallest_key: smallest_key.unwrap_or_default(),
+            largest_key: largest_key.unwrap_or_default(),
+            range_del_offset,
+            blob_files: self.blob_files.iter().copied().collect(),
+            prefix_extractor: self
+                .prefix_extractor
+                .as_ref()
+                .map(|extractor| extractor.name().to_string()),
+            prefix_filter_offset,
+        };
+        let meta_block = props.encode();
+        let mut hasher = Hasher::new();
+        hasher.update(&meta_block);
+        let meta_crc = hasher.finalize();
+
+        self.file
+            .write_all(&(meta_block.len() as u32).to_le_bytes())?;
+        self.file.write_all(&meta_block)?;
+        self.file.write_all(&meta_crc.to_le_bytes())?;
+        self.file.write_all(&meta_offset.to_le_bytes())?;
+
+        self.total_bytes_written += 4 + meta_block.len() as u64 + 4 + 8;
 
         let footer = Footer {
             magic: MAGIC,
-            version: 1,
+            version: FORMAT_VERSION,
             index_offset,
             bloom_offset,
             num_entries: self.num_entries,
             min_sequence: self.min_sequence,
             max_sequence: self.max_sequence,
+            meta_offset,
         };
 
         let mut footer_bytes = [0u8; FOOTER_SIZE];
@@ -168,6 +374,9 @@ impl SSTWriter {
         self.file.write_all(&footer_bytes)?;
         self.file.flush()?;
 
+        // the table must be durable before the manifest starts referencing it
+        self.file.get_ref().sync_all()?;
+
         Ok(())
     }
 }
@@ -0,0 +1,489 @@
+// engine statistics, see `Db::stats`
+//
+// the read and write paths, the flush and compaction workers and the WAL thread bump counters
+// (tickers) and record latencies into histograms. everything is a plain atomic updated with
+// relaxed ordering, so a snapshot isn't a consistent cut across counters but every counter is
+// exact on its own
+//
+// latencies are kept in microseconds in power-of-two buckets: bucket 0 counts zeros and bucket i
+// the values in [2^(i-1), 2^i), which is precise enough for percentiles and costs a single
+// fetch_add per value
+
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::sst::BlockCacheStats;
+
+/// a counter of the engine, see `DbStats::ticker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ticker {
+    Gets,
+    MemtableHits,
+    MemtableMisses,
+    SstProbes,
+    BloomUseful,
+    BloomTruePositive,
+    BloomFalsePositive,
+    BytesRead,
+    Writes,
+    Puts,
+    Deletes,
+    Merges,
+    RangeDeletes,
+    BytesWritten,
+    WriteStalls,
+    WriteStallMicros,
+    WalSyncs,
+    Flushes,
+    BytesFlushed,
+    FlushErrors,
+    Compactions,
+    BytesCompactedRead,
+    BytesCompactedWritten,
+    CompactionErrors,
+}
+
+impl Ticker {
+    pub const COUNT: usize = 24;
+
+    pub const ALL: [Ticker; Self::COUNT] = [
+        Ticker::Gets,
+        Ticker::MemtableHits,
+        Ticker::MemtableMisses,
+        Ticker::SstProbes,
+        Ticker::BloomUseful,
+        Ticker::BloomTruePositive,
+        Ticker::BloomFalsePositive,
+        Ticker::BytesRead,
+        Ticker::Writes,
+        Ticker::Puts,
+        Ticker::Deletes,
+        Ticker::Merges,
+        Ticker::RangeDeletes,
+        Ticker::BytesWritten,
+        Ticker::WriteStalls,
+        Ticker::WriteStallMicros,
+        Ticker::WalSyncs,
+        Ticker::Flushes,
+        Ticker::BytesFlushed,
+        Ticker::FlushErrors,
+        Ticker::Compactions,
+        Ticker::BytesCompactedRead,
+        Ticker::BytesCompactedWritten,
+        Ticker::CompactionErrors,
+    ];
+
+    /// the name of the counter in `DbStats`'s text form and, prefixed with `keylite_` and
+    /// suffixed with `_total`, in the Prometheus export
+    pub fn name(self) -> &'static str {
+        match self {
+            Ticker::Gets => "gets",
+            Ticker::MemtableHits => "memtable_hits",
+            Ticker::MemtableMisses => "memtable_misses",
+            Ticker::SstProbes => "sst_probes",
+            Ticker::BloomUseful => "bloom_useful",
+            Ticker::BloomTruePositive => "bloom_true_positives",
+            Ticker::BloomFalsePositive => "bloom_false_positives",
+            Ticker::BytesRead => "bytes_read",
+            Ticker::Writes => "writes",
+            Ticker::Puts => "puts",
+            Ticker::Deletes => "deletes",
+            Ticker::Merges => "merges",
+            Ticker::RangeDeletes => "range_deletes",
+            Ticker::BytesWritten => "bytes_written",
+            Ticker::WriteStalls => "write_stalls",
+            Ticker::WriteStallMicros => "write_stall_micros",
+            Ticker::WalSyncs => "wal_syncs",
+            Ticker::Flushes => "flushes",
+            Ticker::BytesFlushed => "bytes_flushed",
+            Ticker::FlushErrors => "flush_errors",
+            Ticker::Compactions => "compactions",
+            Ticker::BytesCompactedRead => "bytes_compacted_read",
+            Ticker::BytesCompactedWritten => "bytes_compacted_written",
+            Ticker::CompactionErrors => "compaction_errors",
+        }
+    }
+
+    /// what the counter counts
+    pub fn description(self) -> &'static str {
+        match self {
+            Ticker::Gets => "point lookups",
+            Ticker::MemtableHits => "point lookups answered by a memtable",
+            Ticker::MemtableMisses => "point lookups that had to go on to the sstables",
+            Ticker::SstProbes => "sstables whose key range covered a looked up key",
+            Ticker::BloomUseful => "sstable reads the bloom filter ruled out",
+            Ticker::BloomTruePositive => "bloom filter matches the sstable had a version for",
+            Ticker::BloomFalsePositive => "bloom filter matches the sstable had no version for",
+            Ticker::BytesRead => "bytes of the values returned by point lookups",
+            Ticker::Writes => "writes, a batch or a transaction commit counts once",
+            Ticker::Puts => "puts, including the ones in batches",
+            Ticker::Deletes => "deletes, including the ones in batches",
+            Ticker::Merges => "merge operands written",
+            Ticker::RangeDeletes => "range tombstones written",
+            Ticker::BytesWritten => "bytes of the keys and values written",
+            Ticker::WriteStalls => "writes that waited for another writer to freeze the memtables",
+            Ticker::WriteStallMicros => "microseconds writes spent stalled",
+            Ticker::WalSyncs => "fsyncs of WAL segments",
+            Ticker::Flushes => "memtable flushes",
+            Ticker::BytesFlushed => "bytes of the sstables written by flushes",
+            Ticker::FlushErrors => "flushes that failed",
+            Ticker::Compactions => "compactions",
+            Ticker::BytesCompactedRead => "bytes of the sstables compactions read",
+            Ticker::BytesCompactedWritten => "bytes of the sstables compactions wrote",
+            Ticker::CompactionErrors => "compactions that failed",
+        }
+    }
+}
+
+/// a latency distribution of the engine, in microseconds, see `DbStats::histogram`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Histogram {
+    Get,
+    Write,
+    WalSync,
+    Flush,
+    Compaction,
+}
+
+impl Histogram {
+    pub const COUNT: usize = 5;
+
+    pub const ALL: [Histogram; Self::COUNT] = [
+        Histogram::Get,
+        Histogram::Write,
+        Histogram::WalSync,
+        Histogram::Flush,
+        Histogram::Compaction,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Histogram::Get => "get_micros",
+            Histogram::Write => "write_micros",
+            Histogram::WalSync => "wal_sync_micros",
+            Histogram::Flush => "flush_micros",
+            Histogram::Compaction => "compaction_micros",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Histogram::Get => "latency of point lookups",
+            Histogram::Write => "latency of writes, logging included",
+            Histogram::WalSync => "latency of WAL segment fsyncs",
+            Histogram::Flush => "duration of memtable flushes",
+            Histogram::Compaction => "duration of compactions",
+        }
+    }
+}
+
+// 2^31 microseconds is more than half an hour, the last bucket takes anything longer
+const BUCKETS: usize = 32;
+
+fn bucket(micros: u64) -> usize {
+    ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+// the largest value of a bucket
+fn bucket_bound(bucket: usize) -> u64 {
+    (1u64 << bucket) - 1
+}
+
+struct AtomicHistogram {
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl AtomicHistogram {
+    fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn record(&self, micros: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+        self.buckets[bucket(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramData {
+        HistogramData {
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+/// the values recorded into a histogram so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistogramData {
+    pub count: u64,
+    /// sum of every value, in microseconds
+    pub sum: u64,
+    pub max: u64,
+    // number of values per bucket, see the top of the file
+    buckets: Vec<u64>,
+}
+
+impl HistogramData {
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// the value `p` percent of the recorded values are at most, rounded up to the bound of its
+    /// bucket. 0 while nothing is recorded
+    pub fn percentile(&self, p: f64) -> u64 {
+        let rank = ((p / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_bound(i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// the upper bound of every bucket along with the number of values up to it, the last
+    /// bucket's bound is u64::MAX
+    pub fn cumulative_buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let last = self.buckets.len().saturating_sub(1);
+        self.buckets
+            .iter()
+            .enumerate()
+            .scan(0, move |seen, (i, n)| {
+                *seen += n;
+                let bound = if i == last { u64::MAX } else { bucket_bound(i) };
+                Some((bound, *seen))
+            })
+    }
+}
+
+/// the live counters of a database, see `Db::stats` for a snapshot of them
+//
+// shared by the database with its workers and the WAL thread
+pub struct Statistics {
+    tickers: [AtomicU64; Ticker::COUNT],
+    histograms: [AtomicHistogram; Histogram::COUNT],
+}
+
+impl Statistics {
+    pub(crate) fn new() -> Self {
+        Self {
+            tickers: std::array::from_fn(|_| AtomicU64::new(0)),
+            histograms: std::array::from_fn(|_| AtomicHistogram::new()),
+        }
+    }
+
+    pub(crate) fn add(&self, ticker: Ticker, n: u64) {
+        self.tickers[ticker as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    // records the time passed since `start`
+    pub(crate) fn record_since(&self, histogram: Histogram, start: Instant) {
+        let micros = start.elapsed().as_micros().min(u64::MAX as u128) as u64;
+        self.histograms[histogram as usize].record(micros);
+    }
+
+    // the counters and histograms, the properties of the database are filled in by the caller
+    pub(crate) fn snapshot(&self) -> DbStats {
+        DbStats {
+            tickers: std::array::from_fn(|i| self.tickers[i].load(Ordering::Relaxed)),
+            histograms: self.histograms.iter().map(|h| h.snapshot()).collect(),
+            num_sstables: 0,
+            total_sst_bytes: 0,
+            memtable_bytes: 0,
+            num_immutable_memtables: 0,
+            block_cache: BlockCacheStats::default(),
+        }
+    }
+}
+
+/// the statistics of a database at one point in time, see `Db::stats`
+///
+/// ```no_run
+/// use keylite_kv::core::Db;
+/// use keylite_kv::stats::{Histogram, Ticker};
+///
+/// let db = Db::open("my_db")?;
+/// let stats = db.stats();
+/// let p99 = stats.histogram(Histogram::Get).percentile(99.0);
+/// println!("{} gets, p99 {}us", stats.ticker(Ticker::Gets), p99);
+/// // or served to Prometheus
+/// let metrics = stats.to_prometheus();
+/// # Ok::<(), keylite_kv::error::DbError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DbStats {
+    tickers: [u64; Ticker::COUNT],
+    histograms: Vec<HistogramData>,
+    /// live sstables of every column family
+    pub num_sstables: usize,
+    pub total_sst_bytes: u64,
+    /// size of the memtables of every column family, the frozen ones included
+    pub memtable_bytes: usize,
+    /// frozen memtable sets waiting to be flushed
+    pub num_immutable_memtables: usize,
+    pub block_cache: BlockCacheStats,
+}
+
+impl DbStats {
+    pub fn ticker(&self, ticker: Ticker) -> u64 {
+        self.tickers[ticker as usize]
+    }
+
+    pub fn histogram(&self, histogram: Histogram) -> &HistogramData {
+        &self.histograms[histogram as usize]
+    }
+
+    /// share of the bloom filter checks that ruled an sstable out
+    pub fn bloom_useful_rate(&self) -> f64 {
+        let useful = self.ticker(Ticker::BloomUseful);
+        let checks = useful
+            + self.ticker(Ticker::BloomTruePositive)
+            + self.ticker(Ticker::BloomFalsePositive);
+        ratio(useful, checks)
+    }
+
+    /// share of the bloom filter matches that turned out not to be in the sstable
+    pub fn bloom_false_positive_rate(&self) -> f64 {
+        let false_positives = self.ticker(Ticker::BloomFalsePositive);
+        let matches = false_positives + self.ticker(Ticker::BloomTruePositive);
+        ratio(false_positives, matches)
+    }
+
+    /// share of the point lookups a memtable answered
+    pub fn memtable_hit_rate(&self) -> f64 {
+        ratio(self.ticker(Ticker::MemtableHits), self.ticker(Ticker::Gets))
+    }
+
+    /// the statistics in the Prometheus text exposition format, ready to be served on a
+    /// `/metrics` endpoint. counters are named `keylite_<ticker>_total`, the histograms keep
+    /// their values in microseconds
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // the block cache counts its hits and misses on its own, they only ever grow as well
+        let counters = Ticker::ALL
+            .iter()
+            .map(|&ticker| (ticker.name(), ticker.description(), self.ticker(ticker)))
+            .chain([
+                (
+                    "block_cache_hits",
+                    "block cache hits",
+                    self.block_cache.hits,
+                ),
+                (
+                    "block_cache_misses",
+                    "block cache misses",
+                    self.block_cache.misses,
+                ),
+            ]);
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP keylite_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE keylite_{}_total counter", name);
+            let _ = writeln!(out, "keylite_{}_total {}", name, value);
+        }
+
+        let gauges = [
+            ("num_sstables", "live sstables", self.num_sstables as u64),
+            (
+                "sst_bytes",
+                "size of the live sstables",
+                self.total_sst_bytes,
+            ),
+            (
+                "memtable_bytes",
+                "size of the memtables",
+                self.memtable_bytes as u64,
+            ),
+            (
+                "immutable_memtables",
+                "frozen memtables waiting to be flushed",
+                self.num_immutable_memtables as u64,
+            ),
+            (
+                "block_cache_entries",
+                "blocks in the block cache",
+                self.block_cache.entries as u64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP keylite_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE keylite_{} gauge", name);
+            let _ = writeln!(out, "keylite_{} {}", name, value);
+        }
+
+        for histogram in Histogram::ALL {
+            let name = format!("keylite_{}", histogram.name());
+            let data = self.histogram(histogram);
+            let _ = writeln!(out, "# HELP {} {}", name, histogram.description());
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for (bound, seen) in data.cumulative_buckets() {
+                if bound == u64::MAX {
+                    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, seen);
+                } else {
+                    let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, seen);
+                }
+            }
+            let _ = writeln!(out, "{}_sum {}", name, data.sum);
+            let _ = writeln!(out, "{}_count {}", name, data.count);
+        }
+        out
+    }
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 0.0;
+    }
+    part as f64 / whole as f64
+}
+
+// the text of the `keylite.stats` property
+impl fmt::Display for DbStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ticker in Ticker::ALL {
+            writeln!(f, "{}: {}", ticker.name(), self.ticker(ticker))?;
+        }
+        writeln!(f, "num_sstables: {}", self.num_sstables)?;
+        writeln!(f, "sst_bytes: {}", self.total_sst_bytes)?;
+        writeln!(f, "memtable_bytes: {}", self.memtable_bytes)?;
+        writeln!(f, "immutable_memtables: {}", self.num_immutable_memtables)?;
+        writeln!(
+            f,
+            "block_cache: hits {} misses {} entries {}",
+            self.block_cache.hits, self.block_cache.misses, self.block_cache.entries
+        )?;
+        for histogram in Histogram::ALL {
+            let data = self.histogram(histogram);
+            writeln!(
+                f,
+                "{}: count {} mean {:.1} p50 {} p99 {} max {}",
+                histogram.name(),
+                data.count,
+                data.mean(),
+                data.percentile(50.0),
+                data.percentile(99.0),
+                data.max
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,151 +1,309 @@
-use crossbeam_skiplist::SkipMap;
+use crossbeam_skiplist::{SkipMap, SkipSet};
+use parking_lot::Mutex;
+use std::ops::Bound;
 
 use crate::{
-    core::{Db, DbIterator},
-    error::Result,
+    batch::WriteBatch,
+    core::{Db, DbIterator, Direction, Gap, Snapshot},
+    error::{DbError, Result},
 };
 
+// number of times `Db::transact` runs the closure before giving up on conflicts
+pub const MAX_TRANSACT_ATTEMPTS: usize = 16;
+
+// a range read by `scan`, None bounds are unbounded
+type ReadRange = (Option<Vec<u8>>, Option<Vec<u8>>);
+
 pub enum TxnOp {
     Put { key: Vec<u8>, val: Vec<u8> },
     Del { key: Vec<u8> },
 }
 
+// optimistic transaction: reads come from the snapshot taken at begin plus the transaction's own
+// writes, which are buffered until commit
+//
+// every key read and every scanned range is remembered, commit fails with `DbError::Conflict` if
+// any of them was written after the snapshot, i.e. if the transaction might have acted on data
+// that is stale by now. commits are serialized, so two transactions can't both validate before
+// either of them is written
 pub struct Transaction<'a> {
-    seq: u64,
-    buf: SkipMap<Vec<u8>, Vec<u8>>,
+    // registered with the db so compaction keeps every version the transaction can read
+    snapshot: Snapshot,
+    // None marks a key deleted by the transaction
+    buf: SkipMap<Vec<u8>, Option<Vec<u8>>>,
+    read_keys: SkipSet<Vec<u8>>,
+    read_ranges: Mutex<Vec<ReadRange>>,
     db: &'a Db,
 }
 
 impl<'a> Transaction<'a> {
-    pub fn new(seq: u64, db: &'a Db) -> Self {
+    pub fn new(snapshot: Snapshot, db: &'a Db) -> Self {
         Self {
-            seq,
+            snapshot,
             buf: SkipMap::new(),
+            read_keys: SkipSet::new(),
+            read_ranges: Mutex::new(Vec::new()),
             db,
         }
     }
     pub fn put(&mut self, key: &[u8], val: &[u8]) {
-        self.buf.insert(key.to_vec(), val.to_vec());
+        self.buf.insert(key.to_vec(), Some(val.to_vec()));
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         if let Some(entry) = self.buf.get(key) {
-            if entry.value().is_empty() {
-                return Ok(None);
-            }
-            return Ok(Some(entry.value().to_vec()));
+            return Ok(entry.value().clone());
         }
-        self.db.get_seq(key, self.seq)
+        self.read_keys.insert(key.to_vec());
+        self.db.get_seq(key, &self.snapshot)
     }
 
     pub fn del(&mut self, key: &[u8]) {
-        self.buf.insert(key.to_vec(), vec![]);
+        self.buf.insert(key.to_vec(), None);
     }
 
     pub fn commit(self) -> Result<()> {
-        // Get a NEW sequence number for this transaction's commit
-        // All operations in the transaction will use the same (new) sequence number
-        // to ensure atomicity - they all appear to happen at the same instant
-        let commit_seq = self.db.next_sequence();
+        // a read-only transaction saw a consistent snapshot, there's nothing to validate
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let _commit = self.db.lock_commits();
+        self.validate()?;
 
+        // the buffer goes out as one batch -> a single WAL record under a single new sequence
+        // number, so the transaction appears to happen at one instant and a crash can't replay
+        // half of it
+        let mut batch = WriteBatch::new();
         for entry in self.buf.iter() {
-            self.db.put_seq(entry.key(), entry.value(), commit_seq)?;
+            match entry.value() {
+                Some(val) => batch.put(entry.key(), val),
+                None => batch.delete(entry.key()),
+            }
         }
 
-        Ok(())
+        self.db.write(batch)
     }
 
     pub fn abort(&mut self) {
         self.buf.clear();
+        self.read_keys.clear();
+        self.read_ranges.lock().clear();
     }
 
-    pub fn scan(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> TransactionIterator {
-        // Get underlying DB iterator with snapshot isolation at transaction's sequence
-        let db_iter = self.db.scan_seq(start, end, self.seq);
-
-        // Collect transaction buffer entries within the range
-        let mut txn_entries = Vec::new();
-        for entry in self.buf.iter() {
-            let key = entry.key();
-
-            // Check if key is within range
-            if let Some(s) = start {
-                if key.as_slice() < s {
-                    continue;
-                }
-            }
-            if let Some(e) = end {
-                if key.as_slice() >= e {
-                    continue;
-                }
+    // reads that were answered by the transaction's own buffer depend on nothing in the db and
+    // aren't tracked in the first place
+    fn validate(&self) -> Result<()> {
+        // a transaction only reads and writes the default column family
+        let cf = self.db.default_column_family();
+        for key in self.read_keys.iter() {
+            if self
+                .db
+                .key_written_since(&cf, key.value(), self.snapshot.sequence())?
+            {
+                return Err(DbError::Conflict(format!(
+                    "key {:?} was written after the transaction began",
+                    String::from_utf8_lossy(key.value())
+                )));
             }
+        }
 
-            txn_entries.push((key.clone(), entry.value().clone()));
+        for (start, end) in self.read_ranges.lock().iter() {
+            if self.db.range_written_since(
+                &cf,
+                start.as_deref(),
+                end.as_deref(),
+                self.snapshot.sequence(),
+            )? {
+                return Err(DbError::Conflict(
+                    "a scanned range was written after the transaction began".to_string(),
+                ));
+            }
         }
 
-        TransactionIterator {
+        Ok(())
+    }
+
+    pub fn scan(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> TransactionIterator<'_> {
+        self.read_ranges
+            .lock()
+            .push((start.map(|s| s.to_vec()), end.map(|e| e.to_vec())));
+
+        // Get underlying DB iterator with snapshot isolation at transaction's sequence
+        let db_iter = self.db.scan_seq(start, end, &self.snapshot);
+
+        let mut iter = TransactionIterator {
             db_iter,
-            txn_entries,
-            txn_pos: 0,
+            buf: &self.buf,
+            buf_cursor: None,
+            start: start.map(|s| s.to_vec()),
+            end: end.map(|e| e.to_vec()),
+            direction: Direction::Forward,
+            gap: Gap::Start,
+            peeked_txn_entry: None,
             last_key: None,
             peeked_db_entry: None,
-        }
+        };
+        iter.seek_to_first();
+        iter
+    }
+
+    // the same scan walked from the last key down to the first
+    pub fn scan_rev(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> TransactionRevIterator<'_> {
+        let mut inner = self.scan(start, end);
+        inner.seek_to_last();
+        TransactionRevIterator { inner }
     }
 }
 
-pub struct TransactionIterator {
+// merges the transaction's buffer into a scan of its snapshot, the buffer is walked right from
+// the skiplist the same way DbIterator walks the memtables. like DbIterator it's a cursor that
+// moves either way
+pub struct TransactionIterator<'t> {
     db_iter: DbIterator,
-    txn_entries: Vec<(Vec<u8>, Vec<u8>)>,
-    txn_pos: usize,
+    buf: &'t SkipMap<Vec<u8>, Option<Vec<u8>>>,
+    // where the next buffer entry is looked up, right past the last one peeked in the direction
+    // of the walk. None once there's nothing left in that direction
+    buf_cursor: Option<Bound<Vec<u8>>>,
+    start: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+    direction: Direction,
+    gap: Gap,
+    peeked_txn_entry: Option<(Vec<u8>, Option<Vec<u8>>)>,
     last_key: Option<Vec<u8>>,
     peeked_db_entry: Option<(Vec<u8>, Vec<u8>)>, // Store peeked DB entry
 }
 
-impl Iterator for TransactionIterator {
-    type Item = (Vec<u8>, Vec<u8>);
+impl TransactionIterator<'_> {
+    // positions the iterator in front of the first key >= key, a key before the start bound of
+    // the scan seeks to the start bound
+    pub fn seek(&mut self, key: &[u8]) {
+        self.reposition(Direction::Forward, Gap::Before(key.to_vec()));
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    // positions the iterator back in front of the first key of the scan
+    pub fn seek_to_first(&mut self) {
+        self.reposition(Direction::Forward, Gap::Start);
+    }
+
+    // positions the iterator behind the last key <= key, so `prev` returns that key first
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        self.reposition(Direction::Reverse, Gap::After(key.to_vec()));
+    }
+
+    // positions the iterator behind the last key of the scan
+    pub fn seek_to_last(&mut self) {
+        self.reposition(Direction::Reverse, Gap::End);
+    }
+
+    // the key before the cursor, the cursor moves in front of it
+    pub fn prev(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        if self.direction == Direction::Forward {
+            self.reposition(Direction::Reverse, self.gap.clone());
+        }
+        let item = self.step();
+        self.gap = match &item {
+            Some((key, _)) => Gap::Before(key.clone()),
+            None => Gap::Start,
+        };
+        item
+    }
+
+    fn reposition(&mut self, direction: Direction, gap: Gap) {
+        self.db_iter.reposition(direction, gap.clone());
+
+        // the bounds of the scan clamp the cursor, an excluded key outside of them would let
+        // keys beyond the bound through
+        let start = self.start.clone();
+        let end = self.end.clone();
+        self.buf_cursor = match (direction, gap.clone()) {
+            (Direction::Forward, Gap::Start) => {
+                Some(start.map_or(Bound::Unbounded, Bound::Included))
+            }
+            (Direction::Forward, Gap::Before(key) | Gap::After(key))
+                if start.as_ref().is_some_and(|s| s > &key) =>
+            {
+                start.map(Bound::Included)
+            }
+            (Direction::Forward, Gap::Before(key)) => Some(Bound::Included(key)),
+            (Direction::Forward, Gap::After(key)) => Some(Bound::Excluded(key)),
+            (Direction::Reverse, Gap::End) => Some(end.map_or(Bound::Unbounded, Bound::Excluded)),
+            (Direction::Reverse, Gap::Before(key) | Gap::After(key))
+                if end.as_ref().is_some_and(|e| e <= &key) =>
+            {
+                end.map(Bound::Excluded)
+            }
+            (Direction::Reverse, Gap::After(key)) => Some(Bound::Included(key)),
+            (Direction::Reverse, Gap::Before(key)) => Some(Bound::Excluded(key)),
+            (Direction::Forward, Gap::End) | (Direction::Reverse, Gap::Start) => None,
+        };
+
+        self.direction = direction;
+        self.gap = gap;
+        self.peeked_txn_entry = None;
+        self.peeked_db_entry = None;
+        self.last_key = None;
+    }
+
+    fn peek_txn_entry(&mut self) {
+        if self.peeked_txn_entry.is_some() {
+            return;
+        }
+        let Some(cursor) = &self.buf_cursor else {
+            return;
+        };
+        let entry = match self.direction {
+            Direction::Forward => self.buf.lower_bound(cursor.as_ref()),
+            Direction::Reverse => self.buf.upper_bound(cursor.as_ref()),
+        };
+        let Some(entry) = entry else {
+            return;
+        };
+        let in_bounds = match self.direction {
+            Direction::Forward => self.end.as_ref().is_none_or(|end| entry.key() < end),
+            Direction::Reverse => self.start.as_ref().is_none_or(|start| entry.key() >= start),
+        };
+        if !in_bounds {
+            return;
+        }
+        self.buf_cursor = Some(Bound::Excluded(entry.key().clone()));
+        self.peeked_txn_entry = Some((entry.key().clone(), entry.value().clone()));
+    }
+
+    // the next key in the direction of the walk
+    fn step(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
         loop {
             // peek at both sources first
-            let txn_entry = if self.txn_pos < self.txn_entries.len() {
-                Some(&self.txn_entries[self.txn_pos])
-            } else {
-                None
-            };
+            self.peek_txn_entry();
 
             // get or peek DB entry
             if self.peeked_db_entry.is_none() {
-                self.peeked_db_entry = self.db_iter.next();
+                self.peeked_db_entry = match self.direction {
+                    Direction::Forward => self.db_iter.next(),
+                    Direction::Reverse => self.db_iter.prev(),
+                };
             }
 
-            // determine which entry to be returned
-            let (key, val) = match (txn_entry, &self.peeked_db_entry) {
-                (Some((tk, tv)), Some((dk, _))) => {
-                    // Both have entries, pick the smaller key
-                    // Transaction entries take precedence on equal keys
-                    if tk <= dk {
-                        self.txn_pos += 1;
-                        (tk.clone(), tv.clone())
-                    } else {
-                        let entry = self.peeked_db_entry.take().unwrap();
-                        entry
-                    }
-                }
-                (Some((tk, tv)), None) => {
-                    // only transaction has entry
-                    self.txn_pos += 1;
-                    (tk.clone(), tv.clone())
-                }
-                (None, Some(_)) => {
-                    // only DB has entry
-                    let entry = self.peeked_db_entry.take().unwrap();
-                    entry
-                }
+            // determine which entry to be returned, the one that comes first in the direction of
+            // the walk. Transaction entries take precedence on equal keys
+            let take_txn = match (&self.peeked_txn_entry, &self.peeked_db_entry) {
+                (Some((tk, _)), Some((dk, _))) => match self.direction {
+                    Direction::Forward => tk <= dk,
+                    Direction::Reverse => tk >= dk,
+                },
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
                 (None, None) => {
                     // none has a entry
                     return None;
                 }
             };
+            let (key, val) = if take_txn {
+                self.peeked_txn_entry.take().unwrap()
+            } else {
+                let (dk, dv) = self.peeked_db_entry.take().unwrap();
+                (dk, Some(dv))
+            };
 
             // skip duplicates
             if let Some(ref last) = self.last_key {
@@ -155,12 +313,56 @@ impl Iterator for TransactionIterator {
             }
             self.last_key = Some(key.clone());
 
-            // skip tombstones, i.e. empty values or deleted values
-            if val.is_empty() {
+            // skip keys deleted by the transaction, the db iterator already skips its tombstones
+            let Some(val) = val else {
                 continue;
-            }
+            };
 
             return Some((key, val));
         }
     }
 }
+
+impl Iterator for TransactionIterator<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.direction == Direction::Reverse {
+            self.reposition(Direction::Forward, self.gap.clone());
+        }
+        let item = self.step();
+        self.gap = match &item {
+            Some((key, _)) => Gap::After(key.clone()),
+            None => Gap::End,
+        };
+        item
+    }
+}
+
+// walks a TransactionIterator backwards, see `Transaction::scan_rev`
+pub struct TransactionRevIterator<'t> {
+    inner: TransactionIterator<'t>,
+}
+
+impl<'t> TransactionRevIterator<'t> {
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        self.inner.seek_for_prev(key);
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.inner.seek_to_last();
+    }
+
+    // the underlying cursor, still positioned where the reverse scan left off
+    pub fn into_inner(self) -> TransactionIterator<'t> {
+        self.inner
+    }
+}
+
+impl Iterator for TransactionRevIterator<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.prev()
+    }
+}
@@ -0,0 +1,128 @@
+// small types shared by the memtable, the sstables and the read path of the db
+
+/// what a version of a key does, stored next to it in the memtable, the WAL and the sstables
+///
+/// the discriminant is the tag written to disk, new kinds must take a new value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    /// the key was deleted, the value of the version is empty
+    Delete = 0,
+    /// the key was set to the value of the version, which may be empty
+    Put = 1,
+    /// the key was set to a value kept in a blob file, the version holds a pointer to it (see
+    /// blob/mod.rs). only ever stored in sstables
+    BlobIndex = 2,
+    /// the key was set to a value that expires, the version holds the expiry time followed by the
+    /// value, see `expiring_value`
+    ExpiringPut = 3,
+    /// the version holds an operand of the merge operator, the value of the key is the operand
+    /// applied on top of the older versions, see merge.rs
+    Merge = 4,
+}
+
+impl ValueType {
+    pub fn from_u8(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(ValueType::Delete),
+            1 => Some(ValueType::Put),
+            2 => Some(ValueType::BlobIndex),
+            3 => Some(ValueType::ExpiringPut),
+            4 => Some(ValueType::Merge),
+            _ => None,
+        }
+    }
+
+    // logs and tables written before versions were tagged stored a tombstone as an empty value
+    pub(crate) fn from_untagged(value: &[u8]) -> Self {
+        if value.is_empty() {
+            ValueType::Delete
+        } else {
+            ValueType::Put
+        }
+    }
+}
+
+// the expiry time in front of the value of an ExpiringPut version
+const EXPIRY_SIZE: usize = 8;
+
+// the stored value of an ExpiringPut version: the time it expires at, in milliseconds since the
+// unix epoch (u64), followed by the value itself
+pub(crate) fn expiring_value(expires_at: u64, val: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(EXPIRY_SIZE + val.len());
+    buf.extend_from_slice(&expires_at.to_le_bytes());
+    buf.extend_from_slice(val);
+    buf
+}
+
+// true if the version is an ExpiringPut whose expiry time has come at `now`
+pub(crate) fn is_expired(value_type: ValueType, value: &[u8], now: u64) -> bool {
+    if value_type != ValueType::ExpiringPut {
+        return false;
+    }
+    value
+        .get(..EXPIRY_SIZE)
+        .is_none_or(|b| u64::from_le_bytes(b.try_into().expect("8 bytes")) <= now)
+}
+
+// the value a Put or ExpiringPut version sets the key to, whether it expired is up to the caller
+pub(crate) fn inline_value(value_type: ValueType, mut value: Vec<u8>) -> Vec<u8> {
+    if value_type == ValueType::ExpiringPut {
+        value.drain(..EXPIRY_SIZE.min(value.len()));
+    }
+    value
+}
+
+/// result of looking a key up in a single source (memtable or sstable)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// the newest visible version of the key holds this value
+    Value(Vec<u8>),
+    /// the newest visible version of the key is a tombstone, older sources must not be consulted
+    Deleted,
+    /// the source holds no visible version of the key
+    Absent,
+}
+
+impl Lookup {
+    pub fn into_value(self) -> Option<Vec<u8>> {
+        match self {
+            Lookup::Value(v) => Some(v),
+            Lookup::Deleted | Lookup::Absent => None,
+        }
+    }
+}
+
+/// every version of the keys in [start, end) older than `seq` is deleted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.start.as_slice() <= key && key < self.end.as_slice()
+    }
+
+    // true if the tombstone intersects [start, end), a None end is unbounded
+    pub fn overlaps(&self, start: &[u8], end: Option<&[u8]>) -> bool {
+        self.end.as_slice() > start && end.is_none_or(|end| self.start.as_slice() < end)
+    }
+}
+
+// seq of the newest tombstone visible at snapshot_seq that covers the key, 0 if there is none.
+// versions of the key with a lower seq are deleted
+pub(crate) fn covering_seq<'a>(
+    tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    snapshot_seq: u64,
+) -> u64 {
+    tombstones
+        .into_iter()
+        .filter(|t| t.seq < snapshot_seq && t.contains(key))
+        .map(|t| t.seq)
+        .max()
+        .unwrap_or(0)
+}
@@ -2,3 +2,101 @@ pub mod reader;
 pub mod sync;
 pub mod thread;
 pub mod writer;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+// first bytes of every log written in the batch format, the last byte is the version of the
+// record layout. logs that don't start with it were written one entry per record by older
+// versions and are still replayed that way
+//
+// version 2: batch records, an empty value is a tombstone
+// version 3: every entry carries its ValueType
+// version 4: entries and range deletes carry the id of their column family
+pub(crate) const WAL_MAGIC: [u8; 8] = *b"KLWAL\0\0\x04";
+
+// the frame of a record stores the length of its payload as u32
+pub(crate) const MAX_RECORD_SIZE: usize = u32::MAX as usize;
+
+/// how durable a write is once the call that made it returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalMode {
+    // the write isn't logged at all, it's lost on a crash unless its memtable got flushed first
+    Disabled,
+    // the write is handed to the WAL thread, which fsyncs the log every `wal_sync_interval_ms`,
+    // a crash may lose the writes of the last interval
+    #[default]
+    Async,
+    // the call returns once the write has been fsynced, concurrent writers share a single fsync
+    Sync,
+}
+
+// every memtable logs into a segment of its own, the segment is deleted once the memtable is
+// durable in an sstable. `wal.log` is the single log of older versions, it's replayed before the
+// segments
+pub fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("wal-{}.log", id))
+}
+
+// extracts N from a path of the form .../wal-N.log
+pub fn parse_segment_id(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("wal-")?
+        .strip_suffix(".log")?
+        .parse()
+        .ok()
+}
+
+// the segments in the directory, oldest first
+pub fn list_segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(id) = parse_segment_id(&path) {
+            segments.push((id, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+// with `DbOptions::wal_retained_segments` set, retired segments are moved here instead of being
+// deleted and `Db::subscribe_with` reads the writes a subscriber missed from them. the archive is
+// never replayed, its writes are in tables already
+pub fn archive_dir(dir: &Path) -> PathBuf {
+    dir.join("wal-archive")
+}
+
+// the archived segments, oldest first. there are none before the first segment is archived
+pub fn list_archived_segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    match list_segments(&archive_dir(dir)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+// gets rid of a segment whose writes are durable in tables: it's deleted, or archived if `retain`
+// segments are kept, in which case the oldest archived segments beyond that are deleted
+pub fn retire_segment(dir: &Path, id: u64, retain: usize) -> io::Result<()> {
+    let path = segment_path(dir, id);
+    if retain == 0 {
+        return match std::fs::remove_file(path) {
+            // nothing was ever logged into it, or it's retired twice
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+    }
+
+    let archive = archive_dir(dir);
+    std::fs::create_dir_all(&archive)?;
+    match std::fs::rename(&path, segment_path(&archive, id)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        result => result?,
+    }
+    let archived = list_segments(&archive)?;
+    for (_, path) in archived.iter().take(archived.len().saturating_sub(retain)) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
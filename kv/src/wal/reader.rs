@@ -1,30 +1,125 @@
 use std::{
     fs::File,
-    io::{BufReader, Read, Result},
+    io::{BufRead, BufReader, Read, Result, Seek, SeekFrom, Take},
     path::Path,
 };
 
 use crc32fast::Hasher;
 
+use super::WAL_MAGIC;
+use crate::types::ValueType;
+
+// a single write of a record
+#[derive(Clone)]
 pub struct WalEntry {
-    pub seq: u64,
+    // id of the column family the key belongs to
+    pub cf: u32,
     pub key: Vec<u8>,
+    pub value_type: ValueType,
     pub val: Vec<u8>,
 }
 
+// everything one `Db::write` (or a single put/del) logged, the entries share the sequence number
+// and are recovered all-or-nothing
+#[derive(Clone)]
+pub struct WalRecord {
+    pub seq: u64,
+    pub entries: Vec<WalEntry>,
+    // column family and [start, end) of the ranges deleted by the write, they hide versions older
+    // than seq only so the entries of the same record survive them
+    pub range_deletes: Vec<(u32, Vec<u8>, Vec<u8>)>,
+}
+
 pub struct WalReader {
-    reader: BufReader<File>,
+    reader: BufReader<Take<File>>,
+    // version byte of the magic, None for a log written by an older version, one entry per
+    // record and no magic
+    version: Option<u8>,
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
 }
 
 impl WalReader {
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let file = File::open(path)?;
+        Self::with_len(File::open(path)?, u64::MAX)
+    }
+
+    // reads only the first len bytes of the log, the part of a segment that was written at some
+    // point even if it has grown since
+    pub fn with_len(mut file: File, len: u64) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        let version = match file.read_exact(&mut magic) {
+            Ok(()) if len >= 8 && magic[..7] == WAL_MAGIC[..7] => Some(magic[7]),
+            _ => None,
+        };
+        match version {
+            None => {
+                file.seek(SeekFrom::Start(0))?;
+            }
+            Some(v) if v > WAL_MAGIC[7] => {
+                return Err(invalid(&format!("unsupported WAL version {}", v)));
+            }
+            Some(_) => {}
+        }
+
+        // nothing past the end of the file is read, a length field claiming more is a torn tail
+        let len = len.min(file.metadata()?.len());
+        let remaining = if version.is_some() { len - 8 } else { len };
         Ok(Self {
-            reader: BufReader::new(file),
+            reader: BufReader::new(file.take(remaining)),
+            version,
         })
     }
 
-    pub fn next_entry(&mut self) -> Result<Option<WalEntry>> {
+    pub fn is_legacy(&self) -> bool {
+        self.version.is_none()
+    }
+
+    // bytes of the log left to read
+    fn remaining(&self) -> u64 {
+        self.reader.get_ref().limit() + self.reader.buffer().len() as u64
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<WalRecord>> {
+        let Some(version) = self.version else {
+            return self.next_legacy_record();
+        };
+
+        // | payload len (u32) | crc32 of payload (u32) | payload |
+        let mut header = [0u8; 8];
+        if self.reader.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(header[0..4].try_into().expect("4 bytes")) as usize;
+        let crc = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes"));
+        if len as u64 > self.remaining() {
+            // torn write at the tail, the length was written but not all of the payload
+            return Ok(None);
+        }
+
+        let mut payload = vec![0u8; len];
+        if self.reader.read_exact(&mut payload).is_err() {
+            // torn write at the tail, the batch was never acknowledged
+            return Ok(None);
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update(&payload);
+        if hasher.finalize() != crc {
+            // a bad checksum on the very last record is a torn write as well, anywhere else the
+            // log is corrupted
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid("WAL corruption detected"));
+        }
+
+        decode_record(&payload, version).map(Some)
+    }
+
+    fn next_legacy_record(&mut self) -> Result<Option<WalRecord>> {
         // 14 bytes,
         // 8 for seq
         // 2 for key_len
@@ -38,20 +133,23 @@ impl WalReader {
         let seq = u64::from_le_bytes(
             header[0..8]
                 .try_into()
-                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid seq bytes"))?,
+                .map_err(|_| invalid("Invalid seq bytes"))?,
         );
         let key_len = u16::from_le_bytes(
             header[8..10]
                 .try_into()
-                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid key_len bytes"))?,
+                .map_err(|_| invalid("Invalid key_len bytes"))?,
         ) as usize;
         let val_len = u32::from_le_bytes(
             header[10..14]
                 .try_into()
-                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid val_len bytes"))?,
+                .map_err(|_| invalid("Invalid val_len bytes"))?,
         ) as usize;
 
         let total_len = key_len + val_len;
+        if total_len as u64 > self.remaining() {
+            return Err(invalid("WAL record longer than the log"));
+        }
         let mut data = vec![0u8; total_len];
 
         self.reader.read_exact(&mut data)?;
@@ -69,12 +167,145 @@ impl WalReader {
         let computed_crc = hasher.finalize();
 
         if stored_crc != computed_crc {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "WAL corruption detected",
-            ));
+            return Err(invalid("WAL corruption detected"));
+        }
+
+        Ok(Some(WalRecord {
+            seq,
+            entries: vec![WalEntry {
+                cf: 0,
+                key,
+                value_type: ValueType::from_untagged(&val),
+                val,
+            }],
+            range_deletes: Vec::new(),
+        }))
+    }
+}
+
+// | seq (u64) | entry count (u32) |
+// | (cf (u32) | type (u8) | key len (u32) | val len (u32) | key | val)* |
+// | range count (u32) | (cf (u32) | start len (u32) | end len (u32) | start | end)* |
+//
+// the range part is only written if the record deletes ranges, records written before range
+// deletes existed end right after the entries. entries of version 2 logs have no type byte, and
+// neither entries nor ranges have a column family before version 4, they all belong to the
+// default one
+pub(crate) fn encode_record(record: &WalRecord) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&record.seq.to_le_bytes());
+    buf.extend_from_slice(&(record.entries.len() as u32).to_le_bytes());
+    for entry in &record.entries {
+        buf.extend_from_slice(&entry.cf.to_le_bytes());
+        buf.push(entry.value_type as u8);
+        buf.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(entry.val.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&entry.key);
+        buf.extend_from_slice(&entry.val);
+    }
+    if !record.range_deletes.is_empty() {
+        buf.extend_from_slice(&(record.range_deletes.len() as u32).to_le_bytes());
+        for (cf, start, end) in &record.range_deletes {
+            buf.extend_from_slice(&cf.to_le_bytes());
+            buf.extend_from_slice(&(start.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&(end.len() as u32).to_le_bytes());
+            buf.extend_from_slice(start);
+            buf.extend_from_slice(end);
+        }
+    }
+    buf
+}
+
+// the size of the payload `encode_record` writes for the record, the frame stores it as u32
+pub(crate) fn encoded_len(record: &WalRecord) -> usize {
+    let entries: usize = record
+        .entries
+        .iter()
+        .map(|entry| 13 + entry.key.len() + entry.val.len())
+        .sum();
+    let ranges: usize = match record.range_deletes.len() {
+        0 => 0,
+        _ => {
+            4 + record
+                .range_deletes
+                .iter()
+                .map(|(_, start, end)| 12 + start.len() + end.len())
+                .sum::<usize>()
         }
+    };
+    12 + entries + ranges
+}
 
-        Ok(Some(WalEntry { seq, key, val }))
+// splits the first n bytes off the payload
+fn take<'a>(payload: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if payload.len() < n {
+        return Err(invalid("truncated WAL record"));
     }
+    let (bytes, rest) = payload.split_at(n);
+    *payload = rest;
+    Ok(bytes)
+}
+
+fn take_u32(payload: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(
+        take(payload, 4)?.try_into().expect("4 bytes"),
+    ))
+}
+
+fn decode_record(mut payload: &[u8], version: u8) -> Result<WalRecord> {
+    let seq = u64::from_le_bytes(take(&mut payload, 8)?.try_into().expect("8 bytes"));
+    let count = take_u32(&mut payload)?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let cf = if version >= 4 {
+            take_u32(&mut payload)?
+        } else {
+            0
+        };
+        let tag = if version >= 3 {
+            Some(take(&mut payload, 1)?[0])
+        } else {
+            None
+        };
+        let key_len = take_u32(&mut payload)? as usize;
+        let val_len = take_u32(&mut payload)? as usize;
+        let key = take(&mut payload, key_len)?.to_vec();
+        let val = take(&mut payload, val_len)?.to_vec();
+        let value_type = match tag {
+            Some(tag) => {
+                ValueType::from_u8(tag).ok_or_else(|| invalid("unknown WAL value type"))?
+            }
+            None => ValueType::from_untagged(&val),
+        };
+        entries.push(WalEntry {
+            cf,
+            key,
+            value_type,
+            val,
+        });
+    }
+
+    let mut range_deletes = Vec::new();
+    if !payload.is_empty() {
+        let count = take_u32(&mut payload)?;
+        for _ in 0..count {
+            let cf = if version >= 4 {
+                take_u32(&mut payload)?
+            } else {
+                0
+            };
+            let start_len = take_u32(&mut payload)? as usize;
+            let end_len = take_u32(&mut payload)? as usize;
+            let start = take(&mut payload, start_len)?.to_vec();
+            let end = take(&mut payload, end_len)?.to_vec();
+            range_deletes.push((cf, start, end));
+        }
+    }
+
+    Ok(WalRecord {
+        seq,
+        entries,
+        range_deletes,
+    })
 }
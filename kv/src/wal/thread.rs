@@ -1,59 +1,285 @@
-use crossbeam_channel::Receiver;
-use std::{path::PathBuf, time::Instant};
+// the WAL thread owns the log segments, writers hand their records over through a channel
+//
+// every time the thread wakes up it drains everything that queued up in the meantime before it
+// touches the disk again, so synchronous writers that arrive while an fsync is running are all
+// covered by the next single fsync (group commit) instead of paying for one each
+//
+// asynchronous writes are fsynced every `flush_interval_ms`
+//
+// a segment that failed a write or an fsync is poisoned, it may end in a torn record or have
+// lost records in the page cache, so it takes no more records and every later sync or checkpoint
+// that covers it fails until its memtable is flushed and the segment retired. asynchronous writers
+// don't wait for the outcome of their write, the failure is handed to the next one of them through
+// `background_error` instead
+//
+// each memtable has a segment of its own (see wal/mod.rs), a segment is opened by the first
+// record appended to it and stays open until its memtable is flushed and it gets retired. writers
+// hold `Db::rotation_lock` while they log a record and insert it into the memtables, so once a
+// memtable is frozen no record is handed over for its segment anymore, only the ones queued before
+// the rotation may still be waiting to be appended
+//
+// the thread also publishes every record it logged to the subscriptions of `Db::subscribe`, see
+// cdc.rs
 
-use crate::wal::{reader::WalEntry, writer::WalWriter};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use parking_lot::Mutex;
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io;
+use std::sync::{atomic::AtomicUsize, Arc};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::cdc::{ChangeFeed, Subscriber};
+use crate::stats::{Histogram, Statistics, Ticker};
+use crate::wal::reader::{WalReader, WalRecord};
+use crate::wal::writer::WalWriter;
+use crate::wal::{list_archived_segments, list_segments, retire_segment, segment_path, WalMode};
 
 pub enum WalMessage {
-    Append(WalEntry),
-    Truncate,
+    // record of the memtable logging into the given segment, with the mode of the write
+    Append(u64, WalRecord, WalMode),
+    // fsync everything appended so far and report the outcome for the given segment, sent by
+    // synchronous writers right after their Append
+    Sync(u64, Sender<io::Result<()>>),
+    // fsync everything appended so far and report the length of every segment file at that
+    // point, the cut `Db::checkpoint` copies the WAL up to
+    Checkpoint(Sender<io::Result<Vec<(u64, u64)>>>),
+    // a record that isn't logged (WalMode::Disabled), only sent while there are subscribers so
+    // they get to see it
+    Publish(WalRecord),
+    // adds a subscriber to the change feed. with `replay` the segments still on disk are opened
+    // for it, up to the last record logged before it joined
+    Subscribe {
+        subscriber: Subscriber,
+        replay: bool,
+        ack: Sender<io::Result<Vec<WalReader>>>,
+    },
+    // the memtable of the segment is durable in an sstable, the segment isn't needed anymore
+    Retire(u64),
     Shutdown,
 }
 
-pub fn wal_thread(path: PathBuf, rx: Receiver<WalMessage>, flush_interval_ms: u64) {
-    let mut wal = match WalWriter::new(&path) {
-        Ok(w) => w,
-        Err(e) => {
-            eprintln!("Failed to open WAL at {:?}: {}. WAL thread exiting.", path, e);
-            return;
+// the first WAL failure nobody waited for, taken by the next asynchronous writer
+pub type BackgroundError = Arc<Mutex<Option<io::Error>>>;
+
+// io::Error isn't Clone but every waiter of a group needs its own copy of the outcome
+fn copy_error(e: &io::Error) -> io::Error {
+    io::Error::new(e.kind(), e.to_string())
+}
+
+// the error of the first poisoned segment, if any
+fn check_poisoned(poisoned: &BTreeMap<u64, io::Error>) -> io::Result<()> {
+    match poisoned.values().next() {
+        Some(e) => Err(copy_error(e)),
+        None => Ok(()),
+    }
+}
+
+// keeps the first failure of the segment
+fn poison(poisoned: &mut BTreeMap<u64, io::Error>, id: u64, e: &io::Error) {
+    poisoned.entry(id).or_insert_with(|| copy_error(e));
+}
+
+// fsyncs every segment with records that aren't durable yet. a segment leaves `dirty` once its
+// fsync succeeded, a failed one is poisoned instead, another fsync can't bring back what the
+// failed one dropped. returns the first failure
+fn sync_dirty(
+    segments: &mut BTreeMap<u64, WalWriter>,
+    dirty: &mut BTreeSet<u64>,
+    poisoned: &mut BTreeMap<u64, io::Error>,
+    stats: &Statistics,
+) -> io::Result<()> {
+    let mut result = Ok(());
+    for id in std::mem::take(dirty) {
+        if let Some(wal) = segments.get_mut(&id) {
+            let start = Instant::now();
+            let synced = wal.sync();
+            stats.add(Ticker::WalSyncs, 1);
+            stats.record_since(Histogram::WalSync, start);
+            if let Err(e) = synced {
+                eprintln!("Failed to sync WAL segment {}: {}", id, e);
+                poison(poisoned, id, &e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
         }
-    };
+    }
+    result
+}
+
+// the segment files in the directory with their lengths
+fn segment_lengths(dir: &Path) -> io::Result<Vec<(u64, u64)>> {
+    let mut lengths = Vec::new();
+    for (id, path) in list_segments(dir)? {
+        lengths.push((id, std::fs::metadata(path)?.len()));
+    }
+    Ok(lengths)
+}
 
+// the archived segments and the ones in use, oldest first, each read up to its current end. the
+// records appended later reach the subscriber through the change feed instead
+fn open_for_replay(dir: &Path) -> io::Result<Vec<WalReader>> {
+    let mut readers = Vec::new();
+    for (_, path) in list_archived_segments(dir)?
+        .into_iter()
+        .chain(list_segments(dir)?)
+    {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        readers.push(WalReader::with_len(file, len)?);
+    }
+    Ok(readers)
+}
+
+// `retained_segments` retired segments are archived instead of deleted, `subscribers` is the
+// number of subscribers as seen by the database
+pub fn wal_thread(
+    dir: PathBuf,
+    rx: Receiver<WalMessage>,
+    flush_interval_ms: u64,
+    retained_segments: usize,
+    subscribers: Arc<AtomicUsize>,
+    background_error: BackgroundError,
+    stats: Arc<Statistics>,
+) {
+    let mut segments: BTreeMap<u64, WalWriter> = BTreeMap::new();
+    let mut feed = ChangeFeed::new(subscribers);
+
+    let flush_interval = Duration::from_millis(flush_interval_ms);
     let mut last_flush = Instant::now();
+    // segments with appended records that aren't fsynced yet
+    let mut dirty = BTreeSet::new();
+    // segments that failed a write or an fsync, with the first failure
+    let mut poisoned: BTreeMap<u64, io::Error> = BTreeMap::new();
 
     loop {
-        match rx.recv() {
-            Ok(rec) => match rec {
-                WalMessage::Append(entry) => {
-                    let _ = wal.append(&entry.key, &entry.val, entry.seq);
-                }
-                WalMessage::Truncate => {
-                    let _ = wal.sync();
-                    drop(wal);
-                    if let Err(e) = std::fs::remove_file(&path) {
-                        eprintln!("Failed to remove WAL file: {}", e);
-                    }
-                    wal = match WalWriter::new(&path) {
-                        Ok(w) => w,
+        // a zero interval means fsync after every wake up, don't spin on an empty channel for it
+        let first = match rx.recv_timeout(flush_interval.max(Duration::from_millis(1))) {
+            Ok(msg) => Some(msg),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let mut waiters = Vec::new();
+        let mut checkpoints = Vec::new();
+        let mut shutdown = false;
+        // records of the group for the change feed, they are published once the group is done.
+        // a subscriber that joins in the middle of the group only gets the ones after it joined,
+        // the segments it replays have the others
+        let mut published = Vec::new();
+        let mut joined = Vec::new();
+
+        let mut next = first;
+        while let Some(msg) = next {
+            match msg {
+                WalMessage::Append(id, record, mode) => {
+                    // a record behind a torn one would never be replayed
+                    let appended = match poisoned.get(&id) {
+                        Some(e) => Err(copy_error(e)),
+                        None => match segments.entry(id) {
+                            Entry::Occupied(e) => Ok(e.into_mut()),
+                            Entry::Vacant(e) => {
+                                WalWriter::new(segment_path(&dir, id)).map(|w| e.insert(w))
+                            }
+                        }
+                        .and_then(|wal| wal.append(&record)),
+                    };
+                    match appended {
+                        Ok(()) => {
+                            dirty.insert(id);
+                            if !feed.is_empty() || !joined.is_empty() {
+                                published.push(record);
+                            }
+                        }
                         Err(e) => {
-                            eprintln!("Failed to recreate WAL: {}. WAL thread exiting.", e);
-                            return;
+                            eprintln!("Failed to append to WAL segment {}: {}", id, e);
+                            poison(&mut poisoned, id, &e);
+                            // a synchronous writer hears about it from its Sync
+                            if mode == WalMode::Async {
+                                background_error.lock().get_or_insert(e);
+                            }
                         }
+                    }
+                }
+                WalMessage::Publish(record) => published.push(record),
+                WalMessage::Subscribe {
+                    subscriber,
+                    replay,
+                    ack,
+                } => {
+                    // the segments have to hold everything appended so far before they're opened
+                    let replayed = if replay {
+                        sync_dirty(&mut segments, &mut dirty, &mut poisoned, &stats)
+                            .and_then(|()| check_poisoned(&poisoned))
+                            .and_then(|()| open_for_replay(&dir))
+                    } else {
+                        Ok(Vec::new())
                     };
-                    last_flush = Instant::now();
+                    if replayed.is_ok() {
+                        joined.push((subscriber, published.len()));
+                    } else {
+                        feed.reject();
+                    }
+                    let _ = ack.send(replayed);
+                }
+                WalMessage::Sync(id, ack) => waiters.push((id, ack)),
+                WalMessage::Checkpoint(ack) => checkpoints.push(ack),
+                WalMessage::Retire(id) => {
+                    dirty.remove(&id);
+                    segments.remove(&id);
+                    // the records the segment lost are durable in the flushed table
+                    poisoned.remove(&id);
+                    if let Err(e) = retire_segment(&dir, id, retained_segments) {
+                        eprintln!("Failed to retire WAL segment {}: {}", id, e);
+                    }
                 }
                 WalMessage::Shutdown => {
+                    shutdown = true;
                     break;
                 }
-            },
-
-            Err(_) => {
-                break;
             }
+            next = rx.try_recv().ok();
         }
 
-        if last_flush.elapsed().as_millis() as u64 >= flush_interval_ms {
-            let _ = wal.sync();
+        let due = !dirty.is_empty() && last_flush.elapsed() >= flush_interval;
+        if !waiters.is_empty() || !checkpoints.is_empty() || shutdown || due {
+            let synced = sync_dirty(&mut segments, &mut dirty, &mut poisoned, &stats);
+            // a sync waiter only learns about its own segment, its record is in there
+            for (id, ack) in waiters {
+                let _ = ack.send(match poisoned.get(&id) {
+                    Some(e) => Err(copy_error(e)),
+                    None => Ok(()),
+                });
+            }
+            // nobody waited for the periodic sync of the asynchronous writes
+            if let (Err(e), true) = (synced, due) {
+                background_error.lock().get_or_insert(e);
+            }
+            // only this thread writes to the segments, nothing can grow them between the sync
+            // and the listing
+            if !checkpoints.is_empty() {
+                let lengths = check_poisoned(&poisoned).and_then(|()| segment_lengths(&dir));
+                for ack in checkpoints {
+                    let _ = ack.send(match &lengths {
+                        Ok(lengths) => Ok(lengths.clone()),
+                        Err(e) => Err(copy_error(e)),
+                    });
+                }
+            }
             last_flush = Instant::now();
         }
+
+        feed.publish(&published);
+        for (subscriber, first) in joined {
+            feed.add(subscriber, &published[first..]);
+        }
+
+        if shutdown {
+            break;
+        }
     }
 }
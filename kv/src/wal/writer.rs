@@ -7,6 +7,9 @@ use std::{
 
 use crc32fast::Hasher;
 
+use super::reader::{encode_record, WalRecord};
+use super::WAL_MAGIC;
+
 pub struct WalWriter {
     buf: BufWriter<File>,
     file: Arc<File>,
@@ -15,25 +18,32 @@ pub struct WalWriter {
 impl WalWriter {
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
         let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let fresh = file.metadata()?.len() == 0;
+
+        let mut buf = BufWriter::new(file.try_clone()?);
+        if fresh {
+            buf.write_all(&WAL_MAGIC)?;
+        }
 
         Ok(Self {
-            buf: BufWriter::new(file.try_clone()?),
+            buf,
             file: Arc::new(file),
         })
     }
 
-    pub fn append(&mut self, key: &[u8], val: &[u8], seq: u64) -> Result<()> {
-        let mut buf = Vec::new();
-        buf.extend_from_slice(&seq.to_le_bytes());
-        buf.extend_from_slice(&(key.len() as u16).to_le_bytes());
-        buf.extend_from_slice(&(val.len() as u32).to_le_bytes());
-        buf.extend_from_slice(key);
-        buf.extend_from_slice(val);
+    // the whole record is written in one frame so a crash can only ever tear it at the tail,
+    // which the reader then drops as a unit
+    pub fn append(&mut self, record: &WalRecord) -> Result<()> {
+        let payload = encode_record(record);
 
         let mut hasher = Hasher::new();
-        hasher.update(&buf);
+        hasher.update(&payload);
         let crc = hasher.finalize();
+
+        let mut buf = Vec::with_capacity(payload.len() + 8);
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
         buf.extend_from_slice(&crc.to_le_bytes());
+        buf.extend_from_slice(&payload);
 
         self.buf.write_all(&buf)?;
 
@@ -0,0 +1,205 @@
+use keylite_kv::core::{Db, DbOptions};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+fn blob_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+        .min_blob_size(128)
+        .blob_file_size(64 * 1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn large_value(i: usize, round: u8) -> Vec<u8> {
+    let mut value = vec![b'a' + round; 512];
+    value[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    value
+}
+
+fn files_like(dir: &str, prefix: &str, suffix: &str) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(prefix) && name.ends_with(suffix))
+        .collect();
+    files.sort();
+    files
+}
+
+fn blob_files(dir: &str) -> Vec<String> {
+    files_like(dir, "blob-", ".blob")
+}
+
+fn blob_bytes(dir: &str) -> u64 {
+    blob_files(dir)
+        .iter()
+        .map(|name| fs::metadata(Path::new(dir).join(name)).unwrap().len())
+        .sum()
+}
+
+#[test]
+fn test_large_values_survive_flush_compaction_and_reopen() {
+    let test_dir = "/tmp/test_blob_roundtrip";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, blob_options()).unwrap();
+    for i in 0..500 {
+        db.put(&key(i), &large_value(i, 0)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    assert!(!blob_files(test_dir).is_empty());
+    for i in 0..500 {
+        assert_eq!(db.get(&key(i)).unwrap(), Some(large_value(i, 0)));
+    }
+
+    drop(db);
+    let db = Db::open_with(test_dir, blob_options()).unwrap();
+    for i in 0..500 {
+        assert_eq!(db.get(&key(i)).unwrap(), Some(large_value(i, 0)));
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_small_values_stay_inline() {
+    let test_dir = "/tmp/test_blob_inline";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, blob_options()).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'v'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    assert!(!files_like(test_dir, "sst-", ".db").is_empty());
+    assert!(blob_files(test_dir).is_empty());
+    assert_eq!(db.get(&key(1234)).unwrap(), Some(vec![b'v'; 64]));
+
+    // without a threshold nothing is ever separated
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let opts = blob_options();
+    let opts = DbOptions {
+        min_blob_size: None,
+        ..opts
+    };
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for i in 0..500 {
+        db.put(&key(i), &large_value(i, 0)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+    assert!(blob_files(test_dir).is_empty());
+    assert_eq!(db.get(&key(42)).unwrap(), Some(large_value(42, 0)));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_scans_and_snapshots_resolve_blob_pointers() {
+    let test_dir = "/tmp/test_blob_scan";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, blob_options()).unwrap();
+    for i in 0..300 {
+        // mix of separated and inline values
+        if i % 2 == 0 {
+            db.put(&key(i), &large_value(i, 0)).unwrap();
+        } else {
+            db.put(&key(i), b"small").unwrap();
+        }
+    }
+    let snapshot = db.snapshot();
+    for i in 0..300 {
+        db.put(&key(i), &large_value(i, 1)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+    assert!(!blob_files(test_dir).is_empty());
+
+    let scanned: Vec<_> = db.scan(None, None).collect();
+    assert_eq!(scanned.len(), 300);
+    for (i, (k, v)) in scanned.iter().enumerate() {
+        assert_eq!(k, &key(i));
+        assert_eq!(v, &large_value(i, 1));
+    }
+
+    let reversed: Vec<_> = db.scan_rev(Some(&key(100)), Some(&key(110))).collect();
+    let expected: Vec<_> = (100..110)
+        .rev()
+        .map(|i| (key(i), large_value(i, 1)))
+        .collect();
+    assert_eq!(reversed, expected);
+
+    for (i, (k, v)) in db.scan_seq(None, None, &snapshot).enumerate() {
+        assert_eq!(k, key(i));
+        if i % 2 == 0 {
+            assert_eq!(v, large_value(i, 0));
+        } else {
+            assert_eq!(v, b"small");
+        }
+    }
+    assert_eq!(
+        db.get_seq(&key(10), &snapshot).unwrap(),
+        Some(large_value(10, 0))
+    );
+
+    drop(snapshot);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_blob_gc_reclaims_overwritten_values() {
+    let test_dir = "/tmp/test_blob_gc";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = blob_options().blob_gc_age_cutoff(1.0);
+    let db = Db::open_with(test_dir, opts.clone()).unwrap();
+    let mut first_round = Vec::new();
+    for round in 0..8u8 {
+        for i in 0..300 {
+            db.put(&key(i), &large_value(i, round)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        if first_round.is_empty() {
+            first_round = blob_files(test_dir);
+        }
+    }
+    thread::sleep(Duration::from_millis(500));
+
+    // 8 rounds of 300 * 512 bytes went into blob files, most of it is garbage by now
+    let written = 8 * 300 * 512;
+    assert!(blob_bytes(test_dir) < written / 2);
+    let left = blob_files(test_dir);
+    assert!(first_round.iter().any(|f| !left.contains(f)));
+    for i in 0..300 {
+        assert_eq!(db.get(&key(i)).unwrap(), Some(large_value(i, 7)));
+    }
+
+    // every blob file left is still referenced, reopening keeps all of them
+    let before = blob_files(test_dir);
+    drop(db);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    let after = blob_files(test_dir);
+    assert!(before.iter().all(|f| after.contains(f)));
+    for i in (0..300).step_by(17) {
+        assert_eq!(db.get(&key(i)).unwrap(), Some(large_value(i, 7)));
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,96 @@
+use keylite_kv::core::{Db, DbOptions};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_repeated_reads_hit_the_cache() {
+    let test_dir = "/tmp/test_block_cache_hits";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        for i in 0..1000 {
+            let key = format!("key_{:04}", i);
+            db.put(key.as_bytes(), b"value").unwrap();
+        }
+    }
+
+    // everything lives in sstables now
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.block_cache_stats().entries, 0);
+
+    assert_eq!(db.get(b"key_0042").unwrap(), Some(b"value".to_vec()));
+    let first = db.block_cache_stats();
+    assert!(first.misses > 0);
+    assert!(first.entries > 0);
+
+    assert_eq!(db.get(b"key_0042").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(
+        db.get_seq(b"key_0043", &db.snapshot()).unwrap(),
+        Some(b"value".to_vec())
+    );
+    let second = db.block_cache_stats();
+    assert_eq!(second.misses, first.misses);
+    assert_eq!(second.hits, first.hits + 2);
+
+    // a scan reuses the blocks the gets already loaded
+    assert_eq!(db.scan(None, None).count(), 1000);
+    assert!(db.block_cache_stats().hits > second.hits);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_cache_can_be_disabled() {
+    let test_dir = "/tmp/test_block_cache_disabled";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = || DbOptions::default().block_cache_capacity(0);
+    {
+        let db = Db::open_with(test_dir, opts()).unwrap();
+        db.put(b"a", b"1").unwrap();
+    }
+
+    let db = Db::open_with(test_dir, opts()).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.block_cache_stats(), Default::default());
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_compaction_evicts_deleted_tables() {
+    let test_dir = "/tmp/test_block_cache_eviction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(
+        test_dir,
+        DbOptions::default()
+            .memtable_size_threshold(16 * 1024)
+            .max_sstables(2)
+            .block_size(1024),
+    )
+    .unwrap();
+
+    for i in 0..5000 {
+        let key = format!("key_{:05}", i % 1500);
+        db.put(key.as_bytes(), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(500));
+
+    // compaction reads its inputs through the cache, but all of them are deleted afterwards so
+    // nothing of them may stay behind
+    let stats = db.block_cache_stats();
+    assert!(stats.misses > 0, "compaction should have read blocks");
+    assert_eq!(
+        stats.entries, 0,
+        "blocks of deleted tables are still cached"
+    );
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,177 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::sst::bloom::{self, BloomFilter};
+use keylite_kv::sst::writer::{SSTWriter, WriterOptions};
+use keylite_kv::sst::{SSTIterator, SSTReader};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn write_table(path: &str, opts: WriterOptions, keys: usize, versions: u64) -> SSTReader {
+    let mut writer = SSTWriter::with_options(path, opts).unwrap();
+    let mut seq = 1;
+    for i in 0..keys {
+        for _ in 0..versions {
+            writer.add(&key(i), b"value", seq).unwrap();
+            seq += 1;
+        }
+    }
+    writer.finish().unwrap();
+    SSTReader::open(path).unwrap()
+}
+
+fn false_positive_rate(filter: &BloomFilter, absent: std::ops::Range<usize>) -> f64 {
+    let len = absent.len();
+    let hits = absent
+        .filter(|i| filter.might_contain(format!("absent_{}", i).as_bytes()))
+        .count();
+    hits as f64 / len as f64
+}
+
+#[test]
+fn test_false_positive_rate_follows_bits_per_key() {
+    let keys: Vec<_> = (0..100_000).map(key).collect();
+    let hashes: Vec<_> = keys.iter().map(|k| bloom::hash_key(k)).collect();
+
+    let filter = BloomFilter::build(&hashes, 10, usize::MAX);
+    assert_eq!(filter.num_probes(), 7);
+    assert!(keys.iter().all(|k| filter.might_contain(k)));
+    let rate_10 = false_positive_rate(&filter, 0..100_000);
+    assert!(rate_10 < 0.02, "false positive rate {}", rate_10);
+
+    let filter = BloomFilter::build(&hashes, 4, usize::MAX);
+    assert!(keys.iter().all(|k| filter.might_contain(k)));
+    let rate_4 = false_positive_rate(&filter, 0..100_000);
+    assert!(rate_4 > rate_10);
+    assert!(rate_4 < 0.25, "false positive rate {}", rate_4);
+}
+
+#[test]
+fn test_filter_is_sized_by_key_count() {
+    let dir = "/tmp/test_bloom_sizing";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+
+    let small = write_table(
+        &format!("{}/sst-1.db", dir),
+        WriterOptions::default(),
+        100,
+        1,
+    );
+    let large = write_table(
+        &format!("{}/sst-2.db", dir),
+        WriterOptions::default(),
+        20_000,
+        1,
+    );
+    assert_eq!(small.bloom_filter().num_bits(), 1000);
+    assert_eq!(large.bloom_filter().num_bits(), 200_000);
+    assert_eq!(large.bloom_filter().num_probes(), 7);
+
+    // the versions of a key share its bits
+    let versioned = write_table(
+        &format!("{}/sst-3.db", dir),
+        WriterOptions::default(),
+        100,
+        3,
+    );
+    assert_eq!(versioned.bloom_filter().num_bits(), 1000);
+
+    // a capped filter makes do with fewer probes
+    let opts = WriterOptions {
+        bloom_size: 1024,
+        bloom_bits_per_key: 16,
+        ..WriterOptions::default()
+    };
+    let capped = write_table(&format!("{}/sst-4.db", dir), opts, 20_000, 1);
+    assert_eq!(capped.bloom_filter().num_bits(), 8192);
+    assert_eq!(capped.bloom_filter().num_probes(), 1);
+    for i in (0..20_000).step_by(7) {
+        assert_eq!(capped.get(&key(i)).unwrap(), Some(b"value".to_vec()));
+    }
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_legacy_filters_stay_readable() {
+    let test_dir = "/tmp/test_bloom_legacy";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+
+    // written by format version 6: a fixed 1KB filter probed with crc32 hashes, holding the
+    // even keys 0..2000
+    let legacy = SSTReader::open("test_data/legacy_bloom/sst-1.db").unwrap();
+    assert_eq!(legacy.version(), 6);
+    assert_eq!(legacy.bloom_filter().num_probes(), 3);
+    assert_eq!(legacy.bloom_filter().num_bits(), 8192);
+    for i in (0..2000).step_by(2) {
+        assert!(legacy.bloom_filter().might_contain(&key(i)));
+    }
+    assert_eq!(legacy.get(&key(1)).unwrap(), None);
+
+    // a database mixing legacy tables with new ones
+    fs::copy(
+        "test_data/legacy_bloom/sst-1.db",
+        format!("{}/sst-1.db", test_dir),
+    )
+    .unwrap();
+    let opts = DbOptions::default()
+        .memtable_size_threshold(4 * 1024)
+        .max_sstables(100);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for i in (1..2000).step_by(2) {
+        db.put(&key(i), format!("value_{:05}", i).as_bytes())
+            .unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    assert!(db.num_files_at_level(0) > 1);
+    for i in 0..2000 {
+        assert_eq!(
+            db.get(&key(i)).unwrap(),
+            Some(format!("value_{:05}", i).into_bytes())
+        );
+    }
+    assert_eq!(db.get(&key(2000)).unwrap(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_bits_per_key_option() {
+    let test_dir = "/tmp/test_bloom_option";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let res = Db::open_with(test_dir, DbOptions::default().bloom_bits_per_key(0));
+    assert!(res.is_err());
+
+    let opts = DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .bloom_bits_per_key(20);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    drop(db);
+
+    let mut tables = 0;
+    for entry in fs::read_dir(test_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        let reader = SSTReader::open(&path).unwrap();
+        assert_eq!(reader.bloom_filter().num_probes(), 14);
+        let keys = SSTIterator::new(reader.clone()).count();
+        assert!(reader.bloom_filter().num_bits() >= keys * 20);
+        tables += 1;
+    }
+    assert!(tables > 0);
+
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,316 @@
+use keylite_kv::cdc::{ChangeBatch, ChangeOp, SubscribeOptions, Subscription};
+use keylite_kv::core::{Db, DbOptions, WalMode, WriteBatch, WriteOptions};
+use keylite_kv::error::DbError;
+use keylite_kv::merge::U64AddOperator;
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn small_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+// every batch that arrives within the timeout
+fn drain(sub: &mut Subscription) -> Vec<ChangeBatch> {
+    let mut batches = Vec::new();
+    while let Some(batch) = sub.next_timeout(Duration::from_millis(200)) {
+        batches.push(batch.unwrap());
+    }
+    batches
+}
+
+fn put(value: &[u8]) -> ChangeOp {
+    ChangeOp::Put {
+        value: value.to_vec(),
+        expires_at: None,
+    }
+}
+
+#[test]
+fn test_subscription_sees_committed_writes_in_range() {
+    let test_dir = "/tmp/test_cdc_range";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = small_options().merge_operator(Arc::new(U64AddOperator));
+    let db = Db::open_with(test_dir, opts).unwrap();
+    let other = db.create_column_family("other", small_options()).unwrap();
+    let mut sub = db.subscribe(Some(b"b"), Some(b"d")).unwrap();
+
+    db.put(b"a", b"outside").unwrap();
+    db.put(b"b1", b"1").unwrap();
+    db.put_cf(&other, b"b2", b"other family").unwrap();
+    db.del(b"b1").unwrap();
+    db.merge(b"c1", &5u64.to_le_bytes()).unwrap();
+    db.put_with_ttl(b"c2", b"short lived", Duration::from_secs(60))
+        .unwrap();
+    db.delete_range(b"a", b"b5").unwrap();
+    db.delete_range(b"x", b"z").unwrap();
+
+    // only the part of the batch inside the range, under the batch's single sequence number
+    let mut batch = WriteBatch::new();
+    batch.put(b"a2", b"outside");
+    batch.put(b"b3", b"3");
+    batch.delete(b"c3");
+    db.write(batch).unwrap();
+
+    db.transact(|txn| {
+        txn.put(b"b4", b"4");
+        txn.put(b"c4", b"4");
+        Ok(())
+    })
+    .unwrap();
+
+    let batches = drain(&mut sub);
+    let ops: Vec<Vec<(&[u8], &ChangeOp)>> = batches
+        .iter()
+        .map(|b| b.changes.iter().map(|c| (&c.key[..], &c.op)).collect())
+        .collect();
+    assert_eq!(ops.len(), 7);
+    assert_eq!(ops[0], vec![(&b"b1"[..], &put(b"1"))]);
+    assert_eq!(ops[1], vec![(&b"b1"[..], &ChangeOp::Delete)]);
+    assert_eq!(
+        ops[2],
+        vec![(&b"c1"[..], &ChangeOp::Merge(5u64.to_le_bytes().to_vec()))]
+    );
+    match ops[3][0].1 {
+        ChangeOp::Put { value, expires_at } => {
+            assert_eq!(value, b"short lived");
+            assert!(expires_at.is_some());
+        }
+        op => panic!("unexpected {:?}", op),
+    }
+    assert_eq!(
+        ops[4],
+        vec![(
+            &b"a"[..],
+            &ChangeOp::DeleteRange {
+                end: b"b5".to_vec()
+            }
+        )]
+    );
+    assert_eq!(
+        ops[5],
+        vec![(&b"b3"[..], &put(b"3")), (&b"c3"[..], &ChangeOp::Delete)]
+    );
+    assert_eq!(
+        ops[6],
+        vec![(&b"b4"[..], &put(b"4")), (&b"c4"[..], &put(b"4"))]
+    );
+    assert!(batches.windows(2).all(|w| w[0].seq < w[1].seq));
+    assert!(batches.iter().all(|b| b.changes.iter().all(|c| c.cf == 0)));
+
+    let mut other_sub = db
+        .subscribe_cf(&other, None, None, SubscribeOptions::default())
+        .unwrap();
+    db.put(b"b5", b"default").unwrap();
+    db.put_cf(&other, b"b5", b"other").unwrap();
+    let batches = drain(&mut other_sub);
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].changes[0].op, put(b"other"));
+    assert_eq!(drain(&mut sub).len(), 1);
+
+    // the subscription ends with the database
+    drop(other);
+    drop(db);
+    assert!(sub.next().is_none());
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_unlogged_writes_are_published() {
+    let test_dir = "/tmp/test_cdc_unlogged";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let unlogged = WriteOptions::default().wal_mode(WalMode::Disabled);
+    db.put_with(b"before", b"nobody listens", &unlogged)
+        .unwrap();
+
+    let mut sub = db.subscribe(None, None).unwrap();
+    db.put_with(b"k1", b"unlogged", &unlogged).unwrap();
+    db.put(b"k2", b"logged").unwrap();
+    db.put_with(
+        b"k3",
+        b"synced",
+        &WriteOptions::default().wal_mode(WalMode::Sync),
+    )
+    .unwrap();
+
+    let keys: Vec<_> = drain(&mut sub)
+        .into_iter()
+        .map(|b| b.changes[0].key.clone())
+        .collect();
+    assert_eq!(keys, vec![b"k1".to_vec(), b"k2".to_vec(), b"k3".to_vec()]);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_resume_from_retained_segments() {
+    let test_dir = "/tmp/test_cdc_resume";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = small_options().wal_retained_segments(100);
+    let db = Db::open_with(test_dir, opts.clone()).unwrap();
+    let mut sub = db.subscribe(None, None).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    assert!(db.num_files_at_level(0) + db.num_files_at_level(1) > 0);
+    let seqs: Vec<u64> = drain(&mut sub).iter().map(|b| b.seq).collect();
+    assert_eq!(seqs.len(), 2000);
+    drop(sub);
+
+    // the subscriber went away after key 1499, the batches since are read from the segments,
+    // archived or not, and the new ones follow without a gap or a duplicate
+    let opts_resume = SubscribeOptions::default().from_sequence(seqs[1500]);
+    let mut resumed = db.subscribe_with(None, None, opts_resume).unwrap();
+    for i in 2000..2100 {
+        db.put(&key(i), &[b'w'; 32]).unwrap();
+    }
+    let keys: Vec<_> = drain(&mut resumed)
+        .into_iter()
+        .map(|b| b.changes[0].key.clone())
+        .collect();
+    assert_eq!(keys, (1500..2100).map(key).collect::<Vec<_>>());
+    drop(resumed);
+
+    // the archive survives a reopen
+    drop(db);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    let opts_resume = SubscribeOptions::default().from_sequence(seqs[10]);
+    let mut resumed = db.subscribe_with(None, None, opts_resume).unwrap();
+    db.put(b"new", b"write").unwrap();
+    let batches = drain(&mut resumed);
+    assert_eq!(batches.len(), 2100 - 10 + 1);
+    assert_eq!(batches[0].changes[0].key, key(10));
+    assert_eq!(batches.last().unwrap().changes[0].key, b"new");
+    assert_eq!(db.get(&key(1999)).unwrap(), Some(vec![b'v'; 32]));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_archive_keeps_the_newest_segments() {
+    let test_dir = "/tmp/test_cdc_archive";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options().wal_retained_segments(2)).unwrap();
+    for i in 0..3000 {
+        db.put(&key(i), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let archived = fs::read_dir(format!("{}/wal-archive", test_dir))
+        .unwrap()
+        .count();
+    assert_eq!(archived, 2);
+
+    // the oldest writes are gone, resuming from them fails and tells where the WAL starts
+    let opts = SubscribeOptions::default().from_sequence(0);
+    let oldest = match db.subscribe_with(None, None, opts) {
+        Err(DbError::SequenceGap(oldest)) => oldest,
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("resumed from writes that are gone"),
+    };
+
+    let opts = SubscribeOptions::default().from_sequence(oldest);
+    let mut resumed = db.subscribe_with(None, None, opts).unwrap();
+    let batches = drain(&mut resumed);
+    assert_eq!(batches[0].seq, oldest);
+    let keys: BTreeSet<_> = batches
+        .into_iter()
+        .map(|b| b.changes[0].key.clone())
+        .collect();
+    assert!(!keys.contains(&key(0)));
+    assert!(keys.contains(&key(2999)));
+    let first = keys.iter().next().unwrap().clone();
+    let first = (0..3000).position(|i| key(i) == first).unwrap();
+    assert_eq!(keys.len(), 3000 - first);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_resume_without_retained_segments() {
+    let test_dir = "/tmp/test_cdc_no_archive";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options().wal_retained_segments(0)).unwrap();
+    let mut sub = db.subscribe(None, None).unwrap();
+    for i in 0..3000 {
+        db.put(&key(i), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let seqs: Vec<u64> = drain(&mut sub).iter().map(|b| b.seq).collect();
+    drop(sub);
+
+    // the segments of the first writes were deleted once they were flushed
+    let opts = SubscribeOptions::default().from_sequence(seqs[10]);
+    let oldest = match db.subscribe_with(None, None, opts) {
+        Err(DbError::SequenceGap(oldest)) => oldest,
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("resumed from writes that are gone"),
+    };
+    assert!(oldest > seqs[10]);
+
+    // the writes still in the WAL of the unflushed memtables are there to resume from
+    let opts = SubscribeOptions::default().from_sequence(oldest);
+    let mut resumed = db.subscribe_with(None, None, opts).unwrap();
+    db.put(b"new", b"write").unwrap();
+    let batches = drain(&mut resumed);
+    assert_eq!(batches[0].seq, oldest);
+    assert_eq!(batches.last().unwrap().changes[0].key, b"new");
+    let first = seqs.iter().position(|&seq| seq == oldest).unwrap();
+    assert_eq!(batches.len(), seqs.len() - first + 1);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_subscribing_during_writes() {
+    let test_dir = "/tmp/test_cdc_concurrent";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Arc::new(Db::open_with(test_dir, small_options().wal_retained_segments(100)).unwrap());
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..500 {
+                    db.put(&key(t * 1000 + i), b"v").unwrap();
+                }
+            })
+        })
+        .collect();
+
+    // joining halfway through, every write is seen exactly once whether it comes from the
+    // segments or from the live feed
+    thread::sleep(Duration::from_millis(5));
+    let opts = SubscribeOptions::default().from_sequence(0);
+    let mut sub = db.subscribe_with(None, None, opts).unwrap();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let batches = drain(&mut sub);
+    let seqs: BTreeSet<u64> = batches.iter().map(|b| b.seq).collect();
+    assert_eq!(batches.len(), 2000);
+    assert_eq!(seqs.len(), 2000);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,336 @@
+use keylite_kv::backup::BackupEngine;
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::error::DbError;
+use keylite_kv::stats::Ticker;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn small_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .min_blob_size(256)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:06}", i).into_bytes()
+}
+
+fn value(i: usize, round: u8) -> Vec<u8> {
+    let mut value = vec![b'a' + round; 64];
+    value[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    value
+}
+
+#[test]
+fn test_checkpoint_during_writes() {
+    let test_dir = "/tmp/test_checkpoint_live";
+    let checkpoints: Vec<_> = (0..3)
+        .map(|i| format!("/tmp/test_checkpoint_live_{}", i))
+        .collect();
+    let _ = fs::remove_dir_all(test_dir);
+    for dir in &checkpoints {
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    let db = Arc::new(Db::open_with(test_dir, small_options()).unwrap());
+    let acked = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (db, acked, stop) = (Arc::clone(&db), Arc::clone(&acked), Arc::clone(&stop));
+        thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::SeqCst) {
+                db.put(&key(i), &value(i, 0)).unwrap();
+                i += 1;
+                acked.store(i, Ordering::SeqCst);
+            }
+        })
+    };
+
+    // every write acknowledged before a checkpoint is in it, and since there is a single writer
+    // the keys it holds are always 0..n without gaps
+    let mut taken = Vec::new();
+    for dir in &checkpoints {
+        thread::sleep(Duration::from_millis(100));
+        let before = acked.load(Ordering::SeqCst);
+        db.checkpoint(dir).unwrap();
+        taken.push(before);
+    }
+    stop.store(true, Ordering::SeqCst);
+    writer.join().unwrap();
+
+    for (dir, before) in checkpoints.iter().zip(taken) {
+        let copy = Db::open_with(dir, small_options()).unwrap();
+        let found = copy.scan_prefix(b"key_").count();
+        assert!(
+            found >= before,
+            "{} keys in the checkpoint, {} acked",
+            found,
+            before
+        );
+        for i in 0..found {
+            assert_eq!(copy.get(&key(i)).unwrap(), Some(value(i, 0)));
+        }
+        drop(copy);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_checkpoint_contents() {
+    let test_dir = "/tmp/test_checkpoint_contents";
+    let dest = "/tmp/test_checkpoint_contents_copy";
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(dest);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let meta = db
+        .create_column_family("meta", DbOptions::default())
+        .unwrap();
+    for i in 0..1000 {
+        // every other value is large enough to go to a blob file
+        let val = if i % 2 == 0 {
+            vec![b'b'; 512]
+        } else {
+            value(i, 0)
+        };
+        db.put(&key(i), &val).unwrap();
+    }
+    db.delete_range(&key(100), &key(200)).unwrap();
+    db.put_cf(&meta, b"version", b"3").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    // still in the memtable when the checkpoint is taken
+    db.put(b"last", b"write").unwrap();
+
+    db.checkpoint(dest).unwrap();
+    let err = db.checkpoint(dest).unwrap_err();
+    assert!(matches!(err, DbError::Io(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists));
+
+    // the database goes on without affecting the copy
+    db.put(b"after", b"checkpoint").unwrap();
+    db.del(&key(1)).unwrap();
+    drop(meta);
+    drop(db);
+
+    let copy = Db::open_with(dest, small_options()).unwrap();
+    assert!(fs::read_dir(dest).unwrap().any(|e| e
+        .unwrap()
+        .path()
+        .extension()
+        .is_some_and(|ext| ext == "blob")));
+    for i in (0..1000).filter(|i| !(100..200).contains(i)) {
+        let val = if i % 2 == 0 {
+            vec![b'b'; 512]
+        } else {
+            value(i, 0)
+        };
+        assert_eq!(copy.get(&key(i)).unwrap(), Some(val));
+    }
+    assert_eq!(copy.get(&key(150)).unwrap(), None);
+    assert_eq!(copy.get(b"last").unwrap(), Some(b"write".to_vec()));
+    assert_eq!(copy.get(b"after").unwrap(), None);
+    let meta = copy.column_family("meta").unwrap();
+    assert_eq!(copy.get_cf(&meta, b"version").unwrap(), Some(b"3".to_vec()));
+
+    drop(meta);
+    drop(copy);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(dest);
+}
+
+#[test]
+fn test_checkpoint_after_failed_flush() {
+    let test_dir = "/tmp/test_checkpoint_failed_flush";
+    let dest = "/tmp/test_checkpoint_failed_flush_copy";
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(dest);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let mut written = 0;
+    // writes until the ticker reaches the count, the flush worker catches up in the meantime
+    let mut write_until = |ticker: Ticker, count: u64| {
+        while db.stats().ticker(ticker) < count {
+            assert!(written < 100_000, "{:?} never reached {}", ticker, count);
+            db.put(&key(written), &value(written, 0)).unwrap();
+            written += 1;
+        }
+    };
+    write_until(Ticker::Flushes, 1);
+
+    // a directory where the next table goes makes the flush of the next segment fail, the one
+    // after it takes the next file id and goes through
+    let next_table = fs::read_dir(test_dir)
+        .unwrap()
+        .filter_map(|e| {
+            let name = e.unwrap().file_name().into_string().unwrap();
+            name.strip_prefix("sst-")?
+                .strip_suffix(".db")?
+                .parse::<u64>()
+                .ok()
+        })
+        .max()
+        .unwrap()
+        + 1;
+    fs::create_dir(format!("{}/sst-{}.db", test_dir, next_table)).unwrap();
+    write_until(Ticker::FlushErrors, 1);
+    write_until(Ticker::Flushes, 2);
+
+    // the segment that failed to flush is older than the last flushed one but its data is still
+    // only in the WAL
+    db.checkpoint(dest).unwrap();
+    drop(db);
+
+    let copy = Db::open_with(dest, small_options()).unwrap();
+    for i in 0..written {
+        assert_eq!(copy.get(&key(i)).unwrap(), Some(value(i, 0)), "key {}", i);
+    }
+
+    drop(copy);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(dest);
+}
+
+#[test]
+fn test_incremental_backups() {
+    let test_dir = "/tmp/test_backup_incremental";
+    let backup_dir = "/tmp/test_backup_incremental_backups";
+    let restore_dirs = ["/tmp/test_backup_restore_1", "/tmp/test_backup_restore_2"];
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(backup_dir);
+    for dir in restore_dirs {
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    let opts = small_options().max_sstables(100);
+    let db = Db::open_with(test_dir, opts.clone()).unwrap();
+    let backups = BackupEngine::open(backup_dir).unwrap();
+    for i in 0..1000 {
+        db.put(&key(i), &value(i, 0)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let first = backups.create_backup(&db).unwrap();
+    let shared = || {
+        fs::read_dir(format!("{}/shared", backup_dir))
+            .unwrap()
+            .count()
+    };
+    let after_first = shared();
+    assert!(after_first > 0);
+
+    // the second backup only adds the tables written since the first
+    for i in 0..300 {
+        db.put(&key(i), &value(i, 1)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let second = backups.create_backup(&db).unwrap();
+    assert!(shared() > after_first);
+    assert!(shared() < 2 * after_first);
+
+    let list = backups.list_backups().unwrap();
+    assert_eq!(
+        list.iter().map(|b| b.id).collect::<Vec<_>>(),
+        vec![first, second]
+    );
+    assert!(list.iter().all(|b| b.size > 0 && b.num_files > 0));
+    backups.verify_backup(first).unwrap();
+    backups.verify_backup(second).unwrap();
+
+    backups.restore_backup(first, restore_dirs[0]).unwrap();
+    backups.restore_backup(second, restore_dirs[1]).unwrap();
+    let err = backups.restore_backup(second, restore_dirs[1]).unwrap_err();
+    assert!(matches!(err, DbError::Io(_)));
+    drop(db);
+
+    for (dir, round) in restore_dirs.into_iter().zip([0, 1]) {
+        let restored = Db::open_with(dir, opts.clone()).unwrap();
+        for i in 0..1000 {
+            let round = if i < 300 { round } else { 0 };
+            assert_eq!(restored.get(&key(i)).unwrap(), Some(value(i, round)));
+        }
+        drop(restored);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(backup_dir);
+}
+
+#[test]
+fn test_verify_and_delete_backups() {
+    let test_dir = "/tmp/test_backup_verify";
+    let backup_dir = "/tmp/test_backup_verify_backups";
+    let restore_dir = "/tmp/test_backup_verify_restore";
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(backup_dir);
+    let _ = fs::remove_dir_all(restore_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let backups = BackupEngine::open(backup_dir).unwrap();
+    for i in 0..1000 {
+        db.put(&key(i), &value(i, 0)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let first = backups.create_backup(&db).unwrap();
+    for i in 1000..2000 {
+        db.put(&key(i), &value(i, 0)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    let second = backups.create_backup(&db).unwrap();
+    drop(db);
+
+    // damage a shared table, every backup using it fails to verify and to restore
+    let table = fs::read_dir(format!("{}/shared", backup_dir))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|ext| ext == "db"))
+        .unwrap();
+    let original = fs::read(&table).unwrap();
+    let mut file = OpenOptions::new().write(true).open(&table).unwrap();
+    file.seek(SeekFrom::Start(10)).unwrap();
+    file.write_all(&[original[10] ^ 0xff]).unwrap();
+    drop(file);
+
+    let failed: Vec<_> = [first, second]
+        .into_iter()
+        .filter(|id| backups.verify_backup(*id).is_err())
+        .collect();
+    assert!(!failed.is_empty());
+    let err = backups.restore_backup(failed[0], restore_dir).unwrap_err();
+    assert!(matches!(err, DbError::DataCorruption(_)));
+    fs::write(&table, &original).unwrap();
+    backups.verify_backup(first).unwrap();
+
+    // deleting a backup keeps the files the other one still needs
+    backups.delete_backup(first).unwrap();
+    assert!(backups.delete_backup(first).is_err());
+    assert!(backups.verify_backup(first).is_err());
+    backups.verify_backup(second).unwrap();
+    assert_eq!(backups.list_backups().unwrap().len(), 1);
+
+    backups.delete_backup(second).unwrap();
+    assert_eq!(
+        fs::read_dir(format!("{}/shared", backup_dir))
+            .unwrap()
+            .count(),
+        0
+    );
+    assert_eq!(
+        fs::read_dir(format!("{}/private", backup_dir))
+            .unwrap()
+            .count(),
+        0
+    );
+
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(backup_dir);
+    let _ = fs::remove_dir_all(restore_dir);
+}
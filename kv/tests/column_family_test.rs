@@ -0,0 +1,245 @@
+use keylite_kv::core::{Db, DbOptions, WalMode, WriteBatch};
+use keylite_kv::error::DbError;
+use keylite_kv::merge::U64AddOperator;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn small_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+// copies the database directory as it is right now, as if the process had crashed
+fn crash_copy(from: &str, to: &str) {
+    let _ = fs::remove_dir_all(to);
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(to).join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+fn table_files(dir: &str) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| {
+            let name = e.as_ref().unwrap().file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .count()
+}
+
+#[test]
+fn test_families_are_separate_keyspaces() {
+    let test_dir = "/tmp/test_cf_keyspaces";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let docs = db.create_column_family("docs", small_options()).unwrap();
+    assert!(matches!(
+        db.create_column_family("docs", small_options()),
+        Err(DbError::ColumnFamily(_))
+    ));
+
+    db.put(b"k", b"default").unwrap();
+    db.put_cf(&docs, b"k", b"docs").unwrap();
+    for i in 0..2000 {
+        db.put_cf(&docs, &key(i), &[b'd'; 64]).unwrap();
+    }
+    db.del_cf(&docs, &key(0)).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(db.get(b"k").unwrap(), Some(b"default".to_vec()));
+    assert_eq!(db.get_cf(&docs, b"k").unwrap(), Some(b"docs".to_vec()));
+    assert_eq!(db.get(&key(1)).unwrap(), None);
+    assert_eq!(db.get_cf(&docs, &key(0)).unwrap(), None);
+    assert_eq!(db.scan(None, None).count(), 1);
+    assert_eq!(db.scan_cf(&docs, None, None).unwrap().count(), 2000);
+    assert!(db.num_files_at_level_cf(&docs, 0) + db.num_files_at_level_cf(&docs, 1) > 0);
+
+    drop(docs);
+    drop(db);
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    assert_eq!(db.column_family_names(), vec!["default", "docs"]);
+    let docs = db.column_family("docs").unwrap();
+    assert_eq!(db.get(b"k").unwrap(), Some(b"default".to_vec()));
+    assert_eq!(db.get_cf(&docs, b"k").unwrap(), Some(b"docs".to_vec()));
+    assert_eq!(db.get_cf(&docs, &key(1999)).unwrap(), Some(vec![b'd'; 64]));
+    assert_eq!(db.scan_cf(&docs, None, None).unwrap().count(), 2000);
+
+    drop(docs);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_batch_across_families_is_atomic() {
+    let test_dir = "/tmp/test_cf_batch";
+    let copy_dir = "/tmp/test_cf_batch_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = DbOptions::default().wal_mode(WalMode::Sync);
+    let db = Db::open_with(test_dir, opts.clone()).unwrap();
+    let docs = db
+        .create_column_family("docs", DbOptions::default())
+        .unwrap();
+    let index = db
+        .create_column_family("index", DbOptions::default())
+        .unwrap();
+    db.put_cf(&index, b"name:old", b"doc1").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put_cf(&docs, b"doc1", b"{\"name\":\"new\"}");
+    batch.delete_range_cf(&index, b"name:", b"name;");
+    batch.put_cf(&index, b"name:new", b"doc1");
+    batch.put(b"meta", b"1");
+    let snapshot = db.snapshot();
+    db.write(batch).unwrap();
+
+    // the range delete only applies to its own family
+    db.put_cf(&docs, b"name:x", b"kept").unwrap();
+    assert_eq!(db.get_cf(&index, b"name:old").unwrap(), None);
+    assert_eq!(
+        db.get_cf(&index, b"name:new").unwrap(),
+        Some(b"doc1".to_vec())
+    );
+    assert_eq!(db.get_cf(&docs, b"name:x").unwrap(), Some(b"kept".to_vec()));
+
+    // the families share the sequence numbers, a snapshot covers all of them
+    assert_eq!(db.get_seq(b"meta", &snapshot).unwrap(), None);
+    drop(snapshot);
+
+    // everything is still in the single WAL, it comes back routed to the right families
+    crash_copy(test_dir, copy_dir);
+    let recovered = Db::open_with(copy_dir, opts).unwrap();
+    let docs = recovered.column_family("docs").unwrap();
+    let index = recovered.column_family("index").unwrap();
+    assert_eq!(
+        recovered.get_cf(&docs, b"doc1").unwrap(),
+        Some(b"{\"name\":\"new\"}".to_vec())
+    );
+    assert_eq!(recovered.get_cf(&index, b"name:old").unwrap(), None);
+    assert_eq!(
+        recovered.get_cf(&index, b"name:new").unwrap(),
+        Some(b"doc1".to_vec())
+    );
+    assert_eq!(recovered.get(b"meta").unwrap(), Some(b"1".to_vec()));
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_drop_family_removes_its_data() {
+    let test_dir = "/tmp/test_cf_drop";
+    let copy_dir = "/tmp/test_cf_drop_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = small_options().wal_mode(WalMode::Sync);
+    let db = Db::open_with(test_dir, opts.clone()).unwrap();
+    let logs = db.create_column_family("logs", small_options()).unwrap();
+    for i in 0..3000 {
+        db.put_cf(&logs, &key(i), &[b'l'; 64]).unwrap();
+    }
+    db.put(b"keep", b"me").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let tables_before = table_files(test_dir);
+    assert!(tables_before > 0);
+
+    db.drop_column_family("logs").unwrap();
+    assert!(logs.is_dropped());
+    assert!(matches!(
+        db.get_cf(&logs, &key(0)),
+        Err(DbError::ColumnFamily(_))
+    ));
+    assert!(db.put_cf(&logs, b"k", b"v").is_err());
+    let mut batch = WriteBatch::new();
+    batch.put_cf(&logs, b"k", b"v");
+    assert!(db.write(batch).is_err());
+    assert!(table_files(test_dir) < tables_before);
+    assert!(db.drop_column_family("logs").is_err());
+    assert!(db.drop_column_family("default").is_err());
+
+    // a family of the same name starts out empty, the writes of the old one still in the WAL
+    // don't end up in it
+    let logs = db.create_column_family("logs", small_options()).unwrap();
+    db.put_cf(&logs, b"fresh", b"1").unwrap();
+    crash_copy(test_dir, copy_dir);
+    let recovered = Db::open_with(copy_dir, opts).unwrap();
+    let logs = recovered.column_family("logs").unwrap();
+    assert_eq!(recovered.get_cf(&logs, &key(2999)).unwrap(), None);
+    assert_eq!(
+        recovered.get_cf(&logs, b"fresh").unwrap(),
+        Some(b"1".to_vec())
+    );
+    assert_eq!(recovered.scan_cf(&logs, None, None).unwrap().count(), 1);
+    assert_eq!(recovered.get(b"keep").unwrap(), Some(b"me".to_vec()));
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_families_have_their_own_options() {
+    let test_dir = "/tmp/test_cf_options";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let counters_opts = small_options().merge_operator(Arc::new(U64AddOperator));
+    let db = Db::open_with_column_families(
+        test_dir,
+        small_options(),
+        vec![("counters".to_string(), counters_opts.clone())],
+    )
+    .unwrap();
+    let counters = db.column_family("counters").unwrap();
+    assert!(counters.options().merge_operator.is_some());
+
+    // only the counters family has a merge operator
+    assert!(matches!(
+        db.merge(b"hits", &U64AddOperator::encode(1)),
+        Err(DbError::InvalidOptions(_))
+    ));
+    for _ in 0..10 {
+        db.merge_cf(&counters, b"hits", &U64AddOperator::encode(1))
+            .unwrap();
+    }
+    assert_eq!(
+        db.get_cf(&counters, b"hits").unwrap(),
+        Some(U64AddOperator::encode(10))
+    );
+
+    drop(counters);
+    drop(db);
+    let db = Db::open_with_column_families(
+        test_dir,
+        small_options(),
+        vec![("counters".to_string(), counters_opts)],
+    )
+    .unwrap();
+    let counters = db.column_family("counters").unwrap();
+    db.merge_cf(&counters, b"hits", &U64AddOperator::encode(5))
+        .unwrap();
+    assert_eq!(
+        db.get_cf(&counters, b"hits").unwrap(),
+        Some(U64AddOperator::encode(15))
+    );
+
+    drop(counters);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -513,8 +513,8 @@ fn test_scan_with_versions() {
     db.put(b"key2", b"v2_2").unwrap();
 
     let mut results = vec![];
-    let mut iter = db.scan(None, None);
-    while let Some((key, value)) = iter.next() {
+    let iter = db.scan(None, None);
+    for (key, value) in iter {
         results.push((key, value));
     }
 
@@ -0,0 +1,148 @@
+use keylite_kv::core::{CompressionType, Db, DbOptions};
+use keylite_kv::sst::writer::WriterOptions;
+use keylite_kv::sst::{SSTIterator, SSTReader, SSTWriter};
+use std::fs;
+
+const CODECS: [CompressionType; 4] = [
+    CompressionType::None,
+    CompressionType::Lz4,
+    CompressionType::Zstd,
+    CompressionType::Snappy,
+];
+
+fn json_doc(i: usize) -> String {
+    format!(
+        r#"{{"_id":"{:06}","name":"user {}","email":"user{}@example.com","tags":["a","b","c"],"active":true}}"#,
+        i, i, i
+    )
+}
+
+fn write_table(path: &str, compression: CompressionType, count: usize) -> u64 {
+    let opts = WriterOptions {
+        compression,
+        ..WriterOptions::default()
+    };
+    let mut writer = SSTWriter::with_options(path, opts).unwrap();
+    for i in 0..count {
+        let key = format!("doc_{:06}", i);
+        writer
+            .add(key.as_bytes(), json_doc(i).as_bytes(), i as u64 + 1)
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    fs::metadata(path).unwrap().len()
+}
+
+#[test]
+fn test_every_codec_round_trips() {
+    let test_dir = "/tmp/test_compression_codecs";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut sizes = Vec::new();
+    for (n, codec) in CODECS.iter().enumerate() {
+        let path = format!("{}/sst-{}.db", test_dir, n + 1);
+        sizes.push(write_table(&path, *codec, 5000));
+
+        let reader = SSTReader::open(&path).unwrap();
+        assert_eq!(
+            reader.get(b"doc_000000").unwrap(),
+            Some(json_doc(0).into_bytes())
+        );
+        assert_eq!(
+            reader.get(b"doc_004321").unwrap(),
+            Some(json_doc(4321).into_bytes())
+        );
+        assert_eq!(reader.get(b"doc_999999").unwrap(), None);
+
+        let entries: Vec<_> = SSTIterator::new(reader).map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 5000);
+        assert_eq!(entries[17].1, json_doc(17).into_bytes());
+    }
+
+    // json documents are very repetitive, every codec has to beat the uncompressed table
+    for (codec, size) in CODECS.iter().zip(&sizes).skip(1) {
+        assert!(
+            *size * 2 < sizes[0],
+            "{:?} table is {} bytes, uncompressed is {}",
+            codec,
+            size,
+            sizes[0]
+        );
+    }
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_incompressible_blocks_are_stored_raw() {
+    let test_dir = "/tmp/test_compression_incompressible";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+
+    let path = format!("{}/sst-1.db", test_dir);
+    let mut writer = SSTWriter::with_options(
+        &path,
+        WriterOptions {
+            compression: CompressionType::Zstd,
+            ..WriterOptions::default()
+        },
+    )
+    .unwrap();
+    let mut values = Vec::new();
+    for i in 0..200u64 {
+        let val: Vec<u8> = (0..256).map(|_| fastrand::u8(..)).collect();
+        writer
+            .add(format!("k{:04}", i).as_bytes(), &val, i + 1)
+            .unwrap();
+        values.push(val);
+    }
+    writer.finish().unwrap();
+
+    let reader = SSTReader::open(&path).unwrap();
+    for (i, val) in values.iter().enumerate() {
+        assert_eq!(
+            reader
+                .get(format!("k{:04}", i).as_bytes())
+                .unwrap()
+                .as_ref(),
+            Some(val)
+        );
+    }
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_changing_codec_between_opens() {
+    let test_dir = "/tmp/test_compression_mixed";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = |codec| {
+        DbOptions::default()
+            .memtable_size_threshold(32 * 1024)
+            .max_sstables(2)
+            .compression(codec)
+    };
+
+    // every open writes its tables with a different codec, compaction has to read all of them
+    for (round, codec) in CODECS.iter().enumerate() {
+        let db = Db::open_with(test_dir, opts(*codec)).unwrap();
+        for i in 0..1000 {
+            let key = format!("doc_{:06}", i);
+            let val = format!("{}{}", json_doc(i), round);
+            db.put(key.as_bytes(), val.as_bytes()).unwrap();
+        }
+    }
+
+    let db = Db::open_with(test_dir, opts(CompressionType::Lz4)).unwrap();
+    for i in (0..1000).step_by(7) {
+        let key = format!("doc_{:06}", i);
+        let expected = format!("{}{}", json_doc(i), CODECS.len() - 1);
+        assert_eq!(db.get(key.as_bytes()).unwrap(), Some(expected.into_bytes()));
+    }
+    assert_eq!(db.scan(None, None).count(), 1000);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
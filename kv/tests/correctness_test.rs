@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+type ExpectedState = HashMap<Vec<u8>, Option<Vec<u8>>>;
+
 fn create_test_db(test_name: &str) -> Db {
     let path = format!("test_data/{}", test_name);
     let _ = std::fs::remove_dir_all(&path);
@@ -76,7 +78,7 @@ fn test_empty_key_value() {
     let db = create_test_db("empty_key_value");
 
     db.put(b"key1", b"").unwrap();
-    assert_eq!(db.get(b"key1").unwrap(), None);
+    assert_eq!(db.get(b"key1").unwrap(), Some(Vec::new()));
 
     db.put(b"", b"value").unwrap();
     assert_eq!(db.get(b"").unwrap(), Some(b"value".to_vec()));
@@ -526,8 +528,7 @@ fn test_stress_concurrent_mixed_ops() {
     let num_threads = 8;
     let operations_per_thread = 1000;
 
-    let expected_state: Arc<Mutex<HashMap<Vec<u8>, Option<Vec<u8>>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    let expected_state: Arc<Mutex<ExpectedState>> = Arc::new(Mutex::new(HashMap::new()));
 
     let mut handles = vec![];
 
@@ -0,0 +1,181 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::sst::writer::WriterOptions;
+use keylite_kv::sst::{SSTIterator, SSTReader, SSTWriter};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_seek_across_memtables_and_tables() {
+    let test_dir = "/tmp/test_iterator_seek_sources";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    // the even keys end up in tables, the odd ones are still in the memtables
+    for i in (0..2000).step_by(2) {
+        db.put(&key(i), &[b'e'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    for i in (1..2000).step_by(2) {
+        db.put(&key(i), b"odd").unwrap();
+    }
+    db.del(&key(1001)).unwrap();
+
+    let mut iter = db.scan(None, None);
+    iter.seek(&key(1000));
+    assert_eq!(iter.next().unwrap().0, key(1000));
+    // the deleted key is skipped after a seek like on any other step
+    assert_eq!(iter.next().unwrap().0, key(1002));
+    assert_eq!(iter.next(), Some((key(1003), b"odd".to_vec())));
+
+    // seeking backwards works as well
+    iter.seek(&key(7));
+    assert_eq!(iter.next(), Some((key(7), b"odd".to_vec())));
+    assert_eq!(iter.next(), Some((key(8), vec![b'e'; 64])));
+
+    // a key that isn't stored lands on the next one
+    iter.seek(b"key_00010_");
+    assert_eq!(iter.next().unwrap().0, key(11));
+
+    iter.seek(b"zzz");
+    assert_eq!(iter.next(), None);
+
+    iter.seek_to_first();
+    assert_eq!(iter.next().unwrap().0, key(0));
+    assert_eq!(iter.count(), 1998);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_seek_stays_within_scan_bounds() {
+    let test_dir = "/tmp/test_iterator_seek_bounds";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    for i in 0..100 {
+        db.put(&key(i), b"value").unwrap();
+    }
+
+    let mut iter = db.scan(Some(&key(20)), Some(&key(30)));
+    // a key before the start bound seeks to the start bound
+    iter.seek(&key(5));
+    assert_eq!(iter.next().unwrap().0, key(20));
+
+    iter.seek(&key(28));
+    let keys: Vec<_> = iter.by_ref().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![key(28), key(29)]);
+
+    iter.seek(&key(50));
+    assert_eq!(iter.next(), None);
+
+    iter.seek_to_first();
+    assert_eq!(iter.count(), 10);
+
+    // the snapshot of the scan holds across seeks
+    let mut iter = db.scan(None, None);
+    db.put(&key(100), b"value").unwrap();
+    db.del(&key(50)).unwrap();
+    iter.seek(&key(50));
+    assert_eq!(iter.next().unwrap().0, key(50));
+    iter.seek(&key(99));
+    assert_eq!(iter.next().unwrap().0, key(99));
+    assert_eq!(iter.next(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_table_iterator_seeks_through_the_block_index() {
+    let test_dir = "/tmp/test_iterator_seek_table";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+    let path = Path::new(test_dir).join("sst-1.db");
+
+    // several versions per key, so some keys straddle a block boundary
+    let opts = WriterOptions {
+        block_size: 256,
+        ..WriterOptions::default()
+    };
+    let mut writer = SSTWriter::with_options(&path, opts).unwrap();
+    for i in 0..200 {
+        for seq in (1..=3u64).rev() {
+            writer.add(&key(i), &[b'v'; 16], seq).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let reader = SSTReader::open(&path).unwrap();
+    let mut iter = SSTIterator::new(reader);
+    for i in [0, 57, 123, 199] {
+        iter.seek(&key(i));
+        for seq in (1..=3u64).rev() {
+            let (k, _, s, _) = iter.next().unwrap().unwrap();
+            assert_eq!((k, s), (key(i), seq));
+        }
+    }
+
+    iter.seek(b"key_00042_");
+    assert_eq!(iter.next().unwrap().unwrap().0, key(43));
+
+    iter.seek(b"zzz");
+    assert!(iter.next().is_none());
+
+    iter.seek_to_first();
+    assert_eq!(iter.count(), 600);
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_transaction_scan_seeks_through_its_buffer() {
+    let test_dir = "/tmp/test_iterator_seek_transaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    for i in (0..20).step_by(2) {
+        db.put(&key(i), b"db").unwrap();
+    }
+
+    let mut txn = db.begin();
+    txn.put(&key(5), b"txn");
+    txn.put(&key(6), b"txn");
+    txn.del(&key(8));
+
+    let mut iter = txn.scan(None, Some(&key(12)));
+    iter.seek(&key(5));
+    let scanned: Vec<_> = iter.by_ref().collect();
+    assert_eq!(
+        scanned,
+        vec![
+            (key(5), b"txn".to_vec()),
+            (key(6), b"txn".to_vec()),
+            (key(10), b"db".to_vec())
+        ]
+    );
+
+    iter.seek_to_first();
+    assert_eq!(iter.count(), 6);
+
+    drop(txn);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,243 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::sst::SSTReader;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+fn open_tables(dir: &str) -> Vec<SSTReader> {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().into_string().unwrap_or_default();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .filter_map(|e| SSTReader::open(e.path()).ok())
+        .collect()
+}
+
+#[test]
+fn test_levels_are_created_and_non_overlapping() {
+    let test_dir = "/tmp/test_leveled_non_overlapping";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    let mut expected = BTreeMap::new();
+
+    for round in 0..4 {
+        for i in 0..3000 {
+            let key = format!("key_{:06}", (i * 7919) % 3000);
+            let val = format!("value_{}_{}", round, i);
+            db.put(key.as_bytes(), val.as_bytes()).unwrap();
+            expected.insert(key, val);
+        }
+    }
+    for i in (0..3000).step_by(5) {
+        let key = format!("key_{:06}", i);
+        db.del(key.as_bytes()).unwrap();
+        expected.remove(&key);
+    }
+
+    // let the background workers settle
+    thread::sleep(Duration::from_millis(500));
+
+    let deeper: usize = (1..7).map(|l| db.num_files_at_level(l)).sum();
+    assert!(deeper > 0, "data should have been pushed below level 0");
+
+    for (key, val) in &expected {
+        assert_eq!(
+            db.get(key.as_bytes()).unwrap(),
+            Some(val.as_bytes().to_vec()),
+            "wrong value for {}",
+            key
+        );
+    }
+    let scanned: Vec<_> = db.scan(None, None).collect();
+    assert_eq!(scanned.len(), expected.len());
+
+    drop(db);
+
+    // every level below 0 must hold tables with disjoint key ranges
+    let tables = open_tables(test_dir);
+    for level in 1..7 {
+        let mut ranges: Vec<_> = tables
+            .iter()
+            .filter(|t| t.level() == level)
+            .map(|t| (t.smallest_key().to_vec(), t.largest_key().to_vec()))
+            .collect();
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            assert!(
+                pair[0].1 < pair[1].0,
+                "overlapping tables in level {}: {:?} / {:?}",
+                level,
+                String::from_utf8_lossy(&pair[0].1),
+                String::from_utf8_lossy(&pair[1].0)
+            );
+        }
+    }
+
+    // and the layout survives a reopen
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for (key, val) in expected.iter().step_by(13) {
+        assert_eq!(
+            db.get(key.as_bytes()).unwrap(),
+            Some(val.as_bytes().to_vec())
+        );
+    }
+    assert_eq!(db.get(b"key_000000").unwrap(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_deletes_do_not_resurrect_after_compaction() {
+    let test_dir = "/tmp/test_leveled_no_resurrect";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+
+    for i in 0..2000 {
+        let key = format!("key_{:05}", i);
+        db.put(key.as_bytes(), &[b'x'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    for i in 0..1000 {
+        let key = format!("key_{:05}", i);
+        db.del(key.as_bytes()).unwrap();
+    }
+    // push more data so the tombstones travel through the levels
+    for i in 0..3000 {
+        let key = format!("other_{:05}", i);
+        db.put(key.as_bytes(), &[b'y'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(500));
+
+    for i in 0..2000 {
+        let key = format!("key_{:05}", i);
+        let got = db.get(key.as_bytes()).unwrap();
+        if i < 1000 {
+            assert_eq!(got, None, "{} came back after compaction", key);
+        } else {
+            assert_eq!(got, Some(vec![b'x'; 64]));
+        }
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+// writes a table in the original (version 1) format: data blocks, index, bloom and footer, with
+// no meta block in front of the footer
+fn write_v1_table(path: &str, entries: &[(&[u8], &[u8], u64)]) {
+    fn with_crc(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        out
+    }
+
+    let mut block = Vec::new();
+    for (key, val, seq) in entries {
+        block.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        block.extend_from_slice(&(val.len() as u32).to_le_bytes());
+        block.extend_from_slice(key);
+        block.extend_from_slice(&seq.to_le_bytes());
+        block.extend_from_slice(val);
+    }
+    let mut file = with_crc(&block);
+
+    let index_offset = file.len() as u64;
+    let mut index = Vec::new();
+    index.extend_from_slice(&1u32.to_le_bytes());
+    index.extend_from_slice(&(entries[0].0.len() as u16).to_le_bytes());
+    index.extend_from_slice(&0u64.to_le_bytes());
+    index.extend_from_slice(entries[0].0);
+    file.extend(with_crc(&index));
+
+    // an all ones bloom filter never rules a key out
+    let bloom_offset = file.len() as u64;
+    file.extend(with_crc(&[0xFF; 64]));
+
+    file.extend_from_slice(&0x4B45594C54_u64.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&index_offset.to_le_bytes());
+    file.extend_from_slice(&bloom_offset.to_le_bytes());
+    file.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    let min_seq = entries.iter().map(|e| e.2).min().unwrap();
+    let max_seq = entries.iter().map(|e| e.2).max().unwrap();
+    file.extend_from_slice(&min_seq.to_le_bytes());
+    file.extend_from_slice(&max_seq.to_le_bytes());
+
+    fs::File::create(path).unwrap().write_all(&file).unwrap();
+}
+
+#[test]
+fn test_version_1_tables_still_open() {
+    let test_dir = "/tmp/test_leveled_v1_tables";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+
+    write_v1_table(
+        &format!("{}/sst-1.db", test_dir),
+        &[
+            (b"a", b"old_a", 1),
+            (b"b", b"old_b", 2),
+            (b"c", b"old_c", 3),
+        ],
+    );
+    write_v1_table(
+        &format!("{}/sst-2.db", test_dir),
+        &[(b"b", b"new_b", 4), (b"d", b"new_d", 5)],
+    );
+
+    let table = SSTReader::open(format!("{}/sst-1.db", test_dir)).unwrap();
+    assert_eq!(table.level(), 0);
+    assert_eq!(table.smallest_key(), b"a");
+    assert_eq!(table.largest_key(), b"c");
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    assert_eq!(db.num_files_at_level(0), 2);
+    assert_eq!(db.get(b"a").unwrap(), Some(b"old_a".to_vec()));
+    assert_eq!(db.get(b"b").unwrap(), Some(b"new_b".to_vec()));
+    assert_eq!(db.get(b"d").unwrap(), Some(b"new_d".to_vec()));
+
+    // new writes land on top of the old tables and everything ends up compacted together
+    db.put(b"e", b"e").unwrap();
+    for i in 0..2000 {
+        let key = format!("z_{:05}", i);
+        db.put(key.as_bytes(), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    let all: Vec<_> = db.scan(None, Some(b"z")).collect();
+    assert_eq!(
+        all,
+        vec![
+            (b"a".to_vec(), b"old_a".to_vec()),
+            (b"b".to_vec(), b"new_b".to_vec()),
+            (b"c".to_vec(), b"old_c".to_vec()),
+            (b"d".to_vec(), b"new_d".to_vec()),
+            (b"e".to_vec(), b"e".to_vec()),
+        ]
+    );
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
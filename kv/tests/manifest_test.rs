@@ -0,0 +1,168 @@
+use keylite_kv::core::Db;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+fn table_files(dir: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            let name = p.file_name().unwrap().to_string_lossy();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_manifest_is_created_and_survives_reopen() {
+    let test_dir = "/tmp/test_manifest_reopen";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        for i in 0..100 {
+            let key = format!("key_{:03}", i);
+            db.put(key.as_bytes(), b"value").unwrap();
+        }
+    }
+
+    assert!(Path::new(test_dir).join("MANIFEST").exists());
+    assert!(!Path::new(test_dir).join("MANIFEST.tmp").exists());
+
+    let db = Db::open(test_dir).unwrap();
+    for i in 0..100 {
+        let key = format!("key_{:03}", i);
+        assert_eq!(db.get(key.as_bytes()).unwrap(), Some(b"value".to_vec()));
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_orphaned_tables_are_ignored_and_removed() {
+    let test_dir = "/tmp/test_manifest_orphans";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        db.put(b"k", b"old").unwrap();
+    }
+    let stale = fs::read(&table_files(test_dir)[0]).unwrap();
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        db.put(b"k", b"new").unwrap();
+    }
+
+    // looks like the newest level 0 table, exactly what a compaction that crashed before deleting
+    // its inputs would leave behind
+    let orphan = Path::new(test_dir).join("sst-999.db");
+    fs::write(&orphan, stale).unwrap();
+
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(b"k").unwrap(), Some(b"new".to_vec()));
+    assert!(
+        !orphan.exists(),
+        "orphaned table should be garbage collected"
+    );
+
+    // new tables must not reuse the id of the removed orphan
+    db.put(b"k", b"newer").unwrap();
+    drop(db);
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(b"k").unwrap(), Some(b"newer".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_missing_live_table_is_an_error() {
+    let test_dir = "/tmp/test_manifest_missing_table";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        db.put(b"a", b"1").unwrap();
+    }
+    for path in table_files(test_dir) {
+        fs::remove_file(path).unwrap();
+    }
+
+    assert!(Db::open(test_dir).is_err());
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_directory_without_manifest_is_adopted() {
+    let test_dir = "/tmp/test_manifest_bootstrap";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"2").unwrap();
+    }
+    fs::remove_file(Path::new(test_dir).join("MANIFEST")).unwrap();
+
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert!(Path::new(test_dir).join("MANIFEST").exists());
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_unreadable_table_without_manifest_is_kept() {
+    let test_dir = "/tmp/test_manifest_bootstrap_damaged";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        db.put(b"a", b"1").unwrap();
+    }
+    fs::remove_file(Path::new(test_dir).join("MANIFEST")).unwrap();
+    let tables = table_files(test_dir);
+    assert!(!tables.is_empty());
+    fs::write(&tables[0], b"not a table").unwrap();
+
+    // the damaged table is neither adopted nor collected as an orphan
+    assert!(Db::open(test_dir).is_err());
+    assert_eq!(table_files(test_dir), tables);
+    assert!(!Path::new(test_dir).join("MANIFEST").exists());
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_torn_manifest_tail_is_ignored() {
+    let test_dir = "/tmp/test_manifest_torn_tail";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open(test_dir).unwrap();
+        db.put(b"a", b"1").unwrap();
+    }
+
+    // half a record header, as left by a crash in the middle of an append
+    let mut manifest = fs::OpenOptions::new()
+        .append(true)
+        .open(Path::new(test_dir).join("MANIFEST"))
+        .unwrap();
+    manifest.write_all(&[0x20, 0x00, 0x00]).unwrap();
+    drop(manifest);
+
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
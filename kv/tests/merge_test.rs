@@ -0,0 +1,219 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::error::DbError;
+use keylite_kv::merge::{ListAppendOperator, U64AddOperator};
+use keylite_kv::sst::{SSTIterator, SSTReader};
+use keylite_kv::types::ValueType;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn counter_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .merge_operator(Arc::new(U64AddOperator))
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn counter(db: &Db, key: &[u8]) -> Option<u64> {
+    db.get(key)
+        .unwrap()
+        .map(|v| U64AddOperator::decode(&v).unwrap())
+}
+
+// number of Merge entries in the tables of the directory, a table compaction deletes in the
+// meantime is skipped
+fn merge_entries(dir: &str) -> usize {
+    let mut operands = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        let Ok(reader) = SSTReader::open(&path) else {
+            continue;
+        };
+        operands += SSTIterator::new(reader)
+            .filter_map(|entry| entry.ok())
+            .filter(|(_, _, _, value_type)| *value_type == ValueType::Merge)
+            .count();
+    }
+    operands
+}
+
+#[test]
+fn test_counters_fold_across_flush_and_reopen() {
+    let test_dir = "/tmp/test_merge_counters";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, counter_options()).unwrap();
+    db.put(&key(0), &U64AddOperator::encode(100)).unwrap();
+    for round in 0..20 {
+        for i in 0..100 {
+            db.merge(&key(i), &U64AddOperator::encode(round)).unwrap();
+        }
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    // 0 + 1 + ... + 19 on top of whatever the key held
+    assert_eq!(counter(&db, &key(0)), Some(290));
+    assert_eq!(counter(&db, &key(99)), Some(190));
+    assert_eq!(counter(&db, &key(100)), None);
+
+    drop(db);
+    let db = Db::open_with(test_dir, counter_options()).unwrap();
+    assert_eq!(counter(&db, &key(0)), Some(290));
+    assert_eq!(counter(&db, &key(42)), Some(190));
+    let sums: Vec<_> = db
+        .scan(None, None)
+        .map(|(_, v)| U64AddOperator::decode(&v).unwrap())
+        .collect();
+    assert_eq!(sums.len(), 100);
+    assert!(sums[1..].iter().all(|sum| *sum == 190));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_list_append_over_values_deletes_and_snapshots() {
+    let test_dir = "/tmp/test_merge_list";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = DbOptions::default().merge_operator(Arc::new(ListAppendOperator::default()));
+    let db = Db::open_with(test_dir, opts).unwrap();
+    db.put(b"a", b"x").unwrap();
+    db.merge(b"a", b"y").unwrap();
+    db.merge(b"b", b"1").unwrap();
+    let snapshot = db.snapshot();
+    db.merge(b"a", b"z").unwrap();
+    db.merge(b"b", b"2").unwrap();
+
+    assert_eq!(db.get(b"a").unwrap(), Some(b"x,y,z".to_vec()));
+    assert_eq!(db.get(b"b").unwrap(), Some(b"1,2".to_vec()));
+    assert_eq!(db.get_seq(b"a", &snapshot).unwrap(), Some(b"x,y".to_vec()));
+
+    let scanned: Vec<_> = db.scan(None, None).collect();
+    let expected = vec![
+        (b"a".to_vec(), b"x,y,z".to_vec()),
+        (b"b".to_vec(), b"1,2".to_vec()),
+    ];
+    assert_eq!(scanned, expected);
+    let reversed: Vec<_> = db.scan_rev(None, None).collect();
+    assert_eq!(reversed, expected.into_iter().rev().collect::<Vec<_>>());
+    let scanned: Vec<_> = db.scan_seq(None, None, &snapshot).collect();
+    assert_eq!(
+        scanned,
+        vec![
+            (b"a".to_vec(), b"x,y".to_vec()),
+            (b"b".to_vec(), b"1".to_vec())
+        ]
+    );
+
+    // operands after a delete start from scratch, the ones before it are gone
+    db.del(b"a").unwrap();
+    db.merge(b"a", b"w").unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"w".to_vec()));
+    db.delete_range(b"a", b"c").unwrap();
+    db.merge(b"b", b"3").unwrap();
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"3".to_vec()));
+
+    drop(snapshot);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_compaction_collapses_operands() {
+    let test_dir = "/tmp/test_merge_compaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // with two levels every compaction writes the bottommost level
+    let db = Db::open_with(test_dir, counter_options().max_levels(2)).unwrap();
+    let snapshot = db.snapshot();
+    // compaction runs whenever a memtable is frozen, so it takes a few rounds to reach all the
+    // tables holding operands
+    let mut rounds = 0;
+    for _ in 0..10 {
+        for i in 0..2000 {
+            db.merge(&key(i), &U64AddOperator::encode(1)).unwrap();
+        }
+        rounds += 1;
+        thread::sleep(Duration::from_millis(100));
+        if rounds >= 3 && merge_entries(test_dir) == 0 {
+            break;
+        }
+    }
+    assert_eq!(merge_entries(test_dir), 0);
+    assert!(db.num_files_at_level(1) > 0);
+
+    for i in (0..2000).step_by(199) {
+        assert_eq!(counter(&db, &key(i)), Some(rounds));
+    }
+    // the snapshot was taken before the first operand
+    assert_eq!(db.get_seq(&key(0), &snapshot).unwrap(), None);
+
+    drop(snapshot);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_concurrent_merges_lose_no_updates() {
+    let test_dir = "/tmp/test_merge_concurrent";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Arc::new(Db::open_with(test_dir, counter_options()).unwrap());
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    db.merge(b"hits", &U64AddOperator::encode(1)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(counter(&db, b"hits"), Some(4000));
+    // and still once the flushes and compactions the writes set off have folded the operands
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(counter(&db, b"hits"), Some(4000));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_merge_needs_an_operator() {
+    let test_dir = "/tmp/test_merge_no_operator";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    assert!(matches!(
+        db.merge(b"k", b"v"),
+        Err(DbError::InvalidOptions(_))
+    ));
+    drop(db);
+
+    // operands written with an operator can't be read without one
+    let opts = DbOptions::default().merge_operator(Arc::new(ListAppendOperator::default()));
+    let db = Db::open_with(test_dir, opts).unwrap();
+    db.merge(b"k", b"v").unwrap();
+    drop(db);
+    let db = Db::open(test_dir).unwrap();
+    assert!(db.get(b"k").is_err());
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
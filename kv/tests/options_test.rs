@@ -0,0 +1,93 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::error::DbError;
+use std::fs;
+
+fn count_sst_files(dir: &str) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().into_string().unwrap_or_default();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .count()
+}
+
+#[test]
+fn test_small_memtable_threshold_flushes_early() {
+    let test_dir = "/tmp/test_options_small_memtable";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = DbOptions::default()
+        .memtable_size_threshold(4 * 1024)
+        .max_sstables(100)
+        .block_size(512)
+        .bloom_size(256)
+        .wal_sync_interval_ms(5);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    assert_eq!(db.options().memtable_size_threshold, 4 * 1024);
+
+    for i in 0..2000 {
+        let key = format!("key_{:05}", i);
+        let val = format!("value_{:05}", i);
+        db.put(key.as_bytes(), val.as_bytes()).unwrap();
+    }
+
+    // give the flush worker some time to write the frozen memtables
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(
+        count_sst_files(test_dir) > 0,
+        "a 4KB memtable should have been flushed to an sstable"
+    );
+
+    for i in (0..2000).step_by(97) {
+        let key = format!("key_{:05}", i);
+        let val = format!("value_{:05}", i);
+        assert_eq!(db.get(key.as_bytes()).unwrap(), Some(val.into_bytes()));
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_reopen_with_different_options() {
+    let test_dir = "/tmp/test_options_reopen";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let opts = DbOptions::default().block_size(256).bloom_size(64);
+        let db = Db::open_with(test_dir, opts).unwrap();
+        for i in 0..500 {
+            let key = format!("key_{:04}", i);
+            db.put(key.as_bytes(), b"v").unwrap();
+        }
+    }
+
+    let db = Db::open(test_dir).unwrap();
+    for i in 0..500 {
+        let key = format!("key_{:04}", i);
+        assert_eq!(db.get(key.as_bytes()).unwrap(), Some(b"v".to_vec()));
+    }
+    assert_eq!(db.scan(None, None).count(), 500);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_invalid_options_rejected() {
+    let test_dir = "/tmp/test_options_invalid";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let res = Db::open_with(test_dir, DbOptions::default().memtable_size_threshold(0));
+    assert!(matches!(res, Err(DbError::InvalidOptions(_))));
+
+    let res = Db::open_with(test_dir, DbOptions::default().bloom_size(0));
+    assert!(matches!(res, Err(DbError::InvalidOptions(_))));
+
+    let res = Db::open_with(test_dir, DbOptions::default().block_size(0));
+    assert!(matches!(res, Err(DbError::InvalidOptions(_))));
+
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,174 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::prefix::{FixedPrefix, PrefixExtractor, SeparatorPrefix};
+use keylite_kv::sst::writer::{SSTWriter, WriterOptions};
+use keylite_kv::sst::SSTReader;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn small_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+}
+
+fn user_key(user: usize, item: usize) -> Vec<u8> {
+    format!("user:{:03}:item:{:05}", user, item).into_bytes()
+}
+
+fn keys(iter: impl Iterator<Item = (Vec<u8>, Vec<u8>)>) -> Vec<Vec<u8>> {
+    iter.map(|(k, _)| k).collect()
+}
+
+#[test]
+fn test_scan_prefix_stops_at_the_prefix_boundary() {
+    let test_dir = "/tmp/test_prefix_boundary";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // no extractor, the scan still knows where the prefix ends
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    for user in 0..20 {
+        for item in 0..100 {
+            db.put(&user_key(user, item), &[b'v'; 32]).unwrap();
+        }
+    }
+    db.put(b"ab\xff", b"1").unwrap();
+    db.put(b"ab\xff\xff\x01", b"2").unwrap();
+    db.put(b"ac", b"3").unwrap();
+    db.put(b"\xff\xff", b"4").unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let found = keys(db.scan_prefix(b"user:007:"));
+    assert_eq!(found, (0..100).map(|i| user_key(7, i)).collect::<Vec<_>>());
+    assert_eq!(db.scan_prefix(b"user:007:item:0004").count(), 10);
+    assert_eq!(db.scan_prefix(b"user:").count(), 2000);
+    assert_eq!(db.scan_prefix(b"user:999:").count(), 0);
+
+    assert_eq!(
+        keys(db.scan_prefix(b"ab")),
+        vec![b"ab\xff".to_vec(), b"ab\xff\xff\x01".to_vec()]
+    );
+    assert_eq!(keys(db.scan_prefix(b"\xff")), vec![b"\xff\xff".to_vec()]);
+    assert_eq!(db.scan_prefix(b"").count(), 2004);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_prefix_filter_skips_tables() {
+    let dir = "/tmp/test_prefix_filter";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+
+    let extractor: Arc<dyn PrefixExtractor> = Arc::new(FixedPrefix::new(4));
+    let path = format!("{}/sst-1.db", dir);
+    let opts = WriterOptions {
+        prefix_extractor: Some(Arc::clone(&extractor)),
+        ..WriterOptions::default()
+    };
+    let mut writer = SSTWriter::with_options(&path, opts).unwrap();
+    let mut seq = 1;
+    for prefix in ["aaaa", "cccc", "eeee"] {
+        for i in 0..100 {
+            let key = format!("{}{:04}", prefix, i);
+            writer.add(key.as_bytes(), b"v", seq).unwrap();
+            seq += 1;
+        }
+    }
+    writer.finish().unwrap();
+    let table = SSTReader::open(&path).unwrap();
+
+    assert!(table.may_contain_prefix(b"aaaa", Some(&*extractor)));
+    assert!(table.may_contain_prefix(b"cccc0042", Some(&*extractor)));
+    // inside the key range of the table, but none of its keys has the prefix
+    assert!(!table.may_contain_prefix(b"bbbb", Some(&*extractor)));
+    assert!(!table.may_contain_prefix(b"dddd00", Some(&*extractor)));
+    // outside the key range
+    assert!(!table.may_contain_prefix(b"ffff", Some(&*extractor)));
+    assert!(!table.may_contain_prefix(b"0", Some(&*extractor)));
+
+    // a prefix the extractor can't map, or another extractor, can only go by the key range
+    assert!(table.may_contain_prefix(b"bb", Some(&*extractor)));
+    assert!(table.may_contain_prefix(b"bbbb", Some(&FixedPrefix::new(2))));
+    assert!(table.may_contain_prefix(b"bbbb", None));
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_range_tombstones_are_not_skipped() {
+    let test_dir = "/tmp/test_prefix_tombstones";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = small_options()
+        .max_sstables(100)
+        .prefix_extractor(Arc::new(SeparatorPrefix::new(b':', 2)));
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for user in 0..10 {
+        for item in 0..100 {
+            db.put(&user_key(user, item), &[b'v'; 64]).unwrap();
+        }
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    // the tombstone ends up in a table whose keys all have other prefixes, its prefix filter
+    // doesn't know about user:003:
+    db.delete_range(&user_key(3, 0), &user_key(3, 50)).unwrap();
+    for item in 0..600 {
+        db.put(&user_key(9, 100 + item), &[b'w'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert!(db.num_files_at_level(0) > 1);
+
+    let found = keys(db.scan_prefix(b"user:003:"));
+    assert_eq!(found, (50..100).map(|i| user_key(3, i)).collect::<Vec<_>>());
+    assert_eq!(db.scan_prefix(b"user:009:").count(), 700);
+    assert_eq!(db.scan_prefix(b"user:004:").count(), 100);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_extractor_change_and_column_families() {
+    let test_dir = "/tmp/test_prefix_reopen";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let opts = small_options().prefix_extractor(Arc::new(SeparatorPrefix::new(b':', 2)));
+    let db = Db::open_with(test_dir, opts).unwrap();
+    let items = db
+        .create_column_family(
+            "items",
+            small_options().prefix_extractor(Arc::new(FixedPrefix::new(8))),
+        )
+        .unwrap();
+    for user in 0..10 {
+        for item in 0..100 {
+            db.put(&user_key(user, item), b"default").unwrap();
+            db.put_cf(&items, &user_key(user, item), b"items").unwrap();
+        }
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    let scanned: Vec<_> = db.scan_prefix_cf(&items, b"user:002").unwrap().collect();
+    assert_eq!(scanned.len(), 100);
+    assert!(scanned.iter().all(|(_, v)| v == b"items"));
+    assert_eq!(db.scan_prefix(b"user:002:").count(), 100);
+
+    // tables written with the old extractor are read as if they had no prefix filter
+    drop(items);
+    drop(db);
+    let opts = small_options().prefix_extractor(Arc::new(FixedPrefix::new(6)));
+    let db = Db::open_with(test_dir, opts).unwrap();
+    assert_eq!(db.scan_prefix(b"user:002:").count(), 100);
+    assert_eq!(db.scan_prefix(b"user:0").count(), 1000);
+    let items = db.column_family("items").unwrap();
+    assert_eq!(db.scan_prefix_cf(&items, b"user:00").unwrap().count(), 1000);
+
+    drop(items);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,242 @@
+use keylite_kv::core::{Db, DbOptions, WalMode, WriteBatch};
+use keylite_kv::error::DbError;
+use keylite_kv::sst::{SSTIterator, SSTReader};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+// copies the files of a running database, what's on disk at this point is exactly what a crash
+// would leave behind
+fn crash_copy(from: &str, to: &str) {
+    let _ = fs::remove_dir_all(to);
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(to).join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+fn open_tables(dir: &str) -> Vec<SSTReader> {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().into_string().unwrap_or_default();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .filter_map(|e| SSTReader::open(e.path()).ok())
+        .collect()
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_delete_range_hides_keys() {
+    let test_dir = "/tmp/test_range_delete_basic";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    for i in 0..100 {
+        db.put(&key(i), b"value").unwrap();
+    }
+    db.delete_range(&key(20), &key(50)).unwrap();
+    // written after the tombstone, not covered by it
+    db.put(&key(30), b"again").unwrap();
+
+    for i in 0..100 {
+        let expected = match i {
+            30 => Some(b"again".to_vec()),
+            20..=49 => None,
+            _ => Some(b"value".to_vec()),
+        };
+        assert_eq!(db.get(&key(i)).unwrap(), expected, "key {}", i);
+    }
+
+    let scanned: Vec<_> = db.scan(Some(&key(10)), Some(&key(60))).collect();
+    let keys: Vec<_> = scanned.iter().map(|(k, _)| k.clone()).collect();
+    let mut expected: Vec<_> = (10..20).chain([30]).chain(50..60).map(key).collect();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    // an empty or inverted range deletes nothing
+    db.delete_range(&key(70), &key(70)).unwrap();
+    db.delete_range(&key(90), &key(80)).unwrap();
+    assert_eq!(db.scan(None, None).count(), 71);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_range_tombstone_covers_older_tables_and_snapshots() {
+    let test_dir = "/tmp/test_range_delete_tables";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(
+        test_dir,
+        DbOptions::default().memtable_size_threshold(4 * 1024),
+    )
+    .unwrap();
+    for i in 0..1000 {
+        db.put(&key(i), &[b'x'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    let txn = db.begin();
+    db.delete_range(&key(100), &key(900)).unwrap();
+
+    assert_eq!(db.get(&key(99)).unwrap(), Some(vec![b'x'; 32]));
+    assert_eq!(db.get(&key(100)).unwrap(), None);
+    assert_eq!(db.get(&key(899)).unwrap(), None);
+    assert_eq!(db.get(&key(900)).unwrap(), Some(vec![b'x'; 32]));
+    assert_eq!(db.scan(None, None).count(), 200);
+
+    // the snapshot was taken before the range was deleted
+    assert_eq!(txn.get(&key(500)).unwrap(), Some(vec![b'x'; 32]));
+    assert_eq!(txn.scan(Some(&key(0)), None).count(), 1000);
+    drop(txn);
+
+    // the tombstone gets flushed into a table of its own and still applies after a reopen
+    drop(db);
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(&key(500)).unwrap(), None);
+    assert_eq!(db.scan(None, None).count(), 200);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_range_delete_is_replayed_from_the_wal() {
+    let test_dir = "/tmp/test_range_delete_wal";
+    let copy_dir = "/tmp/test_range_delete_wal_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Sync)).unwrap();
+    for i in 0..50 {
+        db.put(&key(i), b"value").unwrap();
+    }
+    db.delete_range(&key(10), &key(40)).unwrap();
+    db.put(&key(15), b"after").unwrap();
+    crash_copy(test_dir, copy_dir);
+
+    let recovered = Db::open(copy_dir).unwrap();
+    for i in 0..50 {
+        let expected = match i {
+            15 => Some(b"after".to_vec()),
+            10..=39 => None,
+            _ => Some(b"value".to_vec()),
+        };
+        assert_eq!(recovered.get(&key(i)).unwrap(), expected, "key {}", i);
+    }
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_batch_range_delete_and_conflicts() {
+    let test_dir = "/tmp/test_range_delete_batch";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"a1", b"old").unwrap();
+    db.put(b"b1", b"old").unwrap();
+
+    // writes of the batch before the range delete are covered, the ones after aren't
+    let mut batch = WriteBatch::new();
+    batch.put(b"a2", b"before");
+    batch.delete_range(b"a", b"b");
+    batch.put(b"a3", b"after");
+    db.write(batch).unwrap();
+
+    assert_eq!(db.get(b"a1").unwrap(), None);
+    assert_eq!(db.get(b"a2").unwrap(), None);
+    assert_eq!(db.get(b"a3").unwrap(), Some(b"after".to_vec()));
+    assert_eq!(db.get(b"b1").unwrap(), Some(b"old".to_vec()));
+
+    // a range delete over a key a transaction read is a conflicting write
+    let mut txn = db.begin();
+    txn.get(b"b1").unwrap();
+    db.delete_range(b"b", b"c").unwrap();
+    txn.put(b"z", b"1");
+    assert!(matches!(txn.commit(), Err(DbError::Conflict(_))));
+
+    // as is one over a range it scanned, even if the range held nothing
+    let mut txn = db.begin();
+    assert_eq!(txn.scan(Some(b"m"), Some(b"n")).count(), 0);
+    db.delete_range(b"m0", b"m9").unwrap();
+    txn.put(b"z", b"2");
+    assert!(matches!(txn.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(db.get(b"z").unwrap(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+// versions of keys in [start, end) stored in the table files of the directory
+fn entries_on_disk(dir: &str, start: &[u8], end: &[u8]) -> usize {
+    open_tables(dir)
+        .into_iter()
+        .flat_map(SSTIterator::new)
+        .filter_map(|entry| entry.ok())
+        .filter(|(k, _, _, _)| k.as_slice() >= start && k.as_slice() < end)
+        .count()
+}
+
+#[test]
+fn test_compaction_drops_covered_data() {
+    let test_dir = "/tmp/test_range_delete_compaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'x'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    db.delete_range(&key(500), &key(1500)).unwrap();
+
+    // push more data so the tombstone travels through the levels until it met every table holding
+    // covered keys, compactions are kicked off by writes so keep writing while the workers catch up
+    let mut round = 0;
+    while entries_on_disk(test_dir, &key(500), &key(1500)) > 0 {
+        assert!(round < 50, "covered entries survived compaction");
+        for i in 0..1000 {
+            let other = format!("other_{:05}", i);
+            db.put(other.as_bytes(), &[b'a' + (round % 26) as u8; 64])
+                .unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        round += 1;
+    }
+
+    for i in 0..2000 {
+        let expected = (!(500..1500).contains(&i)).then(|| vec![b'x'; 64]);
+        assert_eq!(db.get(&key(i)).unwrap(), expected, "key {}", i);
+    }
+    assert_eq!(db.scan(Some(b"key_"), Some(b"key`")).count(), 1000);
+    drop(db);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    assert_eq!(db.get(&key(1000)).unwrap(), None);
+    assert_eq!(db.get(&key(1500)).unwrap(), Some(vec![b'x'; 64]));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
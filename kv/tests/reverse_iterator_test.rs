@@ -0,0 +1,238 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::sst::writer::WriterOptions;
+use keylite_kv::sst::{SSTIterator, SSTReader, SSTWriter};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_reverse_scan_matches_forward_scan() {
+    let test_dir = "/tmp/test_reverse_scan";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'o'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    // newer versions, point deletes and a range delete on top of the flushed tables
+    for i in (0..2000).step_by(3) {
+        db.put(&key(i), b"new").unwrap();
+    }
+    for i in (0..2000).step_by(7) {
+        db.del(&key(i)).unwrap();
+    }
+    db.delete_range(&key(500), &key(600)).unwrap();
+
+    let mut forward: Vec<_> = db.scan(None, None).collect();
+    forward.reverse();
+    let reverse: Vec<_> = db.scan_rev(None, None).collect();
+    assert_eq!(reverse.len(), forward.len());
+    assert!(reverse == forward);
+
+    let mut forward: Vec<_> = db.scan(Some(&key(450)), Some(&key(1234))).collect();
+    forward.reverse();
+    let reverse: Vec<_> = db.scan_rev(Some(&key(450)), Some(&key(1234))).collect();
+    assert!(reverse == forward);
+    assert_eq!(reverse.first().unwrap().0, key(1233));
+    assert_eq!(reverse.last().unwrap().0, key(450));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_cursor_moves_both_ways() {
+    let test_dir = "/tmp/test_reverse_cursor";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    for i in 0..10 {
+        db.put(&key(i), b"value").unwrap();
+    }
+    db.del(&key(5)).unwrap();
+
+    let mut iter = db.scan(Some(&key(2)), Some(&key(8)));
+    assert_eq!(iter.next().unwrap().0, key(2));
+    assert_eq!(iter.next().unwrap().0, key(3));
+    // the cursor sits right after key 3, so prev returns it again
+    assert_eq!(iter.prev().unwrap().0, key(3));
+    assert_eq!(iter.prev().unwrap().0, key(2));
+    assert_eq!(iter.prev(), None);
+    assert_eq!(iter.next().unwrap().0, key(2));
+
+    // the deleted key is skipped both ways
+    iter.seek_for_prev(&key(5));
+    assert_eq!(iter.prev().unwrap().0, key(4));
+    assert_eq!(iter.next().unwrap().0, key(4));
+    assert_eq!(iter.next().unwrap().0, key(6));
+
+    // seeks are clamped to the bounds of the scan
+    iter.seek_for_prev(&key(9));
+    assert_eq!(iter.prev().unwrap().0, key(7));
+    iter.seek(&key(9));
+    assert_eq!(iter.prev().unwrap().0, key(7));
+    assert_eq!(iter.next().unwrap().0, key(7));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.prev().unwrap().0, key(7));
+
+    iter.seek_to_last();
+    assert_eq!(iter.next(), None);
+    iter.seek_to_first();
+    assert_eq!(iter.prev(), None);
+
+    let keys: Vec<_> = db.scan_rev(None, None).map(|(k, _)| k).collect();
+    let expected: Vec<_> = (0..10).rev().filter(|&i| i != 5).map(key).collect();
+    assert_eq!(keys, expected);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_reverse_scan_reads_the_newest_visible_version() {
+    let test_dir = "/tmp/test_reverse_versions";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..500 {
+        db.put(&key(i), b"first").unwrap();
+    }
+    let snapshot = db.snapshot();
+    // several versions of every key, spread over the memtables and the tables
+    for round in 0..6u8 {
+        for i in 0..500 {
+            db.put(&key(i), &[b'a' + round; 64]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    db.del(&key(499)).unwrap();
+
+    let mut iter = db.scan(None, None);
+    iter.seek_to_last();
+    assert_eq!(iter.prev(), Some((key(498), vec![b'f'; 64])));
+    iter.seek_for_prev(&key(100));
+    assert_eq!(iter.prev(), Some((key(100), vec![b'f'; 64])));
+
+    let mut iter = db.scan_seq(None, None, &snapshot);
+    iter.seek_to_last();
+    assert_eq!(iter.prev(), Some((key(499), b"first".to_vec())));
+    let mut count = 1;
+    while let Some((_, v)) = iter.prev() {
+        assert_eq!(v, b"first");
+        count += 1;
+    }
+    assert_eq!(count, 500);
+
+    drop(snapshot);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_table_iterator_walks_backwards() {
+    let test_dir = "/tmp/test_reverse_table";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+    let path = Path::new(test_dir).join("sst-1.db");
+
+    // several versions per key, so some keys straddle a block boundary
+    let opts = WriterOptions {
+        block_size: 256,
+        ..WriterOptions::default()
+    };
+    let mut writer = SSTWriter::with_options(&path, opts).unwrap();
+    for i in 0..200 {
+        for seq in (1..=3u64).rev() {
+            writer.add(&key(i), &[b'v'; 16], seq).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let reader = SSTReader::open(&path).unwrap();
+    let mut forward: Vec<_> = SSTIterator::new(reader.clone())
+        .map(|e| e.unwrap())
+        .collect();
+    forward.reverse();
+    let mut iter = SSTIterator::new(reader);
+    let mut reverse = Vec::new();
+    while let Some(entry) = iter.prev() {
+        reverse.push(entry.unwrap());
+    }
+    assert!(reverse == forward);
+
+    for i in [0, 57, 123, 199] {
+        iter.seek_for_prev(&key(i));
+        for seq in 1..=3u64 {
+            let (k, _, s, _) = iter.prev().unwrap().unwrap();
+            assert_eq!((k, s), (key(i), seq));
+        }
+    }
+
+    iter.seek_for_prev(b"key_00042_");
+    assert_eq!(iter.prev().unwrap().unwrap().0, key(42));
+
+    iter.seek_for_prev(b"a");
+    assert!(iter.prev().is_none());
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_transaction_reverse_scan_merges_its_buffer() {
+    let test_dir = "/tmp/test_reverse_transaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    for i in (0..20).step_by(2) {
+        db.put(&key(i), b"db").unwrap();
+    }
+
+    let mut txn = db.begin();
+    txn.put(&key(5), b"txn");
+    txn.put(&key(6), b"txn");
+    txn.del(&key(8));
+    txn.put(&key(30), b"txn");
+
+    let scanned: Vec<_> = txn.scan_rev(Some(&key(4)), Some(&key(12))).collect();
+    assert_eq!(
+        scanned,
+        vec![
+            (key(10), b"db".to_vec()),
+            (key(6), b"txn".to_vec()),
+            (key(5), b"txn".to_vec()),
+            (key(4), b"db".to_vec())
+        ]
+    );
+
+    let mut iter = txn.scan(None, None);
+    iter.seek_for_prev(&key(9));
+    assert_eq!(iter.prev(), Some((key(6), b"txn".to_vec())));
+    assert_eq!(iter.prev(), Some((key(5), b"txn".to_vec())));
+    assert_eq!(iter.next(), Some((key(5), b"txn".to_vec())));
+    assert_eq!(iter.next(), Some((key(6), b"txn".to_vec())));
+    assert_eq!(iter.next(), Some((key(10), b"db".to_vec())));
+    iter.seek_to_last();
+    assert_eq!(iter.prev(), Some((key(30), b"txn".to_vec())));
+    assert_eq!(iter.prev(), Some((key(18), b"db".to_vec())));
+
+    drop(txn);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,251 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::sst::{SSTIterator, SSTReader};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+// versions of the key stored in the table files of the directory, whatever their value
+fn versions_on_disk(dir: &str, key: &[u8]) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().into_string().unwrap_or_default();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .filter_map(|e| SSTReader::open(e.path()).ok())
+        .flat_map(SSTIterator::new)
+        .filter_map(|entry| entry.ok())
+        .filter(|(k, _, _, _)| k.as_slice() == key)
+        .count()
+}
+
+// overwrites every key a few times so the tables holding them get compacted into each other,
+// compactions are kicked off by writes so the workers get some time between rounds
+fn churn(db: &Db, keys: usize, rounds: u8) {
+    for round in 0..rounds {
+        for i in 0..keys {
+            db.put(&key(i), &[b'a' + round; 64]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_snapshot_reads_ignore_later_writes() {
+    let test_dir = "/tmp/test_snapshot_reads";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"a", b"1").unwrap();
+    db.put(b"b", b"1").unwrap();
+
+    let snapshot = db.snapshot();
+    db.put(b"a", b"2").unwrap();
+    db.del(b"b").unwrap();
+    db.put(b"c", b"2").unwrap();
+
+    assert_eq!(db.get_seq(b"a", &snapshot).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get_seq(b"b", &snapshot).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get_seq(b"c", &snapshot).unwrap(), None);
+    let scanned: Vec<_> = db.scan_seq(None, None, &snapshot).collect();
+    assert_eq!(
+        scanned,
+        vec![
+            (b"a".to_vec(), b"1".to_vec()),
+            (b"b".to_vec(), b"1".to_vec())
+        ]
+    );
+
+    // a clone is the same view, it stays usable after the original is gone
+    let clone = snapshot.clone();
+    drop(snapshot);
+    assert_eq!(db.get_seq(b"a", &clone).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get(b"a").unwrap(), Some(b"2".to_vec()));
+
+    drop(clone);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_compaction_keeps_versions_of_live_snapshots() {
+    let test_dir = "/tmp/test_snapshot_compaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..500 {
+        db.put(&key(i), b"original").unwrap();
+    }
+    let snapshot = db.snapshot();
+    churn(&db, 500, 12);
+
+    for i in 0..500 {
+        assert_eq!(
+            db.get_seq(&key(i), &snapshot).unwrap(),
+            Some(b"original".to_vec()),
+            "key {}",
+            i
+        );
+        assert_eq!(db.get(&key(i)).unwrap(), Some(vec![b'a' + 11; 64]));
+    }
+    assert!(db
+        .scan_seq(None, None, &snapshot)
+        .all(|(_, v)| v == b"original"));
+
+    // once the snapshot is released the old versions are no longer needed and compaction drops
+    // them, every key ends up with a single version on disk
+    drop(snapshot);
+    let mut round = 0;
+    while versions_on_disk(test_dir, &key(0)) > 1 {
+        assert!(round < 50, "old versions survived compaction");
+        churn(&db, 500, 1);
+        round += 1;
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_snapshot_keeps_deleted_keys_through_compaction() {
+    let test_dir = "/tmp/test_snapshot_deletes";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..500 {
+        db.put(&key(i), b"original").unwrap();
+    }
+    let snapshot = db.snapshot();
+    db.del(&key(1)).unwrap();
+    db.delete_range(&key(100), &key(200)).unwrap();
+
+    // the other keys are rewritten until the deletes reached the bottom level
+    for round in 0..12u8 {
+        for i in 200..500 {
+            db.put(&key(i), &[b'a' + round; 64]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    assert_eq!(db.get(&key(1)).unwrap(), None);
+    assert_eq!(db.get(&key(150)).unwrap(), None);
+    assert_eq!(
+        db.get_seq(&key(1), &snapshot).unwrap(),
+        Some(b"original".to_vec())
+    );
+    assert_eq!(
+        db.get_seq(&key(150), &snapshot).unwrap(),
+        Some(b"original".to_vec())
+    );
+    assert_eq!(db.scan_seq(None, None, &snapshot).count(), 500);
+    assert_eq!(db.scan(None, None).count(), 399);
+
+    drop(snapshot);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_long_running_transaction_survives_compaction() {
+    let test_dir = "/tmp/test_snapshot_transaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..500 {
+        db.put(&key(i), b"original").unwrap();
+    }
+
+    let txn = db.begin();
+    assert_eq!(txn.get(&key(7)).unwrap(), Some(b"original".to_vec()));
+    churn(&db, 500, 12);
+
+    // the transaction still reads from the state it started with
+    assert_eq!(txn.get(&key(7)).unwrap(), Some(b"original".to_vec()));
+    assert_eq!(
+        txn.scan(Some(&key(0)), None)
+            .filter(|(_, v)| v == b"original")
+            .count(),
+        500
+    );
+
+    drop(txn);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_only_applied_writes_take_a_sequence_number() {
+    let test_dir = "/tmp/test_snapshot_sequence_numbers";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"a", b"1").unwrap();
+    let before = db.snapshot().sequence();
+
+    // writes under a sequence number of the caller's and writes that fail validation leave the
+    // sequence alone
+    db.put_seq(b"b", b"2", before - 1).unwrap();
+    db.del_seq(b"x", before - 1).unwrap();
+    assert!(db.put(&vec![b'k'; u16::MAX as usize + 1], b"3").is_err());
+    assert_eq!(db.snapshot().sequence(), before);
+
+    db.put(b"c", b"4").unwrap();
+    assert_eq!(db.snapshot().sequence(), before + 1);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_snapshot_reads_repeat_under_concurrent_writes() {
+    let test_dir = "/tmp/test_snapshot_repeatable";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Arc::new(Db::open(test_dir).unwrap());
+    let written = Arc::new(AtomicUsize::new(0));
+    let writer = {
+        let db = Arc::clone(&db);
+        let written = Arc::clone(&written);
+        thread::spawn(move || {
+            for i in 0..5000 {
+                db.put(&key(i), b"v").unwrap();
+                written.store(i + 1, Ordering::Release);
+            }
+        })
+    };
+
+    // the key the writer is about to put may already have its sequence number but not be in the
+    // memtable yet, a snapshot taken in between must read the same before and after it lands
+    while written.load(Ordering::Acquire) < 5000 {
+        let next = key(written.load(Ordering::Acquire));
+        let snapshot = db.snapshot();
+        let first = db.get_seq(&next, &snapshot).unwrap();
+        let scanned: Vec<_> = db.scan_seq(Some(&next), None, &snapshot).collect();
+        thread::yield_now();
+        assert_eq!(db.get_seq(&next, &snapshot).unwrap(), first);
+        let rescanned: Vec<_> = db.scan_seq(Some(&next), None, &snapshot).collect();
+        assert_eq!(scanned, rescanned);
+    }
+    writer.join().unwrap();
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,218 @@
+use keylite_kv::core::{Db, DbOptions, WalMode, WriteBatch, WriteOptions};
+use keylite_kv::stats::{Histogram, Ticker};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+fn small_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_counts_reads_and_writes() {
+    let test_dir = "/tmp/test_stats_counts";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    db.put(b"a", b"1").unwrap();
+    db.put(b"b", b"22").unwrap();
+    db.del(b"a").unwrap();
+
+    // a batch is a single write of several operations
+    let mut batch = WriteBatch::new();
+    batch.put(b"c", b"333");
+    batch.delete(b"d");
+    batch.delete_range(b"x", b"z");
+    db.write(batch).unwrap();
+
+    assert_eq!(db.get(b"b").unwrap(), Some(b"22".to_vec()));
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"missing").unwrap(), None);
+
+    let stats = db.stats();
+    assert_eq!(stats.ticker(Ticker::Writes), 4);
+    assert_eq!(stats.ticker(Ticker::Puts), 3);
+    assert_eq!(stats.ticker(Ticker::Deletes), 2);
+    assert_eq!(stats.ticker(Ticker::RangeDeletes), 1);
+    assert_eq!(stats.ticker(Ticker::BytesWritten), 2 + 3 + 1 + 4 + 1 + 2);
+    assert_eq!(stats.ticker(Ticker::Gets), 3);
+    assert_eq!(stats.ticker(Ticker::BytesRead), 2);
+    // the tombstone of "a" answers its lookup as well
+    assert_eq!(stats.ticker(Ticker::MemtableHits), 2);
+    assert_eq!(stats.ticker(Ticker::MemtableMisses), 1);
+    assert_eq!(stats.ticker(Ticker::SstProbes), 0);
+    assert_eq!(stats.histogram(Histogram::Get).count, 3);
+    assert_eq!(stats.histogram(Histogram::Write).count, 4);
+    assert!(stats.memtable_bytes > 0);
+    assert_eq!(stats.num_sstables, 0);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_flush_compaction_and_bloom_stats() {
+    let test_dir = "/tmp/test_stats_background";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    for i in 0..3000 {
+        db.put(&key(i), &[b'v'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    let stats = db.stats();
+    assert!(stats.ticker(Ticker::Flushes) > 0);
+    assert!(stats.ticker(Ticker::BytesFlushed) > 0);
+    assert_eq!(
+        stats.histogram(Histogram::Flush).count,
+        stats.ticker(Ticker::Flushes)
+    );
+    assert!(stats.ticker(Ticker::Compactions) > 0);
+    assert!(stats.ticker(Ticker::BytesCompactedRead) > 0);
+    assert!(stats.ticker(Ticker::BytesCompactedWritten) > 0);
+    assert_eq!(
+        stats.histogram(Histogram::Compaction).count,
+        stats.ticker(Ticker::Compactions)
+    );
+    assert_eq!(stats.ticker(Ticker::FlushErrors), 0);
+    assert_eq!(stats.ticker(Ticker::CompactionErrors), 0);
+    assert!(stats.num_sstables > 0);
+    assert!(stats.total_sst_bytes > 0);
+
+    // the oldest keys are only in tables. the absent ones fall in between them, inside the key
+    // range of a table, and mostly stop at its bloom filter
+    for i in 0..100 {
+        let mut absent = key(i);
+        absent.push(b'x');
+        assert!(db.get(&key(i)).unwrap().is_some());
+        assert!(db.get(&absent).unwrap().is_none());
+    }
+    let stats = db.stats();
+    assert!(stats.ticker(Ticker::SstProbes) >= 100);
+    assert!(stats.ticker(Ticker::BloomTruePositive) >= 100);
+    assert!(stats.ticker(Ticker::BloomUseful) > 0);
+    assert!(stats.bloom_useful_rate() > 0.0);
+    assert!(stats.bloom_false_positive_rate() < 0.5);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_properties() {
+    let test_dir = "/tmp/test_stats_properties";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let other = db.create_column_family("other", small_options()).unwrap();
+    for i in 0..3000 {
+        db.put(&key(i), &[b'v'; 32]).unwrap();
+    }
+    db.put_cf(&other, b"k", b"v").unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let num_sstables: usize = db
+        .property("keylite.num-sstables")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let per_level: usize = (0..db.options().max_levels)
+        .map(|level| {
+            db.property(&format!("keylite.num-files-at-level{}", level))
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        })
+        .sum();
+    assert!(num_sstables > 0);
+    assert_eq!(num_sstables, per_level);
+    assert!(
+        db.property("keylite.total-sst-bytes")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+            > 0
+    );
+    assert!(db.property("keylite.num-immutable-memtables").is_some());
+
+    // the other family only has its single entry in a memtable
+    assert_eq!(
+        db.property_cf(&other, "keylite.num-sstables").as_deref(),
+        Some("0")
+    );
+    assert_eq!(
+        db.property_cf(&other, "keylite.num-entries-memtables")
+            .as_deref(),
+        Some("1")
+    );
+    let memtable_bytes: usize = db
+        .property_cf(&other, "keylite.memtable-bytes")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(memtable_bytes > 0);
+
+    assert!(db.property("keylite.stats").unwrap().contains("flushes: "));
+    assert_eq!(db.property("keylite.no-such-property"), None);
+    assert_eq!(db.property("keylite.num-files-at-levelx"), None);
+    assert_eq!(db.property("num-sstables"), None);
+
+    drop(other);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_prometheus_export() {
+    let test_dir = "/tmp/test_stats_prometheus";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_options()).unwrap();
+    let sync = WriteOptions::default().wal_mode(WalMode::Sync);
+    for i in 0..10 {
+        db.put_with(&key(i), b"v", &sync).unwrap();
+        db.get(&key(i)).unwrap();
+    }
+
+    let stats = db.stats();
+    assert!(stats.ticker(Ticker::WalSyncs) >= 10);
+    assert_eq!(
+        stats.histogram(Histogram::WalSync).count,
+        stats.ticker(Ticker::WalSyncs)
+    );
+
+    let text = stats.to_prometheus();
+    assert!(text.contains("# TYPE keylite_puts_total counter\nkeylite_puts_total 10\n"));
+    assert!(text.contains(&format!(
+        "keylite_wal_syncs_total {}\n",
+        stats.ticker(Ticker::WalSyncs)
+    )));
+    assert!(text.contains("# TYPE keylite_get_micros histogram\n"));
+    assert!(text.contains("keylite_get_micros_bucket{le=\"+Inf\"} 10\n"));
+    assert!(text.contains("keylite_get_micros_count 10\n"));
+    assert!(text.contains("# TYPE keylite_num_sstables gauge\n"));
+    assert!(text.contains("# TYPE keylite_block_cache_hits_total counter\n"));
+    assert!(text.contains("# TYPE keylite_block_cache_misses_total counter\n"));
+    assert!(text.contains("# TYPE keylite_block_cache_entries gauge\n"));
+
+    // every bucket line counts the values up to its bound, so the counts never go down
+    let buckets: Vec<u64> = text
+        .lines()
+        .filter(|line| line.starts_with("keylite_get_micros_bucket"))
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(buckets.windows(2).all(|w| w[0] <= w[1]));
+    let p99 = stats.histogram(Histogram::Get).percentile(99.0);
+    assert!(p99 <= stats.histogram(Histogram::Get).max);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
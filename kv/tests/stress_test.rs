@@ -125,7 +125,7 @@ fn test_stress_transaction_abort_rate() {
         }
     }
 
-    assert!(count >= 240 && count <= 260, "Expected ~250, got {}", count);
+    assert!((240..=260).contains(&count), "Expected ~250, got {}", count);
 
     let _ = fs::remove_dir_all(test_dir);
 }
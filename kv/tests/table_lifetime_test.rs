@@ -0,0 +1,132 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::sst::{SSTIterator, SSTReader, SSTWriter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn table_files(dir: &str) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            let name = p.file_name().unwrap().to_string_lossy();
+            name.starts_with("sst-") && name.ends_with(".db")
+        })
+        .collect()
+}
+
+// overwrites the first keys so the tables holding them get compacted away, compactions are kicked off
+// by writes so the workers get some time between rounds
+fn churn(db: &Db, rounds: u8) {
+    for round in 0..rounds {
+        for i in 0..500 {
+            db.put(&key(i), &[b'a' + round; 64]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_obsolete_table_is_deleted_with_its_last_handle() {
+    let test_dir = "/tmp/test_table_lifetime_handles";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+    let path = Path::new(test_dir).join("sst-1.db");
+
+    let mut writer = SSTWriter::new(&path).unwrap();
+    for i in 0..100 {
+        writer.add(&key(i), b"value", i as u64 + 1).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let reader = SSTReader::open(&path).unwrap();
+    let clone = reader.clone();
+    let iter = SSTIterator::new(reader.clone());
+    reader.mark_obsolete();
+    drop(reader);
+
+    // the clone and the iterator still read from the mapping
+    assert!(path.exists());
+    assert_eq!(clone.get(&key(42)).unwrap(), Some(b"value".to_vec()));
+    assert_eq!(iter.count(), 100);
+    assert!(path.exists());
+
+    drop(clone);
+    assert!(!path.exists());
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_iterator_outlives_compaction_of_its_tables() {
+    let test_dir = "/tmp/test_table_lifetime_iterator";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'o'; 64]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    let before = table_files(test_dir);
+    assert!(!before.is_empty());
+    let iter = db.scan(None, None);
+    churn(&db, 12);
+
+    // every table the iterator started with is still on disk, even the ones compacted away
+    assert!(before.iter().all(|p| p.exists()));
+    let entries: Vec<_> = iter.collect();
+    assert_eq!(entries.len(), 2000);
+    assert!(entries.iter().all(|(_, v)| v == &[b'o'; 64]));
+
+    // with the iterator gone nothing holds the compacted tables anymore
+    let mut round = 0;
+    while before.iter().all(|p| p.exists()) {
+        assert!(round < 50, "compacted tables were never deleted");
+        churn(&db, 1);
+        round += 1;
+    }
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_cloned_tables_read_during_concurrent_compaction() {
+    let test_dir = "/tmp/test_table_lifetime_concurrent";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..2000 {
+        db.put(&key(i), &[b'o'; 64]).unwrap();
+    }
+
+    thread::scope(|s| {
+        s.spawn(|| churn(&db, 12));
+        // scans keep picking up table handles while compaction retires them
+        for _ in 0..50 {
+            assert_eq!(db.scan(None, None).count(), 2000);
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -425,7 +425,7 @@ fn test_transaction_empty_value() {
     txn.commit().unwrap();
 
     let result = db.get(b"key_with_empty_value").unwrap();
-    assert_eq!(result, None);
+    assert_eq!(result, Some(Vec::new()));
 
     let _ = fs::remove_dir_all(test_dir);
 }
@@ -0,0 +1,259 @@
+use keylite_kv::core::{Db, DbOptions};
+use keylite_kv::error::DbError;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+fn read_u64(val: Option<Vec<u8>>) -> u64 {
+    String::from_utf8(val.unwrap()).unwrap().parse().unwrap()
+}
+
+#[test]
+fn test_lost_update_is_rejected() {
+    let test_dir = "/tmp/test_txn_conflict_lost_update";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"counter", b"0").unwrap();
+
+    let mut t1 = db.begin();
+    let mut t2 = db.begin();
+    let v1 = read_u64(t1.get(b"counter").unwrap());
+    let v2 = read_u64(t2.get(b"counter").unwrap());
+
+    t2.put(b"counter", (v2 + 1).to_string().as_bytes());
+    t2.commit().unwrap();
+
+    t1.put(b"counter", (v1 + 1).to_string().as_bytes());
+    assert!(matches!(t1.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(db.get(b"counter").unwrap(), Some(b"1".to_vec()));
+
+    // plain writes count as well, a delete included
+    let mut t3 = db.begin();
+    t3.get(b"counter").unwrap();
+    db.del(b"counter").unwrap();
+    t3.put(b"other", b"x");
+    assert!(matches!(t3.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(db.get(b"other").unwrap(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_unrelated_writes_do_not_conflict() {
+    let test_dir = "/tmp/test_txn_conflict_unrelated";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"a", b"1").unwrap();
+    db.put(b"b", b"1").unwrap();
+
+    let mut t1 = db.begin();
+    t1.get(b"a").unwrap();
+    db.put(b"b", b"2").unwrap();
+    t1.put(b"a", b"2");
+    t1.commit().unwrap();
+
+    // blind writes read nothing and can't go stale
+    let mut t2 = db.begin();
+    db.put(b"a", b"3").unwrap();
+    t2.put(b"a", b"4");
+    t2.commit().unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"4".to_vec()));
+
+    // a read-only transaction saw a consistent snapshot and always commits
+    let t3 = db.begin();
+    t3.get(b"a").unwrap();
+    db.put(b"a", b"5").unwrap();
+    t3.commit().unwrap();
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_phantom_in_scanned_range_is_rejected() {
+    let test_dir = "/tmp/test_txn_conflict_phantom";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"user:1", b"alice").unwrap();
+
+    let mut t1 = db.begin();
+    assert_eq!(t1.scan(Some(b"user:"), Some(b"user;")).count(), 1);
+    // outside the scanned range
+    db.put(b"zzz", b"1").unwrap();
+    t1.put(b"user_count", b"1");
+    t1.commit().unwrap();
+
+    let mut t2 = db.begin();
+    assert_eq!(t2.scan(Some(b"user:"), Some(b"user;")).count(), 1);
+    db.put(b"user:2", b"bob").unwrap();
+    t2.put(b"user_count", b"1");
+    assert!(matches!(t2.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(db.get(b"user_count").unwrap(), Some(b"1".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_conflicting_write_already_flushed() {
+    let test_dir = "/tmp/test_txn_conflict_flushed";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(
+        test_dir,
+        DbOptions::default().memtable_size_threshold(4 * 1024),
+    )
+    .unwrap();
+    db.put(b"k", b"old").unwrap();
+
+    let mut txn = db.begin();
+    txn.get(b"k").unwrap();
+    db.put(b"k", b"new").unwrap();
+    // push the conflicting write out of the memtables and into an sstable
+    for i in 0..2000 {
+        db.put(format!("filler_{:04}", i).as_bytes(), &[7; 32])
+            .unwrap();
+    }
+    thread::sleep(std::time::Duration::from_millis(200));
+
+    txn.put(b"k", b"txn");
+    assert!(matches!(txn.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(db.get(b"k").unwrap(), Some(b"new".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_scanned_range_written_in_a_table_or_another_family() {
+    let test_dir = "/tmp/test_txn_conflict_range_flushed";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(
+        test_dir,
+        DbOptions::default().memtable_size_threshold(4 * 1024),
+    )
+    .unwrap();
+    let other = db
+        .create_column_family("other", DbOptions::default())
+        .unwrap();
+
+    // the same range written in another column family isn't a conflict
+    let mut t1 = db.begin();
+    assert_eq!(
+        t1.scan(Some(b"filler_1000"), Some(b"filler_1010")).count(),
+        0
+    );
+    db.put_cf(&other, b"filler_1005", b"other").unwrap();
+    t1.put(b"done", b"1");
+    t1.commit().unwrap();
+
+    // the conflicting write sits in the middle of a table, behind keys before the range
+    let mut t2 = db.begin();
+    assert_eq!(
+        t2.scan(Some(b"filler_1000"), Some(b"filler_1010")).count(),
+        0
+    );
+    for i in 0..2000 {
+        db.put(format!("filler_{:04}", i).as_bytes(), &[7; 32])
+            .unwrap();
+    }
+    thread::sleep(std::time::Duration::from_millis(200));
+    t2.put(b"done", b"2");
+    assert!(matches!(t2.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(db.get(b"done").unwrap(), Some(b"1".to_vec()));
+
+    drop(other);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_concurrent_transfers_with_transact() {
+    let test_dir = "/tmp/test_txn_conflict_transfers";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Arc::new(Db::open(test_dir).unwrap());
+    db.put(b"acct:a", b"1000").unwrap();
+    db.put(b"acct:b", b"1000").unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                let (from, to): (&[u8], &[u8]) = if t % 2 == 0 {
+                    (b"acct:a", b"acct:b")
+                } else {
+                    (b"acct:b", b"acct:a")
+                };
+                for _ in 0..25 {
+                    db.transact(|txn| {
+                        let from_balance = read_u64(txn.get(from)?);
+                        let to_balance = read_u64(txn.get(to)?);
+                        txn.put(from, (from_balance - 1).to_string().as_bytes());
+                        txn.put(to, (to_balance + 1).to_string().as_bytes());
+                        Ok(())
+                    })
+                    .unwrap_or_else(|e| panic!("transfer failed: {e}"));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let a = read_u64(db.get(b"acct:a").unwrap());
+    let b = read_u64(db.get(b"acct:b").unwrap());
+    // every thread moved 25 units, half of them each way
+    assert_eq!(a, 1000);
+    assert_eq!(b, 1000);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_transaction_reads_ignore_writes_in_flight() {
+    let test_dir = "/tmp/test_txn_conflict_in_flight";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Arc::new(Db::open(test_dir).unwrap());
+    let written = Arc::new(AtomicUsize::new(0));
+    let writer = {
+        let db = Arc::clone(&db);
+        let written = Arc::clone(&written);
+        thread::spawn(move || {
+            for i in 0..5000 {
+                db.put(format!("key_{:05}", i).as_bytes(), b"v").unwrap();
+                written.store(i + 1, Ordering::Release);
+            }
+        })
+    };
+
+    // the key the writer is about to put may be logged but not in the memtable yet when the
+    // transaction begins, it must read the same either way until the commit
+    while written.load(Ordering::Acquire) < 5000 {
+        let key = format!("key_{:05}", written.load(Ordering::Acquire));
+        let mut txn = db.begin();
+        let first = txn.get(key.as_bytes()).unwrap();
+        thread::yield_now();
+        let second = txn.get(key.as_bytes()).unwrap();
+        assert_eq!(first, second);
+
+        txn.put(b"marker", key.as_bytes());
+        match txn.commit() {
+            Ok(()) | Err(DbError::Conflict(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+    writer.join().unwrap();
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
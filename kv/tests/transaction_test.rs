@@ -1,5 +1,5 @@
 use keylite_kv::core::Db;
-use std::{fs, string};
+use std::fs;
 
 #[test]
 fn test_basic_transaction_commit() {
@@ -0,0 +1,223 @@
+use keylite_kv::core::{Clock, Db, DbOptions, WalMode, WriteOptions};
+use keylite_kv::sst::{SSTIterator, SSTReader};
+use keylite_kv::types::ValueType;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// a clock the tests move forward by hand
+#[derive(Debug)]
+struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    fn new(now: u64) -> Arc<Self> {
+        Arc::new(Self(AtomicU64::new(now)))
+    }
+
+    fn advance(&self, by: Duration) {
+        self.0.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_expired_values_read_as_absent() {
+    let test_dir = "/tmp/test_ttl_reads";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let clock = ManualClock::new(1_000_000);
+    let db = Db::open_with(test_dir, DbOptions::default().clock(clock.clone())).unwrap();
+    db.put(b"a", b"old").unwrap();
+    db.put_with_ttl(b"a", b"short", Duration::from_secs(10))
+        .unwrap();
+    db.put_with_ttl(b"b", b"long", Duration::from_secs(100))
+        .unwrap();
+    db.put(b"c", b"forever").unwrap();
+    let snapshot = db.snapshot();
+
+    assert_eq!(db.get(b"a").unwrap(), Some(b"short".to_vec()));
+    assert_eq!(db.scan(None, None).count(), 3);
+
+    clock.advance(Duration::from_secs(10));
+    // the expired value hides the older one instead of bringing it back
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get_seq(b"a", &snapshot).unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"long".to_vec()));
+    let keys: Vec<_> = db.scan(None, None).map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"b".to_vec(), b"c".to_vec()]);
+    let keys: Vec<_> = db.scan_rev(None, None).map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"c".to_vec(), b"b".to_vec()]);
+
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(db.get(b"b").unwrap(), None);
+    assert_eq!(db.get(b"c").unwrap(), Some(b"forever".to_vec()));
+
+    // writing the key again brings it back
+    db.put(b"a", b"again").unwrap();
+    assert_eq!(db.get(b"a").unwrap(), Some(b"again".to_vec()));
+
+    drop(snapshot);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_expiry_survives_flush_and_reopen() {
+    let test_dir = "/tmp/test_ttl_reopen";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let clock = ManualClock::new(1_000_000);
+    let opts = DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .clock(clock.clone());
+    let db = Db::open_with(test_dir, opts.clone()).unwrap();
+    for i in 0..2000 {
+        db.put_with_ttl(&key(i), &[b'v'; 64], Duration::from_secs(60))
+            .unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    drop(db);
+
+    // part of the values come back from the tables, the rest from the WAL
+    let db = Db::open_with(test_dir, opts).unwrap();
+    assert_eq!(db.get(&key(0)).unwrap(), Some(vec![b'v'; 64]));
+    assert_eq!(db.get(&key(1999)).unwrap(), Some(vec![b'v'; 64]));
+    assert_eq!(db.scan(None, None).count(), 2000);
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(db.get(&key(0)).unwrap(), None);
+    assert_eq!(db.get(&key(1999)).unwrap(), None);
+    assert_eq!(db.scan(None, None).count(), 0);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+// number of ExpiringPut entries in the tables of the directory, a table compaction deletes in the
+// meantime is skipped
+fn expiring_entries(dir: &str) -> usize {
+    let mut expiring = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        let Ok(reader) = SSTReader::open(&path) else {
+            continue;
+        };
+        expiring += SSTIterator::new(reader)
+            .filter_map(|entry| entry.ok())
+            .filter(|(_, _, _, value_type)| *value_type == ValueType::ExpiringPut)
+            .count();
+    }
+    expiring
+}
+
+#[test]
+fn test_compaction_drops_expired_values() {
+    let test_dir = "/tmp/test_ttl_compaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // with two levels every compaction writes the bottommost level
+    let clock = ManualClock::new(1_000_000);
+    let opts = DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .max_levels(2)
+        .clock(clock.clone());
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for i in (0..4000).step_by(2) {
+        db.put_with_ttl(&key(i), &[b't'; 64], Duration::from_secs(1))
+            .unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    assert!(expiring_entries(test_dir) > 0);
+
+    clock.advance(Duration::from_secs(1));
+    // fresh data over the whole key range pulls the expired tables into compaction, compaction
+    // runs whenever a memtable is frozen so it takes a few rounds to reach all of them
+    for _ in 0..10 {
+        for i in (1..4000).step_by(2) {
+            db.put(&key(i), &[b'p'; 64]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        if expiring_entries(test_dir) == 0 {
+            break;
+        }
+    }
+    assert_eq!(expiring_entries(test_dir), 0);
+    assert!(db.num_files_at_level(1) > 0);
+
+    assert_eq!(db.get(&key(100)).unwrap(), None);
+    assert_eq!(db.get(&key(101)).unwrap(), Some(vec![b'p'; 64]));
+    assert_eq!(db.scan(None, None).count(), 2000);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_transactions_see_expiry() {
+    let test_dir = "/tmp/test_ttl_transaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let clock = ManualClock::new(1_000_000);
+    let db = Db::open_with(test_dir, DbOptions::default().clock(clock.clone())).unwrap();
+    db.put_with_ttl(b"session", b"token", Duration::from_millis(500))
+        .unwrap();
+
+    let txn = db.begin();
+    assert_eq!(txn.get(b"session").unwrap(), Some(b"token".to_vec()));
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(txn.get(b"session").unwrap(), None);
+    assert_eq!(txn.scan(None, None).count(), 0);
+    drop(txn);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_ttl_puts_into_families_and_with_options() {
+    let test_dir = "/tmp/test_ttl_families";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let clock = ManualClock::new(1_000_000);
+    let db = Db::open_with(test_dir, DbOptions::default().clock(clock.clone())).unwrap();
+    let sessions = db
+        .create_column_family("sessions", DbOptions::default())
+        .unwrap();
+    db.put_with_ttl_cf(&sessions, b"a", b"token", Duration::from_secs(1))
+        .unwrap();
+    let sync = WriteOptions::default().wal_mode(WalMode::Sync);
+    db.put_with_ttl_opts(b"b", b"synced", Duration::from_secs(1), &sync)
+        .unwrap();
+    // a ttl past the end of the clock never expires instead of wrapping around
+    db.put_with_ttl(b"c", b"forever", Duration::MAX).unwrap();
+
+    assert_eq!(db.get_cf(&sessions, b"a").unwrap(), Some(b"token".to_vec()));
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"synced".to_vec()));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(db.get_cf(&sessions, b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), None);
+    assert_eq!(db.get(b"c").unwrap(), Some(b"forever".to_vec()));
+
+    drop(sessions);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
@@ -0,0 +1,176 @@
+use keylite_kv::core::{Db, DbOptions, WalMode};
+use keylite_kv::sst::{SSTIterator, SSTReader, SSTWriter};
+use keylite_kv::types::ValueType;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+fn small_level_options() -> DbOptions {
+    DbOptions::default()
+        .memtable_size_threshold(16 * 1024)
+        .max_sstables(2)
+        .block_size(1024)
+        .bloom_size(1024)
+        .level_base_bytes(64 * 1024)
+        .level_size_multiplier(4)
+        .target_file_size(16 * 1024)
+}
+
+// copies the files of a running database, what's on disk at this point is exactly what a crash
+// would leave behind
+fn crash_copy(from: &str, to: &str) {
+    let _ = fs::remove_dir_all(to);
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(to).join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+// a record of a version 2 log, written before entries were tagged
+fn v2_record(seq: u64, entries: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&seq.to_le_bytes());
+    payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (key, val) in entries {
+        payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&(val.len() as u32).to_le_bytes());
+        payload.extend_from_slice(key);
+        payload.extend_from_slice(val);
+    }
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    buf.extend_from_slice(&payload);
+    buf
+}
+
+fn flag(i: usize) -> Vec<u8> {
+    format!("flag_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_empty_value_is_not_a_delete() {
+    let test_dir = "/tmp/test_value_type_empty";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"flag", b"").unwrap();
+    db.put(b"gone", b"x").unwrap();
+    db.del(b"gone").unwrap();
+
+    assert_eq!(db.get(b"flag").unwrap(), Some(Vec::new()));
+    assert_eq!(db.get(b"gone").unwrap(), None);
+    let scanned: Vec<_> = db.scan(None, None).collect();
+    assert_eq!(scanned, vec![(b"flag".to_vec(), Vec::new())]);
+
+    // the same goes for the buffer of a transaction
+    let mut txn = db.begin();
+    txn.put(b"other", b"");
+    txn.del(b"flag");
+    assert_eq!(txn.get(b"other").unwrap(), Some(Vec::new()));
+    assert_eq!(txn.get(b"flag").unwrap(), None);
+    let scanned: Vec<_> = txn.scan(None, None).collect();
+    assert_eq!(scanned, vec![(b"other".to_vec(), Vec::new())]);
+    txn.commit().unwrap();
+    assert_eq!(db.get(b"other").unwrap(), Some(Vec::new()));
+    assert_eq!(db.get(b"flag").unwrap(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_empty_values_survive_flush_and_compaction() {
+    let test_dir = "/tmp/test_value_type_compaction";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..2000 {
+        db.put(&flag(i), b"").unwrap();
+    }
+    for i in (0..2000).step_by(2) {
+        db.del(&flag(i)).unwrap();
+    }
+    // more writes on top so the flags get compacted into the deeper levels
+    for round in 0..8u8 {
+        for i in 0..1000 {
+            let other = format!("other_{:05}", i);
+            db.put(other.as_bytes(), &[b'a' + round; 64]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    drop(db);
+
+    let db = Db::open_with(test_dir, small_level_options()).unwrap();
+    for i in 0..2000 {
+        let expected = (i % 2 == 1).then(Vec::new);
+        assert_eq!(db.get(&flag(i)).unwrap(), expected, "flag {}", i);
+    }
+    assert_eq!(db.scan(Some(b"flag_"), Some(b"flag`")).count(), 1000);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_tables_store_the_value_type() {
+    let test_dir = "/tmp/test_value_type_table";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+    let path = Path::new(test_dir).join("sst-1.db");
+
+    let mut writer = SSTWriter::new(&path).unwrap();
+    writer.add_entry(b"a", ValueType::Put, b"", 3).unwrap();
+    writer.add_entry(b"b", ValueType::Delete, b"", 2).unwrap();
+    writer.add_entry(b"c", ValueType::Put, b"value", 1).unwrap();
+    writer.finish().unwrap();
+
+    let reader = SSTReader::open(&path).unwrap();
+    assert_eq!(reader.get(b"a").unwrap(), Some(Vec::new()));
+    assert_eq!(reader.get(b"b").unwrap(), None);
+    assert_eq!(reader.get(b"c").unwrap(), Some(b"value".to_vec()));
+
+    let types: Vec<_> = SSTIterator::new(reader)
+        .map(|entry| entry.unwrap().3)
+        .collect();
+    assert_eq!(
+        types,
+        vec![ValueType::Put, ValueType::Delete, ValueType::Put]
+    );
+
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_wal_replays_value_types() {
+    let test_dir = "/tmp/test_value_type_wal";
+    let copy_dir = "/tmp/test_value_type_wal_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // an untagged log of the previous format, its empty values are tombstones
+    fs::create_dir_all(test_dir).unwrap();
+    let mut wal = b"KLWAL\0\0\x02".to_vec();
+    wal.extend(v2_record(1, &[(b"old", b"1"), (b"older", b"1")]));
+    wal.extend(v2_record(2, &[(b"old", b"")]));
+    fs::write(Path::new(test_dir).join("wal-1.log"), wal).unwrap();
+
+    let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Sync)).unwrap();
+    assert_eq!(db.get(b"old").unwrap(), None);
+    assert_eq!(db.get(b"older").unwrap(), Some(b"1".to_vec()));
+
+    db.put(b"new", b"").unwrap();
+    db.del(b"older").unwrap();
+    crash_copy(test_dir, copy_dir);
+
+    let recovered = Db::open(copy_dir).unwrap();
+    assert_eq!(recovered.get(b"old").unwrap(), None);
+    assert_eq!(recovered.get(b"older").unwrap(), None);
+    assert_eq!(recovered.get(b"new").unwrap(), Some(Vec::new()));
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
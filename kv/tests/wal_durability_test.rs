@@ -0,0 +1,190 @@
+use keylite_kv::core::{Db, DbOptions, WalMode, WriteOptions};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// bytes in all the WAL segments of the directory, the WAL thread writes them in the background
+// so give it a moment first
+fn wal_len(dir: &str) -> u64 {
+    thread::sleep(Duration::from_millis(50));
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| {
+            let name = entry.file_name().into_string().unwrap_or_default();
+            name.starts_with("wal-") && name.ends_with(".log")
+        })
+        .map(|entry| entry.metadata().unwrap().len())
+        .sum()
+}
+
+// copies the files of a running database, what's on disk at this point is exactly what a crash
+// would leave behind
+fn crash_copy(from: &str, to: &str) {
+    let _ = fs::remove_dir_all(to);
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(to).join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+#[test]
+fn test_sync_writes_survive_a_crash() {
+    let test_dir = "/tmp/test_wal_sync_crash";
+    let copy_dir = "/tmp/test_wal_sync_crash_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // a long interval so only the sync writes themselves can have reached the disk
+    let opts = DbOptions::default()
+        .wal_mode(WalMode::Sync)
+        .wal_sync_interval_ms(60_000);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for i in 0..50 {
+        let key = format!("key_{:02}", i);
+        db.put(key.as_bytes(), b"value").unwrap();
+    }
+    db.del(b"key_07").unwrap();
+    crash_copy(test_dir, copy_dir);
+
+    let recovered = Db::open(copy_dir).unwrap();
+    for i in 0..50 {
+        let key = format!("key_{:02}", i);
+        let expected = if i == 7 {
+            None
+        } else {
+            Some(b"value".to_vec())
+        };
+        assert_eq!(recovered.get(key.as_bytes()).unwrap(), expected);
+    }
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_disabled_wal_writes_nothing_to_the_log() {
+    let test_dir = "/tmp/test_wal_disabled";
+    let _ = fs::remove_dir_all(test_dir);
+
+    {
+        let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Disabled)).unwrap();
+        for i in 0..100 {
+            let key = format!("key_{:03}", i);
+            db.put(key.as_bytes(), b"value").unwrap();
+        }
+        assert_eq!(wal_len(test_dir), 0);
+    }
+
+    // a clean close still flushes the memtable
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(b"key_042").unwrap(), Some(b"value".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_concurrent_sync_writers() {
+    let test_dir = "/tmp/test_wal_group_commit";
+    let copy_dir = "/tmp/test_wal_group_commit_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Arc::new(
+        Db::open_with(test_dir, DbOptions::default().wal_sync_interval_ms(60_000)).unwrap(),
+    );
+    let sync = WriteOptions::default().wal_mode(WalMode::Sync);
+
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let key = format!("t{}_key_{:03}", t, i);
+                    db.put_with(key.as_bytes(), b"value", &sync).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    crash_copy(test_dir, copy_dir);
+
+    let recovered = Db::open(copy_dir).unwrap();
+    for t in 0..8 {
+        for i in 0..100 {
+            let key = format!("t{}_key_{:03}", t, i);
+            assert_eq!(
+                recovered.get(key.as_bytes()).unwrap(),
+                Some(b"value".to_vec())
+            );
+        }
+    }
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_per_write_mode_overrides_the_default() {
+    let test_dir = "/tmp/test_wal_per_write";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Disabled)).unwrap();
+    db.put(b"unlogged", b"1").unwrap();
+    assert_eq!(wal_len(test_dir), 0);
+
+    db.put_with(
+        b"logged",
+        b"2",
+        &WriteOptions::default().wal_mode(WalMode::Sync),
+    )
+    .unwrap();
+    assert!(wal_len(test_dir) > 0);
+
+    db.del_with(b"logged", &WriteOptions::default().wal_mode(WalMode::Sync))
+        .unwrap();
+    assert_eq!(db.get(b"logged").unwrap(), None);
+    assert_eq!(db.get(b"unlogged").unwrap(), Some(b"1".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_failed_segment_is_reported_to_later_writers() {
+    let test_dir = "/tmp/test_wal_failed_segment";
+    let checkpoint_dir = "/tmp/test_wal_failed_segment_checkpoint";
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(checkpoint_dir);
+
+    // a directory in place of the first segment, the WAL thread can't open it
+    let db = Db::open(test_dir).unwrap();
+    fs::create_dir(Path::new(test_dir).join("wal-1.log")).unwrap();
+
+    let sync = WriteOptions::default().wal_mode(WalMode::Sync);
+    let async_write = WriteOptions::default().wal_mode(WalMode::Async);
+    assert!(db.put_with(b"a", b"1", &sync).is_err());
+    assert_eq!(db.get(b"a").unwrap(), None);
+
+    // the segment stays failed for the writers that come after
+    assert!(db.put_with(b"b", b"2", &sync).is_err());
+    assert!(db.checkpoint(checkpoint_dir).is_err());
+
+    // an asynchronous writer doesn't wait for its record, the next one gets the failure
+    db.put_with(b"c", b"3", &async_write).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(db.put_with(b"d", b"4", &async_write).is_err());
+    assert_eq!(db.get(b"d").unwrap(), None);
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(checkpoint_dir);
+}
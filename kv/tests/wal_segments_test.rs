@@ -0,0 +1,141 @@
+use keylite_kv::core::{Db, DbOptions, WalMode};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+// copies the files of a running database, what's on disk at this point is exactly what a crash
+// would leave behind
+fn crash_copy(from: &str, to: &str) {
+    let _ = fs::remove_dir_all(to);
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(to).join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+// ids of the WAL segments in the directory, sorted
+fn segments(dir: &str) -> Vec<u64> {
+    let mut ids: Vec<u64> = fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().ok()?;
+            name.strip_prefix("wal-")?
+                .strip_suffix(".log")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_flush_keeps_the_logs_of_unflushed_memtables() {
+    let test_dir = "/tmp/test_wal_segments_flush";
+    let copy_dir = "/tmp/test_wal_segments_flush_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // enough writes to freeze several memtables, the oldest ones get flushed while the newest
+    // ones only live in memory and in their segments
+    let opts = DbOptions::default()
+        .memtable_size_threshold(8 * 1024)
+        .wal_mode(WalMode::Sync);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for i in 0..1000 {
+        db.put(&key(i), &[b'x'; 32]).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    // the flushed memtables took their segments with them, the rest are still around
+    let live = segments(test_dir);
+    assert!(!live.is_empty());
+    assert!(live[0] > 1, "the first segment should be retired by now");
+    crash_copy(test_dir, copy_dir);
+
+    let recovered = Db::open(copy_dir).unwrap();
+    for i in 0..1000 {
+        assert_eq!(
+            recovered.get(&key(i)).unwrap(),
+            Some(vec![b'x'; 32]),
+            "key {}",
+            i
+        );
+    }
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_clean_close_retires_every_segment() {
+    let test_dir = "/tmp/test_wal_segments_close";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(
+        test_dir,
+        DbOptions::default().memtable_size_threshold(8 * 1024),
+    )
+    .unwrap();
+    for i in 0..500 {
+        db.put(&key(i), b"value").unwrap();
+    }
+    drop(db);
+
+    // the WAL thread is joined on close, everything it was told to retire is gone
+    assert_eq!(segments(test_dir), Vec::<u64>::new());
+
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(&key(499)).unwrap(), Some(b"value".to_vec()));
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_recovery_replays_every_segment_in_order() {
+    let test_dir = "/tmp/test_wal_segments_replay";
+    let copy_dir = "/tmp/test_wal_segments_replay_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    // a tiny memtable so the rounds end up spread over several segments, every round overwrites
+    // the keys of the previous one and deletes one of them
+    let opts = DbOptions::default()
+        .memtable_size_threshold(2 * 1024)
+        .wal_mode(WalMode::Sync);
+    let db = Db::open_with(test_dir, opts).unwrap();
+    for round in 0..5u8 {
+        for i in 0..50 {
+            db.put(&key(i), &[b'0' + round; 16]).unwrap();
+        }
+        db.del(&key(round as usize)).unwrap();
+    }
+    crash_copy(test_dir, copy_dir);
+
+    let before = segments(copy_dir);
+    let recovered = Db::open(copy_dir).unwrap();
+    for i in 0..50 {
+        // only the delete of the last round isn't overwritten later on
+        let expected = (i != 4).then(|| vec![b'4'; 16]);
+        assert_eq!(recovered.get(&key(i)).unwrap(), expected, "key {}", i);
+    }
+
+    // the replayed segments went into a table, new writes go into a segment with a higher id
+    recovered.put(b"after", b"recovery").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    let after = segments(copy_dir);
+    assert_eq!(after.len(), 1);
+    assert!(before.iter().all(|id| *id < after[0]));
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
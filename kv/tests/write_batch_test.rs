@@ -0,0 +1,244 @@
+use keylite_kv::core::{Db, DbOptions, WalMode, WriteBatch};
+use keylite_kv::error::DbError;
+use keylite_kv::sst::{SSTWriter, MAX_KEY_SIZE};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// copies the files of a running database, what's on disk at this point is exactly what a crash
+// would leave behind
+fn crash_copy(from: &str, to: &str) {
+    let _ = fs::remove_dir_all(to);
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(to).join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+// a record in the format written before batches existed
+fn legacy_record(seq: u64, key: &[u8], val: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&(val.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(val);
+    let crc = crc32fast::hash(&buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+#[test]
+fn test_batch_applies_in_order() {
+    let test_dir = "/tmp/test_batch_order";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    db.put(b"stale", b"1").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1");
+    batch.put(b"b", b"1");
+    batch.put(b"a", b"2");
+    batch.delete(b"b");
+    batch.delete(b"stale");
+    batch.put(b"c", b"3");
+    db.write(batch).unwrap();
+
+    assert_eq!(db.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.get(b"b").unwrap(), None);
+    assert_eq!(db.get(b"stale").unwrap(), None);
+    assert_eq!(db.get(b"c").unwrap(), Some(b"3".to_vec()));
+
+    // an empty batch is a no-op
+    db.write(WriteBatch::new()).unwrap();
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_batch_delete_range() {
+    let test_dir = "/tmp/test_batch_delete_range";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    for i in 0..20 {
+        let key = format!("key_{:02}", i);
+        db.put(key.as_bytes(), b"value").unwrap();
+    }
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"key_05a", b"written before the range delete");
+    batch.delete_range(b"key_05", b"key_10");
+    batch.put(b"key_07", b"written after the range delete");
+    db.write(batch).unwrap();
+
+    let keys: Vec<Vec<u8>> = db.scan(None, None).map(|(k, _)| k).collect();
+    let mut expected: Vec<Vec<u8>> = (0..20)
+        .filter(|i| !(5..10).contains(i))
+        .map(|i| format!("key_{:02}", i).into_bytes())
+        .collect();
+    expected.push(b"key_07".to_vec());
+    expected.sort();
+    assert_eq!(keys, expected);
+    assert_eq!(
+        db.get(b"key_07").unwrap(),
+        Some(b"written after the range delete".to_vec())
+    );
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_oversized_keys_are_rejected() {
+    let test_dir = "/tmp/test_batch_oversized_key";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open(test_dir).unwrap();
+    let longest = vec![b'k'; MAX_KEY_SIZE];
+    let too_long = vec![b'k'; MAX_KEY_SIZE + 1];
+
+    db.put(&longest, b"fits").unwrap();
+    assert!(matches!(
+        db.put(&too_long, b"value"),
+        Err(DbError::KeyTooLarge(len)) if len == MAX_KEY_SIZE + 1
+    ));
+
+    // nothing of a batch with an oversized key is applied
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1");
+    batch.delete(&too_long);
+    assert!(matches!(db.write(batch), Err(DbError::KeyTooLarge(_))));
+    assert_eq!(db.get(b"a").unwrap(), None);
+
+    // so is one deleting a range with an oversized bound
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"1");
+    batch.delete_range(b"a", &too_long);
+    assert!(matches!(db.write(batch), Err(DbError::KeyTooLarge(_))));
+    assert_eq!(db.get(b"a").unwrap(), None);
+
+    // the longest key still makes it into a table and back
+    drop(db);
+    let db = Db::open(test_dir).unwrap();
+    assert_eq!(db.get(&longest).unwrap(), Some(b"fits".to_vec()));
+
+    let mut writer = SSTWriter::new(Path::new(test_dir).join("sst-oversized.db")).unwrap();
+    assert!(writer.add(&too_long, b"value", 1).is_err());
+
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+}
+
+#[test]
+fn test_torn_batch_is_not_replayed() {
+    let test_dir = "/tmp/test_batch_torn";
+    let copy_dir = "/tmp/test_batch_torn_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Sync)).unwrap();
+    let mut first = WriteBatch::new();
+    first.put(b"a", b"1");
+    first.put(b"b", b"1");
+    db.write(first).unwrap();
+
+    let mut second = WriteBatch::new();
+    for i in 0..10 {
+        second.put(format!("key_{}", i).as_bytes(), b"2");
+    }
+    second.delete(b"a");
+    db.write(second).unwrap();
+    crash_copy(test_dir, copy_dir);
+
+    // the crash hit in the middle of writing the second batch
+    let wal = Path::new(copy_dir).join("wal-1.log");
+    let len = fs::metadata(&wal).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&wal)
+        .unwrap()
+        .set_len(len - 10)
+        .unwrap();
+
+    let recovered = Db::open(copy_dir).unwrap();
+    assert_eq!(recovered.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(recovered.get(b"b").unwrap(), Some(b"1".to_vec()));
+    for i in 0..10 {
+        assert_eq!(
+            recovered.get(format!("key_{}", i).as_bytes()).unwrap(),
+            None
+        );
+    }
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_torn_record_length_is_bounded_by_the_log() {
+    let test_dir = "/tmp/test_batch_torn_length";
+    let copy_dir = "/tmp/test_batch_torn_length_copy";
+    let _ = fs::remove_dir_all(test_dir);
+
+    let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Sync)).unwrap();
+    db.put(b"a", b"1").unwrap();
+    crash_copy(test_dir, copy_dir);
+
+    // only the header of the next record made it, its length is far beyond the end of the log
+    let wal = Path::new(copy_dir).join("wal-1.log");
+    let mut header = u32::MAX.to_le_bytes().to_vec();
+    header.extend_from_slice(&[0; 4]);
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&wal)
+        .unwrap()
+        .write_all(&header)
+        .unwrap();
+
+    let recovered = Db::open(copy_dir).unwrap();
+    assert_eq!(recovered.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}
+
+#[test]
+fn test_legacy_wal_is_replayed() {
+    let test_dir = "/tmp/test_batch_legacy_wal";
+    let copy_dir = "/tmp/test_batch_legacy_wal_copy";
+    let _ = fs::remove_dir_all(test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut wal = Vec::new();
+    wal.extend(legacy_record(1, b"a", b"1"));
+    wal.extend(legacy_record(2, b"b", b"2"));
+    wal.extend(legacy_record(3, b"a", b""));
+    fs::write(Path::new(test_dir).join("wal.log"), wal).unwrap();
+
+    let db = Db::open_with(test_dir, DbOptions::default().wal_mode(WalMode::Sync)).unwrap();
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+    // new records go into a log of the new format
+    let mut batch = WriteBatch::new();
+    batch.put(b"c", b"3");
+    db.write(batch).unwrap();
+    crash_copy(test_dir, copy_dir);
+
+    let recovered = Db::open(copy_dir).unwrap();
+    assert_eq!(recovered.get(b"a").unwrap(), None);
+    assert_eq!(recovered.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(recovered.get(b"c").unwrap(), Some(b"3".to_vec()));
+
+    drop(recovered);
+    drop(db);
+    let _ = fs::remove_dir_all(test_dir);
+    let _ = fs::remove_dir_all(copy_dir);
+}